@@ -0,0 +1,488 @@
+use crate::config::Configuration;
+use crate::http_client::http_request_with_tls_config;
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hyper::{Method, StatusCode};
+use ring::hmac;
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use rustls_acme::acme::{
+    Directory, LETS_ENCRYPT_PRODUCTION_DIRECTORY, LETS_ENCRYPT_STAGING_DIRECTORY,
+};
+use rustls_acme::caches::DirCache;
+use rustls_acme::{AccountCache, AcmeConfig, AcmeState};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::info;
+
+/// Settings for automatic HTTPS certificates via ACME
+pub struct AcmeSettings {
+    pub domains: Vec<String>,
+    pub contact: String,
+    pub cache_dir: PathBuf,
+    pub directory: String,
+
+    /// Additional trusted CA certificates for the connection to the ACME server
+    pub ca_file: Option<PathBuf>,
+
+    pub eab: Option<ExternalAccountBinding>,
+}
+
+/// Credentials to bind the ACME account to an existing account of the CA (RFC 8555, section 7.3.4)
+pub struct ExternalAccountBinding {
+    pub kid: String,
+    pub key: Vec<u8>,
+}
+
+impl AcmeSettings {
+    pub fn from_config(config: &Configuration) -> Result<Self> {
+        ensure!(
+            !config.https_auto_cert_domain.is_empty(),
+            "HTTPS automatic certificate domain is missing in configuration"
+        );
+        let mail = config
+            .https_auto_cert_mail
+            .as_deref()
+            .context("HTTPS automatic certificate mail is missing in configuration")?;
+        let cache_dir = config
+            .https_auto_cert_cache
+            .clone()
+            .context("HTTPS automatic certificate cache directory is missing in configuration")?;
+        let directory = if config.https_auto_cert_staging {
+            String::from(LETS_ENCRYPT_STAGING_DIRECTORY)
+        } else {
+            config
+                .https_auto_cert_directory
+                .clone()
+                .unwrap_or_else(|| String::from(LETS_ENCRYPT_PRODUCTION_DIRECTORY))
+        };
+        let eab = match (
+            &config.https_auto_cert_eab_kid,
+            &config.https_auto_cert_eab_key,
+        ) {
+            (Some(kid), Some(key)) => Some(ExternalAccountBinding {
+                kid: kid.clone(),
+                key: URL_SAFE_NO_PAD
+                    .decode(key.trim().trim_end_matches('='))
+                    .context("External account binding key is not valid base64url")?,
+            }),
+            _ => None,
+        };
+        Ok(Self {
+            domains: config.https_auto_cert_domain.clone(),
+            contact: format!("mailto:{mail}"),
+            cache_dir,
+            directory,
+            ca_file: config.https_auto_cert_ca_file.clone(),
+            eab,
+        })
+    }
+}
+
+/// TLS configuration trusting the public root certificates and the certificates of the optional PEM file
+pub fn client_tls_config(ca_file: Option<&Path>) -> Result<Arc<ClientConfig>> {
+    let mut root_cert_store = RootCertStore::empty();
+    root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ca_file) = ca_file {
+        let mut certs = Vec::new();
+        for cert in CertificateDer::pem_file_iter(ca_file).context("Failed to read CA file")? {
+            certs.push(cert.context("Failed to parse CA file")?);
+        }
+        let (added, _) = root_cert_store.add_parsable_certificates(certs);
+        ensure!(added > 0, "CA file contains no valid certificates");
+    }
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(root_cert_store)
+        .with_no_client_auth();
+    Ok(Arc::new(client_config))
+}
+
+/// Creates the state of the ACME client, which must be polled to get certificates.
+/// With external account binding the account is registered first,
+/// because the ACME client does not support it and uses the cached account afterwards.
+pub async fn acme_state(settings: &AcmeSettings) -> Result<AcmeState<std::io::Error>> {
+    let tls_config = client_tls_config(settings.ca_file.as_deref())?;
+    let cache = DirCache::new(settings.cache_dir.clone());
+    let contact = vec![settings.contact.clone()];
+
+    if let Some(eab) = &settings.eab {
+        let account = cache
+            .load_account(&contact, &settings.directory)
+            .await
+            .context("Failed to load cached ACME account")?;
+        if account.is_none() {
+            info!("Registering ACME account with external account binding...");
+            let key = EcdsaKeyPair::generate_pkcs8(
+                &ECDSA_P256_SHA256_FIXED_SIGNING,
+                &SystemRandom::new(),
+            )
+            .map_err(|_| anyhow!("Failed to generate ACME account key"))?;
+            register_account(
+                &settings.directory,
+                &contact,
+                key.as_ref(),
+                eab,
+                tls_config.clone(),
+            )
+            .await
+            .context("Failed to register ACME account")?;
+            cache
+                .store_account(&contact, &settings.directory, key.as_ref())
+                .await
+                .context("Failed to store ACME account")?;
+        }
+    }
+
+    Ok(AcmeConfig::new(&settings.domains)
+        .contact(&contact)
+        .cache_option(Some(cache))
+        .directory(&settings.directory)
+        .client_tls_config(tls_config)
+        .state())
+}
+
+/// Creates a new ACME account with external account binding
+async fn register_account(
+    directory_url: &str,
+    contact: &[String],
+    pkcs8: &[u8],
+    eab: &ExternalAccountBinding,
+    tls_config: Arc<ClientConfig>,
+) -> Result<()> {
+    let rng = SystemRandom::new();
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &rng)
+        .map_err(|err| anyhow!("Invalid ACME account key: {err}"))?;
+
+    let headers = HashMap::new();
+    let (status, _, body) = http_request_with_tls_config(
+        Method::GET,
+        directory_url,
+        &headers,
+        Vec::new(),
+        Some(tls_config.clone()),
+    )
+    .await
+    .context("Failed to get ACME directory")?;
+    ensure!(
+        status.is_success(),
+        "ACME directory request failed with status {status}"
+    );
+    let directory: Directory =
+        serde_json::from_slice(&body).context("Failed to parse ACME directory")?;
+
+    let (_, response_headers, _) = http_request_with_tls_config(
+        Method::HEAD,
+        &directory.new_nonce,
+        &headers,
+        Vec::new(),
+        Some(tls_config.clone()),
+    )
+    .await
+    .context("Failed to get ACME nonce")?;
+    let nonce = response_headers
+        .get("replay-nonce")
+        .and_then(|value| value.to_str().ok())
+        .context("ACME server did not send a nonce")?;
+
+    let jwk = jwk(&key_pair);
+    let binding = jws(
+        &json!({
+            "alg": "HS256",
+            "kid": eab.kid,
+            "url": directory.new_account,
+        }),
+        &jwk,
+        |data| {
+            let key = hmac::Key::new(hmac::HMAC_SHA256, &eab.key);
+            Ok(hmac::sign(&key, data).as_ref().to_vec())
+        },
+    )?;
+    let payload = json!({
+        "termsOfServiceAgreed": true,
+        "contact": contact,
+        "externalAccountBinding": binding,
+    });
+    let protected = json!({
+        "alg": "ES256",
+        "jwk": jwk,
+        "nonce": nonce,
+        "url": directory.new_account,
+    });
+    let request = jws(&protected, &payload, |data| {
+        let signature = key_pair
+            .sign(&rng, data)
+            .map_err(|_| anyhow!("Failed to sign ACME request"))?;
+        Ok(signature.as_ref().to_vec())
+    })?;
+
+    let headers = HashMap::from([(
+        String::from("Content-Type"),
+        String::from("application/jose+json"),
+    )]);
+    let (status, _, body) = http_request_with_tls_config(
+        Method::POST,
+        &directory.new_account,
+        &headers,
+        request.to_string().into_bytes(),
+        Some(tls_config),
+    )
+    .await
+    .context("Failed to send ACME account request")?;
+    if status != StatusCode::OK && status != StatusCode::CREATED {
+        bail!(
+            "ACME server rejected account with status {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+    Ok(())
+}
+
+/// Public key of the account in the same format as used by the ACME client
+fn jwk(key_pair: &EcdsaKeyPair) -> Value {
+    let (x, y) = key_pair.public_key().as_ref()[1..].split_at(32);
+    json!({
+        "alg": "ES256",
+        "crv": "P-256",
+        "kty": "EC",
+        "use": "sig",
+        "x": URL_SAFE_NO_PAD.encode(x),
+        "y": URL_SAFE_NO_PAD.encode(y),
+    })
+}
+
+/// JSON web signature in flattened JSON serialization
+fn jws(
+    protected: &Value,
+    payload: &Value,
+    sign: impl FnOnce(&[u8]) -> Result<Vec<u8>>,
+) -> Result<Value> {
+    let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+    let payload = URL_SAFE_NO_PAD.encode(payload.to_string());
+    let signature = sign(format!("{protected}.{payload}").as_bytes())?;
+    Ok(json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": URL_SAFE_NO_PAD.encode(signature),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::random_token;
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::HeaderMap;
+    use axum::response::IntoResponse;
+    use axum::routing::{get, post};
+    use futures::StreamExt;
+    use ring::signature::UnparsedPublicKey;
+    use rustls_acme::EventOk;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const EAB_KID: &str = "kid-1";
+    const EAB_KEY: &[u8] = b"secret eab key of the test account";
+
+    #[derive(Clone)]
+    struct MockServer {
+        base: String,
+        accounts: Arc<Mutex<Vec<Value>>>,
+    }
+
+    fn decode(value: &Value) -> Value {
+        let bytes = URL_SAFE_NO_PAD.decode(value.as_str().unwrap()).unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn signed_data(jws: &Value) -> String {
+        format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        )
+    }
+
+    async fn new_account(
+        State(server): State<MockServer>,
+        body: String,
+    ) -> axum::response::Response {
+        let url = format!("{}/account", server.base);
+        let request: Value = serde_json::from_str(&body).unwrap();
+        let protected = decode(&request["protected"]);
+        let payload = decode(&request["payload"]);
+        assert_eq!(protected["nonce"], "nonce-1");
+        assert_eq!(protected["url"], url);
+
+        // Outer signature with the account key
+        let jwk = &protected["jwk"];
+        let mut public_key = vec![4];
+        for coordinate in ["x", "y"] {
+            let bytes = URL_SAFE_NO_PAD.decode(jwk[coordinate].as_str().unwrap());
+            public_key.extend(bytes.unwrap());
+        }
+        let signature = URL_SAFE_NO_PAD
+            .decode(request["signature"].as_str().unwrap())
+            .unwrap();
+        UnparsedPublicKey::new(&ring::signature::ECDSA_P256_SHA256_FIXED, public_key)
+            .verify(signed_data(&request).as_bytes(), &signature)
+            .unwrap();
+
+        // External account binding
+        let binding = &payload["externalAccountBinding"];
+        let binding_protected = decode(&binding["protected"]);
+        assert_eq!(binding_protected["alg"], "HS256");
+        assert_eq!(binding_protected["kid"], EAB_KID);
+        assert_eq!(binding_protected["url"], url);
+        assert_eq!(&decode(&binding["payload"]), jwk);
+        let signature = URL_SAFE_NO_PAD
+            .decode(binding["signature"].as_str().unwrap())
+            .unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, EAB_KEY);
+        if hmac::verify(&key, signed_data(binding).as_bytes(), &signature).is_err() {
+            return (StatusCode::UNAUTHORIZED, "invalid binding").into_response();
+        }
+
+        server.accounts.lock().unwrap().push(payload);
+        (
+            StatusCode::CREATED,
+            [("location", format!("{url}/1"))],
+            "{}",
+        )
+            .into_response()
+    }
+
+    async fn start_mock_server() -> MockServer {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = MockServer {
+            base: format!("http://{}", listener.local_addr().unwrap()),
+            accounts: Arc::new(Mutex::new(Vec::new())),
+        };
+        let base = server.base.clone();
+        let app = Router::new()
+            .route(
+                "/directory",
+                get(move || async move {
+                    axum::Json(json!({
+                        "newNonce": format!("{base}/nonce"),
+                        "newAccount": format!("{base}/account"),
+                        "newOrder": format!("{base}/order"),
+                    }))
+                }),
+            )
+            .route(
+                "/nonce",
+                get(|| async {
+                    let mut headers = HeaderMap::new();
+                    headers.insert("replay-nonce", "nonce-1".parse().unwrap());
+                    headers
+                }),
+            )
+            .route("/account", post(new_account))
+            .with_state(server.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        server
+    }
+
+    fn settings(directory: String, eab_key: &[u8]) -> AcmeSettings {
+        AcmeSettings {
+            domains: vec![String::from("a.example.com"), String::from("b.example.com")],
+            contact: String::from("mailto:admin@example.com"),
+            cache_dir: std::env::temp_dir().join(format!("dmarc-acme-{}", random_token())),
+            directory,
+            ca_file: None,
+            eab: Some(ExternalAccountBinding {
+                kid: String::from(EAB_KID),
+                key: eab_key.to_vec(),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn external_account_binding() {
+        let server = start_mock_server().await;
+        let directory = format!("{}/directory", server.base);
+
+        // Wrong key is rejected and no account is cached
+        let wrong = settings(directory.clone(), b"wrong key");
+        let err = acme_state(&wrong).await.err().unwrap();
+        assert!(format!("{err:#}").contains("401"));
+        assert!(!wrong.cache_dir.exists());
+
+        let settings = settings(directory, EAB_KEY);
+        acme_state(&settings).await.unwrap();
+        {
+            let accounts = server.accounts.lock().unwrap();
+            assert_eq!(accounts.len(), 1);
+            assert_eq!(accounts[0]["contact"], json!(["mailto:admin@example.com"]));
+            assert_eq!(accounts[0]["termsOfServiceAgreed"], true);
+        }
+
+        // Cached account is used without registering again
+        acme_state(&settings).await.unwrap();
+        assert_eq!(server.accounts.lock().unwrap().len(), 1);
+        std::fs::remove_dir_all(&settings.cache_dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_ca_file() {
+        assert!(client_tls_config(None).is_ok());
+        let path = std::env::temp_dir().join(format!("dmarc-acme-ca-{}.pem", random_token()));
+        std::fs::write(&path, "no certificates").unwrap();
+        assert!(client_tls_config(Some(&path)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(client_tls_config(Some(&path)).is_err());
+    }
+
+    /// Requests a certificate from a local Pebble ACME test server, for example started with
+    /// `docker run -e PEBBLE_VA_ALWAYS_VALID=1 -p 14000:14000 ghcr.io/letsencrypt/pebble`.
+    /// Set `PEBBLE_CA` to the CA file of its HTTPS API (`test/certs/pebble.minica.pem`)
+    /// and optionally `PEBBLE_EAB_KID` and `PEBBLE_EAB_KEY` if Pebble requires EAB.
+    #[tokio::test]
+    #[ignore = "requires a Pebble ACME server"]
+    async fn pebble() {
+        let directory = std::env::var("PEBBLE_DIRECTORY")
+            .unwrap_or_else(|_| String::from("https://localhost:14000/dir"));
+        let ca_file = std::env::var("PEBBLE_CA").expect("PEBBLE_CA must be set");
+        let eab = match (
+            std::env::var("PEBBLE_EAB_KID"),
+            std::env::var("PEBBLE_EAB_KEY"),
+        ) {
+            (Ok(kid), Ok(key)) => Some(ExternalAccountBinding {
+                kid,
+                key: URL_SAFE_NO_PAD.decode(key.trim_end_matches('=')).unwrap(),
+            }),
+            _ => None,
+        };
+        let settings = AcmeSettings {
+            domains: vec![String::from("dmarc.example.com")],
+            contact: String::from("mailto:admin@example.com"),
+            cache_dir: std::env::temp_dir().join(format!("dmarc-pebble-{}", random_token())),
+            directory,
+            ca_file: Some(PathBuf::from(ca_file)),
+            eab,
+        };
+
+        let mut state = acme_state(&settings).await.unwrap();
+        let deployed = tokio::time::timeout(Duration::from_secs(60), async {
+            while let Some(event) = state.next().await {
+                match event {
+                    Ok(EventOk::DeployedNewCert) => return true,
+                    Ok(_) => {}
+                    Err(err) => panic!("ACME error: {err:?}"),
+                }
+            }
+            false
+        })
+        .await
+        .expect("No certificate received in time");
+        assert!(deployed);
+        std::fs::remove_dir_all(&settings.cache_dir).unwrap();
+    }
+}
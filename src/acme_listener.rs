@@ -1,9 +1,8 @@
+use crate::acme::{self, AcmeSettings};
 use axum::serve::Listener;
 use futures::StreamExt;
-use rustls_acme::caches::DirCache;
-use rustls_acme::{AcmeConfig, is_tls_alpn_challenge};
+use rustls_acme::is_tls_alpn_challenge;
 use std::net::SocketAddr;
-use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::io::Result;
@@ -20,21 +19,9 @@ pub struct AcmeListener {
 }
 
 impl AcmeListener {
-    pub fn new(
-        tcp_listener: TcpListener,
-        acme_domain: String,
-        acme_contact: String,
-        acme_cache_dir: PathBuf,
-    ) -> anyhow::Result<Self> {
-        // Set up cert caching dir
-        let acme_cache = DirCache::new(acme_cache_dir);
-
+    pub async fn new(tcp_listener: TcpListener, settings: &AcmeSettings) -> anyhow::Result<Self> {
         // Set up ACME client
-        let mut acme_state = AcmeConfig::new([acme_domain])
-            .contact([acme_contact])
-            .cache_option(Some(acme_cache))
-            .directory_lets_encrypt(true)
-            .state();
+        let mut acme_state = acme::acme_state(settings).await?;
 
         // Prepare the configurations for incoming connections
         let challenge_config = acme_state.challenge_rustls_config();
@@ -0,0 +1,422 @@
+use crate::session::random_token;
+use crate::users::{DomainScope, Role, User};
+use anyhow::{Context, Result, ensure};
+use axum::http::Method;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+/// Prefix of all API tokens, makes them easy to recognize in configurations and secret scanners
+const TOKEN_PREFIX: &str = "dmarc_";
+
+/// Last used timestamps are only written to the tokens file if they changed by at least this many seconds
+const LAST_USED_SAVE_INTERVAL: u64 = 60;
+
+/// Stored metadata of an API token.
+/// The token itself is only returned once when it is created, only its hash is kept.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    /// Public part of the token, used to find the token and to revoke it
+    pub id: String,
+
+    /// Description like "Grafana"
+    pub name: String,
+
+    /// SHA-256 hash of the complete token
+    #[serde(skip_serializing_if = "String::is_empty")]
+    hash: String,
+
+    /// Name of the admin that created the token
+    pub created_by: String,
+
+    /// Unix timestamps
+    pub created: u64,
+    pub expires: Option<u64>,
+    pub last_used: Option<u64>,
+    pub revoked: Option<u64>,
+
+    /// Read only tokens can only be used for GET requests
+    pub read_only: bool,
+
+    /// Paths of the allowed endpoints including their sub paths, empty means all endpoints
+    pub endpoints: Vec<String>,
+
+    /// Visible domains including their subdomains, empty means all domains
+    pub domains: Vec<String>,
+}
+
+impl ApiToken {
+    /// Copy without the hash for API responses
+    pub fn public(&self) -> Self {
+        Self {
+            hash: String::new(),
+            ..self.clone()
+        }
+    }
+
+    fn allows_endpoint(&self, path: &str) -> bool {
+        self.endpoints.is_empty()
+            || self.endpoints.iter().any(|endpoint| {
+                let endpoint = endpoint.trim_end_matches('/');
+                path == endpoint
+                    || path
+                        .strip_prefix(endpoint)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+}
+
+/// Request to create a new API token
+#[derive(Deserialize, Debug)]
+pub struct NewApiToken {
+    pub name: String,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// None means the token does not expire
+    pub expires_in_days: Option<u64>,
+}
+
+/// Reasons why a token was not accepted
+#[derive(Debug)]
+pub enum TokenError {
+    /// Unknown, expired or revoked token
+    Invalid,
+
+    /// Valid token that is not allowed to access the endpoint
+    Forbidden,
+}
+
+/// All API tokens, optionally persisted in a JSON file
+pub struct TokenStore {
+    path: Option<PathBuf>,
+    tokens: Mutex<HashMap<String, ApiToken>>,
+
+    /// Last used timestamps as written to the file
+    saved_last_used: Mutex<HashMap<String, u64>>,
+}
+
+impl TokenStore {
+    /// Loads the tokens from the file if it exists.
+    /// Without file the tokens are lost with a restart of the application.
+    pub async fn new(path: Option<PathBuf>) -> Result<Self> {
+        let tokens: Vec<ApiToken> = match &path {
+            Some(path) => match tokio::fs::read(path).await {
+                Ok(data) => serde_json::from_slice(&data).context("Failed to parse tokens file")?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(err) => return Err(err).context("Failed to read tokens file"),
+            },
+            None => Vec::new(),
+        };
+        let saved_last_used = tokens
+            .iter()
+            .filter_map(|t| Some((t.id.clone(), t.last_used?)))
+            .collect();
+        Ok(Self {
+            path,
+            tokens: Mutex::new(tokens.into_iter().map(|t| (t.id.clone(), t)).collect()),
+            saved_last_used: Mutex::new(saved_last_used),
+        })
+    }
+
+    /// All tokens without hashes, newest first
+    pub fn list(&self) -> Vec<ApiToken> {
+        let tokens = self.tokens.lock().expect("Failed to lock tokens");
+        let mut list: Vec<ApiToken> = tokens.values().map(ApiToken::public).collect();
+        list.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| a.id.cmp(&b.id)));
+        list
+    }
+
+    /// Creates a new token and returns its metadata and the token itself
+    pub async fn create(
+        &self,
+        request: NewApiToken,
+        created_by: &str,
+    ) -> Result<(ApiToken, String)> {
+        let name = request.name.trim();
+        ensure!(!name.is_empty(), "Token name must not be empty");
+        ensure!(
+            request.endpoints.iter().all(|e| e.starts_with('/')),
+            "Endpoints must start with a slash"
+        );
+        ensure!(
+            request.expires_in_days != Some(0),
+            "Expiration must be at least one day"
+        );
+        let now = unix_now();
+        let id = random_token()[..12].to_string();
+        let token = format!("{TOKEN_PREFIX}{id}.{}", random_token());
+        let api_token = ApiToken {
+            id: id.clone(),
+            name: name.to_string(),
+            hash: hash_token(&token),
+            created_by: created_by.to_string(),
+            created: now,
+            expires: request.expires_in_days.map(|days| now + days * 86400),
+            last_used: None,
+            revoked: None,
+            read_only: request.read_only,
+            endpoints: request.endpoints,
+            domains: request
+                .domains
+                .iter()
+                .map(|d| d.trim().trim_end_matches('.').to_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect(),
+        };
+        self.tokens
+            .lock()
+            .expect("Failed to lock tokens")
+            .insert(id, api_token.clone());
+        self.save().await?;
+        Ok((api_token.public(), token))
+    }
+
+    /// Marks the token as revoked, returns false if there is no such token
+    pub async fn revoke(&self, id: &str) -> Result<bool> {
+        {
+            let mut tokens = self.tokens.lock().expect("Failed to lock tokens");
+            let Some(token) = tokens.get_mut(id) else {
+                return Ok(false);
+            };
+            token.revoked.get_or_insert(unix_now());
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    /// Checks the token and its scopes for the request.
+    /// Returns a user with the viewer role, so tokens can never be used to manage tokens.
+    pub async fn authenticate(
+        &self,
+        token: &str,
+        method: &Method,
+        path: &str,
+    ) -> Result<User, TokenError> {
+        let now = unix_now();
+        let (user, id) = {
+            let mut tokens = self.tokens.lock().expect("Failed to lock tokens");
+            let api_token = token
+                .strip_prefix(TOKEN_PREFIX)
+                .and_then(|token| token.split_once('.'))
+                .and_then(|(id, _)| tokens.get_mut(id))
+                .ok_or(TokenError::Invalid)?;
+
+            // Use constant time comparison to avoid timing attacks
+            let hash = hash_token(token);
+            if api_token.hash.as_bytes().ct_eq(hash.as_bytes()).unwrap_u8() != 1
+                || api_token.revoked.is_some()
+                || api_token.expires.is_some_and(|expires| expires <= now)
+            {
+                return Err(TokenError::Invalid);
+            }
+            let read = method == Method::GET || method == Method::HEAD;
+            if (api_token.read_only && !read) || !api_token.allows_endpoint(path) {
+                return Err(TokenError::Forbidden);
+            }
+            api_token.last_used = Some(now);
+            let user = User {
+                name: format!("token:{}", api_token.name),
+                role: Role::Viewer,
+                scope: if api_token.domains.is_empty() {
+                    DomainScope::All
+                } else {
+                    DomainScope::Domains(api_token.domains.clone())
+                },
+            };
+            (user, api_token.id.clone())
+        };
+
+        // Avoid writing the file for every request
+        let outdated = {
+            let saved = self
+                .saved_last_used
+                .lock()
+                .expect("Failed to lock last used");
+            saved
+                .get(&id)
+                .is_none_or(|saved| *saved + LAST_USED_SAVE_INTERVAL <= now)
+        };
+        if outdated && let Err(err) = self.save().await {
+            warn!("Failed to save last used time of API token: {err:#}");
+        }
+        Ok(user)
+    }
+
+    /// Writes all tokens to a temporary file and replaces the old file
+    async fn save(&self) -> Result<()> {
+        let tokens = self.tokens.lock().expect("Failed to lock tokens").clone();
+        *self
+            .saved_last_used
+            .lock()
+            .expect("Failed to lock last used") = tokens
+            .values()
+            .filter_map(|t| Some((t.id.clone(), t.last_used?)))
+            .collect();
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut tokens: Vec<ApiToken> = tokens.into_values().collect();
+        tokens.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.id.cmp(&b.id)));
+        write_file(path, &tokens).await
+    }
+}
+
+async fn write_file(path: &Path, tokens: &[ApiToken]) -> Result<()> {
+    let json = serde_json::to_vec_pretty(tokens).context("Failed to serialize tokens")?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    tokio::fs::write(&tmp_path, json)
+        .await
+        .context("Failed to write temporary tokens file")?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .context("Failed to replace tokens file")
+}
+
+/// Tokens contain enough randomness that a fast hash is sufficient
+fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Failed to get Unix time stamp")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(name: &str) -> NewApiToken {
+        NewApiToken {
+            name: String::from(name),
+            read_only: false,
+            endpoints: Vec::new(),
+            domains: Vec::new(),
+            expires_in_days: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn token_lifecycle() {
+        let store = TokenStore::new(None).await.unwrap();
+        let (token, secret) = store.create(request("script"), "admin").await.unwrap();
+        assert!(token.hash.is_empty());
+        assert!(secret.starts_with(TOKEN_PREFIX));
+
+        let user = store
+            .authenticate(&secret, &Method::POST, "/ips/dns/batch")
+            .await
+            .unwrap();
+        assert_eq!(user.name, "token:script");
+        assert_eq!(user.role, Role::Viewer);
+        assert!(user.scope.is_all());
+        assert!(store.list()[0].last_used.is_some());
+
+        // Wrong secret with correct id
+        let (id, _) = secret.split_once('.').unwrap();
+        let wrong = format!("{id}.{}", random_token());
+        let result = store.authenticate(&wrong, &Method::GET, "/summary").await;
+        assert!(matches!(result, Err(TokenError::Invalid)));
+
+        assert!(store.revoke(&token.id).await.unwrap());
+        assert!(!store.revoke("unknown").await.unwrap());
+        assert!(
+            store
+                .authenticate(&secret, &Method::GET, "/summary")
+                .await
+                .is_err()
+        );
+        assert!(store.list()[0].revoked.is_some());
+    }
+
+    #[tokio::test]
+    async fn token_scopes() {
+        let store = TokenStore::new(None).await.unwrap();
+        let mut scoped = request("grafana");
+        scoped.read_only = true;
+        scoped.endpoints = vec![String::from("/metrics"), String::from("/dmarc-reports/")];
+        scoped.domains = vec![String::from("Example.com.")];
+        let (_, secret) = store.create(scoped, "admin").await.unwrap();
+
+        let user = store
+            .authenticate(&secret, &Method::GET, "/metrics")
+            .await
+            .unwrap();
+        assert_eq!(
+            user.scope,
+            DomainScope::Domains(vec![String::from("example.com")])
+        );
+        for path in ["/dmarc-reports", "/dmarc-reports/abc/json"] {
+            assert!(
+                store
+                    .authenticate(&secret, &Method::GET, path)
+                    .await
+                    .is_ok()
+            );
+        }
+        for (method, path) in [
+            (Method::GET, "/metrics2"),
+            (Method::GET, "/summary"),
+            (Method::POST, "/metrics"),
+        ] {
+            let result = store.authenticate(&secret, &method, path).await;
+            assert!(matches!(result, Err(TokenError::Forbidden)), "{path}");
+        }
+
+        // Expired
+        let (token, secret) = store.create(request("old"), "admin").await.unwrap();
+        store
+            .tokens
+            .lock()
+            .unwrap()
+            .get_mut(&token.id)
+            .unwrap()
+            .expires = Some(unix_now());
+        let result = store.authenticate(&secret, &Method::GET, "/summary").await;
+        assert!(matches!(result, Err(TokenError::Invalid)));
+
+        let mut invalid = request("invalid");
+        invalid.endpoints = vec![String::from("metrics")];
+        assert!(store.create(invalid, "admin").await.is_err());
+        assert!(store.create(request(" "), "admin").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn persistence() {
+        let path = std::env::temp_dir().join(format!("dmarc-tokens-{}.json", random_token()));
+        let store = TokenStore::new(Some(path.clone())).await.unwrap();
+        let (token, secret) = store.create(request("script"), "admin").await.unwrap();
+        store
+            .authenticate(&secret, &Method::GET, "/summary")
+            .await
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(&secret));
+        let loaded = TokenStore::new(Some(path.clone())).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let list = loaded.list();
+        assert_eq!(list[0].id, token.id);
+        assert!(list[0].last_used.is_some());
+        assert!(
+            loaded
+                .authenticate(&secret, &Method::GET, "/summary")
+                .await
+                .is_ok()
+        );
+    }
+}
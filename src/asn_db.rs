@@ -0,0 +1,204 @@
+use crate::ip_net::IpNet;
+use anyhow::{Context, Result, ensure};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Information about the announced IP range and autonomous system of an IP
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AsnEntry {
+    /// First IP of the announced range
+    pub first: IpAddr,
+    /// Last IP of the announced range
+    pub last: IpAddr,
+    /// Number of the autonomous system
+    pub asn: u32,
+    /// Two letter country code of the autonomous system
+    pub country: String,
+    /// Name or description of the autonomous system
+    pub description: String,
+}
+
+impl AsnEntry {
+    /// Returns the range in CIDR notation if possible or as `first-last` otherwise
+    pub fn range_label(&self) -> String {
+        if let Some(net) = IpNet::from_range(self.first, self.last) {
+            net.to_string()
+        } else {
+            format!("{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// Offline IP to ASN database loaded from a local file.
+/// Supports the tab-separated format of https://iptoasn.com/
+/// with one announced range per line in the columns
+/// range start, range end, AS number, country code and AS description.
+pub struct AsnDatabase {
+    v4: Vec<(u32, u32, AsnEntry)>,
+    v6: Vec<(u128, u128, AsnEntry)>,
+}
+
+impl AsnDatabase {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).context("Failed to read IP to ASN database file")?;
+        Self::from_tsv(&String::from_utf8_lossy(&data))
+    }
+
+    pub fn from_tsv(data: &str) -> Result<Self> {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for (index, line) in data.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = Self::parse_line(line)
+                .with_context(|| format!("Failed to parse line {}", index + 1))?;
+
+            // AS number zero is used for ranges that are not routed
+            if entry.asn == 0 {
+                continue;
+            }
+
+            match (entry.first, entry.last) {
+                (IpAddr::V4(first), IpAddr::V4(last)) => {
+                    v4.push((u32::from(first), u32::from(last), entry))
+                }
+                (IpAddr::V6(first), IpAddr::V6(last)) => {
+                    v6.push((u128::from(first), u128::from(last), entry))
+                }
+                _ => unreachable!("IP versions are checked while parsing"),
+            }
+        }
+        v4.sort_by_key(|e| e.0);
+        v6.sort_by_key(|e| e.0);
+        Ok(Self { v4, v6 })
+    }
+
+    fn parse_line(line: &str) -> Result<AsnEntry> {
+        let mut columns = line.split('\t');
+        let mut next = |name: &str| {
+            columns
+                .next()
+                .map(|c| c.trim())
+                .with_context(|| format!("Missing column {name}"))
+        };
+        let first = IpAddr::from_str(next("range start")?).context("Invalid range start")?;
+        let last = IpAddr::from_str(next("range end")?).context("Invalid range end")?;
+        let asn = u32::from_str(next("AS number")?).context("Invalid AS number")?;
+        let country = next("country code")?.to_string();
+        let description = next("AS description").unwrap_or_default().to_string();
+        ensure!(
+            first.is_ipv4() == last.is_ipv4(),
+            "Range start and end must have the same IP version"
+        );
+        ensure!(first <= last, "Range start must not be after range end");
+        Ok(AsnEntry {
+            first,
+            last,
+            asn,
+            country,
+            description,
+        })
+    }
+
+    /// Number of known IP ranges
+    pub fn range_count(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    /// Looks up the announced range and AS of an IP
+    pub fn lookup(&self, ip: &IpAddr) -> Option<&AsnEntry> {
+        match ip {
+            IpAddr::V4(ip) => Self::find(&self.v4, u32::from(*ip)),
+            IpAddr::V6(ip) => {
+                if let Some(mapped) = ip.to_ipv4_mapped() {
+                    Self::find(&self.v4, u32::from(mapped))
+                } else {
+                    Self::find(&self.v6, u128::from(*ip))
+                }
+            }
+        }
+    }
+
+    fn find<T: Ord + Copy>(ranges: &[(T, T, AsnEntry)], value: T) -> Option<&AsnEntry> {
+        let index = ranges.partition_point(|r| r.0 <= value);
+        let (_, last, entry) = ranges.get(index.checked_sub(1)?)?;
+        (value <= *last).then_some(entry)
+    }
+}
+
+/// Parses the AS field from ip-api.com locations like `AS15169 Google LLC`
+pub fn parse_as_field(value: &str) -> Option<(u32, String)> {
+    let (number, name) = value.trim().split_once(' ').unwrap_or((value.trim(), ""));
+    let number = number.strip_prefix("AS")?;
+    let number = u32::from_str(number).ok()?;
+    Some((number, name.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+        1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+        8.8.8.0\t8.8.8.255\t15169\tUS\tGOOGLE\n\
+        2001:4860::\t2001:4860:ffff:ffff:ffff:ffff:ffff:ffff\t15169\tUS\tGOOGLE\n\
+        \n\
+        5.1.0.0\t5.1.2.127\t8422\tDE\tNETCOLOGNE\n";
+
+    #[test]
+    fn lookup() {
+        let db = AsnDatabase::from_tsv(DATA).unwrap();
+        assert_eq!(db.range_count(), 4);
+
+        let entry = db.lookup(&IpAddr::from_str("8.8.8.8").unwrap()).unwrap();
+        assert_eq!(entry.asn, 15169);
+        assert_eq!(entry.country, "US");
+        assert_eq!(entry.description, "GOOGLE");
+        assert_eq!(entry.range_label(), "8.8.8.0/24");
+
+        let entry = db.lookup(&IpAddr::from_str("1.0.0.0").unwrap()).unwrap();
+        assert_eq!(entry.asn, 13335);
+
+        let entry = db
+            .lookup(&IpAddr::from_str("2001:4860::8888").unwrap())
+            .unwrap();
+        assert_eq!(entry.asn, 15169);
+        assert_eq!(entry.range_label(), "2001:4860::/32");
+
+        let entry = db.lookup(&IpAddr::from_str("5.1.1.1").unwrap()).unwrap();
+        assert_eq!(entry.range_label(), "5.1.0.0-5.1.2.127");
+
+        let entry = db.lookup(&IpAddr::from_str("::ffff:8.8.4.4").unwrap());
+        assert!(entry.is_none());
+        let entry = db.lookup(&IpAddr::from_str("::ffff:8.8.8.4").unwrap());
+        assert_eq!(entry.unwrap().asn, 15169);
+
+        // Not routed and unknown ranges
+        assert!(db.lookup(&IpAddr::from_str("1.0.2.1").unwrap()).is_none());
+        assert!(db.lookup(&IpAddr::from_str("0.0.0.1").unwrap()).is_none());
+        assert!(db.lookup(&IpAddr::from_str("255.0.0.1").unwrap()).is_none());
+        assert!(db.lookup(&IpAddr::from_str("::1").unwrap()).is_none());
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(AsnDatabase::from_tsv("1.0.0.0\t1.0.0.255").is_err());
+        assert!(AsnDatabase::from_tsv("1.0.0.0\t::1\t1\tUS\tX").is_err());
+        assert!(AsnDatabase::from_tsv("1.0.0.9\t1.0.0.1\t1\tUS\tX").is_err());
+        assert!(AsnDatabase::from_tsv("1.0.0.0\t1.0.0.1\tAS1\tUS\tX").is_err());
+    }
+
+    #[test]
+    fn as_field() {
+        assert_eq!(
+            parse_as_field("AS15169 Google LLC"),
+            Some((15169, String::from("Google LLC")))
+        );
+        assert_eq!(parse_as_field("AS3320"), Some((3320, String::new())));
+        assert_eq!(parse_as_field(""), None);
+        assert_eq!(parse_as_field("Google LLC"), None);
+    }
+}
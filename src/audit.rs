@@ -0,0 +1,207 @@
+use crate::cache_map::CacheMap;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Number of most recent events kept in memory for the API
+const MAX_RECENT_EVENTS: usize = 10_000;
+
+/// Successful logins of the same user from the same IP are only logged once in this many seconds,
+/// because basic auth and API tokens authenticate every single request.
+const LOGIN_LOG_INTERVAL: i64 = 3600;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginFailed,
+    Lockout,
+    Logout,
+    Export,
+    TokenCreated,
+    TokenRevoked,
+    UpdateRequested,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEvent {
+    pub time: DateTime<Utc>,
+    pub action: AuditAction,
+    pub user: Option<String>,
+    pub ip: IpAddr,
+    pub details: Option<String>,
+}
+
+/// Append-only log of security relevant events.
+/// Events are written as JSON lines to the optional file and the most recent events are kept in memory.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    recent: std::sync::Mutex<VecDeque<AuditEvent>>,
+
+    /// Time of the last logged login for each user and IP
+    logins: std::sync::Mutex<CacheMap<(String, IpAddr), i64>>,
+}
+
+impl AuditLog {
+    /// Opens the file for appending and loads its most recent events
+    pub async fn new(path: Option<&Path>) -> Result<Self> {
+        let mut recent = VecDeque::new();
+        let file = match path {
+            Some(path) => {
+                match tokio::fs::read_to_string(path).await {
+                    Ok(content) => {
+                        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                            let event = serde_json::from_str(line)
+                                .context("Failed to parse audit log file")?;
+                            push_recent(&mut recent, event);
+                        }
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err).context("Failed to read audit log file"),
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .context("Failed to open audit log file")?;
+                Some(Mutex::new(file))
+            }
+            None => None,
+        };
+        Ok(Self {
+            file,
+            recent: std::sync::Mutex::new(recent),
+            logins: std::sync::Mutex::new(CacheMap::new(MAX_RECENT_EVENTS)?),
+        })
+    }
+
+    pub async fn record(
+        &self,
+        action: AuditAction,
+        user: Option<&str>,
+        ip: IpAddr,
+        details: Option<String>,
+    ) {
+        let event = AuditEvent {
+            time: Utc::now(),
+            action,
+            user: user.map(String::from),
+            ip,
+            details,
+        };
+        if let Some(file) = &self.file {
+            let mut line = serde_json::to_vec(&event).expect("Failed to serialize audit event");
+            line.push(b'\n');
+            let mut file = file.lock().await;
+            if let Err(err) = file.write_all(&line).await {
+                warn!("Failed to write audit log: {err:#}");
+            } else if let Err(err) = file.flush().await {
+                warn!("Failed to flush audit log: {err:#}");
+            }
+        }
+        let mut recent = self.recent.lock().expect("Failed to lock audit events");
+        push_recent(&mut recent, event);
+    }
+
+    /// Records a successful login, unless the user already logged in from the IP recently
+    pub async fn record_login(&self, user: &str, ip: IpAddr, method: &str) {
+        let now = Utc::now().timestamp();
+        let key = (user.to_string(), ip);
+        {
+            let mut logins = self.logins.lock().expect("Failed to lock logins");
+            if logins
+                .get(&key)
+                .is_some_and(|last| now - last < LOGIN_LOG_INTERVAL)
+            {
+                return;
+            }
+            logins.remove(&key);
+            logins.insert(key, now);
+        }
+        self.record(
+            AuditAction::Login,
+            Some(user),
+            ip,
+            Some(format!("via {method}")),
+        )
+        .await;
+    }
+
+    /// Recent events, newest first
+    pub fn recent(&self) -> Vec<AuditEvent> {
+        let recent = self.recent.lock().expect("Failed to lock audit events");
+        recent.iter().rev().cloned().collect()
+    }
+}
+
+fn push_recent(recent: &mut VecDeque<AuditEvent>, event: AuditEvent) {
+    if recent.len() >= MAX_RECENT_EVENTS {
+        recent.pop_front();
+    }
+    recent.push_back(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::random_token;
+
+    #[tokio::test]
+    async fn append_and_reload() {
+        let path = std::env::temp_dir().join(format!("dmarc-audit-{}.log", random_token()));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let log = AuditLog::new(Some(&path)).await.unwrap();
+        log.record(AuditAction::LoginFailed, Some("admin"), ip, None)
+            .await;
+        log.record_login("admin", ip, "basic auth").await;
+        log.record_login("admin", ip, "basic auth").await;
+        log.record(
+            AuditAction::Export,
+            Some("admin"),
+            ip,
+            Some(String::from("/sources/export")),
+        )
+        .await;
+        drop(log);
+
+        let log = AuditLog::new(Some(&path)).await.unwrap();
+        log.record(AuditAction::Logout, Some("admin"), ip, None)
+            .await;
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(content.lines().count(), 4);
+        let actions: Vec<AuditAction> = log.recent().iter().map(|e| e.action).collect();
+        assert_eq!(
+            actions,
+            [
+                AuditAction::Logout,
+                AuditAction::Export,
+                AuditAction::Login,
+                AuditAction::LoginFailed
+            ]
+        );
+        assert_eq!(log.recent()[2].details.as_deref(), Some("via basic auth"));
+        assert!(content.contains(r#""action":"login_failed""#));
+    }
+
+    #[tokio::test]
+    async fn memory_only() {
+        let log = AuditLog::new(None).await.unwrap();
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        log.record_login("jane", ip, "token").await;
+        log.record_login("jane", "2001:db8::2".parse().unwrap(), "token")
+            .await;
+        assert_eq!(log.recent().len(), 2);
+        assert_eq!(log.recent()[1].ip, ip);
+    }
+}
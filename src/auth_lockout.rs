@@ -0,0 +1,254 @@
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+
+/// Maximum number of clients with failed logins that are remembered
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+/// Failed logins are forgotten after this many seconds without a new failure
+const FORGET_AFTER: u64 = 24 * 3600;
+
+/// Upper limit for the lockout time in seconds, must not exceed `FORGET_AFTER`
+const MAX_LOCKOUT: u64 = 24 * 3600;
+
+#[derive(Clone, Copy)]
+struct Failures {
+    count: u32,
+    last_failure: u64,
+    locked_until: u64,
+}
+
+impl Failures {
+    fn expires(&self) -> u64 {
+        self.last_failure + FORGET_AFTER
+    }
+}
+
+/// Failures by client with their expiration times in the order of the failures.
+/// Every failure adds an entry to the queue, the entries of older failures of the same client
+/// are outdated and skipped when they reach the front of the queue.
+#[derive(Default)]
+struct Tracker {
+    clients: HashMap<IpAddr, Failures>,
+    expirations: VecDeque<(u64, IpAddr)>,
+}
+
+impl Tracker {
+    /// Removes all expired clients and, to make room for a new client in a full tracker,
+    /// the client that expires first
+    fn prune(&mut self, now: u64, make_room: bool) {
+        while let Some(&(expires, client)) = self.expirations.front() {
+            let full = make_room && self.clients.len() >= MAX_TRACKED_CLIENTS;
+            let current = self.clients.get(&client).map(Failures::expires) == Some(expires);
+            if current && expires > now && !full {
+                break;
+            }
+            self.expirations.pop_front();
+            if current {
+                self.clients.remove(&client);
+            }
+        }
+
+        // Failures of the same clients in quick succession would let the queue grow
+        if self.expirations.len() > 2 * MAX_TRACKED_CLIENTS {
+            let mut expirations: Vec<_> = self
+                .clients
+                .iter()
+                .map(|(client, failures)| (failures.expires(), *client))
+                .collect();
+            expirations.sort_unstable();
+            self.expirations = expirations.into();
+        }
+    }
+}
+
+/// Tracks failed logins per client and locks out clients with too many failures.
+/// The lockout time doubles with every further failure.
+/// IPv6 clients are tracked by their /64 network, since they can usually use all of its addresses.
+pub struct AuthLockout {
+    max_failures: u32,
+    lockout: u64,
+    tracker: Mutex<Tracker>,
+}
+
+impl AuthLockout {
+    /// Zero max failures disables the lockout
+    pub fn new(max_failures: u32, lockout_secs: u64) -> Result<Self> {
+        Ok(Self {
+            max_failures,
+            lockout: lockout_secs,
+            tracker: Mutex::new(Tracker::default()),
+        })
+    }
+
+    /// Returns the remaining lockout time in seconds if the IP is locked out
+    pub fn locked(&self, ip: &IpAddr, now: u64) -> Option<u64> {
+        let tracker = self.tracker.lock().expect("Failed to lock failures");
+        tracker
+            .clients
+            .get(&client(ip))
+            .filter(|f| f.locked_until > now)
+            .map(|f| f.locked_until - now)
+    }
+
+    /// Counts a failed login and returns the lockout time in seconds if the IP is now locked out
+    pub fn failure(&self, ip: &IpAddr, now: u64) -> Option<u64> {
+        if self.max_failures == 0 {
+            return None;
+        }
+        let client = client(ip);
+        let mut tracker = self.tracker.lock().expect("Failed to lock failures");
+        let make_room = !tracker.clients.contains_key(&client);
+        tracker.prune(now, make_room);
+        let mut entry = tracker
+            .clients
+            .get(&client)
+            .copied()
+            .filter(|f| f.expires() > now)
+            .unwrap_or(Failures {
+                count: 0,
+                last_failure: now,
+                locked_until: 0,
+            });
+        entry.count = entry.count.saturating_add(1);
+        entry.last_failure = now;
+        let lockout = (entry.count >= self.max_failures).then(|| {
+            let doublings = (entry.count - self.max_failures).min(32);
+            self.lockout.saturating_mul(1 << doublings).min(MAX_LOCKOUT)
+        });
+        if let Some(lockout) = lockout {
+            entry.locked_until = now + lockout;
+        }
+        tracker.clients.insert(client, entry);
+        tracker.expirations.push_back((entry.expires(), client));
+        lockout
+    }
+
+    /// Resets the failures of the IP after a successful login
+    pub fn success(&self, ip: &IpAddr) {
+        let mut tracker = self.tracker.lock().expect("Failed to lock failures");
+        tracker.clients.remove(&client(ip));
+    }
+}
+
+/// IPv4 address or /64 network of an IPv6 address that identifies a client
+fn client(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => *ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(*v6) & !(u64::MAX as u128))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_lockout() {
+        let lockout = AuthLockout::new(3, 60).unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(lockout.failure(&ip, 1000), None);
+        assert_eq!(lockout.failure(&ip, 1001), None);
+        assert_eq!(lockout.locked(&ip, 1001), None);
+        assert_eq!(lockout.failure(&ip, 1002), Some(60));
+        assert_eq!(lockout.locked(&ip, 1012), Some(50));
+        assert_eq!(lockout.locked(&other, 1012), None);
+        assert_eq!(lockout.locked(&ip, 1062), None);
+        assert_eq!(lockout.failure(&ip, 1062), Some(120));
+        assert_eq!(lockout.failure(&ip, 1200), Some(240));
+
+        // Capped
+        for i in 0..40 {
+            lockout.failure(&ip, 2000 + i);
+        }
+        assert_eq!(lockout.locked(&ip, 2040), Some(MAX_LOCKOUT - 1));
+
+        lockout.success(&ip);
+        assert_eq!(lockout.locked(&ip, 2040), None);
+        assert_eq!(lockout.failure(&ip, 2040), None);
+    }
+
+    #[test]
+    fn forget_and_disable() {
+        let lockout = AuthLockout::new(2, 10).unwrap();
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(lockout.failure(&ip, 0), None);
+        assert_eq!(lockout.failure(&ip, FORGET_AFTER), None);
+        assert_eq!(lockout.failure(&ip, FORGET_AFTER + 1), Some(10));
+
+        let disabled = AuthLockout::new(0, 10).unwrap();
+        for now in 0..10 {
+            assert_eq!(disabled.failure(&ip, now), None);
+        }
+        assert_eq!(disabled.locked(&ip, 10), None);
+    }
+
+    #[test]
+    fn ipv6_networks() {
+        let lockout = AuthLockout::new(2, 60).unwrap();
+        let first: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        let second: IpAddr = "2001:db8:1:2:ffff::2".parse().unwrap();
+        let other: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+        assert_eq!(lockout.failure(&first, 0), None);
+        assert_eq!(lockout.failure(&second, 1), Some(60));
+        assert_eq!(lockout.locked(&first, 2), Some(59));
+        assert_eq!(lockout.locked(&other, 2), None);
+
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(client(&mapped), v4);
+        assert_eq!(
+            client(&"192.0.2.2".parse().unwrap()),
+            "192.0.2.2".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn expiration() {
+        let lockout = AuthLockout::new(3, 60).unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        lockout.failure(&ip, 0);
+        lockout.failure(&ip, 10);
+        lockout.failure(&other, 20);
+
+        // Outdated queue entries of earlier failures do not remove newer ones
+        lockout.failure(&other, FORGET_AFTER + 5);
+        {
+            let tracker = lockout.tracker.lock().unwrap();
+            assert!(tracker.clients.contains_key(&ip));
+            assert_eq!(tracker.expirations.len(), 3);
+        }
+        lockout.failure(&other, FORGET_AFTER + 15);
+        let tracker = lockout.tracker.lock().unwrap();
+        assert!(!tracker.clients.contains_key(&ip));
+        assert_eq!(tracker.clients[&other].count, 3);
+    }
+
+    #[test]
+    fn full_tracker() {
+        let lockout = AuthLockout::new(2, 60).unwrap();
+        let locked: IpAddr = "192.0.2.1".parse().unwrap();
+        lockout.failure(&locked, 0);
+        assert_eq!(lockout.failure(&locked, 0), Some(60));
+
+        // Clients that failed first are removed first, later failures keep clients tracked
+        for i in 0..MAX_TRACKED_CLIENTS as u32 {
+            let ip = IpAddr::V4(i.into());
+            lockout.failure(&ip, 1);
+            if i == 0 {
+                lockout.failure(&locked, 2);
+            }
+        }
+        assert_eq!(lockout.locked(&locked, 3), Some(119));
+        let tracker = lockout.tracker.lock().unwrap();
+        assert_eq!(tracker.clients.len(), MAX_TRACKED_CLIENTS);
+        assert!(!tracker.clients.contains_key(&IpAddr::V4(0.into())));
+    }
+}
@@ -1,19 +1,21 @@
 use crate::config::Configuration;
+use crate::events::{self, Event, Snapshot, UpdateCounts};
 use crate::hasher::create_hash;
 use crate::imap::get_mails;
 use crate::state::{
     AppState, DmarcReportWithMailId, FileType, ReportParsingError, TlsReportWithMailId,
 };
 use crate::unpack::extract_report_files;
+use crate::update_status::{UpdatePhase, UpdateTracker};
 use crate::web_hook::mail_web_hook;
 use crate::{dmarc, tls};
 use anyhow::{Context, Result};
 use chrono::Local;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
-use tokio::sync::Mutex;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::{Mutex, Notify};
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info, trace, warn};
 
@@ -21,22 +23,37 @@ pub fn start_bg_task(
     config: Configuration,
     state: Arc<Mutex<AppState>>,
     mut stop_signal: Receiver<()>,
+    enrichment_trigger: Option<Arc<Notify>>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         info!(
             "Started background task with check interval of {} secs",
             config.imap_check_interval
         );
+        let (events, tracker) = {
+            let locked_state = state.lock().await;
+            (
+                locked_state.events.clone(),
+                locked_state.update_tracker.clone(),
+            )
+        };
         loop {
             let start = Instant::now();
             info!("Starting background update...");
-            match bg_update(&config, &state, &start).await {
-                Ok(new_mails) => {
+            tracker.start();
+            // Sending fails only without subscribers, which is fine
+            let _ = events.send(Event::UpdateStarted);
+            let result = bg_update(&config, &state, &start, &tracker).await;
+            tracker.finish(result.as_ref().err().map(|err| format!("{err:#}")));
+            match result {
+                Ok((new_mails, counts)) => {
                     info!("Detected {} new mails", new_mails.len());
-                    info!(
-                        "Finished background update after {:.3}s",
-                        start.elapsed().as_secs_f64()
-                    );
+                    let duration = start.elapsed().as_secs_f64();
+                    info!("Finished background update after {duration:.3}s");
+                    let _ = events.send(Event::UpdateFinished {
+                        duration,
+                        counts: Some(counts),
+                    });
                     if !new_mails.is_empty() && config.mail_web_hook_url.is_some() {
                         debug!("Calling web hook for all new mails...");
                         for mail_id in &new_mails {
@@ -46,8 +63,17 @@ pub fn start_bg_task(
                         }
                         debug!("Finished calling web hook for all new mails");
                     }
+                    if let Some(trigger) = &enrichment_trigger {
+                        // Enrichment runs in its own task and will not delay the next update
+                        trigger.notify_one();
+                    }
+                }
+                Err(err) => {
+                    error!("Failed background update: {err:#}");
+                    let _ = events.send(Event::UpdateFailed {
+                        error: format!("{err:#}"),
+                    });
                 }
-                Err(err) => error!("Failed background update: {err:#}"),
             };
 
             // Check how many seconds we need to sleep
@@ -67,36 +93,39 @@ pub fn start_bg_task(
 
             tokio::select! {
                 _ = tokio::time::sleep(duration) => {},
+                _ = tracker.requested() => { info!("Update was requested"); },
                 _ = stop_signal.recv() => { break; },
             }
         }
     })
 }
 
-/// Executes a background update and returns the IDs of all new mails
+/// Executes a background update, publishes the events for all new mails, reports,
+/// parsing errors and findings and returns the IDs of all new mails and the counts of the update
 async fn bg_update(
     config: &Configuration,
     state: &Arc<Mutex<AppState>>,
     start: &Instant,
-) -> Result<Vec<String>> {
+    tracker: &UpdateTracker,
+) -> Result<(Vec<String>, UpdateCounts)> {
     let mut mails = BTreeMap::new();
     if let Some(dmarc_folder) = config.imap_folder_dmarc.as_ref() {
         mails.extend(
-            get_mails(config, dmarc_folder)
+            get_mails(config, dmarc_folder, tracker)
                 .await
                 .context("Failed to get mails from DMARC folder")?,
         );
     }
     if let Some(tls_folder) = config.imap_folder_tls.as_ref() {
         mails.extend(
-            get_mails(config, tls_folder)
+            get_mails(config, tls_folder, tracker)
                 .await
                 .context("Failed to get mails from TLS folder")?,
         );
     }
     if config.imap_folder_dmarc.is_none() && config.imap_folder_tls.is_none() {
         mails.extend(
-            get_mails(config, &config.imap_folder)
+            get_mails(config, &config.imap_folder, tracker)
                 .await
                 .context("Failed to get mails")?,
         );
@@ -105,6 +134,7 @@ async fn bg_update(
     let mut xml_files = BTreeMap::new();
     let mut json_files = BTreeMap::new();
     let mut mails_without_reports = 0;
+    tracker.set_phase(UpdatePhase::Extracting);
     for mail in &mut mails.values_mut() {
         if mail.body.is_none() {
             trace!(
@@ -133,33 +163,40 @@ async fn bg_update(
             }
             Err(err) => warn!("Failed to extract report files from mail: {err:#}"),
         }
+        tracker.update_progress(|progress| {
+            progress.extracted_mails += 1;
+            progress.files += mail.xml_files + mail.json_files;
+        });
     }
     if mails_without_reports > 0 {
         warn!("Found {mails_without_reports} mail(s) without report files");
     }
+    let xml_file_count = xml_files.len();
+    let json_file_count = json_files.len();
     info!(
-        "Extracted {} XML report file(s) and {} JSON report file(s)",
-        xml_files.len(),
-        json_files.len()
+        "Extracted {xml_file_count} XML report file(s) and {json_file_count} JSON report file(s)"
     );
 
+    tracker.set_phase(UpdatePhase::Parsing);
     let mut parsing_errors: HashMap<String, Vec<ReportParsingError>> = HashMap::new();
 
     let mut dmarc_reports = BTreeMap::new();
     let mut dmarc_duplication_map: HashMap<String, String> = HashMap::new();
     let mut dmarc_duplicates = 0;
-    for xml_file in xml_files.values() {
+    for xml_file in xml_files.into_values() {
+        tracker.update_progress(|progress| progress.parsed_files += 1);
         match dmarc::Report::from_slice(&xml_file.data) {
             Ok(report) => {
                 let dupl_key = format!(
                     "{}:{}",
                     report.report_metadata.org_name, report.report_metadata.report_id
                 );
+                let hash = create_hash(&[&xml_file.data, xml_file.mail_id.as_bytes()]);
                 let rwi = DmarcReportWithMailId {
                     report,
                     mail_id: xml_file.mail_id.clone(),
+                    raw: Arc::new(xml_file.data),
                 };
-                let hash = create_hash(&[&xml_file.data, xml_file.mail_id.as_bytes()]);
                 if !config.disable_duplicate_filter {
                     if let Some(found_hash) = dmarc_duplication_map.get(&dupl_key) {
                         trace!(
@@ -209,15 +246,17 @@ async fn bg_update(
     let mut tls_reports = BTreeMap::new();
     let mut tls_duplication_map: HashMap<String, String> = HashMap::new();
     let mut tls_duplicates = 0;
-    for json_file in json_files.values() {
+    for json_file in json_files.into_values() {
+        tracker.update_progress(|progress| progress.parsed_files += 1);
         match tls::Report::from_slice(&json_file.data) {
             Ok(report) => {
                 let dupl_key = format!("{}:{}", report.organization_name, report.report_id);
+                let hash = create_hash(&[&json_file.data, json_file.mail_id.as_bytes()]);
                 let rwi = TlsReportWithMailId {
                     report,
                     mail_id: json_file.mail_id.clone(),
+                    raw: Arc::new(json_file.data),
                 };
-                let hash = create_hash(&[&json_file.data, json_file.mail_id.as_bytes()]);
                 if !config.disable_duplicate_filter {
                     if let Some(found_hash) = tls_duplication_map.get(&dupl_key) {
                         trace!(
@@ -280,36 +319,53 @@ async fn bg_update(
         .context("Failed to get Unix time stamp")?
         .as_secs();
 
-    let new_mails = {
-        let mut locked_state = state.lock().await;
+    let mut locked_state = state.lock().await;
 
-        // Remember the IDs of all current mails from before the update
-        let old_mails: HashSet<String> = locked_state.mails.keys().cloned().collect();
+    // Remember the IDs of all current mails and reports from before the update
+    let snapshot = Snapshot::new(&locked_state);
 
-        // Update state with new values
-        locked_state.dmarc_reports = dmarc_reports;
-        locked_state.tls_reports = tls_reports;
-        locked_state.last_update = timestamp;
-        locked_state.xml_files = xml_files.len();
-        locked_state.json_files = json_files.len();
-        locked_state.parsing_errors = parsing_errors;
-        locked_state.mails = mails;
-        locked_state.last_update_duration = start.elapsed().as_secs_f64();
+    // Update state with new values
+    locked_state.dmarc_reports = dmarc_reports;
+    locked_state.tls_reports = tls_reports;
+    locked_state.last_update = timestamp;
+    locked_state.xml_files = xml_file_count;
+    locked_state.json_files = json_file_count;
+    locked_state.parsing_errors = parsing_errors;
+    locked_state.mails = mails;
+    locked_state.last_update_duration = start.elapsed().as_secs_f64();
 
-        // Detect which of the mails are new
-        let new_mails: Vec<String> = locked_state.mails.keys().cloned().collect();
-        if locked_state.first_update {
-            locked_state.first_update = false;
+    // Only new or removed mails and reports are indexed
+    {
+        let mut index = locked_state
+            .search_index
+            .write()
+            .expect("Failed to lock search index");
+        index.sync_mails(&locked_state.mails);
+        index.sync_dmarc_reports(&locked_state.dmarc_reports, &locked_state.enrichments);
+        index.sync_tls_reports(&locked_state.tls_reports, &locked_state.enrichments);
+    }
 
-            // During the intial update we do not report any mails as new
-            vec![]
-        } else {
-            new_mails
-                .into_iter()
-                .filter(|id| !old_mails.contains(id))
-                .collect()
-        }
+    // Detect which of the mails and reports are new
+    let changes = if locked_state.first_update {
+        locked_state.first_update = false;
+
+        // During the intial update we do not report anything as new
+        Vec::new()
+    } else {
+        events::changes(&locked_state, &snapshot)
     };
+    let counts = UpdateCounts::new(&locked_state, &changes);
+    let new_mails = changes
+        .iter()
+        .filter_map(|event| match event {
+            Event::NewMail { id, .. } => Some(id.clone()),
+            _ => None,
+        })
+        .collect();
+    for event in changes {
+        // Sending fails only without subscribers, which is fine
+        let _ = locked_state.events.send(event);
+    }
 
-    Ok(new_mails)
+    Ok((new_mails, counts))
 }
@@ -47,6 +47,10 @@ where
         self.counter += 1;
     }
 
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key).map(|e| e.value)
+    }
+
     fn prune(&mut self) {
         let oldest = self
             .map
@@ -80,6 +84,18 @@ mod tests {
         assert!(cache.get(&1).is_none());
     }
 
+    #[test]
+    fn remove() {
+        let mut cache = CacheMap::new(2).unwrap();
+
+        cache.insert(1, 23);
+        cache.insert(2, 42);
+        assert_eq!(cache.remove(&2), Some(42));
+        assert!(cache.get(&2).is_none());
+        assert!(cache.remove(&2).is_none());
+        assert_eq!(cache.get(&1), Some(&23));
+    }
+
     #[test]
     fn invalid_size() {
         assert!(CacheMap::<i32, i32>::new(0).is_err());
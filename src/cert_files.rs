@@ -0,0 +1,310 @@
+use crate::config::Configuration;
+use anyhow::{Context, Result, ensure};
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tracing::{info, warn};
+
+/// Time between checks for modified certificate files
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Certificate chain, private key and optional client CA bundle in PEM format for HTTPS.
+/// The files are reloaded when they are modified or when the application receives SIGHUP.
+pub struct CertFiles {
+    cert: PathBuf,
+    key: PathBuf,
+
+    /// Clients need a certificate signed by one of these CAs if set
+    client_ca: Option<PathBuf>,
+
+    /// Modification times of the loaded files
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl CertFiles {
+    /// Returns None if HTTPS with certificate files is not configured
+    pub fn from_config(config: &Configuration) -> Option<Self> {
+        Some(Self::new(
+            config.https_cert_file.clone()?,
+            config.https_key_file.clone()?,
+            config.https_client_ca_file.clone(),
+        ))
+    }
+
+    pub fn new(cert: PathBuf, key: PathBuf, client_ca: Option<PathBuf>) -> Self {
+        Self {
+            cert,
+            key,
+            client_ca,
+            modified: Vec::new(),
+        }
+    }
+
+    /// Reads all files and creates the TLS configuration for the server
+    pub async fn load(&mut self) -> Result<ServerConfig> {
+        let modified = self.modified_times().await;
+        let cert = tokio::fs::read(&self.cert)
+            .await
+            .context("Failed to read certificate file")?;
+        let key = tokio::fs::read(&self.key)
+            .await
+            .context("Failed to read private key file")?;
+        let client_ca = match &self.client_ca {
+            Some(path) => Some(
+                tokio::fs::read(path)
+                    .await
+                    .context("Failed to read client CA file")?,
+            ),
+            None => None,
+        };
+        let config = server_config(&cert, &key, client_ca.as_deref())?;
+        self.modified = modified;
+        Ok(config)
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &Path> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+    }
+
+    async fn modified_times(&self) -> Vec<Option<SystemTime>> {
+        let mut times = Vec::new();
+        for path in self.paths() {
+            let modified = tokio::fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            times.push(modified);
+        }
+        times
+    }
+
+    async fn is_modified(&self) -> bool {
+        self.modified_times().await != self.modified
+    }
+
+    /// Loads the files again and replaces the configuration of the server.
+    /// Keeps the old configuration if the new files are invalid, e.g. while they are replaced.
+    async fn reload(&mut self, rustls_config: &RustlsConfig) {
+        match self.load().await {
+            Ok(config) => {
+                rustls_config.reload_from_config(Arc::new(config));
+                info!("Reloaded HTTPS certificate files");
+            }
+            Err(err) => warn!("Failed to reload HTTPS certificate files: {err:#}"),
+        }
+    }
+}
+
+/// Reloads the certificate files when they are modified or on SIGHUP
+pub async fn watch(mut files: CertFiles, rustls_config: RustlsConfig) {
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Failed to install signal handler");
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        #[cfg(unix)]
+        let hangup_received = hangup.recv();
+        #[cfg(not(unix))]
+        let hangup_received = std::future::pending::<Option<()>>();
+
+        tokio::select! {
+            _ = hangup_received => {
+                info!("Received SIGHUP, reloading HTTPS certificate files...");
+                files.reload(&rustls_config).await;
+            }
+            _ = interval.tick() => {
+                if files.is_modified().await {
+                    info!("Detected modified HTTPS certificate files, reloading...");
+                    files.reload(&rustls_config).await;
+                }
+            }
+        }
+    }
+}
+
+fn server_config(cert: &[u8], key: &[u8], client_ca: Option<&[u8]>) -> Result<ServerConfig> {
+    let chain = CertificateDer::pem_slice_iter(cert)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse certificate file")?;
+    ensure!(
+        !chain.is_empty(),
+        "Certificate file contains no certificates"
+    );
+    let key = PrivateKeyDer::from_pem_slice(key).context("Failed to parse private key file")?;
+
+    let builder = ServerConfig::builder();
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_slice_iter(client_ca) {
+                let cert = cert.context("Failed to parse client CA file")?;
+                roots
+                    .add(cert)
+                    .context("Failed to add client CA certificate")?;
+            }
+            ensure!(!roots.is_empty(), "Client CA file contains no certificates");
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .context("Failed to create client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(chain, key)
+        .context("Certificate does not match private key")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::random_token;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    struct Pki {
+        ca_pem: String,
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca_key = KeyPair::generate().unwrap();
+            let ca = params.self_signed(&ca_key).unwrap();
+            Self {
+                ca_pem: ca.pem(),
+                ca,
+                ca_key,
+            }
+        }
+
+        /// Returns certificate and key in PEM format
+        fn issue(&self, name: &str) -> (String, String) {
+            let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+    }
+
+    /// Runs a TLS handshake with the server config and returns true if data could be exchanged
+    async fn handshake(
+        server: ServerConfig,
+        ca_pem: &str,
+        client: Option<(String, String)>,
+    ) -> bool {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server));
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut tls) = acceptor.accept(stream).await {
+                let _ = tls.write_all(b"ok").await;
+                let _ = tls.shutdown().await;
+            }
+        });
+
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(ca_pem.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match client {
+            Some((cert, key)) => {
+                let chain = CertificateDer::pem_slice_iter(cert.as_bytes())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                let key = PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap();
+                builder.with_client_auth_cert(chain, key).unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let connector = TlsConnector::from(Arc::new(config));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let Ok(mut tls) = connector.connect(name, stream).await else {
+            return false;
+        };
+        // With TLS 1.3 a rejected client certificate is only noticed when reading
+        let mut response = Vec::new();
+        tls.read_to_end(&mut response).await.is_ok() && response == b"ok"
+    }
+
+    #[tokio::test]
+    async fn server_certificate_and_reload() {
+        let dir = std::env::temp_dir().join(format!("dmarc-certs-{}", random_token()));
+        std::fs::create_dir(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+        let pki = Pki::new();
+        let (cert, key) = pki.issue("localhost");
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+
+        let mut files = CertFiles::new(cert_path.clone(), key_path.clone(), None);
+        let config = files.load().await.unwrap();
+        assert!(!files.is_modified().await);
+        assert!(handshake(config, &pki.ca_pem, None).await);
+
+        // Replace with certificate of other CA
+        let other = Pki::new();
+        let (cert, key) = other.issue("localhost");
+        std::fs::write(&cert_path, &cert).unwrap();
+        std::fs::write(&key_path, &key).unwrap();
+        let rustls_config = RustlsConfig::from_config(Arc::new(files.load().await.unwrap()));
+        assert!(handshake((*rustls_config.get_inner()).clone(), &other.ca_pem, None).await);
+        assert!(!handshake((*rustls_config.get_inner()).clone(), &pki.ca_pem, None).await);
+
+        // Invalid files keep the old configuration
+        std::fs::write(&key_path, "invalid").unwrap();
+        files.reload(&rustls_config).await;
+        assert!(handshake((*rustls_config.get_inner()).clone(), &other.ca_pem, None).await);
+
+        // Key of other certificate
+        std::fs::write(&key_path, pki.issue("localhost").1).unwrap();
+        assert!(files.load().await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_certificates() {
+        let server_pki = Pki::new();
+        let client_pki = Pki::new();
+        let (cert, key) = server_pki.issue("localhost");
+        let config = || {
+            server_config(
+                cert.as_bytes(),
+                key.as_bytes(),
+                Some(client_pki.ca_pem.as_bytes()),
+            )
+            .unwrap()
+        };
+
+        let client = client_pki.issue("client");
+        assert!(handshake(config(), &server_pki.ca_pem, Some(client)).await);
+        assert!(!handshake(config(), &server_pki.ca_pem, None).await);
+        let foreign = server_pki.issue("client");
+        assert!(!handshake(config(), &server_pki.ca_pem, Some(foreign)).await);
+
+        assert!(server_config(cert.as_bytes(), key.as_bytes(), Some(b"")).is_err());
+    }
+}
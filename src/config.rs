@@ -1,6 +1,8 @@
+use crate::dns_client::DnsServer;
+use crate::http::base_path::BasePath;
+use crate::ip_net::IpNet;
 use clap::{Parser, ValueEnum};
 use cron::Schedule;
-use std::net::SocketAddr;
 use std::path::PathBuf;
 use tracing::{Level, info};
 
@@ -104,6 +106,12 @@ pub struct Configuration {
     #[arg(long, env, default_value_t = HTTP_DEFAULT_PORT)]
     pub http_server_port: u16,
 
+    /// Path prefix for all routes of the web UI and API, like `/dmarc` for `https://tools.example.com/dmarc/`.
+    /// Requests of the UI without trailing slash are redirected.
+    /// The health check stays available at `/health`.
+    #[arg(long, env, default_value = "")]
+    pub http_base_path: BasePath,
+
     /// Embedded HTTP server binding for web UI.
     /// Needs to be a valid IPv4 or IPv6 address.
     /// The default will bind to all IPv4 IPs of the host.
@@ -118,9 +126,104 @@ pub struct Configuration {
 
     /// Password for the HTTP server basic auth login.
     /// Use empty string to disable (not recommended).
-    #[arg(long, env)]
+    /// Not required if `http_users_file` or `oidc_issuer` is set.
+    #[arg(
+        long,
+        env,
+        required_unless_present_any = ["http_users_file", "oidc_issuer"],
+        default_value = ""
+    )]
     pub http_server_password: String,
 
+    /// Optional path to a JSON file with user accounts.
+    /// Each account has an Argon2 password hash, the role `admin` or `viewer`
+    /// and viewers have a list of domains whose reports they are allowed to see.
+    /// Replaces `http_server_user` and `http_server_password` if set.
+    #[arg(long, env)]
+    pub http_users_file: Option<PathBuf>,
+
+    /// Issuer URL of an OpenID Connect provider, like `https://sso.example.com/realms/main`.
+    /// Enables the login to the web UI with the authorization code flow and PKCE.
+    /// Basic auth stays available as fallback, for example for API clients.
+    #[arg(long, env, requires_all = ["oidc_client_id", "oidc_redirect_url"])]
+    pub oidc_issuer: Option<String>,
+
+    /// Client ID of this application at the OpenID Connect provider
+    #[arg(long, env)]
+    pub oidc_client_id: Option<String>,
+
+    /// Optional client secret for confidential OpenID Connect clients
+    #[arg(long, env)]
+    pub oidc_client_secret: Option<String>,
+
+    /// Public URL of the OpenID Connect callback of this application,
+    /// like `https://dmarc.example.com/auth/callback`.
+    /// Must be registered as redirect URI at the provider.
+    #[arg(long, env)]
+    pub oidc_redirect_url: Option<String>,
+
+    /// Comma-separated list of scopes requested from the OpenID Connect provider
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        default_value = "openid,profile,email"
+    )]
+    pub oidc_scopes: Vec<String>,
+
+    /// ID token claim with the user name, falls back to `sub` if missing
+    #[arg(long, env, default_value = "preferred_username")]
+    pub oidc_username_claim: String,
+
+    /// ID token claim with the groups of the user.
+    /// Nested claims can be selected with dots, like `realm_access.roles`.
+    #[arg(long, env, default_value = "groups")]
+    pub oidc_groups_claim: String,
+
+    /// Comma-separated list of groups whose members are admins and see all domains
+    #[arg(long, env, value_delimiter = ',')]
+    pub oidc_admin_groups: Vec<String>,
+
+    /// Comma-separated list of groups whose members are viewers of a domain,
+    /// as `group=domain` like `customer-a=a.example,customer-a=a.example.org`.
+    /// Users without admin or viewer group cannot log in.
+    #[arg(long, env, value_delimiter = ',')]
+    pub oidc_viewer_groups: Vec<String>,
+
+    /// Secret key with at least 32 characters for signing the session cookies of OpenID Connect logins.
+    /// If not set, a random key is used and all users need to log in again after a restart.
+    #[arg(long, env)]
+    pub session_secret: Option<String>,
+
+    /// Lifetime of the sessions of OpenID Connect logins in hours
+    #[arg(long, env, default_value_t = 12)]
+    pub session_lifetime: u64,
+
+    /// Optional path to a JSON file for persisting the API tokens created by admins.
+    /// Only hashes of the tokens are stored.
+    /// If not set, all API tokens are lost with a restart of the application.
+    #[arg(long, env)]
+    pub api_tokens_file: Option<PathBuf>,
+
+    /// Number of failed logins after which a client IP is locked out, 0 disables the lockout
+    #[arg(long, env, default_value_t = 5)]
+    pub http_auth_max_failures: u32,
+
+    /// Lockout time in seconds after too many failed logins.
+    /// Doubles with every further failed login, up to one day.
+    #[arg(long, env, default_value_t = 60)]
+    pub http_auth_lockout: u64,
+
+    /// Comma-separated list of IPs or networks of trusted reverse proxies, like `10.0.0.0/8,::1`.
+    /// For requests from these proxies the client IP is taken from the `X-Forwarded-For` header.
+    #[arg(long, env, value_delimiter = ',')]
+    pub http_trusted_proxies: Vec<IpNet>,
+
+    /// Optional path of the append-only audit log with logins, failed logins, exports and admin actions.
+    /// Events are written as JSON lines. Without file only the recent events are kept in memory.
+    #[arg(long, env)]
+    pub audit_log_file: Option<PathBuf>,
+
     /// Enable automatic HTTPS encryption using Let's Encrypt certificates.
     /// This will replace the HTTP protocol on the configured HTTP port with HTTPS.
     /// There is no second separate port for HTTPS!
@@ -142,9 +245,55 @@ pub struct Configuration {
     #[arg(long, env)]
     pub https_auto_cert_cache: Option<PathBuf>,
 
-    /// HTTPS server domain, required for automatic HTTPS
+    /// HTTPS server domain, required for automatic HTTPS.
+    /// Use a comma-separated list to get one certificate for multiple domains.
+    #[arg(long, env, value_delimiter = ',')]
+    pub https_auto_cert_domain: Vec<String>,
+
+    /// URL of the directory of the ACME server for automatic HTTPS.
+    /// Default is the production directory of Let's Encrypt.
+    /// Set this to use another CA, like an internal step-ca server.
     #[arg(long, env)]
-    pub https_auto_cert_domain: Option<String>,
+    pub https_auto_cert_directory: Option<String>,
+
+    /// Use the staging directory of Let's Encrypt for testing automatic HTTPS.
+    /// Its certificates are not trusted by browsers, but the rate limits are much higher.
+    #[arg(long, env, conflicts_with = "https_auto_cert_directory")]
+    pub https_auto_cert_staging: bool,
+
+    /// Optional path of a PEM file with additional CA certificates that are trusted
+    /// for the connection to the ACME server, for example the root certificate of an internal CA.
+    #[arg(long, env)]
+    pub https_auto_cert_ca_file: Option<PathBuf>,
+
+    /// Key identifier for the external account binding (EAB),
+    /// required by some ACME servers to link the ACME account to an existing account of the CA
+    #[arg(long, env, requires = "https_auto_cert_eab_key")]
+    pub https_auto_cert_eab_kid: Option<String>,
+
+    /// Base64url encoded HMAC key for the external account binding (EAB)
+    #[arg(long, env, requires = "https_auto_cert_eab_kid")]
+    pub https_auto_cert_eab_key: Option<String>,
+
+    /// Path of a PEM file with the certificate chain for HTTPS with your own certificate.
+    /// This will replace the HTTP protocol on the configured HTTP port with HTTPS.
+    /// The certificate files are reloaded when they are modified or when the application receives SIGHUP.
+    #[arg(
+        long,
+        env,
+        requires = "https_key_file",
+        conflicts_with = "https_auto_cert"
+    )]
+    pub https_cert_file: Option<PathBuf>,
+
+    /// Path of a PEM file with the private key of the certificate
+    #[arg(long, env, requires = "https_cert_file")]
+    pub https_key_file: Option<PathBuf>,
+
+    /// Optional path of a PEM file with CA certificates for client certificate authentication (mTLS).
+    /// If set, only clients with a certificate signed by one of these CAs can connect.
+    #[arg(long, env, requires = "https_cert_file")]
+    pub https_client_ca_file: Option<PathBuf>,
 
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, env, default_value_t = Level::INFO)]
@@ -203,15 +352,67 @@ pub struct Configuration {
     #[arg(long, env)]
     pub mail_web_hook_body: Option<String>,
 
-    /// DNS server address for resolving IPs to hostnames.
+    /// Comma-separated list of DNS server addresses for resolving IPs to hostnames.
     /// Default is 1.1.1.1:53, which is the public Cloudflare DNS server.
     /// Do not forget to add the suffix with the port using a colon.
-    #[arg(long, env, default_value = "1.1.1.1:53")]
-    pub dns_server: SocketAddr,
+    /// Queries are distributed round-robin and repeated with the next server on errors.
+    /// For DNS over TLS, the name for verifying the certificate can be appended
+    /// with a hash sign, like `1.1.1.1:853#cloudflare-dns.com`.
+    #[arg(long, env, default_value = "1.1.1.1:53", value_delimiter = ',')]
+    pub dns_server: Vec<DnsServer>,
+
+    /// Send all DNS queries via TLS (DNS over TLS) instead of UDP and TCP.
+    /// The DNS servers need to use port 853 in this case.
+    #[arg(long, env)]
+    pub dns_over_tls: bool,
 
     /// Timeout value for DNS queries in milliseconds.
     #[arg(long, env, default_value_t = 5000)]
     pub dns_timeout: u64,
+
+    /// Comma-separated list of DNSBL zones to check source IPs against (e.g. `zen.spamhaus.org`).
+    /// Many lists refuse queries from public resolvers, so you might need your own DNS server.
+    /// Lookups are done by the IP enrichment and the DNS checks of single IPs.
+    #[arg(long, env, value_delimiter = ',')]
+    pub dnsbl_zones: Vec<String>,
+
+    /// Optional path to an offline IP to ASN database file.
+    /// Expects the tab-separated format from https://iptoasn.com/ (e.g. `ip2asn-combined.tsv`)
+    /// with the columns range start, range end, AS number, country code and AS description.
+    /// Used to group sources by announced prefix or autonomous system.
+    /// Without this file, only the AS information of already located IPs is available.
+    #[arg(long, env)]
+    pub ip_to_asn_file: Option<PathBuf>,
+
+    /// Provider for IP location lookups.
+    /// The default `ip-api` sends the IPs via plain HTTP to the public service ip-api.com,
+    /// which is limited to 45 requests per minute.
+    /// Use `mmdb` for offline lookups in local MaxMind or DB-IP database files.
+    /// Use `disabled` to turn off all location lookups.
+    #[arg(long, env, default_value = "ip-api")]
+    pub location_provider: LocationProviderType,
+
+    /// Path to a MMDB city database file (e.g. GeoLite2-City or DB-IP City Lite).
+    /// Used for the `mmdb` location provider and reloaded automatically when the file changes.
+    #[arg(long, env)]
+    pub location_mmdb_city: Option<PathBuf>,
+
+    /// Path to a MMDB ASN database file (e.g. GeoLite2-ASN or DB-IP ASN Lite).
+    /// Used for the `mmdb` location provider and reloaded automatically when the file changes.
+    #[arg(long, env)]
+    pub location_mmdb_asn: Option<PathBuf>,
+
+    /// Enable proactive enrichment of all source IPs after each update.
+    /// A background task will resolve the PTR records, locations, whois and RDAP records
+    /// and run the DNS checks for all new source IPs while respecting the rate limits of the used services.
+    /// Results are kept for a while and reused by the web UI.
+    #[arg(long, env)]
+    pub enrichment: bool,
+
+    /// Optional path to a JSON file for persisting the IP enrichment results.
+    /// Avoids repeating all lookups after a restart of the application.
+    #[arg(long, env)]
+    pub enrichment_cache_file: Option<PathBuf>,
 }
 
 impl Configuration {
@@ -246,12 +447,35 @@ impl Configuration {
 
         info!("HTTP Binding: {}", self.http_server_binding);
         info!("HTTP Port: {}", self.http_server_port);
+        info!("HTTP Base Path: {}", self.http_base_path);
         info!("HTTP User: {}", self.http_server_user);
+        info!("HTTP Users File: {:?}", self.http_users_file);
+        info!("OIDC Issuer: {:?}", self.oidc_issuer);
+        info!("OIDC Client ID: {:?}", self.oidc_client_id);
+        info!("OIDC Redirect URL: {:?}", self.oidc_redirect_url);
+        info!("OIDC Scopes: {:?}", self.oidc_scopes);
+        info!("OIDC Username Claim: {}", self.oidc_username_claim);
+        info!("OIDC Groups Claim: {}", self.oidc_groups_claim);
+        info!("OIDC Admin Groups: {:?}", self.oidc_admin_groups);
+        info!("OIDC Viewer Groups: {:?}", self.oidc_viewer_groups);
+        info!("Session Lifetime: {} hours", self.session_lifetime);
+        info!("API Tokens File: {:?}", self.api_tokens_file);
+        info!("HTTP Auth Max Failures: {}", self.http_auth_max_failures);
+        info!("HTTP Auth Lockout: {} seconds", self.http_auth_lockout);
+        info!("HTTP Trusted Proxies: {:?}", self.http_trusted_proxies);
+        info!("Audit Log File: {:?}", self.audit_log_file);
 
         info!("HTTPS Enabled: {}", self.https_auto_cert);
         info!("HTTPS Domain: {:?}", self.https_auto_cert_domain);
         info!("HTTPS Mail: {:?}", self.https_auto_cert_mail);
         info!("HTTPS Cache Dir: {:?}", self.https_auto_cert_cache);
+        info!("HTTPS ACME Directory: {:?}", self.https_auto_cert_directory);
+        info!("HTTPS ACME Staging: {}", self.https_auto_cert_staging);
+        info!("HTTPS ACME CA File: {:?}", self.https_auto_cert_ca_file);
+        info!("HTTPS ACME EAB Key ID: {:?}", self.https_auto_cert_eab_kid);
+        info!("HTTPS Cert File: {:?}", self.https_cert_file);
+        info!("HTTPS Key File: {:?}", self.https_key_file);
+        info!("HTTPS Client CA File: {:?}", self.https_client_ca_file);
 
         info!(
             "Disable Duplicate Filter: {}",
@@ -260,6 +484,18 @@ impl Configuration {
 
         info!("Maximum Mail Body Size: {} bytes", self.max_mail_size);
 
+        let dns_servers: Vec<String> = self.dns_server.iter().map(|s| s.to_string()).collect();
+        info!("DNS Servers: {}", dns_servers.join(", "));
+        info!("DNS over TLS: {}", self.dns_over_tls);
+        info!("DNS Timeout: {} ms", self.dns_timeout);
+        info!("DNSBL Zones: {:?}", self.dnsbl_zones);
+        info!("IP to ASN Database File: {:?}", self.ip_to_asn_file);
+        info!("Location Provider: {:?}", self.location_provider);
+        info!("Location MMDB City File: {:?}", self.location_mmdb_city);
+        info!("Location MMDB ASN File: {:?}", self.location_mmdb_asn);
+        info!("Enrichment: {}", self.enrichment);
+        info!("Enrichment Cache File: {:?}", self.enrichment_cache_file);
+
         info!("Mail Web Hook URL: {:?}", self.mail_web_hook_url);
         info!("Mail Web Hook Method: {}", self.mail_web_hook_method);
         info!(
@@ -292,6 +528,17 @@ pub enum ImapBodyRequest {
     Body,
 }
 
+#[derive(Clone, ValueEnum, Debug, Default, PartialEq)]
+pub enum LocationProviderType {
+    /// Online lookups via ip-api.com
+    #[default]
+    IpApi,
+    /// Offline lookups in local MMDB files
+    Mmdb,
+    /// No location lookups
+    Disabled,
+}
+
 impl ImapBodyRequest {
     pub fn to_request_string(&self) -> String {
         match &self {
@@ -0,0 +1,116 @@
+use crate::dns_client::reversed_ip_labels;
+use crate::dns_client_cached::DnsClientCached;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+use tracing::debug;
+
+/// Results of DNS based reputation checks of an IP
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DnsChecks {
+    /// Host name from the PTR record
+    pub hostname: Option<String>,
+    /// Host name from the PTR record if it resolves back to the IP (forward-confirmed reverse DNS)
+    pub verified_hostname: Option<String>,
+    /// DNSBL zones that list the IP
+    pub listed_on: Vec<String>,
+    /// DNSBL zones whose lookup failed, so it is unknown if they list the IP
+    #[serde(default)]
+    pub failed_zones: Vec<String>,
+}
+
+/// Checks the PTR record of the IP with a forward lookup
+/// and queries all DNSBL zones for the IP.
+/// Failed forward lookups are treated as not verified
+/// and DNSBL zones with failed lookups are collected separately.
+pub async fn check_ip(
+    dns_client: &DnsClientCached,
+    ip: IpAddr,
+    dnsbl_zones: &[String],
+) -> Result<DnsChecks> {
+    let hostname = dns_client
+        .host_from_ip(ip)
+        .await
+        .context("Failed to resolve PTR record")?;
+
+    let verified_hostname = match &hostname {
+        Some(host) => match forward_confirmed(dns_client, host, ip).await {
+            Ok(true) => Some(host.clone()),
+            Ok(false) => None,
+            Err(err) => {
+                debug!("Failed forward lookup of {host} for {ip}: {err:#}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut listed_on = Vec::new();
+    let mut failed_zones = Vec::new();
+    for zone in dnsbl_zones {
+        let name = dnsbl_query_name(ip, zone);
+        match dns_client.ipv4_from_host(&name).await {
+            Ok(results) => {
+                if results.iter().any(is_listing) {
+                    listed_on.push(zone.clone());
+                }
+            }
+            Err(err) => {
+                debug!("Failed DNSBL lookup of {ip} on {zone}: {err:#}");
+                failed_zones.push(zone.clone());
+            }
+        }
+    }
+
+    Ok(DnsChecks {
+        hostname,
+        verified_hostname,
+        listed_on,
+        failed_zones,
+    })
+}
+
+/// Checks if the A or AAAA records of the host contain the IP
+async fn forward_confirmed(dns_client: &DnsClientCached, host: &str, ip: IpAddr) -> Result<bool> {
+    Ok(match ip {
+        IpAddr::V4(v4) => dns_client.ipv4_from_host(host).await?.contains(&v4),
+        IpAddr::V6(v6) => dns_client.ipv6_from_host(host).await?.contains(&v6),
+    })
+}
+
+/// Creates the name to query for the IP in a DNSBL zone, like `2.0.0.127.zen.example.org`
+fn dnsbl_query_name(ip: IpAddr, zone: &str) -> String {
+    format!("{}.{}", reversed_ip_labels(ip), zone.trim_matches('.'))
+}
+
+/// Listings are returned as addresses in 127.0.0.0/8.
+/// Some lists use 127.255.255.0/24 to signal errors like refused queries from public resolvers.
+fn is_listing(result: &Ipv4Addr) -> bool {
+    let octets = result.octets();
+    octets[0] == 127 && !(octets[1] == 255 && octets[2] == 255)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_names() {
+        let ip: IpAddr = "192.0.2.99".parse().unwrap();
+        assert_eq!(
+            dnsbl_query_name(ip, "dnsbl.example.org."),
+            "99.2.0.192.dnsbl.example.org"
+        );
+
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(dnsbl_query_name(ip, "dnsbl.example.org").starts_with("1.0.0.0.0.0.0.0."));
+    }
+
+    #[test]
+    fn listing_results() {
+        assert!(is_listing(&Ipv4Addr::new(127, 0, 0, 2)));
+        assert!(is_listing(&Ipv4Addr::new(127, 0, 0, 10)));
+        assert!(!is_listing(&Ipv4Addr::new(127, 255, 255, 254)));
+        assert!(!is_listing(&Ipv4Addr::new(192, 0, 2, 1)));
+    }
+}
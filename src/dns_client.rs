@@ -1,39 +1,146 @@
-use anyhow::{Context, Result, bail, ensure};
-use dns_protocol::{Flags, Message, Question, ResourceRecord, ResourceType};
+use crate::tlsa::TlsaRecord;
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use dns_protocol::{Flags, Message, Question, ResourceRecord, ResourceType, ResponseCode};
+use ring::rand::{SecureRandom, SystemRandom};
 use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
-use tokio::net::UdpSocket;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
 use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::debug;
+
+/// Size of the receive buffer for UDP responses
+const MAX_UDP_SIZE: usize = 4096;
+
+/// Time to live for negative answers without SOA record in seconds
+const DEFAULT_NEGATIVE_TTL: u32 = 300;
+
+/// Data of a resource record from a DNS answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    /// Text of all character strings of the record joined together
+    Txt(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Tlsa(TlsaRecord),
+}
+
+/// Records of a DNS answer with the time they can be cached.
+/// Non-existing names or types result in an empty list of records
+/// with the negative caching time from the SOA record of the zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsAnswer {
+    pub records: Vec<RecordData>,
+    pub ttl: u32,
+}
+
+/// Address of a DNS server with an optional name for verifying its TLS certificate.
+/// Parsed from `ip:port` or `ip:port#name`, like `1.1.1.1:853#cloudflare-dns.com`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsServer {
+    pub addr: SocketAddr,
+    pub tls_name: Option<String>,
+}
+
+impl FromStr for DnsServer {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (addr, tls_name) = match value.trim().split_once('#') {
+            Some((addr, name)) => (addr, Some(name.to_string())),
+            None => (value.trim(), None),
+        };
+        let addr = SocketAddr::from_str(addr).context("Failed to parse DNS server address")?;
+        Ok(Self { addr, tls_name })
+    }
+}
+
+impl Display for DnsServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.tls_name {
+            Some(name) => write!(f, "{}#{name}", self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
 
+/// DNS client that sends queries via UDP with fallback to TCP for truncated responses,
+/// or via TLS if enabled. Queries are distributed round-robin over all servers
+/// and repeated with the next server in case of errors.
 pub struct DnsClient {
-    server: SocketAddr,
-    next_id: AtomicU16,
+    servers: Vec<DnsServer>,
+    tls: Option<TlsConnector>,
+    next_server: AtomicUsize,
+    rng: SystemRandom,
     timeout: Duration,
 }
 
 impl DnsClient {
-    pub fn new(server: SocketAddr, timeout: Duration) -> Self {
-        Self {
-            server,
-            next_id: AtomicU16::new(1),
+    pub fn new(servers: Vec<DnsServer>, timeout: Duration, tls: bool) -> Result<Self> {
+        ensure!(!servers.is_empty(), "At least one DNS server is required");
+        let tls = tls.then(|| {
+            let cert_iter = webpki_roots::TLS_SERVER_ROOTS.iter().cloned();
+            let root_cert_store = RootCertStore::from_iter(cert_iter);
+            let client_config = ClientConfig::builder()
+                .with_root_certificates(root_cert_store)
+                .with_no_client_auth();
+            TlsConnector::from(Arc::new(client_config))
+        });
+        Ok(Self {
+            servers,
+            tls,
+            next_server: AtomicUsize::new(0),
+            rng: SystemRandom::new(),
             timeout,
-        }
+        })
     }
 
-    pub async fn host_from_ip(&self, ip: IpAddr) -> Result<Option<String>> {
-        // Create a unique ID for the query
-        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
-
-        // Create the query string
-        let query = match ip {
-            IpAddr::V4(addr) => Self::ipv4_query(addr),
-            IpAddr::V6(addr) => Self::ipv6_query(addr),
-        };
+    /// Sends a query for the name and returns the answer records of the requested type
+    pub async fn lookup(&self, name: &str, ty: ResourceType) -> Result<DnsAnswer> {
+        // Start with the next server in line and try all others in case of errors
+        let start = self.next_server.fetch_add(1, Ordering::Relaxed);
+        let mut last_error = None;
+        for offset in 0..self.servers.len() {
+            let server = &self.servers[(start + offset) % self.servers.len()];
+            match self.lookup_at(server, name, ty).await {
+                Ok(answer) => return Ok(answer),
+                Err(err) => {
+                    debug!("DNS query for {name} at {server} failed: {err:#}");
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.expect("At least one DNS server was tried"))
+    }
 
-        // Create a message
-        let mut questions = [Question::new(query.as_str(), ResourceType::Ptr, 1)];
+    async fn lookup_at(
+        &self,
+        server: &DnsServer,
+        name: &str,
+        ty: ResourceType,
+    ) -> Result<DnsAnswer> {
+        // Create a random ID for the query, so that spoofed responses are hard to match
+        let mut id = [0; 2];
+        self.rng
+            .fill(&mut id)
+            .map_err(|_| anyhow!("Failed to create random query ID"))?;
+        let id = u16::from_be_bytes(id);
+
+        // Create a message and serialize it into a buffer
+        let mut questions = [Question::new(name, ty, 1)];
         let message = Message::new(
             id,
             Flags::standard_query(),
@@ -42,84 +149,38 @@ impl DnsClient {
             &mut [],
             &mut [],
         );
+        let mut query = vec![0; message.space_needed()];
+        let len = message
+            .write(&mut query)
+            .context("Failed to serialize DNS message")?;
+        query.truncate(len);
 
         // Send message and receive DNS response data
-        let response = self
-            .send_message_receive_udp_data(&message)
-            .await
-            .context("Failed to send/receive DNS data")?;
-
-        // Parse the data as a message
-        let mut answers = [ResourceRecord::default(); 1];
-        let mut authorities = [ResourceRecord::default(); 1];
-        let mut additionals = [ResourceRecord::default(); 1];
-        let message = Message::read(
-            &response,
-            &mut questions,
-            &mut answers,
-            &mut authorities,
-            &mut additionals,
-        )
-        .context("Failed to read DNS message")?;
-
-        // Make sure we got the right answer
-        ensure!(
-            message.id() == id,
-            "Received response with mismatched ID: expected {}, got {}",
-            id,
-            message.id()
-        );
-
-        // Read the answer from the message
-        let Some(answer) = message.answers().first() else {
-            return Ok(None);
+        let response = if let Some(tls) = &self.tls {
+            self.send_receive_tls(server, tls, &query)
+                .await
+                .context("Failed to send/receive DNS data via TLS")?
+        } else {
+            let response = self
+                .send_receive_udp(server.addr, &query)
+                .await
+                .context("Failed to send/receive DNS data via UDP")?;
+            if is_truncated(&response) {
+                // Repeat the query via TCP to get the full response
+                self.send_receive_tcp(server.addr, &query)
+                    .await
+                    .context("Failed to send/receive DNS data via TCP")?
+            } else {
+                response
+            }
         };
 
-        // Check the answer type
-        if answer.ty() != ResourceType::Ptr {
-            bail!("Wrong answer type: {:?}", answer.ty())
-        }
-
-        // Parse the DNS name
-        let host_name =
-            parse_dns_name(&response, answer.data()).context("Failed to parse DNS name")?;
-
-        Ok(Some(host_name))
-    }
-
-    fn ipv4_query(ip: Ipv4Addr) -> String {
-        // Reverse the octets for PTR query
-        let octets = ip.octets();
-        format!(
-            "{}.{}.{}.{}.in-addr.arpa",
-            octets[3], octets[2], octets[1], octets[0]
-        )
-    }
-
-    fn ipv6_query(ip: Ipv6Addr) -> String {
-        // Get the 8bit segments of the IPv6 address
-        let octets = ip.octets();
-
-        // Convert each nibble to hex, reverse order, join with dots
-        let mut nibbles = Vec::with_capacity(32);
-        for &octet in &octets {
-            nibbles.push(format!("{:x}", (octet & 0xF0) >> 4));
-            nibbles.push(format!("{:x}", octet & 0x0F));
-        }
-        nibbles.reverse();
-        format!("{}.ip6.arpa", nibbles.join("."))
+        parse_response(&response, id, ty)
     }
 
-    async fn send_message_receive_udp_data(&self, message: &Message<'_, '_>) -> Result<Vec<u8>> {
-        // Serialize the message into a buffer
-        let mut buf = vec![0; 1024];
-        assert!(message.space_needed() <= buf.len());
-        let len = message
-            .write(&mut buf)
-            .context("Failed to serialize DNS message")?;
-
+    async fn send_receive_udp(&self, server: SocketAddr, query: &[u8]) -> Result<Vec<u8>> {
         // Create a UDP socket bound to the same address family as the server.
-        let bind_addr = if self.server.is_ipv4() {
+        let bind_addr = if server.is_ipv4() {
             "0.0.0.0:0"
         } else {
             "[::]:0"
@@ -128,13 +189,15 @@ impl DnsClient {
             .await
             .context("Failed to bind UDP socket")?;
 
+        // Only accept responses from the server the query was sent to
         socket
-            .send_to(&buf[..len], self.server)
+            .connect(server)
             .await
-            .context("Failed to send data")?;
+            .context("Failed to connect UDP socket")?;
+        socket.send(query).await.context("Failed to send data")?;
 
         // Read response data from the socket
-        let mut response = vec![0; 1024];
+        let mut response = vec![0; MAX_UDP_SIZE];
         let len = timeout(self.timeout, socket.recv(&mut response))
             .await
             .context("Timeout while reading response")?
@@ -143,6 +206,203 @@ impl DnsClient {
 
         Ok(response)
     }
+
+    async fn send_receive_tcp(&self, server: SocketAddr, query: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = timeout(self.timeout, TcpStream::connect(server))
+            .await
+            .context("Timeout while connecting")?
+            .context("Failed to connect")?;
+        timeout(self.timeout, send_receive_stream(&mut stream, query))
+            .await
+            .context("Timeout while exchanging data")?
+    }
+
+    async fn send_receive_tls(
+        &self,
+        server: &DnsServer,
+        tls: &TlsConnector,
+        query: &[u8],
+    ) -> Result<Vec<u8>> {
+        // Verify the certificate against the name or the IP of the server
+        let server_name = match &server.tls_name {
+            Some(name) => ServerName::try_from(name.clone()).context("Invalid TLS server name")?,
+            None => ServerName::IpAddress(server.addr.ip().into()),
+        };
+        let stream = timeout(self.timeout, TcpStream::connect(server.addr))
+            .await
+            .context("Timeout while connecting")?
+            .context("Failed to connect")?;
+        let mut stream = timeout(self.timeout, tls.connect(server_name, stream))
+            .await
+            .context("Timeout during TLS handshake")?
+            .context("Failed TLS handshake")?;
+        timeout(self.timeout, send_receive_stream(&mut stream, query))
+            .await
+            .context("Timeout while exchanging data")?
+    }
+}
+
+/// Returns the name for the PTR query of the IP
+pub fn ptr_query_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(_) => format!("{}.in-addr.arpa", reversed_ip_labels(ip)),
+        IpAddr::V6(_) => format!("{}.ip6.arpa", reversed_ip_labels(ip)),
+    }
+}
+
+/// Returns the labels of the IP in reverse order, as used for PTR and DNSBL queries.
+/// IPv4 addresses are split into octets and IPv6 addresses into nibbles.
+pub fn reversed_ip_labels(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(addr) => {
+            let octets = addr.octets();
+            format!("{}.{}.{}.{}", octets[3], octets[2], octets[1], octets[0])
+        }
+        IpAddr::V6(addr) => {
+            // Convert each nibble to hex, reverse order, join with dots
+            let mut nibbles = Vec::with_capacity(32);
+            for &octet in &addr.octets() {
+                nibbles.push(format!("{:x}", (octet & 0xF0) >> 4));
+                nibbles.push(format!("{:x}", octet & 0x0F));
+            }
+            nibbles.reverse();
+            nibbles.join(".")
+        }
+    }
+}
+
+/// Sends the query with a length prefix over a stream and reads the response (RFC 1035 4.2.2)
+async fn send_receive_stream<S>(stream: &mut S, query: &[u8]) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let len = u16::try_from(query.len()).context("DNS query too long")?;
+    let mut data = Vec::with_capacity(query.len() + 2);
+    data.extend_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(query);
+    stream
+        .write_all(&data)
+        .await
+        .context("Failed to send data")?;
+    stream.flush().await.context("Failed to flush data")?;
+
+    let len = stream
+        .read_u16()
+        .await
+        .context("Failed to read response length")?;
+    let mut response = vec![0; len as usize];
+    stream
+        .read_exact(&mut response)
+        .await
+        .context("Failed to read response")?;
+    Ok(response)
+}
+
+/// Checks the TC bit in the header of a DNS message
+fn is_truncated(message: &[u8]) -> bool {
+    message.len() > 2 && message[2] & 0x02 != 0
+}
+
+/// Reads the records of the requested type and the caching time from the response
+fn parse_response(response: &[u8], id: u16, ty: ResourceType) -> Result<DnsAnswer> {
+    // Parse the data as a message with buffers sized by the counts in the header
+    // (RFC 1035 4.1.1), which are limited by the length of the response
+    ensure!(response.len() >= 12, "DNS message too short");
+    let count = |offset: usize| {
+        let count = u16::from_be_bytes([response[offset], response[offset + 1]]);
+        (count as usize).min(response.len())
+    };
+    let mut questions = vec![Question::default(); count(4)];
+    let mut answers = vec![ResourceRecord::default(); count(6)];
+    let mut authorities = vec![ResourceRecord::default(); count(8)];
+    let mut additionals = vec![ResourceRecord::default(); count(10)];
+    let message = Message::read(
+        response,
+        &mut questions,
+        &mut answers,
+        &mut authorities,
+        &mut additionals,
+    )
+    .context("Failed to read DNS message")?;
+
+    // Make sure we got the right answer
+    ensure!(
+        message.id() == id,
+        "Received response with mismatched ID: expected {}, got {}",
+        id,
+        message.id()
+    );
+    ensure!(!message.flags().truncated(), "Received truncated response");
+
+    // Check the response code
+    match message.flags().response_code() {
+        ResponseCode::NoError | ResponseCode::NameError => {}
+        code => bail!("DNS server responded with error: {code:?}"),
+    }
+
+    // Read the answers with the requested type and skip others like CNAMEs
+    let mut records = Vec::new();
+    let mut ttl = u32::MAX;
+    for answer in message.answers().iter().filter(|a| a.ty() == ty) {
+        let data = answer.data();
+        let record = match ty {
+            ResourceType::A => {
+                let octets: [u8; 4] = data.try_into().context("Invalid A record")?;
+                RecordData::A(Ipv4Addr::from(octets))
+            }
+            ResourceType::AAAA => {
+                let octets: [u8; 16] = data.try_into().context("Invalid AAAA record")?;
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            ResourceType::Ptr => {
+                RecordData::Ptr(parse_dns_name(response, data).context("Failed to parse DNS name")?)
+            }
+            ResourceType::Txt => RecordData::Txt(parse_txt(data).context("Invalid TXT record")?),
+            ResourceType::TLSA => RecordData::Tlsa(TlsaRecord::from_wire(data)?),
+            ResourceType::MX => {
+                ensure!(data.len() > 2, "Invalid MX record");
+                RecordData::Mx {
+                    preference: u16::from_be_bytes([data[0], data[1]]),
+                    exchange: parse_dns_name(response, &data[2..])
+                        .context("Failed to parse DNS name")?,
+                }
+            }
+            _ => bail!("Unsupported record type: {ty:?}"),
+        };
+        records.push(record);
+        ttl = ttl.min(answer.ttl());
+    }
+
+    // Negative answers can be cached for the minimum of the SOA TTL
+    // and the last field of the SOA record (RFC 2308 section 5)
+    if records.is_empty() {
+        ttl = message
+            .authorities()
+            .iter()
+            .filter(|a| a.ty() == ResourceType::Soa)
+            .find_map(|soa| {
+                let data = soa.data();
+                let minimum = data.get(data.len().checked_sub(4)?..)?;
+                let minimum = u32::from_be_bytes(minimum.try_into().ok()?);
+                Some(minimum.min(soa.ttl()))
+            })
+            .unwrap_or(DEFAULT_NEGATIVE_TTL);
+    }
+
+    Ok(DnsAnswer { records, ttl })
+}
+
+/// Joins the length prefixed character strings of a TXT record
+fn parse_txt(data: &[u8]) -> Result<String> {
+    let mut text = Vec::new();
+    let mut rest = data;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = len as usize;
+        ensure!(tail.len() >= len, "Character string out of bounds");
+        text.extend_from_slice(&tail[..len]);
+        rest = &tail[len..];
+    }
+    Ok(String::from_utf8_lossy(&text).into_owned())
 }
 
 // Parse a DNS name
@@ -201,7 +461,237 @@ fn parse_dns_name_at_offset(
 
 #[cfg(test)]
 mod tests {
-    use super::parse_dns_name;
+    use super::*;
+    use dns_protocol::MessageType;
+    use tokio::net::TcpListener;
+
+    /// Creates a response to the query with A records or an SOA record for negative answers
+    fn build_response(
+        query: &[u8],
+        addresses: &[Ipv4Addr],
+        truncated: bool,
+        code: ResponseCode,
+    ) -> Vec<u8> {
+        let mut questions = [Question::default(); 1];
+        let id = Message::read(query, &mut questions, &mut [], &mut [], &mut [])
+            .unwrap()
+            .id();
+        let name = questions[0].name();
+
+        let mut flags = Flags::new();
+        flags.set_qr(MessageType::Reply);
+        flags.set_truncated(truncated);
+        flags.set_response_code(code);
+
+        let octets: Vec<[u8; 4]> = addresses.iter().map(|a| a.octets()).collect();
+        let mut answers: Vec<ResourceRecord> = octets
+            .iter()
+            .map(|o| ResourceRecord::new(name, ResourceType::A, 1, 300, o))
+            .collect();
+
+        // Empty names for the primary server and mailbox, followed by serial,
+        // refresh, retry, expire and minimum
+        let mut soa = vec![0, 0];
+        for value in [1u32, 7200, 3600, 1209600, 60] {
+            soa.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut authorities = Vec::new();
+        if addresses.is_empty() && !truncated {
+            authorities.push(ResourceRecord::new(name, ResourceType::Soa, 1, 3600, &soa));
+        }
+
+        let message = Message::new(
+            id,
+            flags,
+            &mut questions,
+            &mut answers,
+            &mut authorities,
+            &mut [],
+        );
+        let mut buf = vec![0; message.space_needed()];
+        let len = message.write(&mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    /// Starts a UDP server that answers all queries with the function,
+    /// or never answers if the function returns None
+    async fn start_udp_stub<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                if let Some(response) = handler(&buf[..len]) {
+                    socket.send_to(&response, peer).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    /// Starts a TCP server on the same port that answers all queries with the function
+    async fn start_tcp_stub<F>(addr: SocketAddr, handler: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let len = stream.read_u16().await.unwrap();
+                let mut query = vec![0; len as usize];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = handler(&query);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+    }
+
+    fn client(servers: &[SocketAddr]) -> DnsClient {
+        let servers = servers
+            .iter()
+            .map(|addr| DnsServer {
+                addr: *addr,
+                tls_name: None,
+            })
+            .collect();
+        DnsClient::new(servers, Duration::from_millis(500), false).unwrap()
+    }
+
+    #[tokio::test]
+    async fn tcp_fallback_for_truncated_responses() {
+        let addr =
+            start_udp_stub(|query| Some(build_response(query, &[], true, ResponseCode::NoError)))
+                .await;
+        start_tcp_stub(addr, |query| {
+            let ips = [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)];
+            build_response(query, &ips, false, ResponseCode::NoError)
+        })
+        .await;
+
+        let answer = client(&[addr])
+            .lookup("example.com", ResourceType::A)
+            .await
+            .unwrap();
+        assert_eq!(
+            answer.records,
+            vec![
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 2))
+            ]
+        );
+        assert_eq!(answer.ttl, 300);
+    }
+
+    #[tokio::test]
+    async fn many_answers() {
+        let ips: Vec<Ipv4Addr> = (1..=40).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+        let expected: Vec<RecordData> = ips.iter().map(|ip| RecordData::A(*ip)).collect();
+        let addr = start_udp_stub(move |query| {
+            Some(build_response(query, &ips, false, ResponseCode::NoError))
+        })
+        .await;
+
+        let answer = client(&[addr])
+            .lookup("example.com", ResourceType::A)
+            .await
+            .unwrap();
+        assert_eq!(answer.records, expected);
+    }
+
+    #[tokio::test]
+    async fn failover_and_round_robin() {
+        let silent = start_udp_stub(|_| None).await;
+        let first = start_udp_stub(|query| {
+            let ips = [Ipv4Addr::new(192, 0, 2, 1)];
+            Some(build_response(query, &ips, false, ResponseCode::NoError))
+        })
+        .await;
+        let second = start_udp_stub(|query| {
+            let ips = [Ipv4Addr::new(192, 0, 2, 2)];
+            Some(build_response(query, &ips, false, ResponseCode::NoError))
+        })
+        .await;
+
+        // Servers take turns
+        let dns_client = client(&[first, second]);
+        let mut results = Vec::new();
+        for _ in 0..4 {
+            let answer = dns_client.lookup("example.com", ResourceType::A).await;
+            results.push(answer.unwrap().records[0].clone());
+        }
+        assert_eq!(results[0], results[2]);
+        assert_eq!(results[1], results[3]);
+        assert_ne!(results[0], results[1]);
+
+        // Silent server is skipped after timeout
+        let dns_client = client(&[silent, first]);
+        for _ in 0..2 {
+            let answer = dns_client.lookup("example.com", ResourceType::A).await;
+            assert_eq!(answer.unwrap().records.len(), 1);
+        }
+
+        // Error if all servers fail
+        let dns_client = client(&[silent]);
+        assert!(
+            dns_client
+                .lookup("example.com", ResourceType::A)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_answers() {
+        let addr = start_udp_stub(|query| {
+            Some(build_response(query, &[], false, ResponseCode::NameError))
+        })
+        .await;
+        let answer = client(&[addr])
+            .lookup("missing.example.com", ResourceType::A)
+            .await
+            .unwrap();
+        assert!(answer.records.is_empty());
+        assert_eq!(answer.ttl, 60);
+
+        let addr = start_udp_stub(|query| {
+            Some(build_response(
+                query,
+                &[],
+                false,
+                ResponseCode::ServerFailure,
+            ))
+        })
+        .await;
+        let result = client(&[addr]).lookup("example.com", ResourceType::A).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parses_server_addresses() {
+        let server = DnsServer::from_str("1.1.1.1:853#cloudflare-dns.com").unwrap();
+        assert_eq!(server.addr, SocketAddr::from(([1, 1, 1, 1], 853)));
+        assert_eq!(server.tls_name.as_deref(), Some("cloudflare-dns.com"));
+        assert_eq!(server.to_string(), "1.1.1.1:853#cloudflare-dns.com");
+
+        let server = DnsServer::from_str("[2606:4700:4700::1111]:53").unwrap();
+        assert_eq!(server.tls_name, None);
+        assert!(DnsServer::from_str("1.1.1.1").is_err());
+    }
+
+    #[test]
+    fn parses_txt_records() {
+        let data = [3, b'v', b'=', b'S', 4, b'T', b'S', b'v', b'1', 0];
+        assert_eq!(parse_txt(&data).unwrap(), "v=STSv1");
+        assert!(parse_txt(&[5, b'a']).is_err());
+    }
 
     #[test]
     fn parses_dns_wire_format_names() {
@@ -229,6 +719,19 @@ mod tests {
         assert_eq!(parse_dns_name(&message, data).unwrap(), "www.example.com");
     }
 
+    #[test]
+    fn reverses_ip_labels() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(reversed_ip_labels(ip), "1.2.0.192");
+        assert_eq!(ptr_query_name(ip), "1.2.0.192.in-addr.arpa");
+
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            reversed_ip_labels(ip),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2"
+        );
+    }
+
     #[test]
     fn rejects_compression_pointer_loop() {
         let message = [0xC0, 0x02, 0xC0, 0x00];
@@ -1,42 +1,210 @@
-use crate::cache_map::CacheMap;
-use crate::dns_client::DnsClient;
+use crate::dns_client::{DnsAnswer, DnsClient, RecordData, ptr_query_name};
+use crate::tlsa::TlsaRecord;
 use anyhow::Result;
-use std::{net::IpAddr, sync::Arc};
+use dns_protocol::ResourceType;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
 pub struct DnsClientCached {
     dns_client: DnsClient,
-    cache: Arc<Mutex<CacheMap<IpAddr, Option<String>>>>,
+    cache: Arc<Mutex<TtlCache<(String, ResourceType), DnsAnswer>>>,
 }
 
 impl DnsClientCached {
     pub fn new(dns_client: DnsClient, max_cache_size: usize) -> Self {
         Self {
             dns_client,
-            cache: Arc::new(Mutex::new(
-                CacheMap::new(max_cache_size).expect("Failed to create cache"),
-            )),
+            cache: Arc::new(Mutex::new(TtlCache::new(max_cache_size))),
         }
     }
 
     pub async fn host_from_ip(&self, ip: IpAddr) -> Result<Option<String>> {
+        let answer = self.lookup(&ptr_query_name(ip), ResourceType::Ptr).await?;
+        Ok(answer.records.into_iter().find_map(|record| match record {
+            RecordData::Ptr(host_name) => Some(host_name),
+            _ => None,
+        }))
+    }
+
+    /// Returns the IPv4 addresses from the A records of the host
+    pub async fn ipv4_from_host(&self, host: &str) -> Result<Vec<Ipv4Addr>> {
+        let answer = self.lookup(host, ResourceType::A).await?;
+        Ok(answer
+            .records
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordData::A(ip) => Some(ip),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the IPv6 addresses from the AAAA records of the host
+    pub async fn ipv6_from_host(&self, host: &str) -> Result<Vec<Ipv6Addr>> {
+        let answer = self.lookup(host, ResourceType::AAAA).await?;
+        Ok(answer
+            .records
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordData::Aaaa(ip) => Some(ip),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the texts of all TXT records of the host
+    pub async fn txt_from_host(&self, host: &str) -> Result<Vec<String>> {
+        let answer = self.lookup(host, ResourceType::Txt).await?;
+        Ok(answer
+            .records
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordData::Txt(text) => Some(text),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the mail exchanges of the domain ordered by their preference
+    pub async fn mx_from_domain(&self, domain: &str) -> Result<Vec<String>> {
+        let answer = self.lookup(domain, ResourceType::MX).await?;
+        let mut exchanges: Vec<(u16, String)> = answer
+            .records
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordData::Mx {
+                    preference,
+                    exchange,
+                } => Some((preference, exchange)),
+                _ => None,
+            })
+            .collect();
+        exchanges.sort();
+        Ok(exchanges
+            .into_iter()
+            .map(|(_, exchange)| exchange)
+            .collect())
+    }
+
+    /// Returns the TLSA records of the host name, like `_25._tcp.mx.example.com`
+    pub async fn tlsa_from_host(&self, host: &str) -> Result<Vec<TlsaRecord>> {
+        let answer = self.lookup(host, ResourceType::TLSA).await?;
+        Ok(answer
+            .records
+            .into_iter()
+            .filter_map(|record| match record {
+                RecordData::Tlsa(tlsa) => Some(tlsa),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn lookup(&self, name: &str, ty: ResourceType) -> Result<DnsAnswer> {
+        let key = (name.to_ascii_lowercase(), ty);
+
         // First check cache
         {
-            let locked = self.cache.lock().await;
-            if let Some(cached) = locked.get(&ip) {
+            let mut locked = self.cache.lock().await;
+            if let Some(cached) = locked.get(&key, Instant::now()) {
                 return Ok(cached.clone());
             }
         }
 
         // Otherwise send real query over network
-        let result = self.dns_client.host_from_ip(ip).await;
+        let answer = self.dns_client.lookup(name, ty).await?;
 
-        // Cache any result that is not an error
-        if let Ok(response) = &result {
+        // Cache positive and negative answers for their TTL
+        {
             let mut locked = self.cache.lock().await;
-            locked.insert(ip, response.clone());
+            let ttl = Duration::from_secs(answer.ttl as u64);
+            locked.insert(key, answer.clone(), ttl, Instant::now());
         }
 
-        result
+        Ok(answer)
+    }
+}
+
+/// Cache with individual expiration times for all entries.
+/// If the cache is full, expired entries and then the entries that expire first are removed.
+pub struct TtlCache<K, V> {
+    max_size: usize,
+    entries: HashMap<K, (V, Instant)>,
+}
+
+impl<K: Eq + Hash + Clone, V> TtlCache<K, V> {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size: max_size.max(1),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K, now: Instant) -> Option<&V> {
+        let expired = self.entries.get(key).is_some_and(|(_, e)| *e <= now);
+        if expired {
+            self.entries.remove(key);
+        }
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V, ttl: Duration, now: Instant) {
+        if ttl.is_zero() {
+            return;
+        }
+        if self.entries.len() >= self.max_size && !self.entries.contains_key(&key) {
+            self.entries.retain(|_, (_, expires)| *expires > now);
+        }
+        while self.entries.len() >= self.max_size && !self.entries.contains_key(&key) {
+            let first = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(k, _)| k.clone());
+            match first {
+                Some(first) => self.entries.remove(&first),
+                None => break,
+            };
+        }
+        self.entries.insert(key, (value, now + ttl));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiration() {
+        let now = Instant::now();
+        let mut cache = TtlCache::new(10);
+        cache.insert("a", 1, Duration::from_secs(10), now);
+        cache.insert("b", 2, Duration::ZERO, now);
+        assert_eq!(cache.get(&"a", now), Some(&1));
+        assert_eq!(cache.get(&"b", now), None);
+        assert_eq!(cache.get(&"a", now + Duration::from_secs(9)), Some(&1));
+        assert_eq!(cache.get(&"a", now + Duration::from_secs(10)), None);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn eviction() {
+        let now = Instant::now();
+        let mut cache = TtlCache::new(2);
+        cache.insert("a", 1, Duration::from_secs(30), now);
+        cache.insert("b", 2, Duration::from_secs(10), now);
+        cache.insert("c", 3, Duration::from_secs(20), now);
+        assert_eq!(cache.get(&"a", now), Some(&1));
+        assert_eq!(cache.get(&"b", now), None);
+        assert_eq!(cache.get(&"c", now), Some(&3));
+
+        // Expired entries are removed first
+        let later = now + Duration::from_secs(25);
+        cache.insert("d", 4, Duration::from_secs(1), later);
+        assert_eq!(cache.get(&"a", later), Some(&1));
+        assert_eq!(cache.get(&"d", later), Some(&4));
     }
 }
@@ -0,0 +1,483 @@
+use crate::dns_checks::{DnsChecks, check_ip};
+use crate::geolocate::Location;
+use crate::rdap::{RdapClient, RdapNetwork};
+use crate::state::AppState;
+use crate::token_bucket::TokenBucket;
+use crate::whois::WhoIsIp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Time to live for host names from PTR records in seconds
+const PTR_TTL: u64 = 60 * 60 * 24;
+
+/// Time to live for FCrDNS and DNSBL results in seconds
+const DNS_CHECKS_TTL: u64 = 60 * 60 * 24;
+
+/// Time to live for DNS checks with failed DNSBL lookups in seconds,
+/// so that temporary resolver failures don't hide listings for long
+const FAILED_DNS_CHECKS_TTL: u64 = 60 * 15;
+
+/// Time to live for IP locations in seconds
+const LOCATION_TTL: u64 = 60 * 60 * 24 * 7;
+
+/// Time to live for whois records in seconds
+const WHOIS_TTL: u64 = 60 * 60 * 24 * 30;
+
+/// Time to live for RDAP records in seconds
+const RDAP_TTL: u64 = 60 * 60 * 24 * 30;
+
+/// Time in seconds after which failed whois and RDAP lookups are retried
+const FAILED_LOOKUP_TTL: u64 = 60 * 60 * 6;
+
+/// Rate limit for whois lookups to avoid getting blocked by the registries
+const WHOIS_REQUESTS_PER_MINUTE: u32 = 20;
+
+/// Number of enriched IPs after which the intermediate results are saved
+const SAVE_INTERVAL: usize = 100;
+
+/// Value with the Unix timestamp of the lookup that produced it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Timestamped<T> {
+    pub value: T,
+    pub updated: u64,
+}
+
+impl<T> Timestamped<T> {
+    fn new(value: T, now: u64) -> Self {
+        Self {
+            value,
+            updated: now,
+        }
+    }
+
+    fn is_fresh(&self, ttl: u64, now: u64) -> bool {
+        self.updated.saturating_add(ttl) > now
+    }
+}
+
+/// Collected information about a source IP.
+/// Fields are None if the lookup was not yet done or failed.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct IpEnrichment {
+    /// Host name from the PTR record, None inside if there is no PTR record
+    pub ptr: Option<Timestamped<Option<String>>>,
+    /// Location of the IP, None inside if the IP is unknown to the provider
+    pub location: Option<Timestamped<Option<Location>>>,
+    /// Raw whois record of the IP
+    pub whois: Option<Timestamped<String>>,
+    /// Normalized RDAP record of the network that contains the IP
+    pub rdap: Option<Timestamped<RdapNetwork>>,
+    /// Verified host name and DNSBL listings of the IP
+    pub dns_checks: Option<Timestamped<DnsChecks>>,
+    /// Unix timestamp of the last failed whois lookup, to not retry it after every update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whois_failed: Option<u64>,
+    /// Unix timestamp of the last failed RDAP lookup, to not retry it after every update
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rdap_failed: Option<u64>,
+}
+
+impl IpEnrichment {
+    /// Checks if all values are present and none of them is expired
+    fn is_complete(&self, now: u64, with_location: bool) -> bool {
+        self.ptr.as_ref().is_some_and(|v| v.is_fresh(PTR_TTL, now))
+            && (self
+                .whois
+                .as_ref()
+                .is_some_and(|v| v.is_fresh(WHOIS_TTL, now))
+                || is_recent_failure(self.whois_failed, now))
+            && (self
+                .rdap
+                .as_ref()
+                .is_some_and(|v| v.is_fresh(RDAP_TTL, now))
+                || is_recent_failure(self.rdap_failed, now))
+            && self
+                .dns_checks
+                .as_ref()
+                .is_some_and(|v| v.is_fresh(dns_checks_ttl(&v.value), now))
+            && (!with_location
+                || self
+                    .location
+                    .as_ref()
+                    .is_some_and(|v| v.is_fresh(LOCATION_TTL, now)))
+    }
+
+    /// Removes all values with expired TTLs and returns true if nothing is left
+    fn remove_expired(&mut self, now: u64) -> bool {
+        if self.ptr.as_ref().is_some_and(|v| !v.is_fresh(PTR_TTL, now)) {
+            self.ptr = None;
+        }
+        if self
+            .location
+            .as_ref()
+            .is_some_and(|v| !v.is_fresh(LOCATION_TTL, now))
+        {
+            self.location = None;
+        }
+        if self
+            .whois
+            .as_ref()
+            .is_some_and(|v| !v.is_fresh(WHOIS_TTL, now))
+        {
+            self.whois = None;
+        }
+        if self
+            .rdap
+            .as_ref()
+            .is_some_and(|v| !v.is_fresh(RDAP_TTL, now))
+        {
+            self.rdap = None;
+        }
+        if self
+            .dns_checks
+            .as_ref()
+            .is_some_and(|v| !v.is_fresh(dns_checks_ttl(&v.value), now))
+        {
+            self.dns_checks = None;
+        }
+        if !is_recent_failure(self.whois_failed, now) {
+            self.whois_failed = None;
+        }
+        if !is_recent_failure(self.rdap_failed, now) {
+            self.rdap_failed = None;
+        }
+        self.ptr.is_none()
+            && self.location.is_none()
+            && self.whois.is_none()
+            && self.rdap.is_none()
+            && self.dns_checks.is_none()
+            && self.whois_failed.is_none()
+            && self.rdap_failed.is_none()
+    }
+}
+
+fn dns_checks_ttl(checks: &DnsChecks) -> u64 {
+    if checks.failed_zones.is_empty() {
+        DNS_CHECKS_TTL
+    } else {
+        FAILED_DNS_CHECKS_TTL
+    }
+}
+
+fn is_recent_failure(failed: Option<u64>, now: u64) -> bool {
+    failed.is_some_and(|failed| failed.saturating_add(FAILED_LOOKUP_TTL) > now)
+}
+
+/// Loads previously persisted enrichment data and drops all expired values
+pub async fn load_enrichments(path: &Path) -> Result<HashMap<IpAddr, IpEnrichment>> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err).context("Failed to read enrichment cache file"),
+    };
+    let mut enrichments: HashMap<IpAddr, IpEnrichment> =
+        serde_json::from_slice(&data).context("Failed to parse enrichment cache file")?;
+    let now = unix_now();
+    enrichments.retain(|_, e| !e.remove_expired(now));
+    Ok(enrichments)
+}
+
+/// Writes the enrichment data to a temporary file and replaces the old file
+async fn save_enrichments(path: &Path, enrichments: &HashMap<IpAddr, IpEnrichment>) -> Result<()> {
+    let json = serde_json::to_vec(enrichments).context("Failed to serialize enrichment data")?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    tokio::fs::write(&tmp_path, json)
+        .await
+        .context("Failed to write temporary enrichment cache file")?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .context("Failed to replace enrichment cache file")
+}
+
+/// Returns all IPs that appear as sources in DMARC or SMTP TLS reports
+fn source_ips(state: &AppState) -> BTreeSet<IpAddr> {
+    let mut ips = BTreeSet::new();
+    for report in state.dmarc_reports.values() {
+        for record in &report.report.record {
+            ips.insert(record.row.source_ip);
+        }
+    }
+    for report in state.tls_reports.values() {
+        for policy in &report.report.policies {
+            for failure in policy.failure_details.iter().flatten() {
+                if let Some(ip) = failure.sending_mta_ip {
+                    ips.insert(ip);
+                }
+            }
+        }
+    }
+    ips
+}
+
+/// Starts the background task that enriches all source IPs
+/// with PTR, location, whois and RDAP data and DNS checks each time it is triggered.
+pub fn start_enrichment_task(
+    state: Arc<Mutex<AppState>>,
+    trigger: Arc<Notify>,
+    cache_file: Option<PathBuf>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let whois_rate_limit = TokenBucket::per_minute(WHOIS_REQUESTS_PER_MINUTE);
+        loop {
+            trigger.notified().await;
+            let start = Instant::now();
+            info!("Starting enrichment of source IPs...");
+            let count = enrich_all(&state, &whois_rate_limit, cache_file.as_deref()).await;
+            info!(
+                "Finished enrichment of {count} source IP(s) after {:.3}s",
+                start.elapsed().as_secs_f64()
+            );
+        }
+    })
+}
+
+/// Enriches all source IPs with missing or expired data and returns their number
+async fn enrich_all(
+    state: &Arc<Mutex<AppState>>,
+    whois_rate_limit: &TokenBucket,
+    cache_file: Option<&Path>,
+) -> usize {
+    let (ips, dns_client, location_provider, dnsbl_zones) = {
+        let locked = state.lock().await;
+        let now = unix_now();
+        let with_location = locked.location_provider.is_some();
+        let ips: Vec<IpAddr> = source_ips(&locked)
+            .into_iter()
+            .filter(|ip| {
+                !locked
+                    .enrichments
+                    .get(ip)
+                    .is_some_and(|e| e.is_complete(now, with_location))
+            })
+            .collect();
+        (
+            ips,
+            locked.dns_client.clone(),
+            locked.location_provider.clone(),
+            locked.dnsbl_zones.clone(),
+        )
+    };
+
+    let whois = WhoIsIp::default();
+    let rdap = RdapClient::default();
+    for (index, ip) in ips.iter().enumerate() {
+        let mut enrichment = {
+            let locked = state.lock().await;
+            locked.enrichments.get(ip).cloned().unwrap_or_default()
+        };
+        enrichment.remove_expired(unix_now());
+
+        if enrichment.ptr.is_none() {
+            match dns_client.host_from_ip(*ip).await {
+                Ok(host) => enrichment.ptr = Some(Timestamped::new(host, unix_now())),
+                Err(err) => debug!("Failed to resolve PTR record of {ip}: {err:#}"),
+            }
+        }
+
+        if enrichment.dns_checks.is_none() {
+            match check_ip(&dns_client, *ip, &dnsbl_zones).await {
+                Ok(checks) => enrichment.dns_checks = Some(Timestamped::new(checks, unix_now())),
+                Err(err) => debug!("Failed DNS checks of {ip}: {err:#}"),
+            }
+        }
+
+        if enrichment.location.is_none()
+            && let Some(provider) = &location_provider
+        {
+            match provider.locate(ip).await {
+                Ok(location) => {
+                    if let Some(location) = &location {
+                        let mut locked = state.lock().await;
+                        locked.ip_location_cache.insert(*ip, location.clone());
+                    }
+                    enrichment.location = Some(Timestamped::new(location, unix_now()));
+                }
+                Err(err) => debug!("Failed to locate {ip}: {err:#}"),
+            }
+        }
+
+        // Failed lookups are not retried immediately, so that they do not use up the rate limit
+        if enrichment.whois.is_none() && enrichment.whois_failed.is_none() {
+            whois_rate_limit.acquire().await;
+            match whois.lookup(ip).await {
+                Ok(text) => enrichment.whois = Some(Timestamped::new(text, unix_now())),
+                Err(err) => {
+                    debug!("Failed to look up whois record of {ip}: {err:#}");
+                    enrichment.whois_failed = Some(unix_now());
+                }
+            }
+        }
+
+        if enrichment.rdap.is_none() && enrichment.rdap_failed.is_none() {
+            whois_rate_limit.acquire().await;
+            match rdap.lookup(ip).await {
+                Ok(network) => enrichment.rdap = Some(Timestamped::new(network, unix_now())),
+                Err(err) => {
+                    debug!("Failed to look up RDAP record of {ip}: {err:#}");
+                    enrichment.rdap_failed = Some(unix_now());
+                }
+            }
+        }
+
+        {
+            let mut locked = state.lock().await;
+            locked
+                .search_index
+                .write()
+                .expect("Failed to lock search index")
+                .update_ip(*ip, Some(&enrichment));
+            locked.enrichments.insert(*ip, enrichment);
+        }
+
+        if (index + 1) % SAVE_INTERVAL == 0 {
+            publish(state, cache_file).await;
+        }
+    }
+
+    if !ips.is_empty() {
+        publish(state, cache_file).await;
+    }
+    ips.len()
+}
+
+/// Makes the new enrichments visible to cached API responses and saves them.
+/// Done only in intervals, because every change of the version invalidates all ETags.
+async fn publish(state: &Arc<Mutex<AppState>>, cache_file: Option<&Path>) {
+    state.lock().await.enrichments_version += 1;
+    save(state, cache_file).await;
+}
+
+async fn save(state: &Arc<Mutex<AppState>>, cache_file: Option<&Path>) {
+    let Some(path) = cache_file else {
+        return;
+    };
+    let enrichments = state.lock().await.enrichments.clone();
+    if let Err(err) = save_enrichments(path, &enrichments).await {
+        warn!("Failed to save enrichment cache: {err:#}");
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Failed to get Unix time stamp")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiration() {
+        let now = 1_000_000_000;
+        let mut enrichment = IpEnrichment {
+            ptr: Some(Timestamped::new(None, now - PTR_TTL)),
+            location: Some(Timestamped::new(None, now - LOCATION_TTL + 1)),
+            whois: Some(Timestamped::new(String::from("whois"), now)),
+            rdap: None,
+            dns_checks: Some(Timestamped::new(DnsChecks::default(), now)),
+            ..Default::default()
+        };
+        assert!(!enrichment.is_complete(now, false));
+        assert!(!enrichment.remove_expired(now));
+        assert!(enrichment.ptr.is_none());
+        assert!(enrichment.location.is_some());
+        assert!(enrichment.whois.is_some());
+
+        enrichment.ptr = Some(Timestamped::new(None, now));
+        enrichment.rdap = Some(Timestamped::new(RdapNetwork::default(), now));
+        assert!(enrichment.is_complete(now, true));
+
+        assert!(enrichment.remove_expired(now + WHOIS_TTL));
+        assert!(enrichment.location.is_none());
+        assert!(enrichment.whois.is_none());
+        assert!(enrichment.rdap.is_none());
+    }
+
+    #[tokio::test]
+    async fn persistence_roundtrip() {
+        let path = std::env::temp_dir().join(format!(
+            "dmarc-report-viewer-enrichment-test-{}.json",
+            std::process::id()
+        ));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let expired: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = unix_now();
+
+        let mut enrichments = HashMap::new();
+        enrichments.insert(
+            ip,
+            IpEnrichment {
+                ptr: Some(Timestamped::new(Some(String::from("mx.example.com")), now)),
+                ..Default::default()
+            },
+        );
+        enrichments.insert(
+            expired,
+            IpEnrichment {
+                whois: Some(Timestamped::new(String::from("old"), now - WHOIS_TTL)),
+                ..Default::default()
+            },
+        );
+        save_enrichments(&path, &enrichments).await.unwrap();
+
+        let loaded = load_enrichments(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let ptr = loaded[&ip].ptr.as_ref().unwrap();
+        assert_eq!(ptr.value.as_deref(), Some("mx.example.com"));
+
+        // Missing files are treated like an empty cache
+        assert!(load_enrichments(&path).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_lookups() {
+        let now = 1_000_000_000;
+        let mut enrichment = IpEnrichment {
+            ptr: Some(Timestamped::new(None, now)),
+            dns_checks: Some(Timestamped::new(DnsChecks::default(), now)),
+            whois_failed: Some(now),
+            rdap_failed: Some(now - 10),
+            ..Default::default()
+        };
+        assert!(enrichment.is_complete(now, false));
+        assert!(!enrichment.is_complete(now + FAILED_LOOKUP_TTL - 10, false));
+
+        assert!(!enrichment.remove_expired(now + FAILED_LOOKUP_TTL - 5));
+        assert_eq!(enrichment.whois_failed, Some(now));
+        assert!(enrichment.rdap_failed.is_none());
+        assert!(enrichment.remove_expired(now + PTR_TTL));
+    }
+
+    #[test]
+    fn failed_dnsbl_lookups() {
+        let now = 1_000_000_000;
+        let checks = DnsChecks {
+            failed_zones: vec![String::from("dnsbl.example.org")],
+            ..Default::default()
+        };
+        let mut enrichment = IpEnrichment {
+            ptr: Some(Timestamped::new(None, now)),
+            dns_checks: Some(Timestamped::new(checks, now)),
+            whois_failed: Some(now),
+            rdap_failed: Some(now),
+            ..Default::default()
+        };
+        assert!(enrichment.is_complete(now, false));
+        assert!(!enrichment.is_complete(now + FAILED_DNS_CHECKS_TTL, false));
+
+        assert!(!enrichment.remove_expired(now + FAILED_DNS_CHECKS_TTL));
+        assert!(enrichment.dns_checks.is_none());
+    }
+}
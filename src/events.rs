@@ -0,0 +1,517 @@
+use crate::dmarc::DmarcResultType;
+use crate::state::{AppState, FileType};
+use crate::tls::PolicyType;
+use crate::users::DomainScope;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Number of events that are buffered for slow subscribers before they miss events
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// Kind of a report or report file
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportKind {
+    Dmarc,
+    Tls,
+}
+
+impl From<&FileType> for ReportKind {
+    fn from(file_type: &FileType) -> Self {
+        match file_type {
+            FileType::Xml => Self::Dmarc,
+            FileType::Json => Self::Tls,
+        }
+    }
+}
+
+/// Kind of failures that were found in a new report
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// Messages that failed DMARC, because neither DKIM nor SPF passed
+    Dmarc,
+    /// Failed sessions for an MTA-STS policy
+    Sts,
+    /// Failed sessions for a DANE TLSA policy
+    Tlsa,
+}
+
+/// Totals after an update and the number of new items found by the update
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct UpdateCounts {
+    pub mails: usize,
+    pub dmarc_reports: usize,
+    pub tls_reports: usize,
+    pub parsing_errors: usize,
+    pub new_mails: usize,
+    pub new_dmarc_reports: usize,
+    pub new_tls_reports: usize,
+    pub new_parsing_errors: usize,
+    pub new_findings: usize,
+}
+
+impl UpdateCounts {
+    pub fn new(state: &AppState, changes: &[Event]) -> Self {
+        let mut counts = Self {
+            mails: state.mails.len(),
+            dmarc_reports: state.dmarc_reports.len(),
+            tls_reports: state.tls_reports.len(),
+            parsing_errors: state.parsing_errors.values().map(Vec::len).sum(),
+            ..Self::default()
+        };
+        for event in changes {
+            match event {
+                Event::NewMail { .. } => counts.new_mails += 1,
+                Event::NewReport {
+                    kind: ReportKind::Dmarc,
+                    ..
+                } => counts.new_dmarc_reports += 1,
+                Event::NewReport {
+                    kind: ReportKind::Tls,
+                    ..
+                } => counts.new_tls_reports += 1,
+                Event::ParsingError { .. } => counts.new_parsing_errors += 1,
+                Event::Finding { .. } => counts.new_findings += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+}
+
+/// Events that are published by the background task for the `/events` endpoint.
+/// Serialized with the event name as `type` field.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    UpdateStarted,
+    UpdateFinished {
+        /// Duration of the update in seconds
+        duration: f64,
+        /// Only available for users without domain restrictions
+        #[serde(skip_serializing_if = "Option::is_none")]
+        counts: Option<UpdateCounts>,
+    },
+    UpdateFailed {
+        error: String,
+    },
+    NewMail {
+        id: String,
+        subject: String,
+        sender: String,
+        /// Domains of the reports in the mail, used to check the visibility
+        #[serde(skip)]
+        domains: Vec<String>,
+    },
+    NewReport {
+        kind: ReportKind,
+        /// Hash of the report as used by the API
+        id: String,
+        mail_id: String,
+        report_id: String,
+        org: String,
+        domains: Vec<String>,
+    },
+    ParsingError {
+        mail_id: String,
+        kind: ReportKind,
+        error: String,
+    },
+    Finding {
+        kind: ReportKind,
+        /// Hash of the report as used by the API
+        id: String,
+        failure: Failure,
+        domain: String,
+        /// Number of affected messages or sessions
+        count: usize,
+    },
+    /// Sent to subscribers that were too slow and missed events,
+    /// they should reload all data they display.
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl Event {
+    /// Name of the event, same as the `type` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UpdateStarted => "update_started",
+            Self::UpdateFinished { .. } => "update_finished",
+            Self::UpdateFailed { .. } => "update_failed",
+            Self::NewMail { .. } => "new_mail",
+            Self::NewReport { .. } => "new_report",
+            Self::ParsingError { .. } => "parsing_error",
+            Self::Finding { .. } => "finding",
+            Self::Lagged { .. } => "lagged",
+        }
+    }
+
+    /// Returns the event as it is visible for a user with the scope or None if it is not visible.
+    /// Follows the same rules as the API: Mails are only visible with at least one visible report,
+    /// and parsing errors, failures and the total counts only without domain restrictions.
+    pub fn scoped(&self, scope: &DomainScope) -> Option<Self> {
+        if scope.is_all() {
+            return Some(self.clone());
+        }
+        let visible = match self {
+            Self::UpdateStarted | Self::Lagged { .. } => true,
+            Self::UpdateFinished { duration, .. } => {
+                return Some(Self::UpdateFinished {
+                    duration: *duration,
+                    counts: None,
+                });
+            }
+            Self::UpdateFailed { .. } | Self::ParsingError { .. } => false,
+            Self::NewMail { domains, .. } => domains.iter().any(|domain| scope.allows(domain)),
+            Self::NewReport {
+                kind,
+                id,
+                mail_id,
+                report_id,
+                org,
+                domains,
+            } => {
+                // SMTP TLS reports can contain policies of domains that are not visible
+                let domains: Vec<String> = domains
+                    .iter()
+                    .filter(|domain| scope.allows(domain))
+                    .cloned()
+                    .collect();
+                return (!domains.is_empty()).then(|| Self::NewReport {
+                    kind: *kind,
+                    id: id.clone(),
+                    mail_id: mail_id.clone(),
+                    report_id: report_id.clone(),
+                    org: org.clone(),
+                    domains,
+                });
+            }
+            Self::Finding { domain, .. } => scope.allows(domain),
+        };
+        visible.then(|| self.clone())
+    }
+}
+
+/// IDs of mails and reports before an update, to detect which of them are new
+pub struct Snapshot {
+    mails: HashSet<String>,
+    dmarc_reports: HashSet<String>,
+    tls_reports: HashSet<String>,
+}
+
+impl Snapshot {
+    pub fn new(state: &AppState) -> Self {
+        Self {
+            mails: state.mails.keys().cloned().collect(),
+            dmarc_reports: state.dmarc_reports.keys().cloned().collect(),
+            tls_reports: state.tls_reports.keys().cloned().collect(),
+        }
+    }
+}
+
+/// Creates events for all mails, reports, parsing errors and findings
+/// that were added to the state since the snapshot was taken
+pub fn changes(state: &AppState, previous: &Snapshot) -> Vec<Event> {
+    let mut reports = Vec::new();
+    let mut findings = Vec::new();
+    let mut mail_domains: HashMap<&str, Vec<String>> = HashMap::new();
+
+    for (hash, rwi) in &state.dmarc_reports {
+        if previous.dmarc_reports.contains(hash) {
+            continue;
+        }
+        let report = &rwi.report;
+        let domain = report.policy_published.domain.clone();
+        let failed: usize = report
+            .record
+            .iter()
+            .filter(|record| {
+                let evaluated = &record.row.policy_evaluated;
+                evaluated.dkim != Some(DmarcResultType::Pass)
+                    && evaluated.spf != Some(DmarcResultType::Pass)
+            })
+            .map(|record| record.row.count)
+            .sum();
+        if failed > 0 {
+            findings.push(Event::Finding {
+                kind: ReportKind::Dmarc,
+                id: hash.clone(),
+                failure: Failure::Dmarc,
+                domain: domain.clone(),
+                count: failed,
+            });
+        }
+        mail_domains
+            .entry(&rwi.mail_id)
+            .or_default()
+            .push(domain.clone());
+        reports.push(Event::NewReport {
+            kind: ReportKind::Dmarc,
+            id: hash.clone(),
+            mail_id: rwi.mail_id.clone(),
+            report_id: report.report_metadata.report_id.clone(),
+            org: report.report_metadata.org_name.clone(),
+            domains: vec![domain],
+        });
+    }
+
+    for (hash, rwi) in &state.tls_reports {
+        if previous.tls_reports.contains(hash) {
+            continue;
+        }
+        let report = &rwi.report;
+        let mut domains = Vec::new();
+        for policy_result in &report.policies {
+            let domain = &policy_result.policy.policy_domain;
+            if !domains.contains(domain) {
+                domains.push(domain.clone());
+            }
+            let failed = policy_result.summary.total_failure_session_count;
+            let failure = match policy_result.policy.policy_type {
+                PolicyType::Sts => Failure::Sts,
+                PolicyType::Tlsa => Failure::Tlsa,
+                PolicyType::NoPolicyFound => continue,
+            };
+            if failed > 0 {
+                findings.push(Event::Finding {
+                    kind: ReportKind::Tls,
+                    id: hash.clone(),
+                    failure,
+                    domain: domain.clone(),
+                    count: failed,
+                });
+            }
+        }
+        mail_domains
+            .entry(&rwi.mail_id)
+            .or_default()
+            .extend(domains.iter().cloned());
+        reports.push(Event::NewReport {
+            kind: ReportKind::Tls,
+            id: hash.clone(),
+            mail_id: rwi.mail_id.clone(),
+            report_id: report.report_id.clone(),
+            org: report.organization_name.clone(),
+            domains,
+        });
+    }
+
+    let mut events = Vec::new();
+    for (id, mail) in &state.mails {
+        if previous.mails.contains(id) {
+            continue;
+        }
+        let mut domains = mail_domains.remove(id.as_str()).unwrap_or_default();
+        domains.sort();
+        domains.dedup();
+        events.push(Event::NewMail {
+            id: id.clone(),
+            subject: mail.subject.clone(),
+            sender: mail.sender.clone(),
+            domains,
+        });
+        for error in state.parsing_errors.get(id).into_iter().flatten() {
+            events.push(Event::ParsingError {
+                mail_id: id.clone(),
+                kind: ReportKind::from(&error.kind),
+                error: error.error.clone(),
+            });
+        }
+    }
+    events.extend(reports);
+    events.extend(findings);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_client::{DnsClient, DnsServer};
+    use crate::mail::Mail;
+    use crate::state::{DmarcReportWithMailId, TlsReportWithMailId};
+    use crate::{dmarc, tls};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn state() -> AppState {
+        let server = DnsServer {
+            addr: "127.0.0.1:53".parse().unwrap(),
+            tls_name: None,
+        };
+        let dns_client = DnsClient::new(vec![server], Duration::from_millis(100), false).unwrap();
+        AppState::new(dns_client, None, None, Vec::new())
+    }
+
+    fn new_mail(domains: &[&str]) -> Event {
+        Event::NewMail {
+            id: String::from("mail"),
+            subject: String::from("Report"),
+            sender: String::from("noreply@example.org"),
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn serialize() {
+        let events = [
+            Event::UpdateStarted,
+            Event::UpdateFinished {
+                duration: 1.5,
+                counts: None,
+            },
+            Event::UpdateFailed {
+                error: String::from("error"),
+            },
+            new_mail(&["example.com"]),
+            Event::ParsingError {
+                mail_id: String::from("mail"),
+                kind: ReportKind::Tls,
+                error: String::from("error"),
+            },
+            Event::Lagged { missed: 3 },
+        ];
+        for event in &events {
+            let json = serde_json::to_value(event).unwrap();
+            assert_eq!(json["type"], event.name());
+        }
+        assert_eq!(
+            serde_json::to_string(&events[1]).unwrap(),
+            r#"{"type":"update_finished","duration":1.5}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&events[3]).unwrap(),
+            r#"{"type":"new_mail","id":"mail","subject":"Report","sender":"noreply@example.org"}"#
+        );
+    }
+
+    #[test]
+    fn scoped() {
+        let all = DomainScope::All;
+        let scope = DomainScope::Domains(vec![String::from("example.com")]);
+        let finished = Event::UpdateFinished {
+            duration: 1.0,
+            counts: Some(UpdateCounts::default()),
+        };
+
+        assert_eq!(finished.scoped(&all), Some(finished.clone()));
+        assert_eq!(
+            finished.scoped(&scope),
+            Some(Event::UpdateFinished {
+                duration: 1.0,
+                counts: None
+            })
+        );
+        assert!(Event::UpdateStarted.scoped(&scope).is_some());
+        assert!(new_mail(&["example.com"]).scoped(&scope).is_some());
+        assert!(new_mail(&["example.org"]).scoped(&scope).is_none());
+        assert!(new_mail(&[]).scoped(&scope).is_none());
+        assert!(new_mail(&[]).scoped(&all).is_some());
+
+        let finding = |domain: &str| Event::Finding {
+            kind: ReportKind::Dmarc,
+            id: String::from("hash"),
+            failure: Failure::Dmarc,
+            domain: domain.to_string(),
+            count: 1,
+        };
+        let report = Event::NewReport {
+            kind: ReportKind::Tls,
+            id: String::from("hash"),
+            mail_id: String::from("mail"),
+            report_id: String::from("1"),
+            org: String::from("Org"),
+            domains: vec![String::from("example.com"), String::from("example.org")],
+        };
+        let Some(Event::NewReport { domains, .. }) = report.scoped(&scope) else {
+            panic!("Report is not visible");
+        };
+        assert_eq!(domains, ["example.com"]);
+
+        assert!(finding("example.com").scoped(&scope).is_some());
+        assert!(finding("example.org").scoped(&scope).is_none());
+
+        let error = Event::ParsingError {
+            mail_id: String::from("mail"),
+            kind: ReportKind::Dmarc,
+            error: String::from("error"),
+        };
+        assert!(error.scoped(&scope).is_none());
+        assert!(error.scoped(&all).is_some());
+    }
+
+    #[test]
+    fn detect_changes() {
+        let mut state = state();
+        let mail = Mail {
+            id: String::from("mail"),
+            subject: String::from("Report"),
+            ..Mail::default()
+        };
+        state.mails.insert(mail.id.clone(), mail);
+        let snapshot = Snapshot::new(&state);
+        assert!(changes(&state, &snapshot).is_empty());
+
+        let xml = std::fs::read("testdata/dmarc-reports/hardfail.xml").unwrap();
+        let json = std::fs::read("testdata/smtp-tls-reports/rfc-example.json").unwrap();
+        let new_mail = Mail {
+            id: String::from("new"),
+            ..Mail::default()
+        };
+        state.mails.insert(new_mail.id.clone(), new_mail);
+        state.dmarc_reports.insert(
+            String::from("dmarc"),
+            DmarcReportWithMailId {
+                mail_id: String::from("new"),
+                report: dmarc::Report::from_slice(&xml).unwrap(),
+                raw: Arc::new(xml),
+            },
+        );
+        state.tls_reports.insert(
+            String::from("tls"),
+            TlsReportWithMailId {
+                mail_id: String::from("new"),
+                report: tls::Report::from_slice(&json).unwrap(),
+                raw: Arc::new(json),
+            },
+        );
+
+        let events = changes(&state, &snapshot);
+        let names: Vec<&str> = events.iter().map(Event::name).collect();
+        assert_eq!(
+            names,
+            ["new_mail", "new_report", "new_report", "finding", "finding"]
+        );
+        assert!(matches!(
+            &events[0],
+            Event::NewMail { id, domains, .. }
+                if id == "new" && domains == &["company-y.example", "mydomain.org"]
+        ));
+        assert_eq!(
+            events[3],
+            Event::Finding {
+                kind: ReportKind::Dmarc,
+                id: String::from("dmarc"),
+                failure: Failure::Dmarc,
+                domain: String::from("mydomain.org"),
+                count: 1,
+            }
+        );
+        assert!(matches!(
+            &events[4],
+            Event::Finding {
+                failure: Failure::Sts,
+                count: 303,
+                ..
+            }
+        ));
+
+        let counts = UpdateCounts::new(&state, &events);
+        assert_eq!((counts.mails, counts.new_mails), (2, 1));
+        assert_eq!((counts.new_dmarc_reports, counts.new_tls_reports), (1, 1));
+        assert_eq!(counts.new_findings, 2);
+        assert!(changes(&state, &Snapshot::new(&state)).is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::http_client::http_request;
+use crate::token_bucket::TokenBucket;
 use anyhow::{Context, Result, ensure};
+use futures::future::BoxFuture;
 use hyper::{Method, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -23,24 +25,51 @@ pub struct Location {
     pub timezone: String,
 }
 
-impl Location {
+/// Source for the location and network information of IPs
+pub trait LocationProvider: Send + Sync {
+    /// Returns the location of the IP or None if the IP is unknown
+    fn locate<'a>(&'a self, ip: &'a IpAddr) -> BoxFuture<'a, Result<Option<Location>>>;
+}
+
+/// Online location provider using the free API of ip-api.com.
+/// Requests are sent via plain HTTP and reveal the IPs to a third party.
+pub struct IpApiProvider {
+    rate_limit: TokenBucket,
+}
+
+impl IpApiProvider {
     /// Current backend allows 45 requests per minute
-    pub async fn from_ip(ip: &IpAddr) -> Result<Option<Self>> {
-        // Create URL
-        let url = format!(
-            "http://ip-api.com/json/{ip}?fields=country,countryCode,regionName,city,lat,lon,timezone,isp,org,as,proxy,hosting,query"
-        );
-
-        // Send HTTP request
-        let (status, _, body) = http_request(Method::GET, &url, &HashMap::new(), Vec::new())
-            .await
-            .context("Failed to send HTTP request")?;
-        ensure!(status == StatusCode::OK);
-
-        // Parse response JSON
-        let parsed: Self =
-            serde_json::from_slice(&body).context("Failed to parse HTTP response as JSON")?;
-
-        Ok(Some(parsed))
+    const REQUESTS_PER_MINUTE: u32 = 45;
+
+    pub fn new() -> Self {
+        Self {
+            rate_limit: TokenBucket::per_minute(Self::REQUESTS_PER_MINUTE),
+        }
+    }
+}
+
+impl LocationProvider for IpApiProvider {
+    fn locate<'a>(&'a self, ip: &'a IpAddr) -> BoxFuture<'a, Result<Option<Location>>> {
+        Box::pin(async move {
+            // Wait for the rate limit of the backend
+            self.rate_limit.acquire().await;
+
+            // Create URL
+            let url = format!(
+                "http://ip-api.com/json/{ip}?fields=country,countryCode,regionName,city,lat,lon,timezone,isp,org,as,proxy,hosting,query"
+            );
+
+            // Send HTTP request
+            let (status, _, body) = http_request(Method::GET, &url, &HashMap::new(), Vec::new())
+                .await
+                .context("Failed to send HTTP request")?;
+            ensure!(status == StatusCode::OK);
+
+            // Parse response JSON
+            let parsed: Location =
+                serde_json::from_slice(&body).context("Failed to parse HTTP response as JSON")?;
+
+            Ok(Some(parsed))
+        })
     }
 }
@@ -0,0 +1,297 @@
+use crate::geolocate::{Location, LocationProvider};
+use anyhow::{Context, Result, ensure};
+use futures::future::BoxFuture;
+use maxminddb::{Reader, geoip2};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Minimum time between checks for modified database files
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Offline location provider using local MaxMind or DB-IP database files in the MMDB format.
+/// Supports a city database (like GeoLite2-City or DB-IP City Lite) for the location
+/// and an ASN database (like GeoLite2-ASN or DB-IP ASN Lite) for the network information.
+/// The files are reloaded automatically when they are modified.
+pub struct MmdbProvider {
+    city: Option<MmdbFile>,
+    asn: Option<MmdbFile>,
+}
+
+impl MmdbProvider {
+    pub async fn new(city: Option<PathBuf>, asn: Option<PathBuf>) -> Result<Self> {
+        ensure!(
+            city.is_some() || asn.is_some(),
+            "At least one MMDB file for cities or ASNs is required"
+        );
+        let city = match city {
+            Some(path) => Some(MmdbFile::open(path).await?),
+            None => None,
+        };
+        let asn = match asn {
+            Some(path) => Some(MmdbFile::open(path).await?),
+            None => None,
+        };
+        Ok(Self { city, asn })
+    }
+}
+
+impl LocationProvider for MmdbProvider {
+    fn locate<'a>(&'a self, ip: &'a IpAddr) -> BoxFuture<'a, Result<Option<Location>>> {
+        Box::pin(async move {
+            let mut location = None;
+            if let Some(city) = &self.city
+                && let Some(reader) = reader_for(city, ip).await
+            {
+                let result = reader.lookup(*ip).context("Failed to look up IP")?;
+                if let Some(city) = result
+                    .decode::<geoip2::City>()
+                    .context("Failed to decode city data")?
+                {
+                    location = Some(location_from_city(&city));
+                }
+            }
+            if let Some(asn) = &self.asn
+                && let Some(reader) = reader_for(asn, ip).await
+            {
+                let result = reader.lookup(*ip).context("Failed to look up IP")?;
+                if let Some(asn) = result
+                    .decode::<geoip2::Asn>()
+                    .context("Failed to decode ASN data")?
+                {
+                    let location = location.get_or_insert_with(empty_location);
+                    add_asn(location, &asn);
+                }
+            }
+            Ok(location)
+        })
+    }
+}
+
+/// Returns the reader of the file if it can contain the IP.
+/// IPv4-only databases have no entries for IPv6 addresses and fail to look them up.
+async fn reader_for(file: &MmdbFile, ip: &IpAddr) -> Option<Arc<Reader<Vec<u8>>>> {
+    let reader = file.reader().await;
+    (ip.is_ipv4() || reader.metadata().ip_version == 6).then_some(reader)
+}
+
+/// Database file with automatic reload after modifications
+struct MmdbFile {
+    path: PathBuf,
+    loaded: Mutex<LoadedFile>,
+}
+
+struct LoadedFile {
+    reader: Arc<Reader<Vec<u8>>>,
+    modified: SystemTime,
+    checked: Instant,
+}
+
+impl MmdbFile {
+    async fn open(path: PathBuf) -> Result<Self> {
+        let (reader, modified) = Self::load(&path)
+            .await
+            .with_context(|| format!("Failed to load MMDB file {path:?}"))?;
+        info!(
+            "Loaded MMDB file {path:?} with database type {}",
+            reader.metadata().database_type
+        );
+        let loaded = LoadedFile {
+            reader: Arc::new(reader),
+            modified,
+            checked: Instant::now(),
+        };
+        Ok(Self {
+            path,
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    async fn load(path: &PathBuf) -> Result<(Reader<Vec<u8>>, SystemTime)> {
+        let modified = Self::modified(path).await?;
+        let data = tokio::fs::read(path).await.context("Failed to read file")?;
+        let reader = Reader::from_source(data).context("Failed to parse MMDB data")?;
+        Ok((reader, modified))
+    }
+
+    async fn modified(path: &PathBuf) -> Result<SystemTime> {
+        tokio::fs::metadata(path)
+            .await
+            .context("Failed to get file metadata")?
+            .modified()
+            .context("Failed to get file modification time")
+    }
+
+    /// Returns the current reader and reloads the file first if it was modified
+    async fn reader(&self) -> Arc<Reader<Vec<u8>>> {
+        let (reader, modified) = {
+            let mut loaded = self.loaded.lock().expect("Failed to lock MMDB file");
+            if loaded.checked.elapsed() < CHECK_INTERVAL {
+                return loaded.reader.clone();
+            }
+            loaded.checked = Instant::now();
+            (loaded.reader.clone(), loaded.modified)
+        };
+
+        match Self::modified(&self.path).await {
+            Ok(current) if current != modified => {}
+            Ok(_) => return reader,
+            Err(err) => {
+                warn!("Failed to check MMDB file {:?}: {err:#}", self.path);
+                return reader;
+            }
+        }
+
+        // Keep using the old data if the new file cannot be loaded
+        match Self::load(&self.path).await {
+            Ok((new_reader, modified)) => {
+                info!("Reloaded modified MMDB file {:?}", self.path);
+                let new_reader = Arc::new(new_reader);
+                let mut loaded = self.loaded.lock().expect("Failed to lock MMDB file");
+                loaded.reader = new_reader.clone();
+                loaded.modified = modified;
+                new_reader
+            }
+            Err(err) => {
+                warn!("Failed to reload MMDB file {:?}: {err:#}", self.path);
+                reader
+            }
+        }
+    }
+}
+
+fn empty_location() -> Location {
+    Location {
+        autonomous_system: String::new(),
+        country: String::new(),
+        city: String::new(),
+        country_code: String::new(),
+        hosting: false,
+        isp: String::new(),
+        lat: 0.0,
+        lon: 0.0,
+        org: String::new(),
+        proxy: false,
+        region_name: String::new(),
+        timezone: String::new(),
+    }
+}
+
+fn location_from_city(city: &geoip2::City) -> Location {
+    let text = |value: Option<&str>| value.unwrap_or_default().to_string();
+    Location {
+        country: text(city.country.names.english),
+        country_code: text(city.country.iso_code),
+        city: text(city.city.names.english),
+        region_name: text(city.subdivisions.first().and_then(|s| s.names.english)),
+        lat: city.location.latitude.unwrap_or_default(),
+        lon: city.location.longitude.unwrap_or_default(),
+        timezone: text(city.location.time_zone),
+        ..empty_location()
+    }
+}
+
+fn add_asn(location: &mut Location, asn: &geoip2::Asn) {
+    let org = asn.autonomous_system_organization.unwrap_or_default();
+    if let Some(number) = asn.autonomous_system_number {
+        location.autonomous_system = format!("AS{number} {org}").trim().to_string();
+    }
+    location.isp = org.to_string();
+    location.org = org.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::random_token;
+
+    #[test]
+    fn city_and_asn_mapping() {
+        let mut city = geoip2::City::default();
+        city.country.iso_code = Some("DE");
+        city.country.names.english = Some("Germany");
+        city.city.names.english = Some("Berlin");
+        city.location.latitude = Some(52.5);
+        city.location.longitude = Some(13.4);
+        city.location.time_zone = Some("Europe/Berlin");
+
+        let mut location = location_from_city(&city);
+        assert_eq!(location.country, "Germany");
+        assert_eq!(location.country_code, "DE");
+        assert_eq!(location.city, "Berlin");
+        assert_eq!(location.region_name, "");
+        assert_eq!(location.lat, 52.5);
+        assert_eq!(location.lon, 13.4);
+        assert_eq!(location.timezone, "Europe/Berlin");
+        assert_eq!(location.autonomous_system, "");
+
+        let asn = geoip2::Asn {
+            autonomous_system_number: Some(3320),
+            autonomous_system_organization: Some("Deutsche Telekom AG"),
+        };
+        add_asn(&mut location, &asn);
+        assert_eq!(location.autonomous_system, "AS3320 Deutsche Telekom AG");
+        assert_eq!(location.isp, "Deutsche Telekom AG");
+        assert_eq!(location.org, "Deutsche Telekom AG");
+    }
+
+    /// Encodes a string, unsigned integer or map header of the MaxMind DB data format
+    fn encode(out: &mut Vec<u8>, type_id: u8, payload: &[u8], size: usize) {
+        out.push((type_id << 5) | size as u8);
+        out.extend_from_slice(payload);
+    }
+
+    /// Creates an IPv4-only database with a single tree node and no entries
+    fn empty_ipv4_database() -> Vec<u8> {
+        let mut data = vec![0, 0, 1, 0, 0, 1];
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+        encode(&mut data, 7, &[], 9);
+        // Unsigned integers of 16 and 32 bits, 64 bits are an extended type
+        let uints: [(&str, u8, &[u8]); 6] = [
+            ("binary_format_major_version", 5, &[2]),
+            ("binary_format_minor_version", 5, &[0]),
+            ("ip_version", 5, &[4]),
+            ("node_count", 6, &[1]),
+            ("record_size", 5, &[24]),
+            ("build_epoch", 0, &[2, 0]),
+        ];
+        for (key, type_id, value) in uints {
+            encode(&mut data, 2, key.as_bytes(), key.len());
+            encode(&mut data, type_id, value, 1);
+        }
+        encode(&mut data, 2, b"database_type", 13);
+        encode(&mut data, 2, b"Test", 4);
+        encode(&mut data, 2, b"description", 11);
+        encode(&mut data, 7, &[], 0);
+        encode(&mut data, 2, b"languages", 9);
+        // Arrays are an extended type
+        encode(&mut data, 0, &[4], 0);
+        data
+    }
+
+    #[tokio::test]
+    async fn ipv6_in_ipv4_database() {
+        let path = std::env::temp_dir().join(format!("dmarc-{}.mmdb", random_token()));
+        std::fs::write(&path, empty_ipv4_database()).unwrap();
+
+        let provider = MmdbProvider::new(Some(path.clone()), Some(path.clone()))
+            .await
+            .unwrap();
+        let ipv4 = IpAddr::from([192, 0, 2, 1]);
+        assert!(provider.locate(&ipv4).await.unwrap().is_none());
+        let ipv6 = IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]);
+        assert!(provider.locate(&ipv6).await.unwrap().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_files() {
+        assert!(MmdbProvider::new(None, None).await.is_err());
+        let missing = PathBuf::from("testdata/missing.mmdb");
+        assert!(MmdbProvider::new(Some(missing), None).await.is_err());
+    }
+}
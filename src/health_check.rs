@@ -1,8 +1,23 @@
+use crate::acme::client_tls_config;
 use crate::config::{HTTP_DEFAULT_BINDING, HTTP_DEFAULT_PORT};
-use crate::http_client::http_request;
+use crate::http_client::http_request_with_tls_config;
+use anyhow::{Context, Result, bail};
 use clap::Parser;
 use hyper::{Method, StatusCode};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    WebPkiSupportedAlgorithms, verify_tls12_signature, verify_tls13_signature,
+};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
 
 #[derive(Parser)]
 #[command(ignore_errors = true, disable_help_flag = true)]
@@ -24,8 +39,24 @@ struct HealthCheckArgs {
     pub https_auto_cert: bool,
 
     /// See `Configuration::https_auto_cert_domain`
+    #[arg(long, env, value_delimiter = ',')]
+    pub https_auto_cert_domain: Vec<String>,
+
+    /// See `Configuration::https_auto_cert_ca_file`
+    #[arg(long, env)]
+    pub https_auto_cert_ca_file: Option<PathBuf>,
+
+    /// See `Configuration::https_cert_file`
     #[arg(long, env)]
-    pub https_auto_cert_domain: Option<String>,
+    pub https_cert_file: Option<PathBuf>,
+
+    /// See `Configuration::https_key_file`
+    #[arg(long, env)]
+    pub https_key_file: Option<PathBuf>,
+
+    /// See `Configuration::https_client_ca_file`
+    #[arg(long, env)]
+    pub https_client_ca_file: Option<PathBuf>,
 }
 
 pub async fn run_health_check_if_requested() {
@@ -46,7 +77,7 @@ fn create_check_url(args: &HealthCheckArgs) -> String {
         other => String::from(other),
     };
     if args.https_auto_cert
-        && let Some(https_host) = &args.https_auto_cert_domain
+        && let Some(https_host) = args.https_auto_cert_domain.first()
     {
         // When the HTTPS feature with automatic certificates is enabled,
         // we need to use the HTTPS protocol to check via public host name.
@@ -56,16 +87,111 @@ fn create_check_url(args: &HealthCheckArgs) -> String {
         protocol = String::from("https");
         port = 443;
         host = https_host.to_string();
+    } else if args.https_cert_file.is_some() && args.https_key_file.is_some() {
+        // Own certificates replace HTTP with HTTPS on the same port
+        protocol = String::from("https");
     }
     format!("{protocol}://{host}:{port}/health")
 }
 
+/// TLS configuration for the health check request.
+/// With own certificates exactly the configured certificate is trusted,
+/// because it usually does not contain the local address that is checked.
+fn create_tls_config(args: &HealthCheckArgs) -> Result<Arc<ClientConfig>> {
+    if args.https_auto_cert {
+        // Certificates of an internal ACME CA are usually signed by the same CA
+        // that is trusted for the connection to the ACME server
+        return client_tls_config(args.https_auto_cert_ca_file.as_deref());
+    }
+    let Some(cert_file) = &args.https_cert_file else {
+        return client_tls_config(None);
+    };
+    if args.https_client_ca_file.is_some() {
+        bail!("Health check is not supported with client certificates (mTLS)");
+    }
+    let verifier = PinnedCertVerifier::from_file(cert_file)?;
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Accepts only the first certificate of a PEM file, independent of its issuer and host names
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    cert: CertificateDer<'static>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedCertVerifier {
+    fn from_file(path: &Path) -> Result<Self> {
+        let cert = CertificateDer::pem_file_iter(path)
+            .context("Failed to read certificate file")?
+            .next()
+            .context("Certificate file contains no certificates")?
+            .context("Failed to parse certificate file")?;
+        let provider = ClientConfig::builder().crypto_provider().clone();
+        Ok(Self {
+            cert,
+            algorithms: provider.signature_verification_algorithms,
+        })
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(CertificateError::UnknownIssuer.into())
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
 async fn run_health_check(args: &HealthCheckArgs) {
     let url = create_check_url(args);
     println!("Checking health via {url}...");
     let headers = HashMap::new();
     let body = Vec::new();
-    let result = http_request(Method::GET, &url, &headers, body).await;
+    let tls_config = match create_tls_config(args) {
+        Ok(tls_config) => tls_config,
+        Err(err) => {
+            eprintln!("Health check failed: {err:#}");
+            std::process::exit(1);
+        }
+    };
+    let result =
+        http_request_with_tls_config(Method::GET, &url, &headers, body, Some(tls_config)).await;
     match result {
         Ok((status, ..)) => {
             if status == StatusCode::OK {
@@ -82,3 +208,68 @@ async fn run_health_check(args: &HealthCheckArgs) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::random_token;
+    use rcgen::{CertificateParams, KeyPair};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    /// Returns certificate and key in PEM format
+    fn self_signed(name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    /// Returns true if the client config accepts the certificate of the server
+    async fn handshake(client: Arc<ClientConfig>, cert: &str, key: &str) -> bool {
+        let chain = vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()];
+        let key = PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap();
+        let server = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server));
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("127.0.0.1").unwrap();
+        TlsConnector::from(client)
+            .connect(name, stream)
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn own_certificates() {
+        let path = std::env::temp_dir().join(format!("dmarc-health-{}.pem", random_token()));
+        let (cert, key) = self_signed("dmarc.example.com");
+        std::fs::write(&path, &cert).unwrap();
+
+        let mut args = HealthCheckArgs::parse_from(["test", "--health-check"]);
+        args.https_cert_file = Some(path.clone());
+        args.https_key_file = Some(PathBuf::from("key.pem"));
+        assert_eq!(create_check_url(&args), "https://127.0.0.1:8080/health");
+
+        // Only the configured certificate is trusted, despite the different host name
+        let tls_config = create_tls_config(&args).unwrap();
+        assert!(handshake(tls_config.clone(), &cert, &key).await);
+        let (other_cert, other_key) = self_signed("dmarc.example.com");
+        assert!(!handshake(tls_config, &other_cert, &other_key).await);
+
+        args.https_client_ca_file = Some(path.clone());
+        assert!(create_tls_config(&args).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
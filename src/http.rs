@@ -1,71 +1,248 @@
+mod api_tokens;
+mod archive;
+mod audit_log;
+mod auth;
+pub mod base_path;
+mod caching;
+mod client_ip;
+mod dmarc_records;
 mod dmarc_reports;
+mod events;
+mod export;
 mod ips;
+mod list_params;
 mod mails;
 mod metrics;
+mod mta_sts;
+mod mx_hosts;
+mod search;
 mod sources;
 mod static_files;
 mod summary;
 mod tls_reports;
+mod update;
 
+use crate::acme::{self, AcmeSettings};
+use crate::api_tokens::{TokenError, TokenStore};
+use crate::audit::{AuditAction, AuditLog};
+use crate::auth_lockout::AuthLockout;
+use crate::cert_files::{self, CertFiles};
 use crate::config::Configuration;
-use crate::hasher::create_hash;
+use crate::http::base_path::BasePath;
+use crate::http::client_ip::ClientIp;
+use crate::ip_net::IpNet;
+use crate::oidc::{CALLBACK_PATH, OidcClient, OidcSettings};
+use crate::session::Sessions;
 use crate::state::AppState;
+use crate::users::{DomainScope, User, UserStore};
 use anyhow::{Context, Result};
-use axum::Json;
 use axum::body::Body;
-use axum::extract::Request;
-use axum::http::header::{AUTHORIZATION, CACHE_CONTROL, WWW_AUTHENTICATE};
-use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::header::{
+    ACCEPT, AUTHORIZATION, CACHE_CONTROL, LOCATION, RETRY_AFTER, WWW_AUTHENTICATE,
+};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
 use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{IntoMakeService, get, post};
-use axum::{Router, extract::State};
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, Router, extract::State};
 use axum_server::Handle;
+use axum_server::tls_rustls::RustlsConfig;
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
 use futures::StreamExt;
-use rustls_acme::AcmeConfig;
-use rustls_acme::caches::DirCache;
 use serde_json::json;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use subtle::ConstantTimeEq;
+use std::time::SystemTime;
 use tokio::signal;
 use tokio::sync::Mutex;
+use tower_http::compression::CompressionLayer;
 use tracing::{error, info, warn};
 
+/// Authentication methods of the HTTP server
+pub struct Auth {
+    /// Accounts for basic auth
+    users: UserStore,
+
+    /// Sessions of users logged in via OpenID Connect
+    sessions: Sessions,
+
+    /// None if OpenID Connect is disabled
+    oidc: Option<OidcClient>,
+
+    /// API tokens for scripts and other machine access
+    tokens: TokenStore,
+
+    /// Failed logins per client IP
+    lockout: AuthLockout,
+
+    audit: AuditLog,
+
+    /// Reverse proxies whose `X-Forwarded-For` header is used
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl Auth {
+    /// Without basic auth accounts and OpenID Connect everybody has access
+    fn is_enabled(&self) -> bool {
+        self.users.is_enabled() || self.oidc.is_some()
+    }
+
+    /// IP of the client, the peer address is missing in tests without connect info
+    fn client_ip(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+        client_ip::client_ip(peer, headers, &self.trusted_proxies)
+    }
+
+    /// Counts the failed login for the lockout and records it in the audit log
+    async fn failed_login(&self, user: Option<&str>, ip: IpAddr, method: &str) {
+        self.audit
+            .record(
+                AuditAction::LoginFailed,
+                user,
+                ip,
+                Some(format!("via {method}")),
+            )
+            .await;
+        if let Some(lockout) = self.lockout.failure(&ip, unix_now()) {
+            warn!("Locked out {ip} for {lockout} seconds after too many failed logins");
+            self.audit
+                .record(
+                    AuditAction::Lockout,
+                    user,
+                    ip,
+                    Some(format!("{lockout} seconds")),
+                )
+                .await;
+        }
+    }
+}
+
 pub async fn run_http_server(config: &Configuration, state: Arc<Mutex<AppState>>) -> Result<()> {
-    if config.http_server_password.is_empty() {
+    let users = UserStore::from_config(config).context("Failed to load user accounts")?;
+    let oidc = OidcSettings::from_config(config)
+        .context("Invalid OpenID Connect configuration")?
+        .map(OidcClient::new)
+        .transpose()
+        .context("Failed to create OpenID Connect client")?;
+    let secure_cookies = oidc.as_ref().is_some_and(|oidc| oidc.is_https());
+    let sessions = Sessions::new(
+        config.session_secret.as_deref(),
+        config.session_lifetime,
+        secure_cookies,
+    )
+    .context("Failed to create session handling")?;
+    let tokens = TokenStore::new(config.api_tokens_file.clone())
+        .await
+        .context("Failed to load API tokens")?;
+    let lockout = AuthLockout::new(config.http_auth_max_failures, config.http_auth_lockout)
+        .context("Failed to create login lockout")?;
+    let audit = AuditLog::new(config.audit_log_file.as_deref())
+        .await
+        .context("Failed to open audit log")?;
+    let auth = Arc::new(Auth {
+        users,
+        sessions,
+        oidc,
+        tokens,
+        lockout,
+        audit,
+        trusted_proxies: config.http_trusted_proxies.clone(),
+    });
+    if !auth.is_enabled() {
         warn!("Detected empty password: Basic Authentication will be disabled")
+    } else if config.http_users_file.is_some() {
+        info!(
+            "Loaded {} user accounts from users file",
+            auth.users.user_count()
+        );
     }
-    let make_service = Router::new()
+
+    let admin_routes = Router::new()
+        .route(
+            "/tokens",
+            get(api_tokens::list_handler).post(api_tokens::create_handler),
+        )
+        .route("/tokens/{id}", delete(api_tokens::revoke_handler))
+        .route("/audit-log", get(audit_log::handler))
+        .route("/update", post(update::request_handler))
+        .route("/update/status", get(update::status_handler))
+        .layer(Extension(state.lock().await.update_tracker.clone()))
+        .with_state(auth.clone());
+
+    // Routes of the OpenID Connect login are always accessible
+    let auth_routes = Router::new()
+        .route("/auth/login", get(auth::login_handler))
+        .route(CALLBACK_PATH, get(auth::callback_handler))
+        .route("/auth/logout", get(auth::logout_handler))
+        .with_state(auth.clone());
+
+    // Responses derived only from the collected data get ETags
+    let data_routes = Router::new()
         .route("/summary", get(summary::handler))
         .route("/mails", get(mails::list_handler))
         .route("/mails/{id}", get(mails::single_handler))
         .route("/mails/{id}/errors", get(mails::errors_handler))
         .route("/dmarc-reports", get(dmarc_reports::list_handler))
+        .route("/dmarc-records", get(dmarc_records::handler))
         .route("/dmarc-reports/{id}", get(dmarc_reports::single_handler))
         .route("/dmarc-reports/{id}/json", get(dmarc_reports::json_handler))
         .route("/dmarc-reports/{id}/xml", get(dmarc_reports::xml_handler))
         .route("/tls-reports", get(tls_reports::list_handler))
         .route("/tls-reports/{id}", get(tls_reports::single_handler))
         .route("/tls-reports/{id}/json", get(tls_reports::json_handler))
+        .route("/tls/mx-hosts", get(mx_hosts::handler))
+        .route("/search", get(search::handler))
         .route("/sources", get(sources::handler))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            caching::etag_middleware,
+        ));
+
+    let app = Router::new()
+        .merge(data_routes)
+        .route("/mails/export", get(mails::export_handler))
+        .route("/dmarc-reports/export", get(dmarc_reports::export_handler))
+        .route("/dmarc-records/export", get(dmarc_records::export_handler))
+        .route(
+            "/tls-reports/failures/export",
+            get(tls_reports::failures_export_handler),
+        )
+        .route(
+            "/tls-reports/{id}/policies",
+            get(tls_reports::policies_handler),
+        )
+        .route("/reports/archive", get(archive::handler))
+        .route("/sources/export", get(sources::export_handler))
+        .route("/mta-sts", get(mta_sts::list_handler))
+        .route("/mta-sts/{domain}", get(mta_sts::single_handler))
         .route("/ips/{ip}/dns", get(ips::dns_single_handler))
         .route("/ips/dns/batch", post(ips::dns_batch_handler))
+        .route("/ips/{ip}/dns-checks", get(ips::dns_checks_handler))
         .route("/ips/{ip}/location", get(ips::to_location_handler))
         .route("/ips/{ip}/whois", get(ips::to_whois_handler))
+        .route("/ips/{ip}/rdap", get(ips::to_rdap_handler))
+        .route("/ips/{ip}/enrichment", get(ips::enrichment_single_handler))
+        .route("/ips/enrichment/batch", post(ips::enrichment_batch_handler))
+        .route("/events", get(events::handler))
         .route("/build", get(build))
+        .route("/user", get(user))
         .route("/metrics", get(metrics::handler))
+        .merge(admin_routes)
         .route("/", get(static_files::handler)) // index.html
         .route("/{*filepath}", get(static_files::handler)) // all other files
-        .route_layer(middleware::from_fn_with_state(
-            config.clone(),
-            basic_auth_middleware,
-        ))
+        .route_layer(middleware::from_fn_with_state(auth, basic_auth_middleware))
         .route("/health", get(health)) // After auth middleware so its unprotected!
         .with_state(state.clone())
-        .into_make_service();
+        .merge(auth_routes)
+        .layer(CompressionLayer::new())
+        .layer(middleware::from_fn(caching::encoded_etag_middleware))
+        .layer(Extension(config.http_base_path.clone()));
+    let make_service = base_path::nest(app, &config.http_base_path)
+        .into_make_service_with_connect_info::<SocketAddr>();
 
     let binding = format!("{}:{}", config.http_server_binding, config.http_server_port);
     let addr: SocketAddr = binding.parse().context("Failed to parse binding address")?;
@@ -75,6 +252,10 @@ pub async fn run_http_server(config: &Configuration, state: Arc<Mutex<AppState>>
         start_https_server(config, addr, make_service)
             .await
             .context("Failed to start HTTPS server")
+    } else if let Some(cert_files) = CertFiles::from_config(config) {
+        start_https_server_with_files(cert_files, addr, make_service)
+            .await
+            .context("Failed to start HTTPS server")
     } else {
         start_http_server(addr, make_service)
             .await
@@ -84,7 +265,7 @@ pub async fn run_http_server(config: &Configuration, state: Arc<Mutex<AppState>>
 
 async fn start_http_server(
     addr: SocketAddr,
-    make_service: IntoMakeService<Router>,
+    make_service: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
 ) -> anyhow::Result<()> {
     let handle = Handle::new();
     let handle_clone = handle.clone();
@@ -103,7 +284,7 @@ async fn start_http_server(
 async fn start_https_server(
     config: &Configuration,
     addr: SocketAddr,
-    make_service: IntoMakeService<Router>,
+    make_service: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
 ) -> anyhow::Result<()> {
     let handle = Handle::new();
     let handle_clone = handle.clone();
@@ -112,32 +293,10 @@ async fn start_https_server(
         handle_clone.shutdown();
     });
 
-    let acme_domain = config
-        .https_auto_cert_domain
-        .as_deref()
-        .context("HTTPS automatic certificate domain is missing in configuration")?;
-
-    let acme_contact = format!(
-        "mailto:{}",
-        config
-            .https_auto_cert_mail
-            .as_deref()
-            .context("HTTPS automatic certificate mail is missing in configuration")?
-    );
-
-    let acme_cache = DirCache::new(
-        config
-            .https_auto_cert_cache
-            .as_deref()
-            .context("HTTPS automatic certificate cache directory is missing in configuration")?
-            .to_owned(),
-    );
-
-    let mut acme_state = AcmeConfig::new([acme_domain])
-        .contact([acme_contact])
-        .cache_option(Some(acme_cache))
-        .directory_lets_encrypt(true)
-        .state();
+    let acme_settings = AcmeSettings::from_config(config)?;
+    let mut acme_state = acme::acme_state(&acme_settings)
+        .await
+        .context("Failed to set up ACME client")?;
     let rustls_config = acme_state.default_rustls_config();
     let acceptor = acme_state.axum_acceptor(rustls_config);
 
@@ -162,6 +321,32 @@ async fn start_https_server(
         .context("Failed to create axum HTTPS server")
 }
 
+async fn start_https_server_with_files(
+    mut cert_files: CertFiles,
+    addr: SocketAddr,
+    make_service: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+) -> anyhow::Result<()> {
+    let handle = Handle::new();
+    let handle_clone = handle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        handle_clone.shutdown();
+    });
+
+    let server_config = cert_files
+        .load()
+        .await
+        .context("Failed to load HTTPS certificate files")?;
+    let rustls_config = RustlsConfig::from_config(Arc::new(server_config));
+    tokio::spawn(cert_files::watch(cert_files, rustls_config.clone()));
+
+    axum_server::bind_rustls(addr, rustls_config)
+        .handle(handle)
+        .serve(make_service)
+        .await
+        .context("Failed to create axum HTTPS server")
+}
+
 /// Promise will be fulfilled when a shutdown signal is received
 async fn shutdown_signal() {
     let ctrlc = async {
@@ -187,63 +372,158 @@ async fn shutdown_signal() {
     }
 }
 
-/// Middleware to add basic auth password protection
+/// Middleware to add basic auth password protection and check the sessions of OpenID Connect logins and API tokens.
+/// Adds the authenticated `User` and the `ClientIp` to the request extensions,
+/// so handlers can restrict the data to the domains of the user.
 async fn basic_auth_middleware(
-    State(config): State<Configuration>,
+    State(auth): State<Arc<Auth>>,
     request: Request,
     next: Next,
 ) -> Response {
-    // Password empty means basic auth is disabled
-    if config.http_server_password.is_empty() {
-        return next.run(request).await;
+    let (parts, body) = request.into_parts();
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let ip = auth.client_ip(peer, &parts.headers);
+    let user = match authenticate(&auth, &parts, ip).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let mut request = Request::from_parts(parts, body);
+    request.extensions_mut().insert(ClientIp(ip));
+    request.extensions_mut().insert(user.clone());
+
+    let path = request.uri().path().to_owned();
+    let query = request.uri().query().map(|query| format!("?{query}"));
+    let response = next.run(request).await;
+    if is_export(&path) && response.status().is_success() {
+        let details = format!("{path}{}", query.unwrap_or_default());
+        auth.audit
+            .record(AuditAction::Export, Some(&user.name), ip, Some(details))
+            .await;
+    }
+    response
+}
+
+/// Returns the user of the request or the error response
+async fn authenticate(auth: &Arc<Auth>, request: &Parts, ip: IpAddr) -> Result<User, Response> {
+    // No accounts and no OpenID Connect means authentication is disabled
+    if !auth.is_enabled() {
+        return Ok(User::anonymous());
     }
 
-    // Prepare error responses
-    let unauthorized = Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header(WWW_AUTHENTICATE, "Basic realm=\"Access\"")
-        .body(Body::empty())
-        .expect("Failed to create response");
-    let bad_request = Response::builder()
-        .status(StatusCode::BAD_REQUEST)
-        .body(Body::empty())
-        .expect("Failed to create response");
-
-    let Some(header) = request.headers().get(AUTHORIZATION) else {
-        return unauthorized;
+    if let Some(user) = auth.sessions.user(&request.headers) {
+        return Ok(user);
+    }
+
+    // Prepare error responses.
+    // With OpenID Connect the browser should not show its basic auth dialog.
+    let unauthorized = || {
+        let mut unauthorized = Response::builder().status(StatusCode::UNAUTHORIZED);
+        if auth.oidc.is_none() {
+            unauthorized = unauthorized.header(WWW_AUTHENTICATE, "Basic realm=\"Access\"");
+        }
+        unauthorized
+            .body(Body::empty())
+            .expect("Failed to create response")
+    };
+    let bad_request = || {
+        Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::empty())
+            .expect("Failed to create response")
+    };
+
+    let Some(header) = request.headers.get(AUTHORIZATION) else {
+        if auth.oidc.is_some() && is_page_request(request) {
+            let base_path = request.extensions.get::<BasePath>().cloned();
+            let location = base_path.unwrap_or_default().join("/auth/login");
+            return Err(Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(LOCATION, location)
+                .body(Body::empty())
+                .expect("Failed to create response"));
+        }
+        return Err(unauthorized());
     };
+
+    // Credentials of locked out clients are not even checked
+    if let Some(remaining) = auth.lockout.locked(&ip, unix_now()) {
+        return Err(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, remaining)
+            .body(Body::empty())
+            .expect("Failed to create response"));
+    }
+
     let Ok(header) = header.to_str() else {
-        return bad_request;
+        return Err(bad_request());
     };
+    if let Some(token) = header.strip_prefix("Bearer ") {
+        let path = request.uri.path();
+        return match auth
+            .tokens
+            .authenticate(token.trim(), &request.method, path)
+            .await
+        {
+            Ok(user) => {
+                auth.lockout.success(&ip);
+                auth.audit.record_login(&user.name, ip, "API token").await;
+                Ok(user)
+            }
+            Err(TokenError::Invalid) => {
+                auth.failed_login(None, ip, "API token").await;
+                Err(unauthorized())
+            }
+            Err(TokenError::Forbidden) => Err(StatusCode::FORBIDDEN.into_response()),
+        };
+    }
     let Some(base64) = header.strip_prefix("Basic ") else {
-        return bad_request;
+        return Err(bad_request());
     };
     let Ok(decoded) = STANDARD.decode(base64) else {
-        return bad_request;
+        return Err(bad_request());
     };
     let Ok(string) = String::from_utf8(decoded) else {
-        return bad_request;
+        return Err(bad_request());
     };
-    let Some((user, password)) = string.split_once(':') else {
-        return bad_request;
+    let Some((name, password)) = string.split_once(':') else {
+        return Err(bad_request());
     };
 
-    // Convert user and password values to equal length hash before comparison
-    let expected = create_hash(&[
-        config.http_server_user.as_bytes(),
-        b":",
-        config.http_server_password.as_bytes(),
-    ]);
-    let actual = create_hash(&[user.as_bytes(), b":", password.as_bytes()]);
-
-    // Use constant time comparison of the expected and actual value to avoid timing attacks
-    if expected.as_bytes().ct_eq(actual.as_bytes()).unwrap_u8() == 1 {
-        next.run(request).await
+    // Password hash verification is slow by design and must not block the async runtime
+    let (name, password) = (name.to_owned(), password.to_owned());
+    let auth_clone = auth.clone();
+    let name_clone = name.clone();
+    let user =
+        tokio::task::spawn_blocking(move || auth_clone.users.authenticate(&name_clone, &password))
+            .await;
+    if let Ok(Some(user)) = user {
+        auth.lockout.success(&ip);
+        auth.audit.record_login(&user.name, ip, "basic auth").await;
+        Ok(user)
     } else {
-        unauthorized
+        auth.failed_login(Some(&name), ip, "basic auth").await;
+        Err(unauthorized())
     }
 }
 
+/// Exports and archive downloads are recorded in the audit log
+fn is_export(path: &str) -> bool {
+    path.ends_with("/export") || path == "/reports/archive"
+}
+
+/// Browsers navigating to a page accept HTML, requests of scripts usually do not
+fn is_page_request(request: &Parts) -> bool {
+    request.method == Method::GET
+        && request
+            .headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
 async fn build() -> impl IntoResponse {
     Json(json!({
         "version": env!("CARGO_PKG_VERSION"),
@@ -252,9 +532,29 @@ async fn build() -> impl IntoResponse {
     }))
 }
 
+/// Name, role and visible domains of the logged in user
+async fn user(Extension(user): Extension<User>) -> impl IntoResponse {
+    let domains = match &user.scope {
+        DomainScope::All => None,
+        DomainScope::Domains(domains) => Some(domains),
+    };
+    Json(json!({
+        "name": user.name,
+        "role": user.role,
+        "domains": domains,
+    }))
+}
+
 // 200 OK empty response
 async fn health() -> HeaderMap {
     let mut headers = HeaderMap::new();
     headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
     headers
 }
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Failed to get Unix time stamp")
+        .as_secs()
+}
@@ -0,0 +1,103 @@
+use crate::api_tokens::NewApiToken;
+use crate::audit::AuditAction;
+use crate::http::Auth;
+use crate::http::client_ip::ClientIp;
+use crate::users::{Role, User};
+use axum::extract::{Path, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Lists all API tokens with their scopes and last used timestamps
+pub async fn list_handler(
+    State(auth): State<Arc<Auth>>,
+    Extension(user): Extension<User>,
+) -> Response {
+    if user.role != Role::Admin {
+        return forbidden();
+    }
+    Json(auth.tokens.list()).into_response()
+}
+
+/// Creates a new API token, the token is only part of this response
+pub async fn create_handler(
+    State(auth): State<Arc<Auth>>,
+    Extension(user): Extension<User>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    Json(request): Json<NewApiToken>,
+) -> Response {
+    if user.role != Role::Admin {
+        return forbidden();
+    }
+    match auth.tokens.create(request, &user.name).await {
+        Ok((api_token, token)) => {
+            info!("User {} created API token {}", user.name, api_token.id);
+            let details = format!("{} ({})", api_token.id, api_token.name);
+            auth.audit
+                .record(
+                    AuditAction::TokenCreated,
+                    Some(&user.name),
+                    ip,
+                    Some(details),
+                )
+                .await;
+            (
+                StatusCode::CREATED,
+                Json(json!({
+                    "token": token,
+                    "info": api_token,
+                })),
+            )
+                .into_response()
+        }
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("{err:#}"),
+        )
+            .into_response(),
+    }
+}
+
+/// Revokes an API token, it stays in the list to show its last use
+pub async fn revoke_handler(
+    State(auth): State<Arc<Auth>>,
+    Extension(user): Extension<User>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+    Path(id): Path<String>,
+) -> Response {
+    if user.role != Role::Admin {
+        return forbidden();
+    }
+    match auth.tokens.revoke(&id).await {
+        Ok(true) => {
+            info!("User {} revoked API token {id}", user.name);
+            auth.audit
+                .record(AuditAction::TokenRevoked, Some(&user.name), ip, Some(id))
+                .await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Cannot find token",
+        )
+            .into_response(),
+        Err(err) => {
+            warn!("Failed to revoke API token {id}: {err:#}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn forbidden() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        [(header::CONTENT_TYPE, "text/plain")],
+        "Only admins can manage API tokens",
+    )
+        .into_response()
+}
@@ -0,0 +1,281 @@
+use crate::http::list_params::{ListParams, bad_request};
+use crate::state::AppState;
+use crate::users::User;
+use anyhow::{Context, Result};
+use axum::Extension;
+use axum::body::{Body, Bytes};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::http::header::{self, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use futures::stream;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{self, Sender};
+use tracing::warn;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Size of the chunks of the ZIP archive sent to the client
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Report types to include in the archive
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveType {
+    #[default]
+    All,
+    Dmarc,
+    Tls,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArchiveFilters {
+    /// Report types to include, all by default
+    r#type: Option<ArchiveType>,
+
+    /// Only include DMARC reports for this domain and SMTP TLS reports with a policy for it
+    domain: Option<String>,
+
+    /// Only include reports of this organization
+    org: Option<String>,
+}
+
+/// Original report file with its path inside the archive
+struct RawFile {
+    path: String,
+    data: Arc<Vec<u8>>,
+}
+
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    filters: Query<ArchiveFilters>,
+    params: Query<ListParams>,
+) -> Response {
+    let time_range = match params.time_range() {
+        Ok(time_range) => time_range,
+        Err(err) => return bad_request(err),
+    };
+    let archive_type = filters.r#type.unwrap_or_default();
+    let domain = filters.domain.as_ref().map(|d| d.to_lowercase());
+
+    // Only the references to the report data are cloned while locked
+    let mut files = Vec::new();
+    {
+        let lock = state.lock().await;
+        if archive_type != ArchiveType::Tls {
+            for (hash, rwi) in lock.scoped_dmarc_reports(&user.scope) {
+                let metadata = &rwi.report.report_metadata;
+                let range = &metadata.date_range;
+                if !time_range.overlaps(range.begin as i64, range.end as i64)
+                    || filters
+                        .org
+                        .as_ref()
+                        .is_some_and(|o| metadata.org_name != *o)
+                    || domain
+                        .as_ref()
+                        .is_some_and(|d| rwi.report.policy_published.domain.to_lowercase() != *d)
+                {
+                    continue;
+                }
+                files.push(RawFile {
+                    path: file_path("dmarc", range.begin as i64, &metadata.org_name, hash, "xml"),
+                    data: rwi.raw.clone(),
+                });
+            }
+        }
+        if archive_type != ArchiveType::Dmarc {
+            for (hash, rwi) in lock.scoped_tls_reports(&user.scope) {
+                let report = rwi.scoped_report(&user.scope);
+                let range = &report.date_range;
+                if !time_range.overlaps(
+                    range.start_datetime.timestamp(),
+                    range.end_datetime.timestamp(),
+                ) || filters
+                    .org
+                    .as_ref()
+                    .is_some_and(|o| report.organization_name != *o)
+                    || domain.as_ref().is_some_and(|d| {
+                        !report
+                            .policies
+                            .iter()
+                            .any(|p| p.policy.policy_domain.to_lowercase() == *d)
+                    })
+                {
+                    continue;
+                }
+                let path = file_path(
+                    "tls",
+                    range.start_datetime.timestamp(),
+                    &report.organization_name,
+                    hash,
+                    "json",
+                );
+                let data = match report {
+                    Cow::Borrowed(_) => rwi.raw.clone(),
+                    // The original file contains policies of domains that are not visible
+                    Cow::Owned(scoped) => Arc::new(
+                        serde_json::to_vec_pretty(&scoped).expect("Failed to serialize JSON"),
+                    ),
+                };
+                files.push(RawFile { path, data });
+            }
+        }
+    }
+
+    // The archive is compressed in a blocking task and streamed to the client in chunks
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        if let Err(err) = write_archive(&files, writer) {
+            warn!("Failed to create report archive: {err:#}");
+
+            // Abort the response instead of ending it with an incomplete archive
+            let _ = sender.blocking_send(Err(io::Error::other("Failed to create report archive")));
+        }
+    });
+    let body = Body::from_stream(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    }));
+
+    let date = Utc::now().format("%Y-%m-%d");
+    let disposition = format!("attachment; filename=\"reports-{date}.zip\"");
+    (
+        StatusCode::OK,
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/zip"),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition).expect("Invalid file name"),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Path of a report file in the archive, like `dmarc/2024-01-02_google.com_<hash>.xml`
+fn file_path(folder: &str, begin: i64, org: &str, hash: &str, extension: &str) -> String {
+    let date = DateTime::from_timestamp(begin, 0)
+        .map(|dt| dt.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    let org: String = org
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{folder}/{date}_{org}_{hash}.{extension}")
+}
+
+/// Writes the files as compressed ZIP archive to a writer that does not need to support seeking
+fn write_archive<W: Write>(files: &[RawFile], writer: W) -> Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for file in files {
+        zip.start_file(file.path.as_str(), options)
+            .context("Failed to add file to ZIP")?;
+        zip.write_all(&file.data)
+            .context("Failed to write file to ZIP")?;
+    }
+    zip.finish()
+        .context("Failed to finish ZIP")?
+        .flush()
+        .context("Failed to flush ZIP")?;
+    Ok(())
+}
+
+/// Forwards written data in chunks to the response body
+struct ChannelWriter {
+    sender: Sender<Result<Bytes, io::Error>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    #[test]
+    fn file_paths() {
+        assert_eq!(
+            file_path("dmarc", 1704153600, "google.com", "abc", "xml"),
+            "dmarc/2024-01-02_google.com_abc.xml"
+        );
+        assert_eq!(
+            file_path("tls", 0, "Example Org/../x", "def", "json"),
+            "tls/1970-01-01_Example_Org_.._x_def.json"
+        );
+    }
+
+    #[test]
+    fn archive_contains_original_bytes() {
+        let files = vec![
+            RawFile {
+                path: String::from("dmarc/a.xml"),
+                data: Arc::new(b"<feedback/>".to_vec()),
+            },
+            RawFile {
+                path: String::from("tls/b.json"),
+                data: Arc::new(b"{}".to_vec()),
+            },
+        ];
+        let mut data = Vec::new();
+        write_archive(&files, &mut data).unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut xml = String::new();
+        archive
+            .by_name("dmarc/a.xml")
+            .unwrap()
+            .read_to_string(&mut xml)
+            .unwrap();
+        assert_eq!(xml, "<feedback/>");
+    }
+}
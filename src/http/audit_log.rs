@@ -0,0 +1,43 @@
+use crate::http::Auth;
+use crate::http::list_params::{ListParams, bad_request, list_response};
+use crate::users::{Role, User};
+use axum::Extension;
+use axum::extract::{Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+/// Recent events of the audit log, newest first
+pub async fn handler(
+    State(auth): State<Arc<Auth>>,
+    Extension(user): Extension<User>,
+    Query(params): Query<ListParams>,
+) -> Response {
+    if user.role != Role::Admin {
+        return (
+            StatusCode::FORBIDDEN,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Only admins can read the audit log",
+        )
+            .into_response();
+    }
+    let range = match params.time_range() {
+        Ok(range) => range,
+        Err(err) => return bad_request(err),
+    };
+    let (_, descending) = match params.sort(&["time"], "-time") {
+        Ok(sort) => sort,
+        Err(err) => return bad_request(err),
+    };
+    let mut events: Vec<_> = auth
+        .audit
+        .recent()
+        .into_iter()
+        .filter(|event| range.contains(event.time.timestamp()))
+        .collect();
+    if !descending {
+        events.reverse();
+    }
+    let total = events.len();
+    list_response(&params.page(events), total)
+}
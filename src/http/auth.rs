@@ -0,0 +1,148 @@
+use crate::audit::AuditAction;
+use crate::http::Auth;
+use crate::http::base_path::BasePath;
+use crate::session::{LOGIN_COOKIE, cookie_values};
+use axum::Extension;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::header::{self, LOCATION, SET_COOKIE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(Deserialize, Debug)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Starts the OpenID Connect login by redirecting to the provider
+pub async fn login_handler(State(auth): State<Arc<Auth>>) -> Response {
+    let Some(oidc) = &auth.oidc else {
+        return text_response(StatusCode::NOT_FOUND, "OpenID Connect is disabled");
+    };
+    match oidc.login_url().await {
+        Ok((url, state)) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(LOCATION, url)
+            .header(SET_COOKIE, auth.sessions.login_cookie(&state))
+            .body(Body::empty())
+            .expect("Failed to create response"),
+        Err(err) => {
+            warn!("Failed to start OpenID Connect login: {err:#}");
+            text_response(
+                StatusCode::BAD_GATEWAY,
+                "Failed to contact identity provider",
+            )
+        }
+    }
+}
+
+/// Receives the authorization code from the provider and starts the session
+pub async fn callback_handler(
+    State(auth): State<Arc<Auth>>,
+    Extension(base_path): Extension<BasePath>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<CallbackParams>,
+) -> Response {
+    let Some(oidc) = &auth.oidc else {
+        return text_response(StatusCode::NOT_FOUND, "OpenID Connect is disabled");
+    };
+    if let Some(error) = &params.error {
+        let description = params.error_description.as_deref().unwrap_or_default();
+        return text_response(
+            StatusCode::UNAUTHORIZED,
+            &format!("Login failed: {error} {description}"),
+        );
+    }
+    let (Some(code), Some(state)) = (&params.code, &params.state) else {
+        return text_response(StatusCode::BAD_REQUEST, "Missing code or state");
+    };
+
+    // The state must come from a login started in the same browser
+    if !cookie_values(&headers, LOGIN_COOKIE).contains(&state.as_str()) {
+        return text_response(StatusCode::BAD_REQUEST, "Login was started elsewhere");
+    }
+
+    match oidc.finish_login(code, state).await {
+        Ok(user) => {
+            info!("User {} logged in via OpenID Connect", user.name);
+            auth.audit
+                .record(
+                    AuditAction::Login,
+                    Some(&user.name),
+                    auth.client_ip(Some(peer), &headers),
+                    Some(String::from("via OpenID Connect")),
+                )
+                .await;
+            Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(LOCATION, base_path.to_string())
+                .header(SET_COOKIE, auth.sessions.create_cookie(&user))
+                .header(SET_COOKIE, auth.sessions.remove_login_cookie())
+                .body(Body::empty())
+                .expect("Failed to create response")
+        }
+        Err(err) => {
+            warn!("OpenID Connect login failed: {err:#}");
+            auth.audit
+                .record(
+                    AuditAction::LoginFailed,
+                    None,
+                    auth.client_ip(Some(peer), &headers),
+                    Some(format!("via OpenID Connect: {err:#}")),
+                )
+                .await;
+            text_response(StatusCode::FORBIDDEN, "Login failed")
+        }
+    }
+}
+
+/// Ends the session and redirects to the logout of the provider, if it has one
+pub async fn logout_handler(
+    State(auth): State<Arc<Auth>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(user) = auth.sessions.user(&headers) {
+        auth.audit
+            .record(
+                AuditAction::Logout,
+                Some(&user.name),
+                auth.client_ip(Some(peer), &headers),
+                None,
+            )
+            .await;
+    }
+    let logout_url = match &auth.oidc {
+        Some(oidc) => oidc.logout_url().await,
+        None => None,
+    };
+    let builder = Response::builder().header(SET_COOKIE, auth.sessions.remove_cookie());
+    match logout_url {
+        Some(url) => builder
+            .status(StatusCode::SEE_OTHER)
+            .header(LOCATION, url)
+            .body(Body::empty()),
+        None => builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("Logged out")),
+    }
+    .expect("Failed to create response")
+}
+
+fn text_response(status: StatusCode, text: &str) -> Response {
+    (
+        status,
+        [(header::CONTENT_TYPE, "text/plain")],
+        text.to_string(),
+    )
+        .into_response()
+}
@@ -0,0 +1,140 @@
+use anyhow::{Result, ensure};
+use axum::Router;
+use axum::extract::{Request, State};
+use axum::http::Uri;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Path prefix of all routes without trailing slash, empty if the application is served at the root.
+/// Added to the request extensions, so that handlers can create absolute URLs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BasePath(String);
+
+impl BasePath {
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Absolute path of a route, the path must start with a slash
+    pub fn join(&self, path: &str) -> String {
+        format!("{}{path}", self.0)
+    }
+}
+
+impl Display for BasePath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/", self.0)
+    }
+}
+
+impl FromStr for BasePath {
+    type Err = anyhow::Error;
+
+    /// Accepts paths like `/dmarc` or `dmarc/`, the slashes are optional
+    fn from_str(value: &str) -> Result<Self> {
+        let trimmed = value.trim().trim_matches('/');
+        if trimmed.is_empty() {
+            return Ok(Self::default());
+        }
+        for segment in trimmed.split('/') {
+            ensure!(
+                !segment.is_empty() && segment != "." && segment != "..",
+                "Base path contains an empty or relative segment"
+            );
+            ensure!(
+                segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c)),
+                "Base path may only contain letters, digits and the characters -._~"
+            );
+        }
+        Ok(Self(format!("/{trimmed}")))
+    }
+}
+
+/// Serves all routes of the application below the base path.
+/// The health check stays available at `/health` for the `--health-check` mode.
+pub fn nest(app: Router, base_path: &BasePath) -> Router {
+    if base_path.is_root() {
+        return app;
+    }
+    Router::new()
+        .route("/health", get(super::health))
+        .fallback_service(Router::new().nest(&base_path.0, app))
+        .layer(middleware::from_fn_with_state(
+            base_path.clone(),
+            index_path_middleware,
+        ))
+}
+
+/// The nested router only matches the index without trailing slash,
+/// but the UI needs the slash to resolve its relative URLs.
+/// Requests without slash are redirected and requests with slash are routed to the index.
+async fn index_path_middleware(
+    State(base_path): State<BasePath>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let query = request
+        .uri()
+        .query()
+        .map(|query| format!("?{query}"))
+        .unwrap_or_default();
+    if path == base_path.0 {
+        return Redirect::temporary(&format!("{base_path}{query}")).into_response();
+    }
+    if path == base_path.to_string() {
+        let uri = format!("{}{query}", base_path.0);
+        *request.uri_mut() = Uri::from_str(&uri).expect("Failed to create index URI");
+    }
+    next.run(request).await
+}
+
+/// Adds a base element to the HTML page,
+/// which makes all relative URLs of assets and API requests relative to the base path
+pub fn add_base_element(html: &[u8], base_path: &BasePath) -> Vec<u8> {
+    let html = String::from_utf8_lossy(html);
+    let element = format!("<head>\n    <base href=\"{base_path}\" />");
+    html.replacen("<head>", &element, 1).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(BasePath::from_str("").unwrap(), BasePath::default());
+        assert_eq!(BasePath::from_str("/").unwrap(), BasePath::default());
+        let base = BasePath::from_str("dmarc/").unwrap();
+        assert_eq!(base, BasePath::from_str("/dmarc").unwrap());
+        assert_eq!(base.to_string(), "/dmarc/");
+        assert_eq!(base.join("/auth/login"), "/dmarc/auth/login");
+        assert_eq!(BasePath::default().join("/auth/login"), "/auth/login");
+        assert_eq!(BasePath::default().to_string(), "/");
+        assert_eq!(
+            BasePath::from_str("/tools/dmarc-1.0/").unwrap().to_string(),
+            "/tools/dmarc-1.0/"
+        );
+
+        assert!(BasePath::from_str("/a//b").is_err());
+        assert!(BasePath::from_str("/a/../b").is_err());
+        assert!(BasePath::from_str("/a b").is_err());
+        assert!(BasePath::from_str("/\"><script>").is_err());
+    }
+
+    #[test]
+    fn base_element() {
+        let html = b"<html>\n<head>\n    <script src=\"chart.js\"></script>\n</head>";
+        let base = BasePath::from_str("/dmarc").unwrap();
+        let html = String::from_utf8(add_base_element(html, &base)).unwrap();
+        assert_eq!(
+            html,
+            "<html>\n<head>\n    <base href=\"/dmarc/\" />\n    <script src=\"chart.js\"></script>\n</head>"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::hasher::create_hash;
+use crate::state::AppState;
+use crate::users::{DomainScope, User};
+use axum::extract::{Request, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_ENCODING, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Cache control for responses that must be revalidated with their ETag before each use
+pub const REVALIDATE: &str = "no-cache";
+
+/// Cache control for API responses, which are only cached by the browser of the user
+const REVALIDATE_PRIVATE: &str = "private, no-cache";
+
+/// Cache control for assets whose URL changes with their content
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Content encodings that are added as suffix to ETags
+const ENCODINGS: &[&str] = &["br", "gzip"];
+
+/// Adds strong ETags to GET responses of the API and answers matching conditional requests
+/// with 304 Not Modified without creating the response.
+/// The ETag changes with every update of the data, the IP enrichments or the cached locations
+/// and is different for each URL and domain scope of the user.
+pub async fn etag_middleware(
+    State(state): State<Arc<Mutex<AppState>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Responses for relative time spans change without an update
+    if request.method() != Method::GET || has_relative_time_span(request.uri()) {
+        return next.run(request).await;
+    }
+    let version = {
+        let locked = state.lock().await;
+        format!(
+            "{:x}-{:x}-{:x}-{:x}",
+            locked.start_time,
+            locked.last_update,
+            locked.enrichments_version,
+            locked.locations_version
+        )
+    };
+    let scope = match request.extensions().get::<User>().map(|user| &user.scope) {
+        Some(DomainScope::Domains(domains)) => domains.join(","),
+        Some(DomainScope::All) | None => String::new(),
+    };
+    let uri = request.uri().to_string();
+    let hash = create_hash(&[uri.as_bytes(), b"\n", scope.as_bytes()]);
+    let etag = format!("\"{version}-{hash}\"");
+
+    if if_none_match(request.headers(), &etag) {
+        return not_modified(&etag, REVALIDATE_PRIVATE);
+    }
+    let mut response = next.run(request).await;
+    if response.status() == StatusCode::OK {
+        let headers = response.headers_mut();
+        headers.insert(ETAG, HeaderValue::from_str(&etag).expect("Invalid ETag"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static(REVALIDATE_PRIVATE));
+    }
+    response
+}
+
+/// Compressed responses are different representations and need their own strong ETags.
+/// Must wrap the compression layer: ETags of compressed responses get the encoding as suffix
+/// and the suffix is removed from conditional requests, so the inner handlers only see their own ETags.
+pub async fn encoded_etag_middleware(mut request: Request, next: Next) -> Response {
+    let requested: Vec<String> = request
+        .headers()
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().to_string())
+        .collect();
+    if !requested.is_empty() {
+        let stripped = requested
+            .iter()
+            .map(|tag| strip_encoding(tag))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&stripped) {
+            request.headers_mut().insert(IF_NONE_MATCH, value);
+        }
+    }
+
+    let mut response = next.run(request).await;
+    let Some(etag) = response.headers().get(ETAG).and_then(|v| v.to_str().ok()) else {
+        return response;
+    };
+    let encoded = if response.status() == StatusCode::NOT_MODIFIED {
+        // Confirm the representation that the client already has
+        requested
+            .iter()
+            .map(|tag| tag.trim_start_matches("W/"))
+            .find(|tag| strip_encoding(tag) == etag)
+            .map(String::from)
+    } else {
+        response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .filter(|encoding| ENCODINGS.contains(encoding))
+            .and_then(|encoding| {
+                let tag = etag.strip_suffix('"')?;
+                Some(format!("{tag}-{encoding}\""))
+            })
+    };
+    if let Some(value) = encoded.and_then(|tag| HeaderValue::from_str(&tag).ok()) {
+        response.headers_mut().insert(ETAG, value);
+    }
+    response
+}
+
+/// True if one of the ETags in the `If-None-Match` header matches.
+/// Uses the weak comparison, as required for this header.
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+pub fn not_modified(etag: &str, cache_control: &'static str) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [(ETAG, etag), (CACHE_CONTROL, cache_control)],
+    )
+        .into_response()
+}
+
+/// Removes the suffix of a content encoding from an ETag like `"abc-gzip"`
+fn strip_encoding(tag: &str) -> String {
+    let (weak, opaque) = match tag.strip_prefix("W/") {
+        Some(opaque) => ("W/", opaque),
+        None => ("", tag),
+    };
+    for encoding in ENCODINGS {
+        if let Some(base) = opaque
+            .strip_suffix('"')
+            .and_then(|tag| tag.strip_suffix(encoding))
+            .and_then(|tag| tag.strip_suffix('-'))
+        {
+            return format!("{weak}{base}\"");
+        }
+    }
+    tag.to_string()
+}
+
+fn has_relative_time_span(uri: &Uri) -> bool {
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .any(|param| param.starts_with("time_span="))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::http_request;
+    use axum::Router;
+    use axum::routing::get;
+    use std::collections::HashMap;
+    use tower_http::compression::CompressionLayer;
+
+    fn headers(if_none_match: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(if_none_match).unwrap());
+        headers
+    }
+
+    #[test]
+    fn matching() {
+        assert!(if_none_match(&headers("\"a\""), "\"a\""));
+        assert!(if_none_match(&headers("\"b\", W/\"a\""), "\"a\""));
+        assert!(if_none_match(&headers("*"), "\"a\""));
+        assert!(!if_none_match(&headers("\"b\""), "\"a\""));
+        assert!(!if_none_match(&HeaderMap::new(), "\"a\""));
+    }
+
+    #[test]
+    fn encoding_suffix() {
+        assert_eq!(strip_encoding("\"1-2-abc-gzip\""), "\"1-2-abc\"");
+        assert_eq!(strip_encoding("W/\"1-2-abc-br\""), "W/\"1-2-abc\"");
+        assert_eq!(strip_encoding("\"1-2-abc\""), "\"1-2-abc\"");
+        assert_eq!(strip_encoding("\"gzip\""), "\"gzip\"");
+        assert_eq!(strip_encoding("*"), "*");
+    }
+
+    #[tokio::test]
+    async fn compressed_etags() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|headers: HeaderMap| async move {
+                    if if_none_match(&headers, "\"v1\"") {
+                        return not_modified("\"v1\"", REVALIDATE);
+                    }
+                    ([(ETAG, "\"v1\"")], "data ".repeat(100)).into_response()
+                }),
+            )
+            .layer(CompressionLayer::new())
+            .layer(axum::middleware::from_fn(encoded_etag_middleware));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let request = |headers: &[(&str, &str)]| {
+            let headers: HashMap<String, String> = headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let url = url.clone();
+            async move {
+                let (status, headers, _) = http_request(Method::GET, &url, &headers, Vec::new())
+                    .await
+                    .unwrap();
+                (status, headers[ETAG].to_str().unwrap().to_string())
+            }
+        };
+
+        let (status, etag) = request(&[]).await;
+        assert_eq!((status, etag.as_str()), (StatusCode::OK, "\"v1\""));
+        let (status, etag) = request(&[("Accept-Encoding", "gzip")]).await;
+        assert_eq!((status, etag.as_str()), (StatusCode::OK, "\"v1-gzip\""));
+        let (status, etag) = request(&[("Accept-Encoding", "br")]).await;
+        assert_eq!((status, etag.as_str()), (StatusCode::OK, "\"v1-br\""));
+
+        let (status, etag) = request(&[
+            ("Accept-Encoding", "gzip"),
+            ("If-None-Match", "\"v1-gzip\""),
+        ])
+        .await;
+        assert_eq!(
+            (status, etag.as_str()),
+            (StatusCode::NOT_MODIFIED, "\"v1-gzip\"")
+        );
+        let (status, etag) = request(&[("If-None-Match", "\"v0\", \"v1\"")]).await;
+        assert_eq!(
+            (status, etag.as_str()),
+            (StatusCode::NOT_MODIFIED, "\"v1\"")
+        );
+        let (status, _) = request(&[("If-None-Match", "\"v0-gzip\"")]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn time_spans() {
+        assert!(has_relative_time_span(&Uri::from_static(
+            "/summary?domain=a&time_span=24"
+        )));
+        assert!(!has_relative_time_span(&Uri::from_static("/summary")));
+        assert!(!has_relative_time_span(&Uri::from_static(
+            "/dmarc-reports?from=1"
+        )));
+    }
+}
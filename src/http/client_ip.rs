@@ -0,0 +1,105 @@
+use crate::ip_net::IpNet;
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Header set by reverse proxies with the chain of client and proxy IPs
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// IP of the client after resolving trusted reverse proxies.
+/// Added to the request extensions by the authentication middleware.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// Returns the IP of the client.
+/// If the peer is a trusted proxy, the `X-Forwarded-For` header is read from right to left
+/// and the first IP that is not a trusted proxy is used.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .collect();
+    let mut client = peer;
+    for entry in forwarded.into_iter().rev() {
+        let Some(ip) = parse_ip(entry.trim()) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Some proxies add the port to the IP
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(X_FORWARDED_FOR, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()];
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        // Untrusted peers cannot spoof their IP
+        let peer: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(client_ip(peer, &headers(&["192.0.2.1"]), &trusted), peer);
+
+        assert_eq!(client_ip(proxy, &headers(&["192.0.2.1"]), &trusted), client);
+        assert_eq!(
+            client_ip(
+                proxy,
+                &headers(&["203.0.113.9, 192.0.2.1, 10.0.0.2"]),
+                &trusted
+            ),
+            client
+        );
+        assert_eq!(
+            client_ip(
+                proxy,
+                &headers(&["203.0.113.9", "192.0.2.1:1234"]),
+                &trusted
+            ),
+            client
+        );
+        assert_eq!(
+            client_ip(
+                "::1".parse().unwrap(),
+                &headers(&["[2001:db8::1]:80"]),
+                &trusted
+            ),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        // Invalid entries and missing headers
+        assert_eq!(
+            client_ip(proxy, &headers(&["unknown, 10.0.0.2"]), &trusted),
+            "10.0.0.2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(client_ip(proxy, &headers(&[]), &trusted), proxy);
+        assert_eq!(client_ip(proxy, &headers(&["192.0.2.1"]), &[]), proxy);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::http::export::{ExportParams, export_response};
+use crate::http::list_params::bad_request;
+use crate::state::AppState;
+use crate::table_query::{Row, TableQuery, value_to_string};
+use crate::users::{DomainScope, User};
+use axum::Extension;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Columns of the flattened DMARC report records
+const COLUMNS: [&str; 20] = [
+    "id",
+    "report_hash",
+    "report_id",
+    "org",
+    "domain",
+    "date_begin",
+    "date_end",
+    "source_ip",
+    "count",
+    "disposition",
+    "dkim_policy",
+    "spf_policy",
+    "header_from",
+    "envelope_from",
+    "envelope_to",
+    "dkim_domains",
+    "dkim_selectors",
+    "dkim_results",
+    "spf_domains",
+    "spf_results",
+];
+
+/// Flattens all records of the DMARC reports visible in the scope into rows.
+/// The ID of a row is the report hash with the index of the record.
+fn record_rows(app: &AppState, scope: &DomainScope) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for (hash, rwi) in app.scoped_dmarc_reports(scope) {
+        let report = &rwi.report;
+        for (index, record) in report.record.iter().enumerate() {
+            let dkim = record.auth_results.dkim.as_deref().unwrap_or_default();
+            let spf = &record.auth_results.spf;
+            let row = json!({
+                "id": format!("{hash}-{index}"),
+                "report_hash": hash,
+                "report_id": report.report_metadata.report_id,
+                "org": report.report_metadata.org_name,
+                "domain": report.policy_published.domain,
+                "date_begin": report.report_metadata.date_range.begin,
+                "date_end": report.report_metadata.date_range.end,
+                "source_ip": record.row.source_ip,
+                "count": record.row.count,
+                "disposition": record.row.policy_evaluated.disposition,
+                "dkim_policy": record.row.policy_evaluated.dkim,
+                "spf_policy": record.row.policy_evaluated.spf,
+                "header_from": record.identifiers.header_from,
+                "envelope_from": record.identifiers.envelope_from,
+                "envelope_to": record.identifiers.envelope_to,
+                "dkim_domains": dkim.iter().map(|d| &d.domain).collect::<Vec<_>>(),
+                "dkim_selectors": dkim.iter().filter_map(|d| d.selector.as_ref()).collect::<Vec<_>>(),
+                "dkim_results": dkim.iter().map(|d| &d.result).collect::<Vec<_>>(),
+                "spf_domains": spf.iter().map(|s| &s.domain).collect::<Vec<_>>(),
+                "spf_results": spf.iter().map(|s| &s.result).collect::<Vec<_>>(),
+            });
+            if let Value::Object(row) = row {
+                rows.push(row);
+            }
+        }
+    }
+    rows
+}
+
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let query = match TableQuery::from_params(&params, &COLUMNS) {
+        Ok(query) => query,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                [(header::CONTENT_TYPE, "text/plain")],
+                format!("{err:#}"),
+            );
+        }
+    };
+
+    let rows = {
+        let lock = state.lock().await;
+        record_rows(&lock, &user.scope)
+    };
+    match query.apply(rows, "id") {
+        Ok(page) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            serde_json::to_string(&page).expect("Failed to serialize JSON"),
+        ),
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("{err:#}"),
+        ),
+    }
+}
+
+pub async fn export_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Query(mut params): Query<HashMap<String, String>>,
+    export: Query<ExportParams>,
+) -> Response {
+    // The format is not a column filter and the export ignores limit and cursor
+    params.remove("format");
+    let query = match TableQuery::from_params(&params, &COLUMNS) {
+        Ok(query) => query,
+        Err(err) => return bad_request(err),
+    };
+
+    let rows = {
+        let lock = state.lock().await;
+        record_rows(&lock, &user.scope)
+    };
+    let columns = query.columns(&COLUMNS);
+    let (rows, _) = query.select(rows, "id");
+    let cells_columns = columns.clone();
+    export_response(&export, "dmarc-records", columns, rows, move |row| {
+        cells_columns
+            .iter()
+            .map(|column| row.get(column).map(value_to_string).unwrap_or_default())
+            .collect()
+    })
+    .await
+}
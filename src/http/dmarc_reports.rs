@@ -2,14 +2,18 @@ use crate::dmarc::DkimResultType;
 use crate::dmarc::DmarcResultType;
 use crate::dmarc::Report;
 use crate::dmarc::SpfResultType;
+use crate::http::export::{ExportParams, export_response, format_timestamp};
+use crate::http::list_params::{ListParams, bad_request, list_response};
 use crate::state::AppState;
-use axum::Json;
+use crate::users::{DomainScope, User};
+use anyhow::Result;
+use axum::Extension;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::http::header;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
 use serde::Serialize;
 use std::net::IpAddr;
@@ -127,19 +131,88 @@ impl ReportFilters {
 
 pub async fn list_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     mut filters: Query<ReportFilters>,
-) -> impl IntoResponse {
+    params: Query<ListParams>,
+) -> Response {
+    // Remove URL encoding from strings in filters
+    filters.url_decode();
+
+    let reports = match filtered_reports(&*state.lock().await, &user.scope, &filters, &params) {
+        Ok(reports) => reports,
+        Err(err) => return bad_request(err),
+    };
+    let total = reports.len();
+    list_response(&params.page(reports), total)
+}
+
+pub async fn export_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    mut filters: Query<ReportFilters>,
+    params: Query<ListParams>,
+    export: Query<ExportParams>,
+) -> Response {
     // Remove URL encoding from strings in filters
     filters.url_decode();
 
+    let reports = match filtered_reports(&*state.lock().await, &user.scope, &filters, &params) {
+        Ok(reports) => reports,
+        Err(err) => return bad_request(err),
+    };
+    let columns = [
+        "hash",
+        "id",
+        "org",
+        "domain",
+        "date_begin",
+        "date_end",
+        "records",
+        "flagged_dkim",
+        "flagged_spf",
+        "flagged_dmarc",
+    ];
+    export_response(
+        &export,
+        "dmarc-reports",
+        columns.map(String::from).to_vec(),
+        params.page(reports),
+        |report| {
+            vec![
+                report.hash,
+                report.id,
+                report.org,
+                report.domain,
+                format_timestamp(report.date_begin as i64),
+                format_timestamp(report.date_end as i64),
+                report.records.to_string(),
+                report.flagged_dkim.to_string(),
+                report.flagged_spf.to_string(),
+                report.flagged_dmarc.to_string(),
+            ]
+        },
+    )
+    .await
+}
+
+/// Filters and sorts the report headers for the list and export endpoints
+fn filtered_reports(
+    app: &AppState,
+    scope: &DomainScope,
+    filters: &ReportFilters,
+    params: &ListParams,
+) -> Result<Vec<ReportHeader>> {
+    let time_range = params.time_range()?;
+    let (sort_field, descending) = params.sort(
+        &["date_begin", "date_end", "org", "domain", "id", "records"],
+        "-date_begin",
+    )?;
+
     // Parse IP once to speed up filters
     let ip_filter = filters.ip.as_deref().and_then(|s| IpAddr::from_str(s).ok());
 
-    let reports: Vec<ReportHeader> = state
-        .lock()
-        .await
-        .dmarc_reports
-        .iter()
+    let mut reports: Vec<ReportHeader> = app
+        .scoped_dmarc_reports(scope)
         .filter(|(_, rwi)| {
             if let Some(id) = &filters.id {
                 rwi.mail_id == *id
@@ -147,6 +220,10 @@ pub async fn list_handler(
                 true
             }
         })
+        .filter(|(_, rwi)| {
+            let range = &rwi.report.report_metadata.date_range;
+            time_range.overlaps(range.begin as i64, range.end as i64)
+        })
         .filter(|(_, rwi)| {
             if let Some(org) = &filters.org {
                 rwi.report.report_metadata.org_name == *org
@@ -198,15 +275,36 @@ pub async fn list_handler(
             }
         })
         .collect();
-    Json(reports)
+
+    reports.sort_by(|a, b| {
+        let ordering = match sort_field {
+            "date_end" => a.date_end.cmp(&b.date_end),
+            "org" => a.org.cmp(&b.org),
+            "domain" => a.domain.cmp(&b.domain),
+            "id" => a.id.cmp(&b.id),
+            "records" => a.records.cmp(&b.records),
+            _ => a.date_begin.cmp(&b.date_begin),
+        };
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(reports)
 }
 
 pub async fn single_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if let Some(rwi) = lock.dmarc_reports.get(&id) {
+    if let Some(rwi) = lock
+        .dmarc_reports
+        .get(&id)
+        .filter(|rwi| rwi.is_visible(&user.scope))
+    {
         let report_json = serde_json::to_string(rwi).expect("Failed to serialize JSON");
         (
             StatusCode::OK,
@@ -224,10 +322,15 @@ pub async fn single_handler(
 
 pub async fn json_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if let Some(rwi) = lock.dmarc_reports.get(&id) {
+    if let Some(rwi) = lock
+        .dmarc_reports
+        .get(&id)
+        .filter(|rwi| rwi.is_visible(&user.scope))
+    {
         let report_json = serde_json::to_string(&rwi.report).expect("Failed to serialize JSON");
         (
             StatusCode::OK,
@@ -245,10 +348,15 @@ pub async fn json_handler(
 
 pub async fn xml_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if let Some(rwi) = lock.dmarc_reports.get(&id) {
+    if let Some(rwi) = lock
+        .dmarc_reports
+        .get(&id)
+        .filter(|rwi| rwi.is_visible(&user.scope))
+    {
         let mut report_xml = String::new();
         let mut serializer = quick_xml::se::Serializer::new(&mut report_xml);
         serializer.indent(' ', 2);
@@ -0,0 +1,41 @@
+use crate::events::Event;
+use crate::state::AppState;
+use crate::users::User;
+use axum::Extension;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::response::sse::{self, KeepAlive, Sse};
+use futures::stream;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Server-sent events of the background task, filtered by the domain scope of the user.
+/// The name of each event is its type and the data the event as JSON.
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    let receiver = state.lock().await.events.subscribe();
+    let events = stream::unfold(receiver, move |mut receiver| {
+        let scope = user.scope.clone();
+        async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => Event::Lagged { missed },
+                    Err(RecvError::Closed) => return None,
+                };
+                if let Some(event) = event.scoped(&scope) {
+                    let sse_event = sse::Event::default()
+                        .event(event.name())
+                        .json_data(&event)
+                        .expect("Failed to serialize JSON");
+                    return Some((Ok::<_, Infallible>(sse_event), receiver));
+                }
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,323 @@
+use anyhow::{Context, Result};
+use axum::body::{Body, Bytes};
+use axum::http::StatusCode;
+use axum::http::header::{self, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::io::{Cursor, Write};
+use tracing::error;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+/// Number of CSV rows that are encoded together into one chunk of the response body
+const CSV_CHUNK_ROWS: usize = 500;
+
+/// File formats for exports
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Xlsx,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportParams {
+    format: Option<ExportFormat>,
+}
+
+/// Creates a download response with the rows in the requested format.
+/// The rows are only converted to cells and encoded while the response is streamed,
+/// so the caller should collect them with the state locked and then release the lock.
+/// XLSX files are built completely in memory before they are sent.
+pub async fn export_response<T, F>(
+    params: &ExportParams,
+    name: &str,
+    columns: Vec<String>,
+    rows: Vec<T>,
+    to_cells: F,
+) -> Response
+where
+    T: Send + 'static,
+    F: Fn(T) -> Vec<String> + Send + 'static,
+{
+    let format = params.format.unwrap_or_default();
+    let (extension, mime_type) = match format {
+        ExportFormat::Csv => ("csv", "text/csv; charset=utf-8"),
+        ExportFormat::Xlsx => (
+            "xlsx",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+    };
+    let date = Utc::now().format("%Y-%m-%d");
+    let disposition = format!("attachment; filename=\"{name}-{date}.{extension}\"");
+    let disposition = HeaderValue::from_str(&disposition).expect("Invalid file name");
+
+    let body = match format {
+        ExportFormat::Csv => {
+            let header = stream::once(async move { Ok(Bytes::from(csv_line(&columns))) });
+            let lines = stream::iter(rows).chunks(CSV_CHUNK_ROWS).map(move |chunk| {
+                let mut text = String::new();
+                for row in chunk {
+                    text.push_str(&csv_line(&to_cells(row)));
+                }
+                Ok::<_, Infallible>(Bytes::from(text))
+            });
+            Body::from_stream(header.chain(lines))
+        }
+        ExportFormat::Xlsx => {
+            let result = tokio::task::spawn_blocking(move || {
+                let rows: Vec<Vec<String>> = rows.into_iter().map(to_cells).collect();
+                xlsx(&columns, &rows)
+            })
+            .await;
+            match result {
+                Ok(Ok(data)) => Body::from(data),
+                Ok(Err(err)) => return export_error(err),
+                Err(err) => return export_error(err.into()),
+            }
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(mime_type)),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn export_error(err: anyhow::Error) -> Response {
+    error!("Failed to create export: {err:#}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        [(header::CONTENT_TYPE, "text/plain")],
+        String::from("Failed to create export"),
+    )
+        .into_response()
+}
+
+/// Formats Unix timestamps for spreadsheets
+pub fn format_timestamp(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the serialized name of enum variants, like `starttls-not-supported`
+pub fn serde_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        Ok(value) => value.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// Encodes one line of CSV as described in RFC 4180.
+/// Cells that spreadsheet applications would interpret as formula are prefixed with a quote,
+/// because the content of reports and mails is controlled by third parties.
+fn csv_line(cells: &[String]) -> String {
+    let mut line = cells
+        .iter()
+        .map(|cell| {
+            let cell = if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+                format!("'{cell}")
+            } else {
+                cell.clone()
+            };
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+/// Creates a minimal Office Open XML workbook with a single sheet
+fn xlsx(columns: &[String], rows: &[Vec<String>]) -> Result<Vec<u8>> {
+    let mut sheet = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+        r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#
+    ));
+    for (index, row) in std::iter::once(columns)
+        .chain(rows.iter().map(|r| &r[..]))
+        .enumerate()
+    {
+        let number = index + 1;
+        sheet.push_str(&format!(r#"<row r="{number}">"#));
+        for (column, cell) in row.iter().enumerate() {
+            let reference = format!("{}{number}", column_name(column));
+            if index > 0 && is_number(cell) {
+                sheet.push_str(&format!(r#"<c r="{reference}"><v>{cell}</v></c>"#));
+            } else {
+                sheet.push_str(&format!(
+                    r#"<c r="{reference}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    xml_escape(cell)
+                ));
+            }
+        }
+        sheet.push_str("</row>");
+    }
+    sheet.push_str("</sheetData></worksheet>");
+
+    let files = [
+        (
+            "[Content_Types].xml",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+                r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+                r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+                r#"<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+                r#"<Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+                r#"</Types>"#
+            ),
+        ),
+        (
+            "_rels/.rels",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#,
+                r#"</Relationships>"#
+            ),
+        ),
+        (
+            "xl/workbook.xml",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+                r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">"#,
+                r#"<sheets><sheet name="Export" sheetId="1" r:id="rId1"/></sheets></workbook>"#
+            ),
+        ),
+        (
+            "xl/_rels/workbook.xml.rels",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>"#,
+                r#"</Relationships>"#
+            ),
+        ),
+        ("xl/worksheets/sheet1.xml", sheet.as_str()),
+    ];
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    for (path, content) in files {
+        zip.start_file(path, options)
+            .context("Failed to add file to XLSX")?;
+        zip.write_all(content.as_bytes())
+            .context("Failed to write file to XLSX")?;
+    }
+    let cursor = zip.finish().context("Failed to finish XLSX")?;
+    Ok(cursor.into_inner())
+}
+
+/// Converts a zero based column index to spreadsheet names like A, B, ..., Z, AA, AB
+fn column_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("Column names are ASCII")
+}
+
+/// Only plain integers are stored as numbers, things like IDs with leading zeros stay text
+fn is_number(cell: &str) -> bool {
+    !cell.is_empty()
+        && cell.len() < 16
+        && cell.chars().all(|c| c.is_ascii_digit())
+        && (cell == "0" || !cell.starts_with('0'))
+}
+
+/// Escapes text for XML and drops control characters that are not allowed in XML 1.0
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    fn cells(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn csv_escaping() {
+        assert_eq!(csv_line(&cells(&["a", "b"])), "a,b\r\n");
+        assert_eq!(
+            csv_line(&cells(&["a,b", "say \"hi\"", "line\nbreak"])),
+            "\"a,b\",\"say \"\"hi\"\"\",\"line\nbreak\"\r\n"
+        );
+        assert_eq!(
+            csv_line(&cells(&["=HYPERLINK(\"x\")", "@SUM(A1)", "ok"])),
+            "\"'=HYPERLINK(\"\"x\"\")\",'@SUM(A1),ok\r\n"
+        );
+    }
+
+    #[test]
+    fn column_names() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+
+    #[test]
+    fn xlsx_workbook() {
+        let data = xlsx(
+            &cells(&["name", "count"]),
+            &[cells(&["a<b", "42"]), cells(&["007", "x\u{1}y"])],
+        )
+        .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(archive.len(), 5);
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap()
+            .read_to_string(&mut sheet)
+            .unwrap();
+        assert!(sheet.contains(
+            r#"<c r="A2" t="inlineStr"><is><t xml:space="preserve">a&lt;b</t></is></c>"#
+        ));
+        assert!(sheet.contains(r#"<c r="B2"><v>42</v></c>"#));
+        assert!(sheet.contains(r#"<t xml:space="preserve">007</t>"#));
+        assert!(sheet.contains(r#"<t xml:space="preserve">xy</t>"#));
+    }
+}
@@ -1,20 +1,40 @@
-use crate::geolocate::Location;
+use crate::dns_checks::check_ip;
+use crate::enrichment::IpEnrichment;
+use crate::rdap::RdapClient;
 use crate::state::AppState;
+use crate::users::User;
 use crate::whois::WhoIsIp;
-use axum::Json;
+use crate::whois_parser::WhoisRecord;
 use axum::extract::Path;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::http::StatusCode;
-use axum::http::header;
+use axum::http::header::{self, HeaderName};
 use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// Response for IPs that are not part of any report visible to the user
+fn unknown_ip() -> (StatusCode, [(HeaderName, &'static str); 1], String) {
+    (
+        StatusCode::NOT_FOUND,
+        [(header::CONTENT_TYPE, "text/plain")],
+        String::from("Cannot find IP"),
+    )
+}
+
 pub async fn dns_single_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(ip): Path<IpAddr>,
 ) -> impl IntoResponse {
+    if !state.lock().await.is_ip_visible(&ip, &user.scope) {
+        return unknown_ip();
+    }
+
     // First get DNS client from state and then send a new query...
     let dns_client = {
         let locked = state.lock().await;
@@ -48,6 +68,7 @@ pub async fn dns_single_handler(
 
 pub async fn dns_batch_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Json(ips): Json<Vec<IpAddr>>,
 ) -> impl IntoResponse {
     // Check number of IPs
@@ -60,17 +81,28 @@ pub async fn dns_batch_handler(
         );
     }
 
-    // Get DNS client from state
-    let dns_client = {
+    // Get DNS client and the IPs the user can see from state
+    let (dns_client, visible) = {
         let locked = state.lock().await;
-        locked.dns_client.clone()
+        let visible_ips = locked.visible_ips(&user.scope);
+        let visible: Vec<bool> = ips
+            .iter()
+            .map(|ip| user.scope.is_all() || visible_ips.contains(ip))
+            .collect();
+        (locked.dns_client.clone(), visible)
     };
 
-    // Spawn tasks for all requests
+    // Spawn tasks for all requests, IPs that are not visible result in None
     let mut handles = Vec::with_capacity(ips.len());
-    for ip in ips {
+    for (ip, visible) in ips.into_iter().zip(visible) {
         let dns_client = dns_client.clone();
-        let handle = tokio::spawn(async move { dns_client.host_from_ip(ip).await });
+        let handle = tokio::spawn(async move {
+            if visible {
+                dns_client.host_from_ip(ip).await
+            } else {
+                Ok(None)
+            }
+        });
         handles.push(handle);
     }
 
@@ -106,14 +138,72 @@ pub async fn dns_batch_handler(
     }
 }
 
+pub async fn dns_checks_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Path(ip): Path<IpAddr>,
+) -> impl IntoResponse {
+    // Check results from background enrichment first, unless DNSBL lookups failed
+    let (cached, dns_client, dnsbl_zones) = {
+        let app = state.lock().await;
+        if !app.is_ip_visible(&ip, &user.scope) {
+            return unknown_ip();
+        }
+        let cached = app
+            .enrichments
+            .get(&ip)
+            .and_then(|e| e.dns_checks.as_ref())
+            .map(|c| c.value.clone())
+            .filter(|c| c.failed_zones.is_empty());
+        (cached, app.dns_client.clone(), app.dnsbl_zones.clone())
+    };
+
+    let checks = if let Some(checks) = cached {
+        checks
+    } else {
+        let Ok(checks) = check_ip(&dns_client, ip, &dnsbl_zones).await else {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                String::from("DNS checks failed"),
+            );
+        };
+        checks
+    };
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&checks).expect("Failed to serialize JSON"),
+    )
+}
+
 pub async fn to_location_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(ip): Path<IpAddr>,
 ) -> impl IntoResponse {
-    // Check cache
-    let cached = {
+    // Check caches and get location provider
+    let (cached, provider) = {
         let app = state.lock().await;
-        app.ip_location_cache.get(&ip).cloned()
+        if !app.is_ip_visible(&ip, &user.scope) {
+            return unknown_ip();
+        }
+        let cached = app.ip_location_cache.get(&ip).cloned().or_else(|| {
+            app.enrichments
+                .get(&ip)
+                .and_then(|e| e.location.as_ref())
+                .and_then(|l| l.value.clone())
+        });
+        (cached, app.location_provider.clone())
+    };
+
+    let Some(provider) = provider else {
+        return (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            String::from("Location lookups are disabled"),
+        );
     };
 
     let result = if let Some(location) = cached {
@@ -121,7 +211,7 @@ pub async fn to_location_handler(
         Some(location)
     } else {
         // Nothing in cache, send new request!
-        let Ok(result) = Location::from_ip(&ip).await else {
+        let Ok(result) = provider.locate(&ip).await else {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 [(header::CONTENT_TYPE, "text/plain")],
@@ -133,6 +223,7 @@ pub async fn to_location_handler(
         if let Some(location) = result {
             let mut app = state.lock().await;
             app.ip_location_cache.insert(ip, location.clone());
+            app.locations_version += 1;
             Some(location)
         } else {
             None
@@ -154,19 +245,180 @@ pub async fn to_location_handler(
     )
 }
 
-pub async fn to_whois_handler(Path(ip): Path<IpAddr>) -> impl IntoResponse {
-    let whois = WhoIsIp::default();
-    let Ok(whois) = whois.lookup(&ip).await else {
+/// Response formats of the whois endpoint
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum WhoisFormat {
+    /// Raw whois text that can be shown directly in the browser
+    #[default]
+    Text,
+    /// Raw whois text with the normalized fields as JSON
+    Json,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WhoisParams {
+    format: Option<WhoisFormat>,
+}
+
+/// Raw whois text with the normalized fields parsed from it
+#[derive(Serialize)]
+struct WhoisResponse {
+    raw: String,
+    parsed: WhoisRecord,
+}
+
+pub async fn to_whois_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Path(ip): Path<IpAddr>,
+    Query(params): Query<WhoisParams>,
+) -> impl IntoResponse {
+    // Check results from background enrichment first
+    let cached = {
+        let app = state.lock().await;
+        if !app.is_ip_visible(&ip, &user.scope) {
+            return unknown_ip();
+        }
+        app.enrichments
+            .get(&ip)
+            .and_then(|e| e.whois.as_ref())
+            .map(|w| w.value.clone())
+    };
+
+    let raw = if let Some(whois) = cached {
+        whois
+    } else {
+        let whois = WhoIsIp::default();
+        let Ok(whois) = whois.lookup(&ip).await else {
+            return (
+                StatusCode::NOT_FOUND,
+                [(header::CONTENT_TYPE, "text/plain")],
+                String::from("Failed to look up IP"),
+            );
+        };
+        whois
+    };
+
+    if params.format.unwrap_or_default() == WhoisFormat::Text {
+        return (StatusCode::OK, [(header::CONTENT_TYPE, "text/plain")], raw);
+    }
+    let response = WhoisResponse {
+        parsed: WhoisRecord::parse(&raw),
+        raw,
+    };
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&response).expect("Failed to serialize JSON"),
+    )
+}
+
+pub async fn to_rdap_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Path(ip): Path<IpAddr>,
+) -> impl IntoResponse {
+    // Check results from background enrichment first
+    let cached = {
+        let app = state.lock().await;
+        if !app.is_ip_visible(&ip, &user.scope) {
+            return unknown_ip();
+        }
+        app.enrichments
+            .get(&ip)
+            .and_then(|e| e.rdap.as_ref())
+            .map(|r| r.value.clone())
+    };
+
+    let network = if let Some(network) = cached {
+        network
+    } else {
+        let rdap = RdapClient::default();
+        let Ok(network) = rdap.lookup(&ip).await else {
+            return (
+                StatusCode::NOT_FOUND,
+                [(header::CONTENT_TYPE, "text/plain")],
+                String::from("Failed to look up IP"),
+            );
+        };
+        network
+    };
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&network).expect("Failed to serialize JSON"),
+    )
+}
+
+pub async fn enrichment_single_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Path(ip): Path<IpAddr>,
+) -> impl IntoResponse {
+    let enrichment = {
+        let app = state.lock().await;
+        if !app.is_ip_visible(&ip, &user.scope) {
+            return unknown_ip();
+        }
+        app.enrichments.get(&ip).cloned()
+    };
+
+    let Some(enrichment) = enrichment else {
         return (
             StatusCode::NOT_FOUND,
             [(header::CONTENT_TYPE, "text/plain")],
-            String::from("Failed to look up IP"),
+            String::from("No enrichment data found"),
         );
     };
 
     (
         StatusCode::OK,
-        [(header::CONTENT_TYPE, "text/plain")],
-        whois,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&enrichment).expect("Failed to serialize JSON"),
     )
 }
+
+pub async fn enrichment_batch_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Json(ips): Json<Vec<IpAddr>>,
+) -> impl IntoResponse {
+    // Check number of IPs
+    const MAX_IP_COUNT: usize = 100;
+    if ips.len() > MAX_IP_COUNT {
+        return (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("Requests can only contain up to {MAX_IP_COUNT} IPs"),
+        );
+    }
+
+    // Results are in the same order as the IPs, unknown and not visible IPs will be null
+    let results: Vec<Option<IpEnrichment>> = {
+        let app = state.lock().await;
+        let visible = app.visible_ips(&user.scope);
+        ips.iter()
+            .map(|ip| {
+                let visible = user.scope.is_all() || visible.contains(ip);
+                app.enrichments.get(ip).filter(|_| visible).cloned()
+            })
+            .collect()
+    };
+
+    // Serialize results to JSON
+    if let Ok(json) = serde_json::to_string_pretty(&results) {
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            json,
+        )
+    } else {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, "text/plain")],
+            String::from("Unable to serialize result"),
+        )
+    }
+}
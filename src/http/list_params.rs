@@ -0,0 +1,187 @@
+use anyhow::{Context, Result, ensure};
+use axum::http::header::{self, HeaderName};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+/// Header with the number of items on all pages
+pub const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Pagination, sorting and time range parameters shared by all list endpoints
+#[derive(Deserialize, Debug, Default)]
+pub struct ListParams {
+    /// Maximum number of items to return, None means all items
+    limit: Option<usize>,
+
+    /// Number of items to skip
+    offset: Option<usize>,
+
+    /// Field to sort by, prefixed with `-` for descending order
+    sort: Option<String>,
+
+    /// Start of the time range as Unix timestamp, RFC 3339 date time or `YYYY-MM-DD` date
+    from: Option<String>,
+
+    /// End of the time range as Unix timestamp, RFC 3339 date time or `YYYY-MM-DD` date
+    to: Option<String>,
+}
+
+/// Time range with inclusive bounds as Unix timestamps
+#[derive(Debug, Default, PartialEq)]
+pub struct TimeRange {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+impl TimeRange {
+    /// Checks if the range overlaps with the time span of an item, like the date range of a report
+    pub fn overlaps(&self, begin: i64, end: i64) -> bool {
+        self.from.is_none_or(|from| end >= from) && self.to.is_none_or(|to| begin <= to)
+    }
+
+    /// Checks if the point in time is inside the range
+    pub fn contains(&self, time: i64) -> bool {
+        self.overlaps(time, time)
+    }
+}
+
+impl ListParams {
+    pub fn time_range(&self) -> Result<TimeRange> {
+        let from = self
+            .from
+            .as_deref()
+            .map(|f| parse_time(f, false).context("Invalid from parameter"))
+            .transpose()?;
+        let to = self
+            .to
+            .as_deref()
+            .map(|t| parse_time(t, true).context("Invalid to parameter"))
+            .transpose()?;
+        Ok(TimeRange { from, to })
+    }
+
+    /// Returns the requested sort field and if the order is descending.
+    /// The default uses the same syntax as the parameter, like `-date`.
+    pub fn sort<'a>(&'a self, fields: &[&str], default: &'a str) -> Result<(&'a str, bool)> {
+        let sort = self.sort.as_deref().unwrap_or(default);
+        let (field, descending) = match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort, false),
+        };
+        ensure!(
+            fields.contains(&field),
+            "Invalid sort field {field}, expected one of {}",
+            fields.join(", ")
+        );
+        Ok((field, descending))
+    }
+
+    /// Returns the requested page of the sorted items
+    pub fn page<T>(&self, items: Vec<T>) -> Vec<T> {
+        let items = items.into_iter().skip(self.offset.unwrap_or(0));
+        match self.limit {
+            Some(limit) => items.take(limit).collect(),
+            None => items.collect(),
+        }
+    }
+}
+
+/// Parses Unix timestamps, RFC 3339 date times and dates.
+/// Dates are interpreted as the start or end of the day in UTC.
+fn parse_time(value: &str, end_of_day: bool) -> Result<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date_time.timestamp());
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .context("Expected Unix timestamp, RFC 3339 date time or YYYY-MM-DD date")?;
+    let time = if end_of_day {
+        date.and_hms_opt(23, 59, 59)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    };
+    Ok(time.context("Invalid time")?.and_utc().timestamp())
+}
+
+/// JSON response with one page of items and the total number of items in a header
+pub fn list_response<T: Serialize>(items: &[T], total: usize) -> Response {
+    let json = serde_json::to_string(items).expect("Failed to serialize JSON");
+    (
+        StatusCode::OK,
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+            (TOTAL_COUNT_HEADER, HeaderValue::from(total)),
+        ],
+        json,
+    )
+        .into_response()
+}
+
+/// Plain text response for invalid list parameters
+pub fn bad_request(err: anyhow::Error) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        [(header::CONTENT_TYPE, "text/plain")],
+        format!("{err:#}"),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        assert_eq!(parse_time("1700000000", false).unwrap(), 1700000000);
+        assert_eq!(
+            parse_time("2024-01-02T03:04:05+01:00", false).unwrap(),
+            1704161045
+        );
+        assert_eq!(parse_time("2024-01-02", false).unwrap(), 1704153600);
+        assert_eq!(parse_time("2024-01-02", true).unwrap(), 1704239999);
+        assert!(parse_time("yesterday", false).is_err());
+    }
+
+    #[test]
+    fn time_ranges() {
+        let range = TimeRange {
+            from: Some(100),
+            to: Some(200),
+        };
+        assert!(range.overlaps(50, 100));
+        assert!(range.overlaps(150, 300));
+        assert!(!range.overlaps(201, 300));
+        assert!(!range.overlaps(0, 99));
+        assert!(range.contains(200));
+        assert!(!range.contains(201));
+        assert!(TimeRange::default().contains(0));
+    }
+
+    #[test]
+    fn sort_and_page() {
+        let params = ListParams {
+            limit: Some(2),
+            offset: Some(1),
+            sort: Some(String::from("-date")),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.sort(&["date", "size"], "size").unwrap(),
+            ("date", true)
+        );
+        assert!(params.sort(&["size"], "size").is_err());
+        assert_eq!(
+            ListParams::default().sort(&["date"], "-date").unwrap(),
+            ("date", true)
+        );
+        assert_eq!(params.page(vec![1, 2, 3, 4]), vec![2, 3]);
+        assert_eq!(ListParams::default().page(vec![1, 2]), vec![1, 2]);
+    }
+}
@@ -1,20 +1,30 @@
+use crate::http::export::{ExportParams, export_response, format_timestamp};
+use crate::http::list_params::{ListParams, bad_request, list_response};
 use crate::mail::Mail;
 use crate::state::AppState;
+use crate::users::{DomainScope, User};
+use anyhow::Result;
+use axum::Extension;
 use axum::extract::State;
 use axum::extract::{Path, Query};
 use axum::http::StatusCode;
 use axum::http::header;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 pub async fn single_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if let Some(mail) = lock.mails.get(&id) {
+    if let Some(mail) = lock
+        .mails
+        .get(&id)
+        .filter(|_| lock.is_mail_visible(&id, &user.scope))
+    {
         let mail_json = serde_json::to_string(mail).expect("Failed to serialize JSON");
         (
             StatusCode::OK,
@@ -32,10 +42,11 @@ pub async fn single_handler(
 
 pub async fn errors_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if !lock.mails.contains_key(&id) {
+    if !lock.is_mail_visible(&id, &user.scope) {
         return (
             StatusCode::NOT_FOUND,
             [(header::CONTENT_TYPE, "text/plain")],
@@ -81,15 +92,105 @@ impl MailFilters {
 
 pub async fn list_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     mut filters: Query<MailFilters>,
-) -> impl IntoResponse {
+    params: Query<ListParams>,
+) -> Response {
     // Remove URL encoding from strings in filters
     filters.url_decode();
 
     let lock = state.lock().await;
-    let mails: Vec<&Mail> = lock
-        .mails
-        .values()
+    let mails = match filtered_mails(&lock, &user.scope, &filters, &params) {
+        Ok(mails) => mails,
+        Err(err) => return bad_request(err),
+    };
+    let total = mails.len();
+    list_response(&params.page(mails), total)
+}
+
+pub async fn export_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    mut filters: Query<MailFilters>,
+    params: Query<ListParams>,
+    export: Query<ExportParams>,
+) -> Response {
+    // Remove URL encoding from strings in filters
+    filters.url_decode();
+
+    // Only copy the exported fields while locked, mails can contain large bodies
+    let rows: Vec<Vec<String>> = {
+        let lock = state.lock().await;
+        let mails = match filtered_mails(&lock, &user.scope, &filters, &params) {
+            Ok(mails) => mails,
+            Err(err) => return bad_request(err),
+        };
+        params
+            .page(mails)
+            .into_iter()
+            .map(|m| {
+                vec![
+                    m.id.clone(),
+                    m.account.clone(),
+                    m.folder.clone(),
+                    m.uid.to_string(),
+                    format_timestamp(m.date),
+                    m.sender.clone(),
+                    m.to.clone(),
+                    m.subject.clone(),
+                    m.size.to_string(),
+                    m.oversized.to_string(),
+                    m.xml_files.to_string(),
+                    m.json_files.to_string(),
+                    m.xml_parsing_errors.to_string(),
+                    m.json_parsing_errors.to_string(),
+                    (m.dmarc_duplicates.len() + m.tls_duplicates.len()).to_string(),
+                ]
+            })
+            .collect()
+    };
+
+    let columns = [
+        "id",
+        "account",
+        "folder",
+        "uid",
+        "date",
+        "sender",
+        "to",
+        "subject",
+        "size",
+        "oversized",
+        "xml_files",
+        "json_files",
+        "xml_parsing_errors",
+        "json_parsing_errors",
+        "duplicates",
+    ];
+    export_response(
+        &export,
+        "mails",
+        columns.map(String::from).to_vec(),
+        rows,
+        |row| row,
+    )
+    .await
+}
+
+/// Filters and sorts the mails for the list and export endpoints
+fn filtered_mails<'a>(
+    app: &'a AppState,
+    scope: &'a DomainScope,
+    filters: &MailFilters,
+    params: &ListParams,
+) -> Result<Vec<&'a Mail>> {
+    let time_range = params.time_range()?;
+    let (sort_field, descending) = params.sort(&["date", "sender", "subject", "size"], "-date")?;
+
+    let mut mails: Vec<&Mail> = app
+        .scoped_mails(scope)
+        .map(|(_, m)| m)
+        .filter(|m| time_range.contains(m.date))
         .filter(|m| {
             if let Some(queried_sender) = &filters.sender {
                 m.sender.to_lowercase() == *queried_sender
@@ -130,10 +231,19 @@ pub async fn list_handler(
             }
         })
         .collect();
-    let mails_json = serde_json::to_string(&mails).expect("Failed to serialize JSON");
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/json")],
-        mails_json,
-    )
+
+    mails.sort_by(|a, b| {
+        let ordering = match sort_field {
+            "sender" => a.sender.cmp(&b.sender),
+            "subject" => a.subject.cmp(&b.subject),
+            "size" => a.size.cmp(&b.size),
+            _ => a.date.cmp(&b.date),
+        };
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(mails)
 }
@@ -1,4 +1,6 @@
 use crate::state::AppState;
+use crate::users::{DomainScope, User};
+use axum::Extension;
 use axum::extract::State;
 use axum::response::IntoResponse;
 use std::collections::BTreeMap;
@@ -6,14 +8,23 @@ use std::sync::Arc;
 use std::time::SystemTime;
 use tokio::sync::Mutex;
 
-pub async fn handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
     let lock = state.lock().await;
+    let scope = &user.scope;
 
-    let mails = lock.mails.len();
-    let xml_files = lock.xml_files;
-    let json_files = lock.json_files;
-    let dmarc_reports = lock.dmarc_reports.len();
-    let tls_reports = lock.tls_reports.len();
+    let mails = lock.scoped_mails(scope).count();
+    let dmarc_reports = lock.scoped_dmarc_reports(scope).count();
+    let tls_reports = lock.scoped_tls_reports(scope).count();
+
+    // Files that could not be parsed cannot be assigned to a domain
+    let (xml_files, json_files) = if scope.is_all() {
+        (lock.xml_files, lock.json_files)
+    } else {
+        (dmarc_reports, tls_reports)
+    };
     let start_time = lock.start_time;
     let uptime = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -23,8 +34,8 @@ pub async fn handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResp
     let last_update = lock.last_update;
     let last_update_duration = lock.last_update_duration;
 
-    let dmarc_domains = get_dmarc_per_domain(&lock);
-    let tls_domains = get_tls_per_domain(&lock);
+    let dmarc_domains = get_dmarc_per_domain(&lock, scope);
+    let tls_domains = get_tls_per_domain(&lock, scope);
 
     drop(lock);
 
@@ -46,9 +57,9 @@ pub async fn handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResp
     )
 }
 
-fn get_dmarc_per_domain(state: &AppState) -> BTreeMap<String, usize> {
+fn get_dmarc_per_domain(state: &AppState, scope: &DomainScope) -> BTreeMap<String, usize> {
     let mut result = BTreeMap::new();
-    for report in state.dmarc_reports.values() {
+    for (_, report) in state.scoped_dmarc_reports(scope) {
         let domain = report.report.policy_published.domain.clone();
         let value = result.entry(domain).or_insert(0);
         *value += 1;
@@ -56,10 +67,13 @@ fn get_dmarc_per_domain(state: &AppState) -> BTreeMap<String, usize> {
     result
 }
 
-fn get_tls_per_domain(state: &AppState) -> BTreeMap<String, usize> {
+fn get_tls_per_domain(state: &AppState, scope: &DomainScope) -> BTreeMap<String, usize> {
     let mut result = BTreeMap::new();
-    for report in state.tls_reports.values() {
+    for (_, report) in state.scoped_tls_reports(scope) {
         for p in &report.report.policies {
+            if !scope.allows(&p.policy.policy_domain) {
+                continue;
+            }
             let domain = p.policy.policy_domain.clone();
             let value = result.entry(domain).or_insert(0);
             *value += 1;
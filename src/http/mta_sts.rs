@@ -0,0 +1,102 @@
+use crate::mta_sts::{StsCheck, check_domain, is_valid_hostname};
+use crate::state::AppState;
+use crate::tls::PolicyType;
+use crate::users::{DomainScope, User};
+use axum::Extension;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header;
+use axum::response::IntoResponse;
+use futures::stream::{self, StreamExt};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Maximum number of domains that are checked at the same time
+const MAX_CONCURRENT_CHECKS: usize = 8;
+
+/// Collects all own domains in the scope from the DMARC and SMTP TLS reports
+/// together with the MX patterns reporters applied for their STS policies
+fn domains_with_reported_mx(app: &AppState, scope: &DomainScope) -> BTreeMap<String, Vec<String>> {
+    let mut domains: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (_, rwi) in app.scoped_dmarc_reports(scope) {
+        let domain = rwi.report.policy_published.domain.to_ascii_lowercase();
+        domains.entry(domain).or_default();
+    }
+    for (_, rwi) in app.scoped_tls_reports(scope) {
+        for policy in &rwi.report.policies {
+            if !scope.allows(&policy.policy.policy_domain) {
+                continue;
+            }
+            let domain = policy.policy.policy_domain.to_ascii_lowercase();
+            let entry = domains.entry(domain).or_default();
+            if policy.policy.policy_type == PolicyType::Sts {
+                entry.extend(policy.policy.mx_host.iter().flatten().cloned());
+            }
+        }
+    }
+    domains
+}
+
+pub async fn list_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    let (domains, dns_client, policies) = {
+        let app = state.lock().await;
+        (
+            domains_with_reported_mx(&app, &user.scope),
+            app.dns_client.clone(),
+            app.sts_policies.clone(),
+        )
+    };
+
+    let mut checks: Vec<StsCheck> = stream::iter(domains)
+        .map(|(domain, reported_mx)| {
+            let dns_client = dns_client.clone();
+            let policies = policies.clone();
+            async move { check_domain(&dns_client, &policies, &domain, &reported_mx).await }
+        })
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .collect()
+        .await;
+    checks.sort_by(|a, b| a.domain.cmp(&b.domain));
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&checks).expect("Failed to serialize JSON"),
+    )
+}
+
+pub async fn single_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Path(domain): Path<String>,
+) -> impl IntoResponse {
+    // Only check valid host names of domains from the reports in the scope of the user
+    let domain = domain.to_ascii_lowercase();
+    let (reported_mx, dns_client, policies) = {
+        let app = state.lock().await;
+        let reported_mx = domains_with_reported_mx(&app, &user.scope).remove(&domain);
+        (
+            reported_mx,
+            app.dns_client.clone(),
+            app.sts_policies.clone(),
+        )
+    };
+    let Some(reported_mx) = reported_mx.filter(|_| is_valid_hostname(&domain)) else {
+        return (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            String::from("Cannot find domain"),
+        );
+    };
+
+    let check = check_domain(&dns_client, &policies, &domain, &reported_mx).await;
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&check).expect("Failed to serialize JSON"),
+    )
+}
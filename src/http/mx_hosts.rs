@@ -0,0 +1,135 @@
+use crate::state::{AppState, TlsReportWithMailId};
+use crate::tls::FailureResultType;
+use crate::users::User;
+use axum::extract::{Query, State};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Deserialize, Debug)]
+pub struct MxHostFilters {
+    /// Number of hours from current time backwards to include.
+    /// None or a value of zero means the filter is disabled!
+    time_span: Option<u64>,
+
+    /// Policy domain to be filtered. Other domains will be ignored.
+    /// None means the filter is disabled!
+    domain: Option<String>,
+}
+
+/// Failures of a single receiving MX host aggregated over all SMTP TLS reports
+#[derive(Serialize, Default)]
+struct MxHost {
+    /// Host name of the MX or None if the reporters did not include it
+    hostname: Option<String>,
+
+    /// Policy domains the MX host was evaluated for
+    domains: BTreeSet<String>,
+
+    /// Receiving IPs the sending MTAs connected to
+    receiving_ips: BTreeSet<IpAddr>,
+
+    /// HELO or EHLO strings announced by the MX host
+    helos: BTreeSet<String>,
+
+    /// Number of failed sessions for this MX host
+    failed_sessions: usize,
+
+    /// Failed sessions per day, based on the start of the report date range
+    timeline: BTreeMap<String, usize>,
+
+    /// Failed sessions per result type
+    result_types: HashMap<FailureResultType, usize>,
+
+    /// Failed sessions per failure reason code
+    failure_reasons: BTreeMap<String, usize>,
+
+    /// Failed sessions per IP of the sending MTA
+    sending_mtas: BTreeMap<String, usize>,
+
+    /// End of the date range of the last report with failures for this MX host
+    last_failure: Option<DateTime<Utc>>,
+}
+
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    filters: Query<MxHostFilters>,
+) -> impl IntoResponse {
+    let threshold = filters
+        .time_span
+        .filter(|hours| *hours > 0)
+        .map(|hours| Utc::now() - Duration::hours(hours as i64));
+    let domain_filter = filters.domain.as_ref().map(|d| d.to_lowercase());
+
+    let guard = state.lock().await;
+    let mut hosts: BTreeMap<Option<String>, MxHost> = BTreeMap::new();
+    for (_, TlsReportWithMailId { report, .. }) in guard.scoped_tls_reports(&user.scope) {
+        if let Some(threshold) = threshold
+            && report.date_range.end_datetime < threshold
+        {
+            continue;
+        }
+        let day = report
+            .date_range
+            .start_datetime
+            .format("%Y-%m-%d")
+            .to_string();
+        for policy_result in &report.policies {
+            let domain = policy_result.policy.policy_domain.to_lowercase();
+            if !user.scope.allows(&domain) {
+                continue;
+            }
+            if let Some(df) = &domain_filter
+                && domain != *df
+            {
+                continue;
+            }
+            for details in policy_result.failure_details.iter().flatten() {
+                let hostname = details
+                    .receiving_mx_hostname
+                    .as_ref()
+                    .map(|h| h.trim_end_matches('.').to_lowercase());
+                let host = hosts.entry(hostname.clone()).or_insert_with(|| MxHost {
+                    hostname,
+                    ..Default::default()
+                });
+                let count = details.failed_session_count;
+                host.domains.insert(domain.clone());
+                host.receiving_ips.extend(details.receiving_ip);
+                host.helos.extend(details.receiving_mx_helo.clone());
+                host.failed_sessions += count;
+                *host.timeline.entry(day.clone()).or_insert(0) += count;
+                *host
+                    .result_types
+                    .entry(details.result_type.clone())
+                    .or_insert(0) += count;
+                let reason = details
+                    .failure_reason_code
+                    .clone()
+                    .unwrap_or_else(|| String::from("unknown"));
+                *host.failure_reasons.entry(reason).or_insert(0) += count;
+                let mta = details
+                    .sending_mta_ip
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_else(|| String::from("unknown"));
+                *host.sending_mtas.entry(mta).or_insert(0) += count;
+                let end = report.date_range.end_datetime;
+                if host.last_failure.is_none_or(|last| last < end) {
+                    host.last_failure = Some(end);
+                }
+            }
+        }
+    }
+
+    // Hosts with the most failures first
+    let mut hosts: Vec<MxHost> = hosts.into_values().collect();
+    hosts.sort_by_key(|host| Reverse(host.failed_sessions));
+    Json(hosts)
+}
@@ -0,0 +1,54 @@
+use crate::http::list_params::bad_request;
+use crate::state::AppState;
+use crate::users::User;
+use anyhow::anyhow;
+use axum::Extension;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Number of results per entity type if no limit was requested
+const DEFAULT_LIMIT: usize = 50;
+
+/// Maximum number of results per entity type
+const MAX_LIMIT: usize = 500;
+
+#[derive(Deserialize, Debug)]
+pub struct SearchParams {
+    /// Words that must all be found, or an IP or CIDR network
+    q: Option<String>,
+
+    /// Maximum number of results per entity type
+    limit: Option<usize>,
+}
+
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    params: Query<SearchParams>,
+) -> Response {
+    let query = params.q.as_deref().unwrap_or_default().trim();
+    if query.is_empty() {
+        return bad_request(anyhow!("Missing search query in parameter q"));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    // The index has its own lock, so the state is only locked to get it
+    let index = state.lock().await.search_index.clone();
+    let results =
+        index
+            .read()
+            .expect("Failed to lock search index")
+            .search(query, limit, &user.scope);
+    let json = serde_json::to_string(&results).expect("Failed to serialize JSON");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        json,
+    )
+        .into_response()
+}
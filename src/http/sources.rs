@@ -1,21 +1,28 @@
+use crate::asn_db::{AsnDatabase, parse_as_field};
 use crate::dmarc::DkimResultType;
 use crate::dmarc::DmarcResultType;
 use crate::dmarc::RecordType;
 use crate::dmarc::SpfResultType;
+use crate::dns_checks::DnsChecks;
+use crate::http::export::{ExportParams, export_response, serde_name};
+use crate::ip_net::IpNet;
 use crate::state::AppState;
 use crate::tls::FailureResultType;
-use axum::extract::State;
+use crate::users::{DomainScope, User};
+use axum::Extension;
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::http::header;
-use axum::response::IntoResponse;
-use serde::Serialize;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-#[derive(Serialize, PartialEq, Eq, Hash)]
+#[derive(Serialize, PartialEq, Eq, Hash, Clone, Copy)]
 enum Issue {
     // DMARC
     SpfPolicy,
@@ -37,7 +44,7 @@ enum Issue {
     StsWebpkiInvalid,
 }
 
-#[derive(Serialize, PartialEq, Eq, Hash)]
+#[derive(Serialize, PartialEq, Eq, Hash, Clone, Copy)]
 enum ReportType {
     Dmarc,
     Tls,
@@ -56,15 +63,175 @@ struct Source {
     ip: IpAddr,
     #[serde(flatten)]
     details: SourceDetails,
+    /// Verified host name and DNSBL listings from the background enrichment
+    dns_checks: Option<DnsChecks>,
 }
 
-pub async fn handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
+/// Aggregated sources from the same network or autonomous system
+#[derive(Serialize)]
+struct SourceGroup {
+    /// Network prefix, IP range or AS number identifying the group
+    key: String,
+    /// Name of the autonomous system, if known
+    name: Option<String>,
+    count: usize,
+    domains: BTreeSet<String>,
+    issues: HashSet<Issue>,
+    types: HashSet<ReportType>,
+    /// DNSBL zones that list any of the member IPs
+    listed_on: BTreeSet<String>,
+    /// Member IPs sorted descending by count
+    ips: Vec<IpAddr>,
+}
+
+/// Modes for aggregating the sources
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Grouping {
+    /// No aggregation, one entry per IP
+    #[default]
+    Ip,
+    /// Fixed size prefixes, /24 for IPv4 and /48 for IPv6
+    Prefix,
+    /// Announced prefixes from the IP to ASN database
+    Announced,
+    /// Autonomous systems from the IP to ASN database or the location cache
+    Asn,
+}
+
+#[derive(Deserialize)]
+pub struct SourceFilters {
+    group: Option<Grouping>,
+}
+
+/// Key used for all sources without known network information
+const UNKNOWN_GROUP: &str = "unknown";
+
+/// Sources of one IP each or aggregated groups, depending on the grouping
+enum SourceList {
+    Ips(Vec<Source>),
+    Groups(Vec<SourceGroup>),
+}
+
+pub async fn handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    filters: Query<SourceFilters>,
+) -> impl IntoResponse {
+    let grouping = filters.group.unwrap_or_default();
+    let json = match source_list(&state, &user.scope, grouping).await {
+        SourceList::Ips(sources) => serde_json::to_string(&sources),
+        SourceList::Groups(groups) => serde_json::to_string(&groups),
+    }
+    .expect("Failed to serialize sources as JSON");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        json,
+    )
+}
+
+pub async fn export_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    filters: Query<SourceFilters>,
+    export: Query<ExportParams>,
+) -> Response {
+    let grouping = filters.group.unwrap_or_default();
+    match source_list(&state, &user.scope, grouping).await {
+        SourceList::Ips(sources) => {
+            let columns = [
+                "ip",
+                "count",
+                "domain",
+                "types",
+                "issues",
+                "hostname",
+                "verified_hostname",
+                "listed_on",
+            ];
+            export_response(
+                &export,
+                "sources",
+                columns.map(String::from).to_vec(),
+                sources,
+                |source| {
+                    let checks = source.dns_checks.unwrap_or_default();
+                    vec![
+                        source.ip.to_string(),
+                        source.details.count.to_string(),
+                        source.details.domain,
+                        join_names(&source.details.types),
+                        join_names(&source.details.issues),
+                        checks.hostname.unwrap_or_default(),
+                        checks.verified_hostname.unwrap_or_default(),
+                        checks.listed_on.join(" "),
+                    ]
+                },
+            )
+            .await
+        }
+        SourceList::Groups(groups) => {
+            let columns = [
+                "key",
+                "name",
+                "count",
+                "domains",
+                "types",
+                "issues",
+                "listed_on",
+                "ips",
+            ];
+            export_response(
+                &export,
+                "sources",
+                columns.map(String::from).to_vec(),
+                groups,
+                |group| {
+                    vec![
+                        group.key,
+                        group.name.unwrap_or_default(),
+                        group.count.to_string(),
+                        group.domains.into_iter().collect::<Vec<_>>().join(" "),
+                        join_names(&group.types),
+                        join_names(&group.issues),
+                        group.listed_on.into_iter().collect::<Vec<_>>().join(" "),
+                        group
+                            .ips
+                            .iter()
+                            .map(|ip| ip.to_string())
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ]
+                },
+            )
+            .await
+        }
+    }
+}
+
+/// Sorted names of issues or report types, separated by spaces
+fn join_names<T: Serialize>(values: &HashSet<T>) -> String {
+    let mut names: Vec<String> = values.iter().map(serde_name).collect();
+    names.sort();
+    names.join(" ")
+}
+
+/// Collects the sources in the scope while locked and groups them after releasing the lock
+async fn source_list(
+    state: &Mutex<AppState>,
+    scope: &DomainScope,
+    grouping: Grouping,
+) -> SourceList {
     let mut ip_map = HashMap::new();
+    let asn_database;
+    let mut cached_as = HashMap::new();
+    let mut dns_checks = HashMap::new();
     {
         let locked_state = state.lock().await;
 
         // Get source IPs from DMARC reports
-        for report in locked_state.dmarc_reports.values() {
+        for (_, report) in locked_state.scoped_dmarc_reports(scope) {
             for record in &report.report.record {
                 // Get or create details for IP
                 let details = ip_map.entry(record.row.source_ip).or_insert(SourceDetails {
@@ -86,8 +253,11 @@ pub async fn handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResp
         }
 
         // get source IPs from TLS reports
-        for report in locked_state.tls_reports.values() {
+        for (_, report) in locked_state.scoped_tls_reports(scope) {
             for policy in &report.report.policies {
+                if !scope.allows(&policy.policy.policy_domain) {
+                    continue;
+                }
                 let Some(failures) = &policy.failure_details else {
                     continue;
                 };
@@ -131,22 +301,119 @@ pub async fn handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResp
                 }
             }
         }
+
+        // Get DNS checks of enriched IPs
+        for ip in ip_map.keys() {
+            if let Some(checks) = locked_state
+                .enrichments
+                .get(ip)
+                .and_then(|e| e.dns_checks.as_ref())
+            {
+                dns_checks.insert(*ip, checks.value.clone());
+            }
+        }
+
+        // Get network information needed for grouping
+        asn_database = locked_state.asn_database.clone();
+        if grouping == Grouping::Asn {
+            for ip in ip_map.keys() {
+                if let Some(location) = locked_state.ip_location_cache.get(ip) {
+                    cached_as.insert(*ip, location.autonomous_system.clone());
+                }
+            }
+        }
     }
 
     let mut sources: Vec<Source> = ip_map
         .into_iter()
-        .map(|(ip, details)| Source { ip, details })
+        .map(|(ip, details)| Source {
+            ip,
+            details,
+            dns_checks: dns_checks.remove(&ip),
+        })
         .collect();
 
     // Sort descending by count
     sources.sort_by_key(|s| std::cmp::Reverse(s.details.count));
 
-    let json = serde_json::to_string(&sources).expect("Failed to serialize sources as JSON");
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/json")],
-        json,
-    )
+    if grouping == Grouping::Ip {
+        SourceList::Ips(sources)
+    } else {
+        let groups = group_sources(sources, grouping, asn_database.as_deref(), &cached_as);
+        SourceList::Groups(groups)
+    }
+}
+
+/// Aggregates the sources by network prefix or AS.
+/// Expects sources sorted by count and keeps that order for the member IPs.
+fn group_sources(
+    sources: Vec<Source>,
+    grouping: Grouping,
+    asn_database: Option<&AsnDatabase>,
+    cached_as: &HashMap<IpAddr, String>,
+) -> Vec<SourceGroup> {
+    let mut groups: BTreeMap<String, SourceGroup> = BTreeMap::new();
+    for source in sources {
+        let (key, name) = group_key(&source.ip, grouping, asn_database, cached_as);
+        let group = groups.entry(key.clone()).or_insert(SourceGroup {
+            key,
+            name,
+            count: 0,
+            domains: BTreeSet::new(),
+            issues: HashSet::new(),
+            types: HashSet::new(),
+            listed_on: BTreeSet::new(),
+            ips: Vec::new(),
+        });
+        group.count += source.details.count;
+        group.domains.insert(source.details.domain);
+        group.issues.extend(source.details.issues);
+        group.types.extend(source.details.types);
+        if let Some(checks) = source.dns_checks {
+            group.listed_on.extend(checks.listed_on);
+        }
+        group.ips.push(source.ip);
+    }
+
+    // Sort descending by count
+    let mut groups: Vec<SourceGroup> = groups.into_values().collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.count));
+    groups
+}
+
+/// Returns the key and optional name of the group for an IP
+fn group_key(
+    ip: &IpAddr,
+    grouping: Grouping,
+    asn_database: Option<&AsnDatabase>,
+    cached_as: &HashMap<IpAddr, String>,
+) -> (String, Option<String>) {
+    let entry = asn_database.and_then(|db| db.lookup(ip));
+    match grouping {
+        Grouping::Ip => (ip.to_string(), None),
+        Grouping::Prefix => {
+            let prefix_len = if ip.is_ipv4() { 24 } else { 48 };
+            let net = IpNet::new(*ip, prefix_len).expect("Prefix length should be valid");
+            (net.to_string(), None)
+        }
+        Grouping::Announced => {
+            if let Some(entry) = entry {
+                let name = format!("AS{} {}", entry.asn, entry.description);
+                (entry.range_label(), Some(name))
+            } else {
+                (String::from(UNKNOWN_GROUP), None)
+            }
+        }
+        Grouping::Asn => {
+            if let Some(entry) = entry {
+                (format!("AS{}", entry.asn), Some(entry.description.clone()))
+            } else if let Some((asn, name)) = cached_as.get(ip).and_then(|f| parse_as_field(f)) {
+                (format!("AS{asn}"), Some(name))
+            } else {
+                (String::from(UNKNOWN_GROUP), None)
+            }
+        }
+    }
 }
 
 fn detect_dmarc_issues(record: &RecordType, issues: &mut HashSet<Issue>) {
@@ -1,40 +1,69 @@
+use crate::hasher::create_hash;
+use crate::http::base_path::{self, BasePath};
+use crate::http::caching::{self, IMMUTABLE, REVALIDATE};
+use axum::Extension;
 use axum::extract::Request;
 use axum::http::StatusCode;
 use axum::http::header;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
+use std::borrow::Cow;
 
-pub async fn handler(req: Request) -> impl IntoResponse {
+pub async fn handler(Extension(base): Extension<BasePath>, req: Request) -> Response {
     let path = req.uri().path();
     for sf in STATIC_FILES {
         if sf.http_path == path {
             let mime_type = MimeType::from_path(sf.file_path);
-            return (
-                StatusCode::OK,
-                [(header::CONTENT_TYPE, mime_type)],
-                #[cfg(debug_assertions)]
-                {
-                    // During debug builds we first try to load the file from the checkout folder.
-                    // If that does not work, we fall back to the embeddef file from the binary.
+            #[cfg(debug_assertions)]
+            let data = {
+                // During debug builds we first try to load the file from the checkout folder.
+                // If that does not work, we fall back to the embeddef file from the binary.
+                Cow::Owned(
                     tokio::fs::read(sf.file_path)
                         .await
-                        .unwrap_or(sf.data.to_vec())
-                },
-                #[cfg(not(debug_assertions))]
-                {
-                    // During release builds we always use the files embedded into the binary!
-                    sf.data
-                },
-            );
+                        .unwrap_or(sf.data.to_vec()),
+                )
+            };
+            #[cfg(not(debug_assertions))]
+            let data = {
+                // During release builds we always use the files embedded into the binary!
+                Cow::Borrowed(sf.data)
+            };
+            let data = if sf.http_path == "/" && !base.is_root() {
+                Cow::Owned(base_path::add_base_element(&data, &base))
+            } else {
+                data
+            };
+            if sf.immutable {
+                return (
+                    [
+                        (header::CONTENT_TYPE, mime_type),
+                        (header::CACHE_CONTROL, IMMUTABLE),
+                    ],
+                    data,
+                )
+                    .into_response();
+            }
+            let etag = format!("\"{}\"", create_hash(&[&data]));
+            if caching::if_none_match(req.headers(), &etag) {
+                return caching::not_modified(&etag, REVALIDATE);
+            }
+            return (
+                [
+                    (header::CONTENT_TYPE, mime_type),
+                    (header::CACHE_CONTROL, REVALIDATE),
+                    (header::ETAG, etag.as_str()),
+                ],
+                data,
+            )
+                .into_response();
         }
     }
     (
         StatusCode::NOT_FOUND,
         [(header::CONTENT_TYPE, "text/plain")],
-        #[cfg(debug_assertions)]
-        b"File not found".to_vec(),
-        #[cfg(not(debug_assertions))]
-        b"File not found",
+        "File not found",
     )
+        .into_response()
 }
 
 const STATIC_FILES: &[StaticFile] = &[
@@ -42,91 +71,115 @@ const STATIC_FILES: &[StaticFile] = &[
         http_path: "/",
         file_path: "ui/index.html",
         data: include_bytes!("../../ui/index.html"),
+        immutable: false,
     },
     StaticFile {
-        http_path: "/chart.js",
+        http_path: "/chart.umd.4.5.0.min.js",
         file_path: "ui/chart.umd.4.5.0.min.js",
         data: include_bytes!("../../ui/chart.umd.4.5.0.min.js"),
+        immutable: true,
     },
     StaticFile {
-        http_path: "/lit.js",
+        http_path: "/lit-core.3.3.0.min.js",
         file_path: "ui/lit-core.3.3.0.min.js",
         data: include_bytes!("../../ui/lit-core.3.3.0.min.js"),
+        immutable: true,
     },
     StaticFile {
         http_path: "/style.js",
         file_path: "ui/style.js",
         data: include_bytes!("../../ui/style.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/utils.js",
         file_path: "ui/utils.js",
         data: include_bytes!("../../ui/utils.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/app.js",
         file_path: "ui/components/app.js",
         data: include_bytes!("../../ui/components/app.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/dashboard.js",
         file_path: "ui/components/dashboard.js",
         data: include_bytes!("../../ui/components/dashboard.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/mail-table.js",
         file_path: "ui/components/mail-table.js",
         data: include_bytes!("../../ui/components/mail-table.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/dmarc-report.js",
         file_path: "ui/components/dmarc-report.js",
         data: include_bytes!("../../ui/components/dmarc-report.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/tls-report.js",
         file_path: "ui/components/tls-report.js",
         data: include_bytes!("../../ui/components/tls-report.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/dmarc-reports.js",
         file_path: "ui/components/dmarc-reports.js",
         data: include_bytes!("../../ui/components/dmarc-reports.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/tls-reports.js",
         file_path: "ui/components/tls-reports.js",
         data: include_bytes!("../../ui/components/tls-reports.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/mails.js",
         file_path: "ui/components/mails.js",
         data: include_bytes!("../../ui/components/mails.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/mail.js",
         file_path: "ui/components/mail.js",
         data: include_bytes!("../../ui/components/mail.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/sources.js",
         file_path: "ui/components/sources.js",
         data: include_bytes!("../../ui/components/sources.js"),
+        immutable: false,
+    },
+    StaticFile {
+        http_path: "/components/mta-sts.js",
+        file_path: "ui/components/mta-sts.js",
+        data: include_bytes!("../../ui/components/mta-sts.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/about.js",
         file_path: "ui/components/about.js",
         data: include_bytes!("../../ui/components/about.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/dmarc-report-table.js",
         file_path: "ui/components/dmarc-report-table.js",
         data: include_bytes!("../../ui/components/dmarc-report-table.js"),
+        immutable: false,
     },
     StaticFile {
         http_path: "/components/tls-report-table.js",
         file_path: "ui/components/tls-report-table.js",
         data: include_bytes!("../../ui/components/tls-report-table.js"),
+        immutable: false,
     },
 ];
 
@@ -165,4 +218,7 @@ struct StaticFile {
     http_path: &'static str,
     file_path: &'static str,
     data: &'static [u8],
+
+    /// Versioned files can be cached forever, because a new version gets a new URL
+    immutable: bool,
 }
@@ -1,12 +1,13 @@
 use crate::dmarc::{DkimResultType, DmarcResultType, SpfResultType};
 use crate::state::{AppState, DmarcReportWithMailId, TlsReportWithMailId};
 use crate::tls::{FailureResultType, PolicyType, TlsResultType};
-use axum::Json;
+use crate::users::{DomainScope, User};
 use axum::extract::{Query, State};
 use axum::response::IntoResponse;
+use axum::{Extension, Json};
 use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -34,6 +35,7 @@ impl SummaryFilters {
 
 pub async fn handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     mut filters: Query<SummaryFilters>,
 ) -> impl IntoResponse {
     filters.url_decode();
@@ -44,16 +46,34 @@ pub async fn handler(
     {
         time_span = Some(Duration::hours(hours as i64));
     }
-    let summary = Summary::new(
-        guard.mails.len(),
+    let reports = Reports {
+        dmarc: guard
+            .scoped_dmarc_reports(&user.scope)
+            .map(|(_, rwi)| rwi)
+            .collect(),
+        tls: guard
+            .scoped_tls_reports(&user.scope)
+            .map(|(_, rwi)| rwi)
+            .collect(),
+    };
+
+    // Files that could not be parsed cannot be assigned to a domain
+    let files = if user.scope.is_all() {
         Files {
             xml: guard.xml_files,
             json: guard.json_files,
-        },
-        Reports {
-            dmarc: &guard.dmarc_reports,
-            tls: &guard.tls_reports,
-        },
+        }
+    } else {
+        Files {
+            xml: reports.dmarc.len(),
+            json: reports.tls.len(),
+        }
+    };
+    let summary = Summary::new(
+        guard.scoped_mails(&user.scope).count(),
+        files,
+        reports,
+        &user.scope,
         guard.last_update,
         time_span,
         filters.domain.clone(),
@@ -127,11 +147,11 @@ pub struct Files {
 }
 
 pub struct Reports<'a> {
-    /// Parsed DMARC reports with mail UID and corresponding hash as key
-    pub dmarc: &'a BTreeMap<String, DmarcReportWithMailId>,
+    /// Parsed DMARC reports visible to the user
+    pub dmarc: Vec<&'a DmarcReportWithMailId>,
 
-    /// Parsed SMTP TLS reports with mail UID and corresponding hash as key
-    pub tls: &'a BTreeMap<String, TlsReportWithMailId>,
+    /// Parsed SMTP TLS reports visible to the user
+    pub tls: Vec<&'a TlsReportWithMailId>,
 }
 
 #[derive(Serialize, Default, Clone)]
@@ -154,6 +174,7 @@ impl Summary {
         mails: usize,
         files: Files,
         reports: Reports,
+        scope: &DomainScope,
         last_update: u64,
         time_span: Option<Duration>,
         domain_filter: Option<String>,
@@ -173,7 +194,7 @@ impl Summary {
         let threshold = time_span.map(|d| (Utc::now() - d).timestamp() as u64);
         let threshold_datetime = time_span.map(|d| Utc::now() - d);
         let domain_filter = domain_filter.map(|d| d.to_lowercase());
-        for DmarcReportWithMailId { report, .. } in reports.dmarc.values() {
+        for DmarcReportWithMailId { report, .. } in reports.dmarc {
             if let Some(threshold) = threshold
                 && report.report_metadata.date_range.end < threshold
             {
@@ -209,7 +230,7 @@ impl Summary {
                 }
             }
         }
-        for TlsReportWithMailId { report, .. } in reports.tls.values() {
+        for TlsReportWithMailId { report, .. } in reports.tls {
             if let Some(threshold_datetime) = threshold_datetime
                 && report.date_range.end_datetime < threshold_datetime
             {
@@ -226,6 +247,10 @@ impl Summary {
             let org = report.organization_name.clone();
             *tls.orgs.entry(org).or_insert(0) += 1;
             for policy_result in report.policies.iter() {
+                // Reports can contain policies of domains that are not visible to the user
+                if !scope.allows(&policy_result.policy.policy_domain) {
+                    continue;
+                }
                 let domain = policy_result.policy.policy_domain.to_lowercase();
                 *tls.domains.entry(domain).or_insert(0) += 1;
                 *tls.policy_types
@@ -1,15 +1,22 @@
+use crate::http::export::{ExportParams, export_response, format_timestamp, serde_name};
+use crate::http::list_params::{ListParams, bad_request, list_response};
+use crate::policy_diff::{PolicyComparison, compare_policy};
 use crate::state::AppState;
+use crate::tls::Policy;
 use crate::tls::PolicyType;
 use crate::tls::Report;
-use axum::Json;
+use crate::users::{DomainScope, User};
+use anyhow::Result;
+use axum::Extension;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::http::header;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use chrono::DateTime;
 use chrono::Utc;
+use futures::future::join_all;
 use serde::Deserialize;
 use serde::Serialize;
 use std::net::IpAddr;
@@ -107,89 +114,262 @@ impl ReportFilters {
 
 pub async fn list_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     mut filters: Query<ReportFilters>,
-) -> impl IntoResponse {
+    params: Query<ListParams>,
+) -> Response {
+    // Remove URL encoding from strings in filters
+    filters.url_decode();
+
+    let reports = match filtered_reports(&*state.lock().await, &user.scope, &filters, &params) {
+        Ok(reports) => reports,
+        Err(err) => return bad_request(err),
+    };
+    let total = reports.len();
+    list_response(&params.page(reports), total)
+}
+
+/// Single failure details entry of a report, flattened for exports
+struct FailureRow {
+    report_hash: String,
+    report_id: String,
+    org: String,
+    date_begin: DateTime<Utc>,
+    date_end: DateTime<Utc>,
+    policy_domain: String,
+    policy_type: String,
+    result_type: String,
+    sending_mta_ip: Option<IpAddr>,
+    receiving_mx_hostname: Option<String>,
+    receiving_mx_helo: Option<String>,
+    receiving_ip: Option<IpAddr>,
+    failed_session_count: usize,
+    failure_reason_code: Option<String>,
+    additional_information: Option<String>,
+}
+
+pub async fn failures_export_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    mut filters: Query<ReportFilters>,
+    params: Query<ListParams>,
+    export: Query<ExportParams>,
+) -> Response {
     // Remove URL encoding from strings in filters
     filters.url_decode();
 
+    // Collect the failures of the selected reports while locked and convert them while streaming
+    let mut rows = Vec::new();
+    {
+        let app = state.lock().await;
+        let reports = match filtered_reports(&app, &user.scope, &filters, &params) {
+            Ok(reports) => reports,
+            Err(err) => return bad_request(err),
+        };
+        for header in params.page(reports) {
+            let Some(rwi) = app.tls_reports.get(&header.hash) else {
+                continue;
+            };
+            let report = &rwi.report;
+            for policy_result in &report.policies {
+                let policy = &policy_result.policy;
+                if !user.scope.allows(&policy.policy_domain) {
+                    continue;
+                }
+                if let Some(fd) = &filters.domain
+                    && policy.policy_domain.to_lowercase() != *fd
+                {
+                    continue;
+                }
+                for details in policy_result.failure_details.iter().flatten() {
+                    rows.push(FailureRow {
+                        report_hash: header.hash.clone(),
+                        report_id: report.report_id.clone(),
+                        org: report.organization_name.clone(),
+                        date_begin: report.date_range.start_datetime,
+                        date_end: report.date_range.end_datetime,
+                        policy_domain: policy.policy_domain.clone(),
+                        policy_type: serde_name(&policy.policy_type),
+                        result_type: serde_name(&details.result_type),
+                        sending_mta_ip: details.sending_mta_ip,
+                        receiving_mx_hostname: details.receiving_mx_hostname.clone(),
+                        receiving_mx_helo: details.receiving_mx_helo.clone(),
+                        receiving_ip: details.receiving_ip,
+                        failed_session_count: details.failed_session_count,
+                        failure_reason_code: details.failure_reason_code.clone(),
+                        additional_information: details.additional_information.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    let columns = [
+        "report_hash",
+        "report_id",
+        "org",
+        "date_begin",
+        "date_end",
+        "policy_domain",
+        "policy_type",
+        "result_type",
+        "sending_mta_ip",
+        "receiving_mx_hostname",
+        "receiving_mx_helo",
+        "receiving_ip",
+        "failed_session_count",
+        "failure_reason_code",
+        "additional_information",
+    ];
+    export_response(
+        &export,
+        "tls-failures",
+        columns.map(String::from).to_vec(),
+        rows,
+        |row| {
+            vec![
+                row.report_hash,
+                row.report_id,
+                row.org,
+                format_timestamp(row.date_begin.timestamp()),
+                format_timestamp(row.date_end.timestamp()),
+                row.policy_domain,
+                row.policy_type,
+                row.result_type,
+                row.sending_mta_ip
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_default(),
+                row.receiving_mx_hostname.unwrap_or_default(),
+                row.receiving_mx_helo.unwrap_or_default(),
+                row.receiving_ip
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_default(),
+                row.failed_session_count.to_string(),
+                row.failure_reason_code.unwrap_or_default(),
+                row.additional_information.unwrap_or_default(),
+            ]
+        },
+    )
+    .await
+}
+
+/// Filters and sorts the report headers for the list and export endpoints
+fn filtered_reports(
+    app: &AppState,
+    scope: &DomainScope,
+    filters: &ReportFilters,
+    params: &ListParams,
+) -> Result<Vec<ReportHeader>> {
+    let time_range = params.time_range()?;
+    let (sort_field, descending) = params.sort(
+        &["date_begin", "date_end", "org", "id", "records"],
+        "-date_begin",
+    )?;
+
     // Parse IP once to speed up filters
     let ip_filter = filters.ip.as_deref().and_then(|s| IpAddr::from_str(s).ok());
 
-    let reports: Vec<ReportHeader> = state
-        .lock()
-        .await
-        .tls_reports
-        .iter()
-        .filter(|(_, rwi)| {
-            if let Some(id) = &filters.id {
-                rwi.mail_id == *id
-            } else {
-                true
-            }
-        })
-        .filter(|(_, rwi)| {
-            if let Some(org) = &filters.org {
-                rwi.report.organization_name == *org
-            } else {
-                true
-            }
-        })
-        .filter(|(_, rwi)| {
-            if let Some(fd) = &filters.domain {
-                rwi.report
-                    .policies
-                    .iter()
-                    .any(|policy_result| policy_result.policy.policy_domain.to_lowercase() == *fd)
-            } else {
-                true
-            }
-        })
-        .filter(|(_, rwi)| {
-            if let Some(ip) = &ip_filter {
-                rwi.report.policies.iter().any(|p| {
-                    if let Some(failures) = &p.failure_details {
-                        failures.iter().any(|f| f.sending_mta_ip == Some(*ip))
-                    } else {
-                        false
-                    }
-                })
-            } else {
-                true
-            }
-        })
-        .map(|(hash, rwi)| ReportHeader::from_report(hash, &rwi.report))
-        .filter(|rh| {
-            if let Some(flagged) = &filters.flagged {
-                rh.flagged == *flagged
-            } else {
-                true
-            }
-        })
-        .filter(|rh| {
-            if let Some(sts) = &filters.flagged_sts {
-                rh.flagged_sts == *sts
-            } else {
-                true
-            }
-        })
-        .filter(|rh| {
-            if let Some(tlsa) = &filters.flagged_tlsa {
-                rh.flagged_tlsa == *tlsa
-            } else {
-                true
-            }
-        })
-        .collect();
-    Json(reports)
+    let mut reports: Vec<ReportHeader> =
+        app.scoped_tls_reports(scope)
+            .filter(|(_, rwi)| {
+                if let Some(id) = &filters.id {
+                    rwi.mail_id == *id
+                } else {
+                    true
+                }
+            })
+            .filter(|(_, rwi)| {
+                let range = &rwi.report.date_range;
+                time_range.overlaps(
+                    range.start_datetime.timestamp(),
+                    range.end_datetime.timestamp(),
+                )
+            })
+            .filter(|(_, rwi)| {
+                if let Some(org) = &filters.org {
+                    rwi.report.organization_name == *org
+                } else {
+                    true
+                }
+            })
+            // Policies of domains that are not visible must not match the filters
+            .map(|(hash, rwi)| (hash, rwi.scoped_report(scope)))
+            .filter(|(_, report)| {
+                if let Some(fd) = &filters.domain {
+                    report.policies.iter().any(|policy_result| {
+                        policy_result.policy.policy_domain.to_lowercase() == *fd
+                    })
+                } else {
+                    true
+                }
+            })
+            .filter(|(_, report)| {
+                if let Some(ip) = &ip_filter {
+                    report.policies.iter().any(|p| {
+                        if let Some(failures) = &p.failure_details {
+                            failures.iter().any(|f| f.sending_mta_ip == Some(*ip))
+                        } else {
+                            false
+                        }
+                    })
+                } else {
+                    true
+                }
+            })
+            .map(|(hash, report)| ReportHeader::from_report(hash, &report))
+            .filter(|rh| {
+                if let Some(flagged) = &filters.flagged {
+                    rh.flagged == *flagged
+                } else {
+                    true
+                }
+            })
+            .filter(|rh| {
+                if let Some(sts) = &filters.flagged_sts {
+                    rh.flagged_sts == *sts
+                } else {
+                    true
+                }
+            })
+            .filter(|rh| {
+                if let Some(tlsa) = &filters.flagged_tlsa {
+                    rh.flagged_tlsa == *tlsa
+                } else {
+                    true
+                }
+            })
+            .collect();
+
+    reports.sort_by(|a, b| {
+        let ordering = match sort_field {
+            "date_end" => a.date_end.cmp(&b.date_end),
+            "org" => a.org.cmp(&b.org),
+            "id" => a.id.cmp(&b.id),
+            "records" => a.records.cmp(&b.records),
+            _ => a.date_begin.cmp(&b.date_begin),
+        };
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(reports)
 }
 
 pub async fn single_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if let Some(rwi) = lock.tls_reports.get(&id) {
-        let report_json = serde_json::to_string(rwi).expect("Failed to serialize JSON");
+    if let Some(rwi) = lock
+        .tls_reports
+        .get(&id)
+        .filter(|rwi| rwi.is_visible(&user.scope))
+    {
+        let rwi = rwi.scoped(&user.scope);
+        let report_json = serde_json::to_string(&rwi).expect("Failed to serialize JSON");
         (
             StatusCode::OK,
             [(header::CONTENT_TYPE, "application/json")],
@@ -206,11 +386,17 @@ pub async fn single_handler(
 
 pub async fn json_handler(
     State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let lock = state.lock().await;
-    if let Some(rwi) = lock.tls_reports.get(&id) {
-        let report_json = serde_json::to_string(&rwi.report).expect("Failed to serialize JSON");
+    if let Some(rwi) = lock
+        .tls_reports
+        .get(&id)
+        .filter(|rwi| rwi.is_visible(&user.scope))
+    {
+        let report = rwi.scoped_report(&user.scope);
+        let report_json = serde_json::to_string(&report).expect("Failed to serialize JSON");
         (
             StatusCode::OK,
             [(header::CONTENT_TYPE, "application/json")],
@@ -224,3 +410,45 @@ pub async fn json_handler(
         )
     }
 }
+
+pub async fn policies_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let (policies, dns_client, sts_policies) = {
+        let lock = state.lock().await;
+        let Some(rwi) = lock
+            .tls_reports
+            .get(&id)
+            .filter(|rwi| rwi.is_visible(&user.scope))
+        else {
+            return (
+                StatusCode::NOT_FOUND,
+                [(header::CONTENT_TYPE, "text/plain")],
+                String::from("Cannot find report"),
+            );
+        };
+        let policies: Vec<Policy> = rwi
+            .report
+            .policies
+            .iter()
+            .filter(|p| user.scope.allows(&p.policy.policy_domain))
+            .map(|p| p.policy.clone())
+            .collect();
+        (policies, lock.dns_client.clone(), lock.sts_policies.clone())
+    };
+
+    // Comparisons are in the same order as the policies of the report
+    let comparisons: Vec<PolicyComparison> = join_all(
+        policies
+            .iter()
+            .map(|p| compare_policy(&dns_client, &sts_policies, p)),
+    )
+    .await;
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string_pretty(&comparisons).expect("Failed to serialize JSON"),
+    )
+}
@@ -0,0 +1,60 @@
+use crate::audit::AuditAction;
+use crate::http::Auth;
+use crate::http::client_ip::ClientIp;
+use crate::update_status::{UpdateStatus, UpdateTracker};
+use crate::users::{Role, User};
+use axum::Extension;
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use tracing::info;
+
+/// Requests an update that starts immediately or after the running update.
+/// Concurrent requests are coalesced into one update.
+pub async fn request_handler(
+    State(auth): State<Arc<Auth>>,
+    Extension(tracker): Extension<UpdateTracker>,
+    Extension(user): Extension<User>,
+    Extension(ClientIp(ip)): Extension<ClientIp>,
+) -> Response {
+    if user.role != Role::Admin {
+        return forbidden("Only admins can request updates");
+    }
+    let status = tracker.request();
+    info!("User {} requested an update", user.name);
+    auth.audit
+        .record(AuditAction::UpdateRequested, Some(&user.name), ip, None)
+        .await;
+    status_response(StatusCode::ACCEPTED, &status)
+}
+
+/// Status and progress of the running update and the error of the last failed update
+pub async fn status_handler(
+    Extension(tracker): Extension<UpdateTracker>,
+    Extension(user): Extension<User>,
+) -> Response {
+    if user.role != Role::Admin {
+        return forbidden("Only admins can read the update status");
+    }
+    status_response(StatusCode::OK, &tracker.status())
+}
+
+fn status_response(status_code: StatusCode, status: &UpdateStatus) -> Response {
+    let status_json = serde_json::to_string(status).expect("Failed to serialize JSON");
+    (
+        status_code,
+        [(header::CONTENT_TYPE, "application/json")],
+        status_json,
+    )
+        .into_response()
+}
+
+fn forbidden(message: &'static str) -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        [(header::CONTENT_TYPE, "text/plain")],
+        message,
+    )
+        .into_response()
+}
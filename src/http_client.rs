@@ -20,6 +20,18 @@ pub async fn http_request(
     url: &str,
     headers: &HashMap<String, String>,
     body: Vec<u8>,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+    http_request_with_tls_config(method, url, headers, body, None).await
+}
+
+/// Same as `http_request`, but HTTPS connections use the given TLS configuration,
+/// for example to trust the certificates of a custom CA
+pub async fn http_request_with_tls_config(
+    method: Method,
+    url: &str,
+    headers: &HashMap<String, String>,
+    body: Vec<u8>,
+    tls_config: Option<Arc<ClientConfig>>,
 ) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
     // Parse and check URI
     let uri = url.parse::<Uri>().context("Failed to parse URL")?;
@@ -40,7 +52,9 @@ pub async fn http_request(
     };
 
     // Open a TCP or TLS connection to the remote host
-    let stream = create_stream(&host, port, *scheme == Scheme::HTTPS)
+    let tls_config =
+        (*scheme == Scheme::HTTPS).then(|| tls_config.unwrap_or_else(default_tls_config));
+    let stream = create_stream(&host, port, tls_config)
         .await
         .context("Failed to create stream")?;
 
@@ -102,7 +116,7 @@ pub async fn http_request(
 async fn create_stream(
     host: &str,
     port: u16,
-    tls: bool,
+    tls_config: Option<Arc<ClientConfig>>,
 ) -> Result<Either<TcpStream, TlsStream<TcpStream>>> {
     // Open a TCP connection to the remote host
     let address = format!("{host}:{port}");
@@ -111,17 +125,12 @@ async fn create_stream(
         .context(format!("Failed to connect TCP stream at {address}"))?;
 
     // Early out in case of TCP without TLS
-    if !tls {
+    let Some(tls_config) = tls_config else {
         return Ok(Either::Left(tcp_stream));
-    }
+    };
 
     // Create a TLS stream for HTTPS
-    let cert_iter = webpki_roots::TLS_SERVER_ROOTS.iter().cloned();
-    let root_cert_store = RootCertStore::from_iter(cert_iter);
-    let client_config = ClientConfig::builder()
-        .with_root_certificates(root_cert_store)
-        .with_no_client_auth();
-    let connector = TlsConnector::from(Arc::new(client_config));
+    let connector = TlsConnector::from(tls_config);
     let dns_name =
         ServerName::try_from(host.to_string()).context("Failed to get DNS name from host")?;
     let tls_stream = connector
@@ -130,3 +139,13 @@ async fn create_stream(
         .context("Failed to create TLS stream")?;
     Ok(Either::Right(tls_stream))
 }
+
+/// TLS configuration trusting the usual public root certificates
+fn default_tls_config() -> Arc<ClientConfig> {
+    let cert_iter = webpki_roots::TLS_SERVER_ROOTS.iter().cloned();
+    let root_cert_store = RootCertStore::from_iter(cert_iter);
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(root_cert_store)
+        .with_no_client_auth();
+    Arc::new(client_config)
+}
@@ -1,6 +1,7 @@
 use crate::config::Configuration;
 use crate::hasher::create_hash;
 use crate::mail::{Mail, decode_subject};
+use crate::update_status::{UpdatePhase, UpdateTracker};
 use anyhow::{Context, Result, anyhow, ensure};
 use async_imap::Client;
 use async_imap::imap_proto::Address;
@@ -26,7 +27,11 @@ type Session = async_imap::Session<Either<TcpStream, TlsStream<TcpStream>>>;
 pub async fn get_mails(
     config: &Configuration,
     imap_folder: &String,
+    tracker: &UpdateTracker,
 ) -> Result<HashMap<String, Mail>> {
+    tracker.set_phase(UpdatePhase::ListingFolders {
+        folder: imap_folder.clone(),
+    });
     let client = create_client(config)
         .await
         .context("Failed to create IMAP client")?;
@@ -48,7 +53,7 @@ pub async fn get_mails(
 
     let mut mails = HashMap::new();
     for folder in &folders {
-        let folder_mails = get_mails_from_folder(&mut session, config, folder)
+        let folder_mails = get_mails_from_folder(&mut session, config, folder, tracker)
             .await
             .context(format!("Failed to get mails from IMAP folder {folder}"))?;
         mails.extend(folder_mails);
@@ -140,7 +145,11 @@ async fn get_mails_from_folder(
     session: &mut Session,
     config: &Configuration,
     imap_folder: &str,
+    tracker: &UpdateTracker,
 ) -> Result<HashMap<String, Mail>> {
+    tracker.set_phase(UpdatePhase::FetchingMetadata {
+        folder: imap_folder.to_string(),
+    });
     let mailbox = session
         .select(imap_folder)
         .await
@@ -173,6 +182,7 @@ async fn get_mails_from_folder(
             mails.insert(mail.id.clone(), mail);
         }
         info!("Downloaded metadata of {} mails", mails.len());
+        tracker.update_progress(|progress| progress.mails += mails.len());
 
         let no_size_mails = mails.values().filter(|m| m.size == 0).count();
         if no_size_mails > 0 {
@@ -203,8 +213,14 @@ async fn get_mails_from_folder(
             config.imap_chunk_size > 0,
             "IMAP Chunk size must be non-zero"
         );
-        for chunk in ids.chunks(config.imap_chunk_size) {
+        let chunks = ids.len().div_ceil(config.imap_chunk_size);
+        for (index, chunk) in ids.chunks(config.imap_chunk_size).enumerate() {
             debug!("Downloading chunk with {} mails...", chunk.len());
+            tracker.set_phase(UpdatePhase::FetchingChunk {
+                folder: imap_folder.to_string(),
+                chunk: index + 1,
+                chunks,
+            });
             let mut uid_id_map = HashMap::new();
             let uids: Vec<String> = chunk
                 .iter()
@@ -258,6 +274,7 @@ async fn get_mails_from_folder(
                     mail.size, mail.sender
                 );
             }
+            tracker.update_progress(|progress| progress.fetched_mails += fetched_mails);
             if fetched_mails != chunk.len() {
                 warn!(
                     "Unable to fetch some mails from chunk, expected {} mails but got {fetched_mails}. \
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result, ensure};
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// IPv4 or IPv6 network in CIDR notation, like `192.0.2.0/24` or `2001:db8::/48`.
+/// The address is always stored with all host bits set to zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Creates the network with the given prefix length that contains the IP
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max_len = max_prefix_len(&ip);
+        ensure!(
+            prefix_len <= max_len,
+            "Prefix length {prefix_len} is bigger than {max_len}"
+        );
+        let addr = match ip {
+            IpAddr::V4(v4) => {
+                let bits = u32::from(v4) & v4_mask(prefix_len);
+                IpAddr::V4(Ipv4Addr::from(bits))
+            }
+            IpAddr::V6(v6) => {
+                let bits = u128::from(v6) & v6_mask(prefix_len);
+                IpAddr::V6(Ipv6Addr::from(bits))
+            }
+        };
+        Ok(Self { addr, prefix_len })
+    }
+
+    /// Returns the smallest network that covers exactly the given IP range.
+    /// Returns None if the range cannot be expressed as a single network.
+    pub fn from_range(first: IpAddr, last: IpAddr) -> Option<Self> {
+        let (first, last, bits) = match (first, last) {
+            (IpAddr::V4(f), IpAddr::V4(l)) => (u32::from(f) as u128, u32::from(l) as u128, 32),
+            (IpAddr::V6(f), IpAddr::V6(l)) => (u128::from(f), u128::from(l), 128),
+            _ => return None,
+        };
+        // The differing bits must be a continuous block of host bits,
+        // which are all zero for the first and all one for the last IP.
+        let host_mask = first ^ last;
+        if host_mask & host_mask.wrapping_add(1) != 0 || first & host_mask != 0 {
+            return None;
+        }
+        let host_bits = 128 - host_mask.leading_zeros();
+        let prefix_len = (bits - host_bits) as u8;
+        let addr = if bits == 32 {
+            IpAddr::V4(Ipv4Addr::from(first as u32))
+        } else {
+            IpAddr::V6(Ipv6Addr::from(first))
+        };
+        Some(Self { addr, prefix_len })
+    }
+
+    /// Returns the number of network bits
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns the first IP of the network
+    pub fn first(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the last IP of the network
+    pub fn last(&self) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(net) => {
+                IpAddr::V4(Ipv4Addr::from(u32::from(net) | !v4_mask(self.prefix_len)))
+            }
+            IpAddr::V6(net) => {
+                IpAddr::V6(Ipv6Addr::from(u128::from(net) | !v6_mask(self.prefix_len)))
+            }
+        }
+    }
+
+    /// Checks if the IP is part of this network.
+    /// IPs of the other address family are never contained.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(*ip) & v4_mask(self.prefix_len) == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(*ip) & v6_mask(self.prefix_len) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = anyhow::Error;
+
+    /// Parses CIDR notation. A plain IP without suffix is treated as single host network.
+    fn from_str(value: &str) -> Result<Self> {
+        if let Some((addr, len)) = value.split_once('/') {
+            let addr = IpAddr::from_str(addr.trim()).context("Failed to parse IP address")?;
+            let len = u8::from_str(len.trim()).context("Failed to parse prefix length")?;
+            Self::new(addr, len)
+        } else {
+            let addr = IpAddr::from_str(value.trim()).context("Failed to parse IP address")?;
+            Self::new(addr, max_prefix_len(&addr))
+        }
+    }
+}
+
+fn max_prefix_len(ip: &IpAddr) -> u8 {
+    if ip.is_ipv4() { 32 } else { 128 }
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let net = IpNet::from_str("192.0.2.77/24").unwrap();
+        assert_eq!(net.to_string(), "192.0.2.0/24");
+
+        let net = IpNet::from_str("2001:db8:1234:5678::1/48").unwrap();
+        assert_eq!(net.to_string(), "2001:db8:1234::/48");
+
+        let net = IpNet::from_str("10.1.2.3").unwrap();
+        assert_eq!(net.to_string(), "10.1.2.3/32");
+
+        let net = IpNet::from_str("0.0.0.0/0").unwrap();
+        assert_eq!(net.to_string(), "0.0.0.0/0");
+
+        assert!(IpNet::from_str("10.0.0.0/33").is_err());
+        assert!(IpNet::from_str("::/129").is_err());
+        assert!(IpNet::from_str("foo/8").is_err());
+    }
+
+    #[test]
+    fn contains() {
+        let net = IpNet::from_str("192.0.2.0/24").unwrap();
+        assert!(net.contains(&IpAddr::from_str("192.0.2.1").unwrap()));
+        assert!(!net.contains(&IpAddr::from_str("192.0.3.1").unwrap()));
+        assert!(!net.contains(&IpAddr::from_str("::ffff:192.0.2.1").unwrap()));
+
+        let all = IpNet::from_str("::/0").unwrap();
+        assert!(all.contains(&IpAddr::from_str("2001:db8::1").unwrap()));
+    }
+
+    #[test]
+    fn first_and_last() {
+        let net = IpNet::from_str("192.0.2.77/24").unwrap();
+        assert_eq!(net.first().to_string(), "192.0.2.0");
+        assert_eq!(net.last().to_string(), "192.0.2.255");
+
+        let net = IpNet::from_str("2001:db8::/32").unwrap();
+        assert_eq!(
+            net.last().to_string(),
+            "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff"
+        );
+
+        let host = IpNet::from_str("10.1.2.3").unwrap();
+        assert_eq!(host.first(), host.last());
+    }
+
+    #[test]
+    fn from_range() {
+        let first = IpAddr::from_str("192.0.2.0").unwrap();
+        let last = IpAddr::from_str("192.0.2.255").unwrap();
+        assert_eq!(
+            IpNet::from_range(first, last).unwrap().to_string(),
+            "192.0.2.0/24"
+        );
+
+        let first = IpAddr::from_str("192.0.2.0").unwrap();
+        let last = IpAddr::from_str("192.0.3.127").unwrap();
+        assert!(IpNet::from_range(first, last).is_none());
+
+        let first = IpAddr::from_str("::").unwrap();
+        let last = IpAddr::from_str("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff").unwrap();
+        assert_eq!(IpNet::from_range(first, last).unwrap().to_string(), "::/0");
+
+        let single = IpAddr::from_str("2001:db8::1").unwrap();
+        assert_eq!(
+            IpNet::from_range(single, single).unwrap().to_string(),
+            "2001:db8::1/128"
+        );
+    }
+}
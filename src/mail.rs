@@ -5,7 +5,7 @@ use encoding_rs::Encoding;
 use regex::Regex;
 use serde::Serialize;
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 pub struct Mail {
     /// Unique ID as hash of UID + account + folder
     pub id: String,
@@ -1,35 +1,63 @@
 #![forbid(unsafe_code)]
 
+mod acme;
+mod api_tokens;
+mod asn_db;
+mod audit;
+mod auth_lockout;
 mod background;
 mod cache_map;
+mod cert_files;
 mod config;
 mod dmarc;
+mod dns_checks;
 mod dns_client;
 mod dns_client_cached;
+mod enrichment;
+mod events;
 mod geolocate;
+mod geolocate_mmdb;
 mod hasher;
 mod health_check;
 mod http;
 mod http_client;
 mod imap;
+mod ip_net;
 mod mail;
+mod mta_sts;
+mod oidc;
+mod policy_diff;
+mod rdap;
+mod search;
+mod session;
 mod state;
+mod table_query;
 mod tls;
+mod tlsa;
+mod token_bucket;
 mod unpack;
+mod update_status;
+mod users;
 mod web_hook;
 mod whois;
+mod whois_parser;
 
+use crate::asn_db::AsnDatabase;
 use crate::background::start_bg_task;
 use crate::dns_client::DnsClient;
+use crate::enrichment::{load_enrichments, start_enrichment_task};
+use crate::geolocate::{IpApiProvider, LocationProvider};
+use crate::geolocate_mmdb::MmdbProvider;
 use crate::health_check::run_health_check_if_requested;
 use crate::http::run_http_server;
 use crate::state::AppState;
+use crate::users::run_hash_password_if_requested;
 use anyhow::{Context, Result};
-use config::Configuration;
+use config::{Configuration, LocationProviderType};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
 use tokio::sync::mpsc::channel;
+use tokio::sync::{Mutex, Notify};
 use tracing::info;
 
 #[tokio::main]
@@ -38,6 +66,10 @@ async fn main() -> Result<()> {
     // If the flag is found, this method will never return!
     run_health_check_if_requested().await;
 
+    // Check for special argument to create password hashes for the users file.
+    // If the flag is found, this method will never return!
+    run_hash_password_if_requested();
+
     // Create config from args and ENV variables.
     // Will exit early in case of error or help and version command.
     let config = Configuration::new();
@@ -67,14 +99,71 @@ async fn main() -> Result<()> {
 
     // Create DNS client
     let timeout = Duration::from_millis(config.dns_timeout);
-    let dns_client = DnsClient::new(config.dns_server, timeout);
+    let dns_client = DnsClient::new(config.dns_server.clone(), timeout, config.dns_over_tls)
+        .context("Failed to create DNS client")?;
+
+    // Load optional IP to ASN database
+    let asn_database = if let Some(path) = &config.ip_to_asn_file {
+        let db = AsnDatabase::from_file(path).context("Failed to load IP to ASN database")?;
+        info!(
+            "Loaded {} IP ranges from IP to ASN database",
+            db.range_count()
+        );
+        Some(db)
+    } else {
+        None
+    };
+
+    // Create IP location provider
+    let location_provider: Option<Arc<dyn LocationProvider>> = match config.location_provider {
+        LocationProviderType::IpApi => Some(Arc::new(IpApiProvider::new())),
+        LocationProviderType::Mmdb => Some(Arc::new(
+            MmdbProvider::new(
+                config.location_mmdb_city.clone(),
+                config.location_mmdb_asn.clone(),
+            )
+            .await
+            .context("Failed to create MMDB location provider")?,
+        )),
+        LocationProviderType::Disabled => None,
+    };
 
     // Prepare shared application state
-    let state = Arc::new(Mutex::new(AppState::new(dns_client)));
+    let state = Arc::new(Mutex::new(AppState::new(
+        dns_client,
+        asn_database,
+        location_provider,
+        config.dnsbl_zones.clone(),
+    )));
+
+    // Start optional IP enrichment task
+    let enrichment_trigger = if config.enrichment {
+        if let Some(path) = &config.enrichment_cache_file {
+            let enrichments = load_enrichments(path)
+                .await
+                .context("Failed to load IP enrichment cache")?;
+            info!("Loaded {} enriched IPs from cache file", enrichments.len());
+            state.lock().await.enrichments = enrichments;
+        }
+        let trigger = Arc::new(Notify::new());
+        start_enrichment_task(
+            state.clone(),
+            trigger.clone(),
+            config.enrichment_cache_file.clone(),
+        );
+        Some(trigger)
+    } else {
+        None
+    };
 
     // Start background task
     let (stop_sender, stop_receiver) = channel(1);
-    let bg_handle = start_bg_task(config.clone(), state.clone(), stop_receiver);
+    let bg_handle = start_bg_task(
+        config.clone(),
+        state.clone(),
+        stop_receiver,
+        enrichment_trigger,
+    );
 
     // Starting HTTP server
     run_http_server(&config, state.clone())
@@ -0,0 +1,435 @@
+use crate::dns_client_cached::{DnsClientCached, TtlCache};
+use crate::http_client::http_request;
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use hyper::{Method, StatusCode, header};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+/// Largest allowed value for max_age in seconds (about one year, RFC 8461 section 3.2)
+const MAX_AGE_LIMIT: u64 = 31557600;
+
+/// Timeout for fetching a policy via HTTPS
+const POLICY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum time policies and failed fetches are cached,
+/// shorter for policies with a smaller max_age
+const POLICY_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Mode of an MTA-STS policy
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StsMode {
+    Enforce,
+    Testing,
+    None,
+}
+
+impl StsMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StsMode::Enforce => "enforce",
+            StsMode::Testing => "testing",
+            StsMode::None => "none",
+        }
+    }
+}
+
+/// MTA-STS policy as published by a domain or applied by a reporter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StsPolicy {
+    pub mode: StsMode,
+    /// MX host patterns, like `mail.example.com` or `*.example.net`
+    pub mx: Vec<String>,
+    /// Maximum lifetime of the policy in seconds
+    pub max_age: u64,
+}
+
+impl StsPolicy {
+    /// Parses the key value lines of a policy as defined in RFC 8461 section 3.2
+    pub fn parse_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut version = None;
+        let mut mode = None;
+        let mut mx = Vec::new();
+        let mut max_age = None;
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .with_context(|| format!("Invalid policy line: {line}"))?;
+            let value = value.trim();
+            match key.trim() {
+                "version" => version = Some(value.to_string()),
+                "mode" => {
+                    mode = Some(match value {
+                        "enforce" => StsMode::Enforce,
+                        "testing" => StsMode::Testing,
+                        "none" => StsMode::None,
+                        _ => bail!("Invalid policy mode: {value}"),
+                    })
+                }
+                "mx" => mx.push(value.to_ascii_lowercase()),
+                "max_age" => {
+                    let seconds: u64 = value
+                        .parse()
+                        .with_context(|| format!("Invalid max_age: {value}"))?;
+                    ensure!(seconds <= MAX_AGE_LIMIT, "max_age is too large: {seconds}");
+                    max_age = Some(seconds);
+                }
+                _ => {} // Unknown keys must be ignored
+            }
+        }
+
+        ensure!(
+            version.as_deref() == Some("STSv1"),
+            "Policy has no version STSv1"
+        );
+        let mode = mode.context("Policy has no mode")?;
+        let max_age = max_age.context("Policy has no max_age")?;
+        ensure!(
+            mode == StsMode::None || !mx.is_empty(),
+            "Policy has no MX patterns"
+        );
+        Ok(Self { mode, mx, max_age })
+    }
+
+    /// Parses the text of a policy file
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_lines(text.lines())
+    }
+
+    /// Checks if the MX host matches any of the patterns of the policy
+    pub fn matches(&self, host: &str) -> bool {
+        self.mx.iter().any(|pattern| mx_matches(pattern, host))
+    }
+}
+
+/// Checks if the host matches the MX pattern.
+/// A wildcard like `*.example.com` matches exactly one additional label.
+pub fn mx_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.');
+    let host = host.trim_end_matches('.');
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        match host.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && rest.eq_ignore_ascii_case(suffix),
+            None => false,
+        }
+    } else {
+        pattern.eq_ignore_ascii_case(host)
+    }
+}
+
+/// Checks if the domain is a valid host name made of letters, digits and hyphens
+/// (RFC 1123 section 2.1), so that it can be safely used in URLs and DNS queries
+pub fn is_valid_hostname(domain: &str) -> bool {
+    domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Extracts the policy ID from an `_mta-sts` TXT record like `v=STSv1; id=20160831085700Z;`.
+/// Returns None for records of other kinds.
+fn parse_sts_record(text: &str) -> Option<Result<String>> {
+    let mut fields = text.split(';').map(str::trim).filter(|f| !f.is_empty());
+    if fields.next() != Some("v=STSv1") {
+        return None;
+    }
+    let id = fields.find_map(|field| field.strip_prefix("id="));
+    Some(match id {
+        Some(id) if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            Ok(id.to_string())
+        }
+        Some(id) => Err(anyhow::anyhow!("Invalid policy ID: {id}")),
+        None => Err(anyhow::anyhow!("Record has no policy ID")),
+    })
+}
+
+/// Live MX record of the domain and if the policy allows it
+#[derive(Debug, Clone, Serialize)]
+pub struct MxCheck {
+    pub host: String,
+    pub matches_policy: bool,
+}
+
+/// MX pattern that was applied by reporters and if it is still part of the policy
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportedMxCheck {
+    pub pattern: String,
+    pub in_policy: bool,
+}
+
+/// Result of checking the MTA-STS setup of a domain
+#[derive(Debug, Clone, Serialize)]
+pub struct StsCheck {
+    pub domain: String,
+    /// Policy ID from the `_mta-sts` TXT record
+    pub record_id: Option<String>,
+    pub policy: Option<StsPolicy>,
+    pub mx_records: Vec<MxCheck>,
+    pub reported_mx: Vec<ReportedMxCheck>,
+    /// Problems found during the check, empty if everything is fine
+    pub errors: Vec<String>,
+}
+
+/// Checks the TXT record, the policy and the MX records of the domain.
+/// The reported MX patterns are taken from the SMTP TLS reports of the domain.
+pub async fn check_domain(
+    dns_client: &DnsClientCached,
+    policies: &StsPolicyCache,
+    domain: &str,
+    reported_mx: &[String],
+) -> StsCheck {
+    let mut errors = Vec::new();
+    if !is_valid_hostname(domain) {
+        errors.push(String::from("Invalid domain name"));
+        return StsCheck {
+            domain: domain.to_string(),
+            record_id: None,
+            policy: None,
+            mx_records: Vec::new(),
+            reported_mx: Vec::new(),
+            errors,
+        };
+    }
+
+    let record_id = match dns_client
+        .txt_from_host(&format!("_mta-sts.{domain}"))
+        .await
+    {
+        Ok(texts) => {
+            let mut records = texts.iter().filter_map(|t| parse_sts_record(t));
+            match (records.next(), records.next()) {
+                (Some(Ok(id)), None) => Some(id),
+                (Some(Err(err)), None) => {
+                    errors.push(format!("Invalid MTA-STS TXT record: {err}"));
+                    None
+                }
+                (Some(_), Some(_)) => {
+                    errors.push(String::from("Found multiple MTA-STS TXT records"));
+                    None
+                }
+                (None, _) => {
+                    errors.push(String::from("Found no MTA-STS TXT record"));
+                    None
+                }
+            }
+        }
+        Err(err) => {
+            errors.push(format!("Failed to query MTA-STS TXT record: {err:#}"));
+            None
+        }
+    };
+
+    let policy = match policies.get(domain).await {
+        Ok(policy) => Some(policy),
+        Err(err) => {
+            errors.push(format!("Failed to get policy: {err:#}"));
+            None
+        }
+    };
+
+    let mx_records = match dns_client.mx_from_domain(domain).await {
+        Ok(hosts) => hosts
+            .into_iter()
+            .map(|host| MxCheck {
+                matches_policy: policy.as_ref().is_some_and(|p| p.matches(&host)),
+                host,
+            })
+            .collect(),
+        Err(err) => {
+            errors.push(format!("Failed to query MX records: {err:#}"));
+            Vec::new()
+        }
+    };
+    // Policies with mode none don't need to list any MX hosts
+    let enforced = policy.as_ref().is_some_and(|p| p.mode != StsMode::None);
+    if enforced {
+        for mx in mx_records.iter().filter(|mx| !mx.matches_policy) {
+            errors.push(format!("MX host {} is not covered by the policy", mx.host));
+        }
+    }
+
+    let mut patterns: Vec<String> = reported_mx.iter().map(|p| p.to_ascii_lowercase()).collect();
+    patterns.sort();
+    patterns.dedup();
+    let reported_mx: Vec<ReportedMxCheck> = patterns
+        .into_iter()
+        .map(|pattern| ReportedMxCheck {
+            in_policy: policy.as_ref().is_some_and(|p| p.mx.contains(&pattern)),
+            pattern,
+        })
+        .collect();
+    if enforced {
+        for reported in reported_mx.iter().filter(|r| !r.in_policy) {
+            errors.push(format!(
+                "Reporters used MX pattern {} that is not part of the policy",
+                reported.pattern
+            ));
+        }
+    }
+
+    StsCheck {
+        domain: domain.to_string(),
+        record_id,
+        policy,
+        mx_records,
+        reported_mx,
+        errors,
+    }
+}
+
+/// Fetches the policy from the well-known HTTPS URL of the domain.
+/// Redirects are not followed as required by RFC 8461 section 3.3.
+pub async fn fetch_policy(domain: &str) -> Result<StsPolicy> {
+    ensure!(is_valid_hostname(domain), "Invalid domain name {domain:?}");
+    let url = format!("https://mta-sts.{domain}/.well-known/mta-sts.txt");
+    let headers = HashMap::new();
+    let request = http_request(Method::GET, &url, &headers, Vec::new());
+    let (status, headers, body) = timeout(POLICY_TIMEOUT, request)
+        .await
+        .context("Timeout while fetching policy")?
+        .context("Failed to send HTTP request")?;
+    ensure!(status == StatusCode::OK, "Policy request returned {status}");
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    ensure!(
+        content_type.starts_with("text/plain"),
+        "Policy has unexpected content type {content_type:?}"
+    );
+    let text = String::from_utf8(body).context("Policy is not valid UTF-8")?;
+    StsPolicy::parse(&text)
+}
+
+/// Cache for fetched policies and failed fetches per domain
+pub struct StsPolicyCache {
+    cache: Mutex<TtlCache<String, Result<StsPolicy, String>>>,
+}
+
+impl StsPolicyCache {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            cache: Mutex::new(TtlCache::new(max_size)),
+        }
+    }
+
+    /// Returns the cached policy of the domain or fetches it
+    pub async fn get(&self, domain: &str) -> Result<StsPolicy> {
+        let key = domain.to_ascii_lowercase();
+        if let Some(cached) = self.cache.lock().await.get(&key, Instant::now()) {
+            return cached.clone().map_err(|err| anyhow!(err));
+        }
+
+        let result = fetch_policy(domain).await;
+        let (cached, ttl) = match &result {
+            Ok(policy) => (
+                Ok(policy.clone()),
+                POLICY_CACHE_TTL.min(Duration::from_secs(policy.max_age)),
+            ),
+            Err(err) => (Err(format!("{err:#}")), POLICY_CACHE_TTL),
+        };
+        self.cache
+            .lock()
+            .await
+            .insert(key, cached, ttl, Instant::now());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy() {
+        let text = "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.example.net\r\nmx: backupmx.example.com\r\nmax_age: 604800\r\n";
+        let policy = StsPolicy::parse(text).unwrap();
+        assert_eq!(policy.mode, StsMode::Enforce);
+        assert_eq!(
+            policy.mx,
+            vec!["mail.example.com", "*.example.net", "backupmx.example.com"]
+        );
+        assert_eq!(policy.max_age, 604800);
+
+        let policy = StsPolicy::parse("version: STSv1\nmode: none\nmax_age: 86400\n").unwrap();
+        assert_eq!(policy.mode, StsMode::None);
+        assert!(policy.mx.is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_policies() {
+        assert!(StsPolicy::parse("").is_err());
+        assert!(StsPolicy::parse("mode: enforce\nmx: a.example\nmax_age: 1").is_err());
+        assert!(
+            StsPolicy::parse("version: STSv1\nmode: strict\nmx: a.example\nmax_age: 1").is_err()
+        );
+        assert!(StsPolicy::parse("version: STSv1\nmode: testing\nmax_age: 1").is_err());
+        assert!(
+            StsPolicy::parse("version: STSv1\nmode: testing\nmx: a.example\nmax_age: 99999999")
+                .is_err()
+        );
+        assert!(
+            StsPolicy::parse("version: STSv1\nmode: testing\nmx: a.example\nmax_age: -1").is_err()
+        );
+    }
+
+    #[test]
+    fn match_mx_patterns() {
+        assert!(mx_matches("mail.example.com", "mail.example.com"));
+        assert!(mx_matches("mail.example.com", "MAIL.example.com."));
+        assert!(!mx_matches("mail.example.com", "mail2.example.com"));
+        assert!(mx_matches("*.example.net", "mx1.example.net"));
+        assert!(!mx_matches("*.example.net", "example.net"));
+        assert!(!mx_matches("*.example.net", "a.mx1.example.net"));
+        assert!(!mx_matches("*.example.net", ".example.net"));
+    }
+
+    #[test]
+    fn valid_hostnames() {
+        assert!(is_valid_hostname("example.com"));
+        assert!(is_valid_hostname("mail-1.Example.com"));
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("example.com."));
+        assert!(!is_valid_hostname("-example.com"));
+        assert!(!is_valid_hostname("exa_mple.com"));
+        assert!(!is_valid_hostname("x@10.0.0.1:8080/#.customer.com"));
+        assert!(!is_valid_hostname("example.com%2F"));
+        assert!(!is_valid_hostname("example.com?a"));
+        assert!(!is_valid_hostname(&"a".repeat(64)));
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_domains() {
+        let err = fetch_policy("x@127.0.0.1:1/#.example.com")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid domain name"));
+    }
+
+    #[test]
+    fn parse_txt_records() {
+        assert_eq!(
+            parse_sts_record("v=STSv1; id=20160831085700Z;")
+                .unwrap()
+                .unwrap(),
+            "20160831085700Z"
+        );
+        assert_eq!(parse_sts_record("v=STSv1;id=abc").unwrap().unwrap(), "abc");
+        assert!(parse_sts_record("v=spf1 -all").is_none());
+        assert!(parse_sts_record("v=STSv1;").unwrap().is_err());
+        assert!(parse_sts_record("v=STSv1; id=a-b").unwrap().is_err());
+    }
+}
@@ -0,0 +1,793 @@
+use crate::cache_map::CacheMap;
+use crate::config::Configuration;
+use crate::http_client::http_request;
+use crate::session::random_token;
+use crate::users::{DomainScope, Role, User};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use hyper::{Method, StatusCode};
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Path of the callback handler that must be part of the redirect URL
+pub const CALLBACK_PATH: &str = "/auth/callback";
+
+/// Maximum number of logins that were started but not finished yet
+const MAX_PENDING_LOGINS: usize = 1000;
+
+/// Time the user has to log in at the provider
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Provider metadata is fetched again after this time
+const METADATA_MAX_AGE: Duration = Duration::from_secs(24 * 3600);
+
+/// The keys of the provider are fetched again after this time
+const JWKS_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// Minimum time between two key downloads caused by unknown key IDs
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// Allowed clock difference to the provider for the token timestamps
+const CLOCK_SKEW: u64 = 60;
+
+/// Timeout for requests to the provider
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// OpenID Connect client configuration
+#[derive(Clone, Debug)]
+pub struct OidcSettings {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    pub username_claim: String,
+    pub groups_claim: String,
+    pub admin_groups: Vec<String>,
+    /// Domains that members of a group may see
+    pub viewer_groups: HashMap<String, Vec<String>>,
+}
+
+impl OidcSettings {
+    /// Returns None if no issuer is configured
+    pub fn from_config(config: &Configuration) -> Result<Option<Self>> {
+        let Some(issuer) = &config.oidc_issuer else {
+            return Ok(None);
+        };
+        let redirect_url = config
+            .oidc_redirect_url
+            .clone()
+            .context("OpenID Connect redirect URL is missing in configuration")?;
+        ensure!(
+            redirect_url.ends_with(CALLBACK_PATH),
+            "OpenID Connect redirect URL must end with {CALLBACK_PATH}"
+        );
+        let mut viewer_groups: HashMap<String, Vec<String>> = HashMap::new();
+        for mapping in &config.oidc_viewer_groups {
+            let (group, domain) = mapping
+                .split_once('=')
+                .with_context(|| format!("Invalid viewer group mapping {mapping}"))?;
+            ensure!(
+                !group.is_empty() && !domain.is_empty(),
+                "Invalid viewer group mapping {mapping}"
+            );
+            viewer_groups
+                .entry(group.to_string())
+                .or_default()
+                .push(domain.trim_end_matches('.').to_lowercase());
+        }
+        Ok(Some(Self {
+            issuer: issuer.clone(),
+            client_id: config
+                .oidc_client_id
+                .clone()
+                .context("OpenID Connect client ID is missing in configuration")?,
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url,
+            scopes: config.oidc_scopes.clone(),
+            username_claim: config.oidc_username_claim.clone(),
+            groups_claim: config.oidc_groups_claim.clone(),
+            admin_groups: config.oidc_admin_groups.clone(),
+            viewer_groups,
+        }))
+    }
+}
+
+/// Subset of the provider metadata from the discovery document
+#[derive(Deserialize, Clone, Debug)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    end_session_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// Public key of the provider as JSON Web Key
+#[derive(Deserialize, Clone, Debug)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    #[serde(rename = "use")]
+    usage: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+struct PendingLogin {
+    verifier: String,
+    nonce: String,
+    started: Instant,
+}
+
+struct Cached<T> {
+    value: T,
+    fetched: Instant,
+}
+
+/// Relying party for the authorization code flow with PKCE
+pub struct OidcClient {
+    settings: OidcSettings,
+    metadata: AsyncMutex<Option<Cached<ProviderMetadata>>>,
+    jwks: AsyncMutex<Option<Cached<JwkSet>>>,
+    /// Logins that were started, with the state parameter as key
+    pending: Mutex<CacheMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings) -> Result<Self> {
+        Ok(Self {
+            settings,
+            metadata: AsyncMutex::new(None),
+            jwks: AsyncMutex::new(None),
+            pending: Mutex::new(CacheMap::new(MAX_PENDING_LOGINS)?),
+        })
+    }
+
+    /// True if the redirect URL and thereby the cookies should use HTTPS
+    pub fn is_https(&self) -> bool {
+        self.settings.redirect_url.starts_with("https://")
+    }
+
+    /// Starts a new login and returns the URL of the authorization endpoint with the state parameter.
+    /// The state needs to be bound to the browser to prevent login CSRF.
+    pub async fn login_url(&self) -> Result<(String, String)> {
+        let metadata = self.metadata().await?;
+        let state = random_token();
+        let nonce = random_token();
+        let verifier = random_token();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let scopes = self.settings.scopes.join(" ");
+        let params = [
+            ("response_type", "code"),
+            ("client_id", &self.settings.client_id),
+            ("redirect_uri", &self.settings.redirect_url),
+            ("scope", &scopes),
+            ("state", &state),
+            ("nonce", &nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        let url = with_query(&metadata.authorization_endpoint, &params);
+        self.pending
+            .lock()
+            .expect("Failed to lock pending logins")
+            .insert(
+                state.clone(),
+                PendingLogin {
+                    verifier,
+                    nonce,
+                    started: Instant::now(),
+                },
+            );
+        Ok((url, state))
+    }
+
+    /// Finishes a login with the authorization code from the callback
+    /// and returns the user described by the validated ID token
+    pub async fn finish_login(&self, code: &str, state: &str) -> Result<User> {
+        let pending = self
+            .pending
+            .lock()
+            .expect("Failed to lock pending logins")
+            .remove(&state.to_string())
+            .context("Unknown login state")?;
+        ensure!(
+            pending.started.elapsed() < LOGIN_TIMEOUT,
+            "Login took too long"
+        );
+
+        let metadata = self.metadata().await?;
+        let body = form_encode(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.settings.redirect_url),
+            ("client_id", &self.settings.client_id),
+            ("code_verifier", &pending.verifier),
+        ]);
+        let mut headers = HashMap::from([
+            (
+                String::from("Content-Type"),
+                String::from("application/x-www-form-urlencoded"),
+            ),
+            (String::from("Accept"), String::from("application/json")),
+        ]);
+        if let Some(secret) = &self.settings.client_secret {
+            // Client credentials are URL encoded before they are combined (RFC 6749 section 2.3.1)
+            let credentials = format!(
+                "{}:{}",
+                urlencoding::encode(&self.settings.client_id),
+                urlencoding::encode(secret)
+            );
+            headers.insert(
+                String::from("Authorization"),
+                format!("Basic {}", STANDARD.encode(credentials)),
+            );
+        }
+        let response: TokenResponse = request_json(
+            Method::POST,
+            &metadata.token_endpoint,
+            &headers,
+            body.into_bytes(),
+        )
+        .await
+        .context("Failed to redeem authorization code")?;
+
+        let claims = self
+            .validate_id_token(&metadata, &response.id_token, &pending.nonce)
+            .await
+            .context("Invalid ID token")?;
+        self.map_user(&claims)
+    }
+
+    /// URL of the logout endpoint of the provider, if it has one
+    pub async fn logout_url(&self) -> Option<String> {
+        let metadata = self.metadata().await.ok()?;
+        let endpoint = metadata.end_session_endpoint?;
+        Some(with_query(
+            &endpoint,
+            &[("client_id", &self.settings.client_id)],
+        ))
+    }
+
+    /// Provider metadata from the discovery document, cached for a day
+    async fn metadata(&self) -> Result<ProviderMetadata> {
+        let mut cache = self.metadata.lock().await;
+        if let Some(cached) = cache.as_ref()
+            && cached.fetched.elapsed() < METADATA_MAX_AGE
+        {
+            return Ok(cached.value.clone());
+        }
+        let issuer = self.settings.issuer.trim_end_matches('/');
+        let url = format!("{issuer}/.well-known/openid-configuration");
+        let metadata: ProviderMetadata =
+            request_json(Method::GET, &url, &HashMap::new(), Vec::new())
+                .await
+                .context("Failed to get OpenID Connect discovery document")?;
+        ensure!(
+            metadata.issuer.trim_end_matches('/') == issuer,
+            "Issuer {} of discovery document does not match configuration",
+            metadata.issuer
+        );
+        *cache = Some(Cached {
+            value: metadata.clone(),
+            fetched: Instant::now(),
+        });
+        Ok(metadata)
+    }
+
+    /// Finds the signing key in the cached key set.
+    /// Unknown key IDs cause a new download of the keys, in case the provider rotated them.
+    async fn signing_key(&self, jwks_uri: &str, kid: Option<&str>) -> Result<Jwk> {
+        let mut cache = self.jwks.lock().await;
+        let age = cache.as_ref().map(|c| c.fetched.elapsed());
+        if let Some(age) = age
+            && age < JWKS_MAX_AGE
+            && let Some(key) = find_key(&cache.as_ref().expect("Cache is set").value, kid)
+        {
+            return Ok(key);
+        }
+        if age.is_none_or(|age| age >= JWKS_MIN_REFRESH) {
+            let jwks: JwkSet = request_json(Method::GET, jwks_uri, &HashMap::new(), Vec::new())
+                .await
+                .context("Failed to get keys of provider")?;
+            *cache = Some(Cached {
+                value: jwks,
+                fetched: Instant::now(),
+            });
+        }
+        cache
+            .as_ref()
+            .and_then(|c| find_key(&c.value, kid))
+            .context("Signing key of ID token not found")
+    }
+
+    /// Verifies the signature and claims of the ID token and returns its claims
+    async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        token: &str,
+        nonce: &str,
+    ) -> Result<Map<String, Value>> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("ID token is not a signed JWT");
+        };
+        let header: JwtHeader = decode_json(header).context("Invalid JWT header")?;
+        let claims: Map<String, Value> = decode_json(payload).context("Invalid JWT payload")?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .context("Invalid JWT signature encoding")?;
+
+        let key = self
+            .signing_key(&metadata.jwks_uri, header.kid.as_deref())
+            .await?;
+        let (message, _) = token
+            .rsplit_once('.')
+            .context("ID token is not a signed JWT")?;
+        verify_signature(&key, &header.alg, message.as_bytes(), &signature)?;
+        validate_claims(
+            &claims,
+            &metadata.issuer,
+            &self.settings.client_id,
+            nonce,
+            unix_time(),
+        )?;
+        Ok(claims)
+    }
+
+    /// Maps the claims of the ID token to a user with role and domains
+    fn map_user(&self, claims: &Map<String, Value>) -> Result<User> {
+        let name = claim(claims, &self.settings.username_claim)
+            .or_else(|| claims.get("sub"))
+            .and_then(Value::as_str)
+            .context("ID token contains no user name")?
+            .to_string();
+        let groups: BTreeSet<&str> = match claim(claims, &self.settings.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(group)) => BTreeSet::from([group.as_str()]),
+            _ => BTreeSet::new(),
+        };
+
+        if self
+            .settings
+            .admin_groups
+            .iter()
+            .any(|group| groups.contains(group.as_str()))
+        {
+            return Ok(User {
+                name,
+                role: Role::Admin,
+                scope: DomainScope::All,
+            });
+        }
+        let mut domains: Vec<String> = groups
+            .iter()
+            .filter_map(|group| self.settings.viewer_groups.get(*group))
+            .flatten()
+            .cloned()
+            .collect();
+        domains.sort();
+        domains.dedup();
+        if domains.is_empty() {
+            bail!("User {name} is not member of any configured group");
+        }
+        Ok(User {
+            name,
+            role: Role::Viewer,
+            scope: DomainScope::Domains(domains),
+        })
+    }
+}
+
+/// Looks up a claim, dots separate the names of nested claims like `realm_access.roles`
+fn claim<'a>(claims: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    if let Some(value) = claims.get(path) {
+        return Some(value);
+    }
+    let mut parts = path.split('.');
+    let mut value = claims.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_object()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    let mut keys = jwks
+        .keys
+        .iter()
+        .filter(|key| key.usage.as_deref().is_none_or(|usage| usage == "sig"));
+    match kid {
+        Some(kid) => keys.find(|key| key.kid.as_deref() == Some(kid)).cloned(),
+        None => {
+            // Without key ID only an unambiguous key can be used
+            let key = keys.next()?;
+            keys.next().is_none().then(|| key.clone())
+        }
+    }
+}
+
+/// Checks the JWS signature with the RSA or elliptic curve key of the provider.
+/// Unsigned tokens and symmetric algorithms are rejected.
+fn verify_signature(key: &Jwk, alg: &str, message: &[u8], signature: &[u8]) -> Result<()> {
+    let decode = |value: &Option<String>, name: &str| -> Result<Vec<u8>> {
+        let value = value
+            .as_deref()
+            .with_context(|| format!("Key has no parameter {name}"))?;
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .with_context(|| format!("Invalid key parameter {name}"))
+    };
+    let result = match (alg, key.kty.as_str()) {
+        ("RS256" | "RS384" | "RS512", "RSA") => {
+            let params = match alg {
+                "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+            };
+            let components = RsaPublicKeyComponents {
+                n: decode(&key.n, "n")?,
+                e: decode(&key.e, "e")?,
+            };
+            components.verify(params, message, signature)
+        }
+        ("ES256" | "ES384", "EC") => {
+            let (params, curve) = match alg {
+                "ES256" => (&signature::ECDSA_P256_SHA256_FIXED, "P-256"),
+                _ => (&signature::ECDSA_P384_SHA384_FIXED, "P-384"),
+            };
+            ensure!(
+                key.crv.as_deref() == Some(curve),
+                "Key curve does not match algorithm {alg}"
+            );
+            let mut point = vec![0x04];
+            point.extend(decode(&key.x, "x")?);
+            point.extend(decode(&key.y, "y")?);
+            UnparsedPublicKey::new(params, point).verify(message, signature)
+        }
+        _ => bail!(
+            "Unsupported signature algorithm {alg} for key type {}",
+            key.kty
+        ),
+    };
+    result.map_err(|_| anyhow!("Invalid signature"))
+}
+
+/// Checks issuer, audience, authorized party, expiration and nonce of the ID token
+fn validate_claims(
+    claims: &Map<String, Value>,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: u64,
+) -> Result<()> {
+    ensure!(
+        claims.get("iss").and_then(Value::as_str) == Some(issuer),
+        "Wrong issuer"
+    );
+    let audiences: Vec<&str> = match claims.get("aud") {
+        Some(Value::String(aud)) => vec![aud.as_str()],
+        Some(Value::Array(auds)) => auds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    ensure!(audiences.contains(&client_id), "Wrong audience");
+    if let Some(azp) = claims.get("azp") {
+        ensure!(azp.as_str() == Some(client_id), "Wrong authorized party");
+    }
+    let exp = claims
+        .get("exp")
+        .and_then(Value::as_u64)
+        .context("Missing expiration time")?;
+    ensure!(exp + CLOCK_SKEW > now, "Token expired");
+    if let Some(iat) = claims.get("iat").and_then(Value::as_u64) {
+        ensure!(iat <= now + CLOCK_SKEW, "Token issued in the future");
+    }
+    ensure!(
+        claims.get("nonce").and_then(Value::as_str) == Some(nonce),
+        "Wrong nonce"
+    );
+    Ok(())
+}
+
+fn decode_json<T: DeserializeOwned>(part: &str) -> Result<T> {
+    let json = URL_SAFE_NO_PAD
+        .decode(part)
+        .context("Invalid base64 encoding")?;
+    serde_json::from_slice(&json).context("Invalid JSON")
+}
+
+async fn request_json<T: DeserializeOwned>(
+    method: Method,
+    url: &str,
+    headers: &HashMap<String, String>,
+    body: Vec<u8>,
+) -> Result<T> {
+    let (status, _, body) =
+        tokio::time::timeout(REQUEST_TIMEOUT, http_request(method, url, headers, body))
+            .await
+            .context("Request timed out")??;
+    ensure!(status == StatusCode::OK, "Unexpected status code {status}");
+    serde_json::from_slice(&body).context("Failed to parse JSON response")
+}
+
+fn form_encode(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(key, value)| format!("{key}={}", urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn with_query(url: &str, params: &[(&str, &str)]) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}{}", form_encode(params))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Failed to get Unix time stamp")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Form, State};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// Minimal identity provider that issues ES256 signed ID tokens
+    struct MockProvider {
+        issuer: String,
+        key: EcdsaKeyPair,
+        /// Claims of the ID token to issue for the next code
+        claims: Mutex<Map<String, Value>>,
+        /// PKCE verifier of the last token request
+        verifier: Mutex<Option<String>>,
+    }
+
+    impl MockProvider {
+        fn sign(&self, claims: &Map<String, Value>) -> String {
+            let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256","kid":"test"}"#);
+            let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+            let message = format!("{header}.{payload}");
+            let signature = self
+                .key
+                .sign(&SystemRandom::new(), message.as_bytes())
+                .unwrap();
+            format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        }
+
+        fn jwk(&self) -> Value {
+            // Uncompressed point with prefix byte, X and Y coordinate
+            let point = self.key.public_key().as_ref();
+            json!({
+                "kty": "EC",
+                "kid": "test",
+                "use": "sig",
+                "crv": "P-256",
+                "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            })
+        }
+    }
+
+    async fn start_provider() -> Arc<MockProvider> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let provider = Arc::new(MockProvider {
+            issuer,
+            key,
+            claims: Mutex::new(Map::new()),
+            verifier: Mutex::new(None),
+        });
+
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(|State(p): State<Arc<MockProvider>>| async move {
+                    Json(json!({
+                        "issuer": p.issuer,
+                        "authorization_endpoint": format!("{}/authorize", p.issuer),
+                        "token_endpoint": format!("{}/token", p.issuer),
+                        "jwks_uri": format!("{}/jwks", p.issuer),
+                    }))
+                }),
+            )
+            .route(
+                "/jwks",
+                get(|State(p): State<Arc<MockProvider>>| async move {
+                    Json(json!({ "keys": [p.jwk()] }))
+                }),
+            )
+            .route(
+                "/token",
+                post(
+                    |State(p): State<Arc<MockProvider>>,
+                     Form(form): Form<HashMap<String, String>>| async move {
+                        *p.verifier.lock().unwrap() = form.get("code_verifier").cloned();
+                        let claims = p.claims.lock().unwrap().clone();
+                        Json(json!({ "id_token": p.sign(&claims), "token_type": "Bearer" }))
+                    },
+                ),
+            )
+            .with_state(provider.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        provider
+    }
+
+    fn settings(issuer: &str) -> OidcSettings {
+        OidcSettings {
+            issuer: issuer.to_string(),
+            client_id: String::from("dmarc"),
+            client_secret: Some(String::from("secret")),
+            redirect_url: format!("https://dmarc.example.com{CALLBACK_PATH}"),
+            scopes: vec![String::from("openid"), String::from("profile")],
+            username_claim: String::from("preferred_username"),
+            groups_claim: String::from("groups"),
+            admin_groups: vec![String::from("dmarc-admins")],
+            viewer_groups: HashMap::from([(
+                String::from("customer-a"),
+                vec![String::from("a.example")],
+            )]),
+        }
+    }
+
+    fn query_param(url: &str, name: &str) -> String {
+        let query = url.split_once('?').unwrap().1;
+        let value = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{name}=")))
+            .unwrap();
+        urlencoding::decode(value).unwrap().to_string()
+    }
+
+    fn claims(provider: &MockProvider, nonce: &str, groups: &[&str]) -> Map<String, Value> {
+        let now = unix_time();
+        let claims = json!({
+            "iss": provider.issuer,
+            "sub": "1234",
+            "aud": "dmarc",
+            "exp": now + 300,
+            "iat": now,
+            "nonce": nonce,
+            "preferred_username": "jane",
+            "groups": groups,
+        });
+        claims.as_object().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn login_with_mock_provider() {
+        let provider = start_provider().await;
+        let client = OidcClient::new(settings(&provider.issuer)).unwrap();
+
+        let (url, state) = client.login_url().await.unwrap();
+        assert!(url.starts_with(&format!("{}/authorize?", provider.issuer)));
+        assert_eq!(query_param(&url, "code_challenge_method"), "S256");
+        assert_eq!(query_param(&url, "scope"), "openid profile");
+        assert_eq!(query_param(&url, "state"), state);
+        let nonce = query_param(&url, "nonce");
+        let challenge = query_param(&url, "code_challenge");
+
+        *provider.claims.lock().unwrap() = claims(&provider, &nonce, &["customer-a"]);
+        let user = client.finish_login("code", &state).await.unwrap();
+        assert_eq!(user.name, "jane");
+        assert_eq!(user.role, Role::Viewer);
+        assert_eq!(
+            user.scope,
+            DomainScope::Domains(vec![String::from("a.example")])
+        );
+
+        // The provider got the verifier matching the challenge
+        let verifier = provider.verifier.lock().unwrap().clone().unwrap();
+        assert_eq!(
+            URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())),
+            challenge
+        );
+
+        // States can only be used once
+        assert!(client.finish_login("code", &state).await.is_err());
+
+        // Admins
+        let (url, state) = client.login_url().await.unwrap();
+        let nonce = query_param(&url, "nonce");
+        *provider.claims.lock().unwrap() = claims(&provider, &nonce, &["dmarc-admins"]);
+        let user = client.finish_login("code", &state).await.unwrap();
+        assert_eq!(user.role, Role::Admin);
+        assert!(user.scope.is_all());
+    }
+
+    #[tokio::test]
+    async fn rejected_logins() {
+        let provider = start_provider().await;
+        let client = OidcClient::new(settings(&provider.issuer)).unwrap();
+
+        let cases = [
+            ("nonce", json!("other")),
+            ("aud", json!(["other"])),
+            ("iss", json!("https://evil.example")),
+            ("exp", json!(unix_time() - 3600)),
+            ("groups", json!(["unknown"])),
+        ];
+        for (name, value) in cases {
+            let (url, state) = client.login_url().await.unwrap();
+            let mut claims = claims(&provider, &query_param(&url, "nonce"), &["customer-a"]);
+            claims.insert(String::from(name), value);
+            *provider.claims.lock().unwrap() = claims;
+            let result = client.finish_login("code", &state).await;
+            assert!(result.is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn forged_signatures() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let provider = MockProvider {
+            issuer: String::new(),
+            key,
+            claims: Mutex::new(Map::new()),
+            verifier: Mutex::new(None),
+        };
+        let jwk: Jwk = serde_json::from_value(provider.jwk()).unwrap();
+        let token = provider.sign(&Map::new());
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        assert!(verify_signature(&jwk, "ES256", message.as_bytes(), &signature).is_ok());
+        assert!(verify_signature(&jwk, "ES256", b"other", &signature).is_err());
+        assert!(verify_signature(&jwk, "none", message.as_bytes(), &[]).is_err());
+        assert!(verify_signature(&jwk, "HS256", message.as_bytes(), &signature).is_err());
+        assert!(verify_signature(&jwk, "RS256", message.as_bytes(), &signature).is_err());
+    }
+
+    #[test]
+    fn nested_claims() {
+        let claims = json!({"realm_access": {"roles": ["admin"]}, "a.b": 1});
+        let claims = claims.as_object().unwrap();
+        assert_eq!(claim(claims, "realm_access.roles"), Some(&json!(["admin"])));
+        assert_eq!(claim(claims, "a.b"), Some(&json!(1)));
+        assert!(claim(claims, "realm_access.missing").is_none());
+    }
+}
@@ -0,0 +1,326 @@
+use crate::dns_client_cached::DnsClientCached;
+use crate::mta_sts::{StsPolicy, StsPolicyCache, is_valid_hostname};
+use crate::tls::{Policy, PolicyType};
+use crate::tlsa::TlsaRecord;
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// Structured content of the policy string from an SMTP TLS report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ParsedPolicy {
+    Sts(StsPolicy),
+    Tlsa { records: Vec<TlsaRecord> },
+}
+
+impl ParsedPolicy {
+    /// Parses the policy string of a reported policy.
+    /// Returns None for policies without policy string or of type no-policy-found.
+    pub fn parse(policy: &Policy) -> Option<Result<Self>> {
+        let lines = policy.policy_string.as_ref()?;
+        Some(match policy.policy_type {
+            PolicyType::Sts => StsPolicy::parse_lines(lines.iter().map(String::as_str))
+                .map(Self::Sts)
+                .context("Invalid STS policy string"),
+            PolicyType::Tlsa => lines
+                .iter()
+                .map(|line| TlsaRecord::parse(line))
+                .collect::<Result<Vec<_>>>()
+                .map(|records| Self::Tlsa { records })
+                .context("Invalid TLSA policy string"),
+            PolicyType::NoPolicyFound => return None,
+        })
+    }
+}
+
+/// Policy from an SMTP TLS report compared with the currently published policy
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyComparison {
+    pub policy_domain: String,
+    /// Policy the reporter applied
+    pub reported: Option<ParsedPolicy>,
+    /// Policy that is currently published in DNS or via HTTPS
+    pub published: Option<ParsedPolicy>,
+    /// Differences between the reported and the published policy
+    pub differences: Vec<String>,
+    /// Errors from parsing the policy string or getting the published policy
+    pub errors: Vec<String>,
+}
+
+/// Parses the reported policy and compares it against the currently published one.
+/// The published policy is only looked up if the policy domain is a valid host name.
+pub async fn compare_policy(
+    dns_client: &DnsClientCached,
+    policies: &StsPolicyCache,
+    policy: &Policy,
+) -> PolicyComparison {
+    let mut errors = Vec::new();
+    let reported = match ParsedPolicy::parse(policy) {
+        Some(Ok(parsed)) => Some(parsed),
+        Some(Err(err)) => {
+            errors.push(format!("{err:#}"));
+            None
+        }
+        None => None,
+    };
+
+    let valid_domain = is_valid_hostname(&policy.policy_domain);
+    if !valid_domain && reported.is_some() {
+        errors.push(String::from(
+            "Failed to get published policy: Invalid policy domain",
+        ));
+    }
+    let published = match &reported {
+        _ if !valid_domain => None,
+        Some(ParsedPolicy::Sts(_)) => Some(
+            policies
+                .get(&policy.policy_domain)
+                .await
+                .map(ParsedPolicy::Sts)
+                .context("Failed to get published STS policy"),
+        ),
+        Some(ParsedPolicy::Tlsa { .. }) => Some(
+            published_tlsa(dns_client, policy, &mut errors)
+                .await
+                .map(|records| ParsedPolicy::Tlsa { records })
+                .context("Failed to get published TLSA records"),
+        ),
+        None => None,
+    };
+    let published = match published {
+        Some(Ok(published)) => Some(published),
+        Some(Err(err)) => {
+            errors.push(format!("{err:#}"));
+            None
+        }
+        None => None,
+    };
+
+    let differences = match (&reported, &published) {
+        (Some(ParsedPolicy::Sts(reported)), Some(ParsedPolicy::Sts(published))) => {
+            diff_sts(reported, published)
+        }
+        (
+            Some(ParsedPolicy::Tlsa { records: reported }),
+            Some(ParsedPolicy::Tlsa { records: published }),
+        ) => diff_tlsa(reported, published),
+        _ => Vec::new(),
+    };
+
+    PolicyComparison {
+        policy_domain: policy.policy_domain.clone(),
+        reported,
+        published,
+        differences,
+        errors,
+    }
+}
+
+/// Gets the TLSA records of all MX hosts from the report
+/// or of the MX records of the domain if the report has no MX hosts.
+/// Failed lookups of single hosts are added to the errors and don't affect the other hosts.
+async fn published_tlsa(
+    dns_client: &DnsClientCached,
+    policy: &Policy,
+    errors: &mut Vec<String>,
+) -> Result<Vec<TlsaRecord>> {
+    let hosts = match &policy.mx_host {
+        Some(hosts) if !hosts.is_empty() => hosts.clone(),
+        _ => dns_client.mx_from_domain(&policy.policy_domain).await?,
+    };
+    let mut records = Vec::new();
+    for host in hosts {
+        let host = host.trim_end_matches('.');
+        if !is_valid_hostname(host) {
+            errors.push(format!("Invalid MX host {host:?}"));
+            continue;
+        }
+        let host_records = match dns_client.tlsa_from_host(&format!("_25._tcp.{host}")).await {
+            Ok(host_records) => host_records,
+            Err(err) => {
+                errors.push(format!("Failed to get TLSA records of {host}: {err:#}"));
+                continue;
+            }
+        };
+        for record in host_records {
+            if !records.contains(&record) {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Describes how the STS policy of the reporter differs from the published one
+fn diff_sts(reported: &StsPolicy, published: &StsPolicy) -> Vec<String> {
+    let mut differences = Vec::new();
+    if reported == published {
+        return differences;
+    }
+    differences.push(format!(
+        "Reporter used an outdated STS policy with max_age {}",
+        reported.max_age
+    ));
+    if reported.mode != published.mode {
+        differences.push(format!(
+            "Mode changed from {} to {}",
+            reported.mode.as_str(),
+            published.mode.as_str()
+        ));
+    }
+    if reported.max_age != published.max_age {
+        differences.push(format!(
+            "max_age changed from {} to {}",
+            reported.max_age, published.max_age
+        ));
+    }
+    for mx in reported.mx.iter().filter(|mx| !published.mx.contains(mx)) {
+        differences.push(format!("MX pattern {mx} is no longer part of the policy"));
+    }
+    for mx in published.mx.iter().filter(|mx| !reported.mx.contains(mx)) {
+        differences.push(format!(
+            "MX pattern {mx} was not part of the reported policy"
+        ));
+    }
+    differences
+}
+
+/// Describes which TLSA records of the reporter are no longer published and which are new
+fn diff_tlsa(reported: &[TlsaRecord], published: &[TlsaRecord]) -> Vec<String> {
+    let mut differences = Vec::new();
+    for record in reported.iter().filter(|r| !published.contains(r)) {
+        differences.push(format!("TLSA record {} no longer matches", record.short()));
+    }
+    for record in published.iter().filter(|r| !reported.contains(r)) {
+        differences.push(format!(
+            "TLSA record {} was not part of the reported policy",
+            record.short()
+        ));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_client::{DnsClient, DnsServer};
+    use crate::mta_sts::StsMode;
+    use std::time::Duration;
+
+    fn policy(policy_type: PolicyType, lines: &[&str]) -> Policy {
+        Policy {
+            policy_type,
+            policy_string: Some(lines.iter().map(|l| l.to_string()).collect()),
+            policy_domain: String::from("example.com"),
+            mx_host: None,
+        }
+    }
+
+    #[test]
+    fn parse_policy_strings() {
+        let sts = policy(
+            PolicyType::Sts,
+            &[
+                "version: STSv1",
+                "mode: testing",
+                "mx: *.mail.example.com",
+                "max_age: 86400",
+            ],
+        );
+        let Some(Ok(ParsedPolicy::Sts(parsed))) = ParsedPolicy::parse(&sts) else {
+            panic!("Failed to parse STS policy");
+        };
+        assert_eq!(parsed.mode, StsMode::Testing);
+        assert_eq!(parsed.mx, vec!["*.mail.example.com"]);
+        assert_eq!(parsed.max_age, 86400);
+
+        let tlsa = policy(
+            PolicyType::Tlsa,
+            &["3 0 1 1F850A337E6DB9C609C522D136A475638CC43E1ED424F8EEC8513D747D1D085D"],
+        );
+        let Some(Ok(ParsedPolicy::Tlsa { records })) = ParsedPolicy::parse(&tlsa) else {
+            panic!("Failed to parse TLSA policy");
+        };
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].usage, 3);
+
+        let invalid = policy(PolicyType::Tlsa, &["invalid"]);
+        assert!(ParsedPolicy::parse(&invalid).unwrap().is_err());
+
+        let none = policy(PolicyType::NoPolicyFound, &[]);
+        assert!(ParsedPolicy::parse(&none).is_none());
+    }
+
+    #[tokio::test]
+    async fn skip_invalid_domains() {
+        let server: DnsServer = "127.0.0.1:9".parse().unwrap();
+        let dns_client = DnsClient::new(vec![server], Duration::from_millis(100), false).unwrap();
+        let dns_client = DnsClientCached::new(dns_client, 10);
+        let policies = StsPolicyCache::new(10);
+        let lines = ["3 1 1 aaaa"];
+
+        let mut invalid = policy(PolicyType::Tlsa, &lines);
+        invalid.policy_domain = String::from("x@10.0.0.1:8080/#.example.com");
+        let comparison = compare_policy(&dns_client, &policies, &invalid).await;
+        assert!(comparison.reported.is_some());
+        assert!(comparison.published.is_none());
+        assert_eq!(
+            comparison.errors,
+            vec!["Failed to get published policy: Invalid policy domain"]
+        );
+
+        let mut invalid_mx = policy(PolicyType::Tlsa, &lines);
+        invalid_mx.mx_host = Some(vec![String::from("10.0.0.1:8080/")]);
+        let comparison = compare_policy(&dns_client, &policies, &invalid_mx).await;
+        assert_eq!(
+            comparison.published,
+            Some(ParsedPolicy::Tlsa {
+                records: Vec::new()
+            })
+        );
+        assert_eq!(
+            comparison.errors,
+            vec!["Invalid MX host \"10.0.0.1:8080/\""]
+        );
+    }
+
+    #[test]
+    fn sts_differences() {
+        let published = StsPolicy {
+            mode: StsMode::Enforce,
+            mx: vec![String::from("mx1.example.com")],
+            max_age: 604800,
+        };
+        assert!(diff_sts(&published, &published).is_empty());
+
+        let reported = StsPolicy {
+            mode: StsMode::Testing,
+            mx: vec![String::from("mx0.example.com")],
+            max_age: 86400,
+        };
+        assert_eq!(
+            diff_sts(&reported, &published),
+            vec![
+                "Reporter used an outdated STS policy with max_age 86400",
+                "Mode changed from testing to enforce",
+                "max_age changed from 86400 to 604800",
+                "MX pattern mx0.example.com is no longer part of the policy",
+                "MX pattern mx1.example.com was not part of the reported policy",
+            ]
+        );
+    }
+
+    #[test]
+    fn tlsa_differences() {
+        let old = TlsaRecord::parse("3 1 1 aaaa").unwrap();
+        let new = TlsaRecord::parse("3 1 1 bbbb").unwrap();
+        assert!(diff_tlsa(std::slice::from_ref(&old), std::slice::from_ref(&old)).is_empty());
+        assert_eq!(
+            diff_tlsa(&[old], &[new]),
+            vec![
+                "TLSA record 3 1 1 aaaa no longer matches",
+                "TLSA record 3 1 1 bbbb was not part of the reported policy",
+            ]
+        );
+    }
+}
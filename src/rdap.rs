@@ -0,0 +1,464 @@
+// This file contains a minimal RDAP client for IP networks (RFC 9082 and RFC 9083).
+// The responsible registry is found with the IANA bootstrap files (RFC 9224),
+// which are embedded into the binary. See https://data.iana.org/rdap/ for updates.
+
+use crate::http_client::http_request;
+use crate::ip_net::IpNet;
+use anyhow::{Context, Result, bail};
+use hyper::{Method, StatusCode, Uri, header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// IANA bootstrap file for IPv4 address allocations
+const BOOTSTRAP_IPV4: &str = include_str!("../rdap/ipv4.json");
+
+/// IANA bootstrap file for IPv6 address allocations
+const BOOTSTRAP_IPV6: &str = include_str!("../rdap/ipv6.json");
+
+/// Normalized network information from an RDAP response
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RdapNetwork {
+    /// Registry handle of the network, like `NET-192-0-2-0-1`
+    pub handle: Option<String>,
+    /// Name of the network, like `EXAMPLE-NET`
+    pub name: Option<String>,
+    /// Networks in CIDR notation that make up the registered range
+    pub cidrs: Vec<String>,
+    pub start_address: Option<String>,
+    pub end_address: Option<String>,
+    /// Two letter country code
+    pub country: Option<String>,
+    /// Name of the registrant organization
+    pub registrant: Option<String>,
+    pub abuse_contact: Option<RdapContact>,
+    /// URL of the RDAP record after following all redirects
+    pub source: String,
+}
+
+/// Contact information from the vCard of an RDAP entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RdapContact {
+    pub name: Option<String>,
+    pub emails: Vec<String>,
+    pub phones: Vec<String>,
+}
+
+/// Mapping of IP networks to the base URLs of the responsible RDAP servers
+pub struct RdapBootstrap {
+    services: Vec<(IpNet, String)>,
+}
+
+impl Default for RdapBootstrap {
+    fn default() -> Self {
+        Self::from_json(&[BOOTSTRAP_IPV4, BOOTSTRAP_IPV6]).expect("Failed to parse RDAP bootstrap")
+    }
+}
+
+impl RdapBootstrap {
+    /// Parses bootstrap files in the format of RFC 9224.
+    /// HTTPS URLs are preferred over plain HTTP for each service.
+    pub fn from_json(files: &[&str]) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct BootstrapFile {
+            services: Vec<(Vec<String>, Vec<String>)>,
+        }
+
+        let mut services = Vec::new();
+        for file in files {
+            let parsed: BootstrapFile =
+                serde_json::from_str(file).context("Failed to parse bootstrap JSON")?;
+            for (nets, urls) in parsed.services {
+                let Some(url) = urls
+                    .iter()
+                    .find(|u| u.starts_with("https://"))
+                    .or_else(|| urls.first())
+                else {
+                    continue;
+                };
+                for net in nets {
+                    let net = IpNet::from_str(&net).context("Failed to parse bootstrap network")?;
+                    services.push((net, url.clone()));
+                }
+            }
+        }
+        Ok(Self { services })
+    }
+
+    /// Returns the base URL of the RDAP server for the most specific matching network
+    pub fn base_url(&self, ip: &IpAddr) -> Option<&str> {
+        self.services
+            .iter()
+            .filter(|(net, _)| net.contains(ip))
+            .max_by_key(|(net, _)| net.prefix_len())
+            .map(|(_, url)| url.as_str())
+    }
+}
+
+pub struct RdapClient {
+    bootstrap: RdapBootstrap,
+    timeout: Duration,
+    max_follows: u8,
+}
+
+impl Default for RdapClient {
+    fn default() -> Self {
+        Self::new(RdapBootstrap::default())
+    }
+}
+
+impl RdapClient {
+    pub fn new(bootstrap: RdapBootstrap) -> Self {
+        Self {
+            bootstrap,
+            timeout: Duration::from_secs(10),
+            max_follows: 3,
+        }
+    }
+
+    /// Looks up the most specific network registered for the IP
+    pub async fn lookup(&self, ip: &IpAddr) -> Result<RdapNetwork> {
+        // IPv4-mapped IPv6 addresses are handled by the IPv4 registries
+        let ip = ip.to_canonical();
+        let base_url = self
+            .bootstrap
+            .base_url(&ip)
+            .context("No RDAP server known for IP")?;
+        let separator = if base_url.ends_with('/') { "" } else { "/" };
+        let mut url = format!("{base_url}{separator}ip/{ip}");
+
+        let mut headers = HashMap::new();
+        headers.insert(
+            String::from("Accept"),
+            String::from("application/rdap+json, application/json"),
+        );
+
+        // Registries redirect to each other for ranges that were transferred
+        for _ in 0..=self.max_follows {
+            let (status, response_headers, body) = timeout(
+                self.timeout,
+                http_request(Method::GET, &url, &headers, Vec::new()),
+            )
+            .await
+            .context("RDAP request timed out")?
+            .context("Failed to send RDAP request")?;
+
+            if status.is_redirection() {
+                let location = response_headers
+                    .get(header::LOCATION)
+                    .and_then(|l| l.to_str().ok())
+                    .context("RDAP redirect without location")?;
+                url = resolve_redirect(&url, location)?;
+                continue;
+            }
+            if status == StatusCode::NOT_FOUND {
+                bail!("No RDAP record found for IP");
+            }
+            if status != StatusCode::OK {
+                bail!("RDAP server responded with status {status}");
+            }
+
+            let json: Value =
+                serde_json::from_slice(&body).context("Failed to parse RDAP response as JSON")?;
+            return parse_network(&json, &url);
+        }
+        bail!("Too many RDAP redirects")
+    }
+}
+
+/// Returns the absolute URL for the location header of a redirect
+fn resolve_redirect(current: &str, location: &str) -> Result<String> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return Ok(location.to_string());
+    }
+    let uri = Uri::from_str(current).context("Failed to parse RDAP URL")?;
+    let scheme = uri.scheme_str().context("RDAP URL has no scheme")?;
+    let authority = uri.authority().context("RDAP URL has no authority")?;
+    if location.starts_with('/') {
+        Ok(format!("{scheme}://{authority}{location}"))
+    } else {
+        bail!("Unsupported RDAP redirect location {location}")
+    }
+}
+
+/// Converts the JSON of an RDAP IP network object into the normalized form
+fn parse_network(json: &Value, source: &str) -> Result<RdapNetwork> {
+    let class = json.get("objectClassName").and_then(Value::as_str);
+    if class != Some("ip network") {
+        bail!("RDAP response is not an IP network object");
+    }
+    let text = |key: &str| json.get(key).and_then(Value::as_str).map(String::from);
+
+    let start_address = text("startAddress");
+    let end_address = text("endAddress");
+
+    // Prefer the CIDR extension and fall back to the address range
+    let mut cidrs: Vec<String> = json
+        .get("cidr0_cidrs")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|cidr| {
+            let prefix = cidr
+                .get("v4prefix")
+                .or_else(|| cidr.get("v6prefix"))
+                .and_then(Value::as_str)?;
+            let length = cidr.get("length").and_then(Value::as_u64)?;
+            Some(format!("{prefix}/{length}"))
+        })
+        .collect();
+    if cidrs.is_empty()
+        && let (Some(start), Some(end)) = (&start_address, &end_address)
+        && let (Ok(start), Ok(end)) = (IpAddr::from_str(start), IpAddr::from_str(end))
+        && let Some(net) = IpNet::from_range(start, end)
+    {
+        cidrs.push(net.to_string());
+    }
+
+    let entities = json.get("entities").and_then(Value::as_array);
+    let registrant = entities
+        .and_then(|e| find_entity(e, "registrant"))
+        .and_then(|e| contact_from_entity(e).name);
+    let abuse_contact = entities
+        .and_then(|e| find_entity(e, "abuse"))
+        .map(contact_from_entity);
+
+    Ok(RdapNetwork {
+        handle: text("handle"),
+        name: text("name"),
+        cidrs,
+        start_address,
+        end_address,
+        country: text("country"),
+        registrant,
+        abuse_contact,
+        source: source.to_string(),
+    })
+}
+
+/// Searches the entities and their nested entities for the first one with the role
+fn find_entity<'a>(entities: &'a [Value], role: &str) -> Option<&'a Value> {
+    for entity in entities {
+        let has_role = entity
+            .get("roles")
+            .and_then(Value::as_array)
+            .is_some_and(|roles| roles.iter().any(|r| r.as_str() == Some(role)));
+        if has_role {
+            return Some(entity);
+        }
+    }
+    entities
+        .iter()
+        .filter_map(|e| e.get("entities").and_then(Value::as_array))
+        .find_map(|nested| find_entity(nested, role))
+}
+
+/// Extracts name, emails and phone numbers from the jCard (RFC 7095) of the entity
+fn contact_from_entity(entity: &Value) -> RdapContact {
+    let mut contact = RdapContact::default();
+    let properties = entity
+        .get("vcardArray")
+        .and_then(|v| v.get(1))
+        .and_then(Value::as_array);
+    for property in properties.into_iter().flatten() {
+        let name = property.get(0).and_then(Value::as_str);
+        let Some(value) = property.get(3).and_then(Value::as_str) else {
+            continue;
+        };
+        match name {
+            Some("fn") if contact.name.is_none() && !value.is_empty() => {
+                contact.name = Some(value.to_string());
+            }
+            Some("email") => contact.emails.push(value.to_string()),
+            Some("tel") => {
+                let value = value.strip_prefix("tel:").unwrap_or(value);
+                contact.phones.push(value.to_string());
+            }
+            _ => {}
+        }
+    }
+    contact
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const ARIN_NETWORK: &str = r#"{
+        "objectClassName": "ip network",
+        "handle": "NET-192-0-2-0-1",
+        "startAddress": "192.0.2.0",
+        "endAddress": "192.0.2.255",
+        "name": "EXAMPLE-NET",
+        "country": "US",
+        "cidr0_cidrs": [{"v4prefix": "192.0.2.0", "length": 24}],
+        "entities": [{
+            "objectClassName": "entity",
+            "handle": "EXAMP",
+            "roles": ["registrant"],
+            "vcardArray": ["vcard", [
+                ["version", {}, "text", "4.0"],
+                ["fn", {}, "text", "Example Org"],
+                ["kind", {}, "text", "org"]
+            ]],
+            "entities": [{
+                "objectClassName": "entity",
+                "handle": "ABUSE-ARIN",
+                "roles": ["abuse"],
+                "vcardArray": ["vcard", [
+                    ["version", {}, "text", "4.0"],
+                    ["fn", {}, "text", "Abuse Team"],
+                    ["email", {}, "text", "abuse@example.com"],
+                    ["tel", {"type": ["work", "voice"]}, "uri", "tel:+1-555-0100"]
+                ]]
+            }]
+        }]
+    }"#;
+
+    const RIPE_NETWORK: &str = r#"{
+        "objectClassName": "ip network",
+        "handle": "2001:db8::/32",
+        "startAddress": "2001:db8::/32",
+        "endAddress": "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff/32",
+        "name": "DOC-NET",
+        "country": "NL",
+        "entities": [{
+            "objectClassName": "entity",
+            "handle": "AR123-RIPE",
+            "roles": ["abuse"],
+            "vcardArray": ["vcard", [
+                ["version", {}, "text", "4.0"],
+                ["fn", {}, "text", ""],
+                ["email", {}, "text", "abuse@example.net"]
+            ]]
+        }]
+    }"#;
+
+    #[test]
+    fn bootstrap() {
+        let bootstrap = RdapBootstrap::default();
+        let url = |ip: &str| bootstrap.base_url(&IpAddr::from_str(ip).unwrap());
+        assert_eq!(url("8.8.8.8"), Some("https://rdap.arin.net/registry/"));
+        assert_eq!(url("193.0.6.139"), Some("https://rdap.db.ripe.net/"));
+        assert_eq!(url("1.1.1.1"), Some("https://rdap.apnic.net/"));
+        assert_eq!(url("200.1.2.3"), Some("https://rdap.lacnic.net/rdap/"));
+        assert_eq!(url("41.1.2.3"), Some("https://rdap.afrinic.net/rdap/"));
+        assert_eq!(url("2a00:1450::1"), Some("https://rdap.db.ripe.net/"));
+        assert_eq!(url("10.0.0.1"), None);
+
+        // Most specific network wins
+        let bootstrap = RdapBootstrap::from_json(&[r#"{"services": [
+            [["2001::/16"], ["http://a.example/"]],
+            [["2001:db8::/32"], ["http://b.example/", "https://b.example/"]]
+        ]}"#])
+        .unwrap();
+        let ip = IpAddr::from_str("2001:db8::1").unwrap();
+        assert_eq!(bootstrap.base_url(&ip), Some("https://b.example/"));
+    }
+
+    #[test]
+    fn parse_arin_network() {
+        let json: Value = serde_json::from_str(ARIN_NETWORK).unwrap();
+        let network = parse_network(&json, "https://rdap.example/ip/192.0.2.1").unwrap();
+        assert_eq!(network.handle.as_deref(), Some("NET-192-0-2-0-1"));
+        assert_eq!(network.name.as_deref(), Some("EXAMPLE-NET"));
+        assert_eq!(network.cidrs, vec!["192.0.2.0/24"]);
+        assert_eq!(network.country.as_deref(), Some("US"));
+        assert_eq!(network.registrant.as_deref(), Some("Example Org"));
+        let abuse = network.abuse_contact.unwrap();
+        assert_eq!(abuse.name.as_deref(), Some("Abuse Team"));
+        assert_eq!(abuse.emails, vec!["abuse@example.com"]);
+        assert_eq!(abuse.phones, vec!["+1-555-0100"]);
+    }
+
+    #[test]
+    fn parse_ripe_network() {
+        let json: Value = serde_json::from_str(RIPE_NETWORK).unwrap();
+        let network = parse_network(&json, "https://rdap.example/ip/2001:db8::1").unwrap();
+        assert_eq!(network.name.as_deref(), Some("DOC-NET"));
+        assert!(network.cidrs.is_empty());
+        assert_eq!(network.registrant, None);
+        let abuse = network.abuse_contact.unwrap();
+        assert_eq!(abuse.name, None);
+        assert_eq!(abuse.emails, vec!["abuse@example.net"]);
+
+        let json: Value = serde_json::from_str(r#"{"objectClassName": "entity"}"#).unwrap();
+        assert!(parse_network(&json, "").is_err());
+    }
+
+    /// Serves canned responses for request paths and closes each connection afterwards
+    async fn start_stub(responses: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buffer[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                // The request target can be in absolute form
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let path = Uri::from_str(target)
+                    .map(|uri| uri.path().to_string())
+                    .unwrap_or_default();
+                let response = responses
+                    .iter()
+                    .find(|(p, _)| *p == path)
+                    .map(|(_, r)| r.clone())
+                    .unwrap_or_else(|| {
+                        String::from("HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n")
+                    });
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn lookup_with_redirect() {
+        let ok = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/rdap+json\r\ncontent-length: {}\r\n\r\n{}",
+            ARIN_NETWORK.len(),
+            ARIN_NETWORK
+        );
+        let redirect = String::from(
+            "HTTP/1.1 301 Moved Permanently\r\nlocation: /other/ip/192.0.2.1\r\ncontent-length: 0\r\n\r\n",
+        );
+        let base = start_stub(vec![
+            ("/rdap/ip/192.0.2.1", redirect),
+            ("/other/ip/192.0.2.1", ok),
+        ])
+        .await;
+
+        let bootstrap = format!(r#"{{"services": [[["192.0.2.0/24"], ["{base}/rdap/"]]]}}"#);
+        let client = RdapClient::new(RdapBootstrap::from_json(&[&bootstrap]).unwrap());
+
+        let ip = IpAddr::from_str("192.0.2.1").unwrap();
+        let network = client.lookup(&ip).await.unwrap();
+        assert_eq!(network.name.as_deref(), Some("EXAMPLE-NET"));
+        assert_eq!(network.source, format!("{base}/other/ip/192.0.2.1"));
+
+        // Unknown IP at the server
+        let ip = IpAddr::from_str("192.0.2.2").unwrap();
+        assert!(client.lookup(&ip).await.is_err());
+
+        // No server for IP in bootstrap
+        let ip = IpAddr::from_str("198.51.100.1").unwrap();
+        assert!(client.lookup(&ip).await.is_err());
+    }
+}
@@ -0,0 +1,672 @@
+use crate::enrichment::IpEnrichment;
+use crate::ip_net::IpNet;
+use crate::mail::Mail;
+use crate::state::{DmarcReportWithMailId, TlsReportWithMailId};
+use crate::users::DomainScope;
+use crate::whois_parser::WhoisRecord;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Searchable entity with its key in the application state
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Entity {
+    Mail(String),
+    DmarcReport(String),
+    TlsReport(String),
+    Ip(IpAddr),
+}
+
+/// Field of an entity that matched the query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Subject,
+    Sender,
+    ReportId,
+    Org,
+    Domain,
+    SourceIp,
+    Hostname,
+    DkimSelector,
+    WhoisOrg,
+}
+
+/// Indexed terms and IPs of a single entity
+#[derive(Debug, Default)]
+struct Document {
+    /// Short description shown in the search results
+    label: String,
+    terms: BTreeSet<(String, Field)>,
+    ips: BTreeSet<IpAddr>,
+    /// Own domains of reports, used to restrict results to the domains of users
+    domains: Vec<String>,
+    /// Mail that contained a report
+    mail_id: Option<String>,
+}
+
+impl Document {
+    fn add_text(&mut self, text: &str, field: Field) {
+        for term in terms(text) {
+            self.terms.insert((term, field));
+        }
+    }
+
+    fn add_ip(&mut self, ip: IpAddr) {
+        self.add_text(&ip.to_string(), Field::SourceIp);
+        self.ips.insert(ip);
+    }
+}
+
+/// Single search result
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub label: String,
+    /// Fields that matched the query
+    pub fields: BTreeSet<Field>,
+}
+
+/// Search results grouped by entity type
+#[derive(Debug, Default, Serialize)]
+pub struct SearchResults {
+    pub mails: Vec<SearchHit>,
+    pub dmarc_reports: Vec<SearchHit>,
+    pub tls_reports: Vec<SearchHit>,
+    pub ips: Vec<SearchHit>,
+    /// Number of matching entities, including the ones cut off by the limit
+    pub total: usize,
+}
+
+/// Inverted index over mails, reports and source IPs.
+/// Documents are only added or removed when their keys appear in or disappear from the state,
+/// so unchanged mails and reports are not indexed again after each update.
+/// Source IPs are indexed as long as any indexed report references them.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    documents: HashMap<Entity, Document>,
+    terms: BTreeMap<String, HashSet<(Entity, Field)>>,
+    ips: BTreeMap<IpAddr, HashSet<Entity>>,
+    /// Number of indexed reports per source IP
+    ip_references: HashMap<IpAddr, usize>,
+    /// Indexed reports per mail ID
+    mail_reports: HashMap<String, HashSet<Entity>>,
+}
+
+impl SearchIndex {
+    /// Adds new mails and removes mails that no longer exist
+    pub fn sync_mails(&mut self, mails: &BTreeMap<String, Mail>) {
+        self.remove_missing(|entity| match entity {
+            Entity::Mail(id) => Some(mails.contains_key(id)),
+            _ => None,
+        });
+        for (id, mail) in mails {
+            let entity = Entity::Mail(id.clone());
+            if self.documents.contains_key(&entity) {
+                continue;
+            }
+            let mut document = Document {
+                label: mail.subject.clone(),
+                ..Default::default()
+            };
+            document.add_text(&mail.subject, Field::Subject);
+            document.add_text(&mail.sender, Field::Sender);
+            self.insert(entity, document);
+        }
+    }
+
+    /// Adds new DMARC reports with their source IPs and removes reports that no longer exist
+    pub fn sync_dmarc_reports(
+        &mut self,
+        reports: &BTreeMap<String, DmarcReportWithMailId>,
+        enrichments: &HashMap<IpAddr, IpEnrichment>,
+    ) {
+        self.remove_missing(|entity| match entity {
+            Entity::DmarcReport(hash) => Some(reports.contains_key(hash)),
+            _ => None,
+        });
+        for (hash, rwi) in reports {
+            let entity = Entity::DmarcReport(hash.clone());
+            if self.documents.contains_key(&entity) {
+                continue;
+            }
+            let report = &rwi.report;
+            let metadata = &report.report_metadata;
+            let mut document = Document {
+                label: format!(
+                    "{} {} ({})",
+                    metadata.org_name, metadata.report_id, report.policy_published.domain
+                ),
+                domains: vec![report.policy_published.domain.clone()],
+                mail_id: Some(rwi.mail_id.clone()),
+                ..Default::default()
+            };
+            document.add_text(&metadata.report_id, Field::ReportId);
+            document.add_text(&metadata.org_name, Field::Org);
+            document.add_text(&report.policy_published.domain, Field::Domain);
+            for record in &report.record {
+                document.add_ip(record.row.source_ip);
+                let identifiers = &record.identifiers;
+                document.add_text(&identifiers.header_from, Field::Domain);
+                for domain in [&identifiers.envelope_from, &identifiers.envelope_to]
+                    .into_iter()
+                    .flatten()
+                {
+                    document.add_text(domain, Field::Domain);
+                }
+                for dkim in record.auth_results.dkim.iter().flatten() {
+                    document.add_text(&dkim.domain, Field::Domain);
+                    if let Some(selector) = &dkim.selector {
+                        document.add_text(selector, Field::DkimSelector);
+                    }
+                }
+                for spf in &record.auth_results.spf {
+                    document.add_text(&spf.domain, Field::Domain);
+                }
+            }
+            self.insert(entity, document);
+        }
+        self.sync_ips(enrichments);
+    }
+
+    /// Adds new SMTP TLS reports with their sending MTA IPs and removes reports that no longer exist
+    pub fn sync_tls_reports(
+        &mut self,
+        reports: &BTreeMap<String, TlsReportWithMailId>,
+        enrichments: &HashMap<IpAddr, IpEnrichment>,
+    ) {
+        self.remove_missing(|entity| match entity {
+            Entity::TlsReport(hash) => Some(reports.contains_key(hash)),
+            _ => None,
+        });
+        for (hash, rwi) in reports {
+            let entity = Entity::TlsReport(hash.clone());
+            if self.documents.contains_key(&entity) {
+                continue;
+            }
+            let report = &rwi.report;
+            let mut document = Document {
+                label: format!("{} {}", report.organization_name, report.report_id),
+                domains: report
+                    .policies
+                    .iter()
+                    .map(|p| p.policy.policy_domain.clone())
+                    .collect(),
+                mail_id: Some(rwi.mail_id.clone()),
+                ..Default::default()
+            };
+            document.add_text(&report.report_id, Field::ReportId);
+            document.add_text(&report.organization_name, Field::Org);
+            for policy_result in &report.policies {
+                document.add_text(&policy_result.policy.policy_domain, Field::Domain);
+                for mx_host in policy_result.policy.mx_host.iter().flatten() {
+                    document.add_text(mx_host, Field::Hostname);
+                }
+                for details in policy_result.failure_details.iter().flatten() {
+                    if let Some(ip) = details.sending_mta_ip {
+                        document.add_ip(ip);
+                    }
+                    if let Some(hostname) = &details.receiving_mx_hostname {
+                        document.add_text(hostname, Field::Hostname);
+                    }
+                }
+            }
+            self.insert(entity, document);
+        }
+        self.sync_ips(enrichments);
+    }
+
+    /// Indexes the PTR host names and whois organizations of a source IP again
+    /// after its enrichment data changed. IPs that are not referenced by any report are ignored.
+    pub fn update_ip(&mut self, ip: IpAddr, enrichment: Option<&IpEnrichment>) {
+        if self.ip_references.contains_key(&ip) {
+            self.remove(&Entity::Ip(ip));
+            self.insert(Entity::Ip(ip), ip_document(ip, enrichment));
+        }
+    }
+
+    /// Searches for entities matching all words of the query as prefix of any of their terms.
+    /// A query with an IP or CIDR network finds the reports and source IPs in that network.
+    /// Only entities related to reports of domains in the scope are returned.
+    pub fn search(&self, query: &str, limit: usize, scope: &DomainScope) -> SearchResults {
+        let mut matches: BTreeMap<&Entity, BTreeSet<Field>> = BTreeMap::new();
+        let query = query.trim();
+        if let Ok(net) = IpNet::from_str(query) {
+            for entities in self.ips.range(net.first()..=net.last()).map(|(_, e)| e) {
+                for entity in entities {
+                    matches.entry(entity).or_default().insert(Field::SourceIp);
+                }
+            }
+        } else {
+            for (index, word) in terms_of_query(query).iter().enumerate() {
+                let mut word_matches: HashMap<&Entity, BTreeSet<Field>> = HashMap::new();
+                for (_, postings) in self
+                    .terms
+                    .range(word.clone()..)
+                    .take_while(|(term, _)| term.starts_with(word.as_str()))
+                {
+                    for (entity, field) in postings {
+                        word_matches.entry(entity).or_default().insert(*field);
+                    }
+                }
+                if index == 0 {
+                    matches.extend(word_matches);
+                } else {
+                    matches.retain(|entity, _| word_matches.contains_key(entity));
+                    for (entity, fields) in matches.iter_mut() {
+                        fields.extend(&word_matches[entity]);
+                    }
+                }
+            }
+        }
+
+        matches.retain(|entity, _| self.is_visible(entity, scope));
+        let mut results = SearchResults {
+            total: matches.len(),
+            ..Default::default()
+        };
+        for (entity, fields) in matches {
+            let (list, id) = match entity {
+                Entity::Mail(id) => (&mut results.mails, id.clone()),
+                Entity::DmarcReport(hash) => (&mut results.dmarc_reports, hash.clone()),
+                Entity::TlsReport(hash) => (&mut results.tls_reports, hash.clone()),
+                Entity::Ip(ip) => (&mut results.ips, ip.to_string()),
+            };
+            if list.len() < limit {
+                let label = self
+                    .documents
+                    .get(entity)
+                    .map(|d| d.label.clone())
+                    .unwrap_or_default();
+                list.push(SearchHit { id, label, fields });
+            }
+        }
+        results
+    }
+
+    /// Reports are visible if one of their domains is in the scope,
+    /// mails and source IPs if one of their reports is visible
+    fn is_visible(&self, entity: &Entity, scope: &DomainScope) -> bool {
+        if scope.is_all() {
+            return true;
+        }
+        match entity {
+            Entity::Mail(id) => self
+                .mail_reports
+                .get(id)
+                .is_some_and(|reports| reports.iter().any(|r| self.is_visible(r, scope))),
+            Entity::DmarcReport(_) | Entity::TlsReport(_) => self
+                .documents
+                .get(entity)
+                .is_some_and(|document| document.domains.iter().any(|domain| scope.allows(domain))),
+            Entity::Ip(ip) => self.ips.get(ip).is_some_and(|entities| {
+                entities
+                    .iter()
+                    .any(|e| !matches!(e, Entity::Ip(_)) && self.is_visible(e, scope))
+            }),
+        }
+    }
+
+    /// Removes all documents for which the check returns `Some(false)`
+    fn remove_missing(&mut self, exists: impl Fn(&Entity) -> Option<bool>) {
+        let missing: Vec<Entity> = self
+            .documents
+            .keys()
+            .filter(|entity| exists(entity) == Some(false))
+            .cloned()
+            .collect();
+        for entity in missing {
+            self.remove(&entity);
+        }
+    }
+
+    /// Adds documents for newly referenced source IPs and removes unreferenced ones
+    fn sync_ips(&mut self, enrichments: &HashMap<IpAddr, IpEnrichment>) {
+        let unreferenced: Vec<IpAddr> = self
+            .documents
+            .keys()
+            .filter_map(|entity| match entity {
+                Entity::Ip(ip) if !self.ip_references.contains_key(ip) => Some(*ip),
+                _ => None,
+            })
+            .collect();
+        for ip in unreferenced {
+            self.remove(&Entity::Ip(ip));
+        }
+        let new_ips: Vec<IpAddr> = self
+            .ip_references
+            .keys()
+            .filter(|ip| !self.documents.contains_key(&Entity::Ip(**ip)))
+            .copied()
+            .collect();
+        for ip in new_ips {
+            self.insert(Entity::Ip(ip), ip_document(ip, enrichments.get(&ip)));
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, document: Document) {
+        for (term, field) in &document.terms {
+            self.terms
+                .entry(term.clone())
+                .or_default()
+                .insert((entity.clone(), *field));
+        }
+        for ip in &document.ips {
+            self.ips.entry(*ip).or_default().insert(entity.clone());
+            if !matches!(entity, Entity::Ip(_)) {
+                *self.ip_references.entry(*ip).or_insert(0) += 1;
+            }
+        }
+        if let Some(mail_id) = &document.mail_id {
+            self.mail_reports
+                .entry(mail_id.clone())
+                .or_default()
+                .insert(entity.clone());
+        }
+        self.documents.insert(entity, document);
+    }
+
+    fn remove(&mut self, entity: &Entity) {
+        let Some(document) = self.documents.remove(entity) else {
+            return;
+        };
+        for (term, field) in document.terms {
+            if let Some(postings) = self.terms.get_mut(&term) {
+                postings.remove(&(entity.clone(), field));
+                if postings.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+        for ip in document.ips {
+            if let Some(entities) = self.ips.get_mut(&ip) {
+                entities.remove(entity);
+                if entities.is_empty() {
+                    self.ips.remove(&ip);
+                }
+            }
+            if !matches!(entity, Entity::Ip(_))
+                && let Some(count) = self.ip_references.get_mut(&ip)
+            {
+                *count -= 1;
+                if *count == 0 {
+                    self.ip_references.remove(&ip);
+                }
+            }
+        }
+        if let Some(mail_id) = document.mail_id
+            && let Some(reports) = self.mail_reports.get_mut(&mail_id)
+        {
+            reports.remove(entity);
+            if reports.is_empty() {
+                self.mail_reports.remove(&mail_id);
+            }
+        }
+    }
+}
+
+/// Document of a source IP with host names and organizations from the enrichment data
+fn ip_document(ip: IpAddr, enrichment: Option<&IpEnrichment>) -> Document {
+    let mut document = Document {
+        label: ip.to_string(),
+        ..Default::default()
+    };
+    document.add_ip(ip);
+    let Some(enrichment) = enrichment else {
+        return document;
+    };
+    if let Some(hostname) = enrichment.ptr.as_ref().and_then(|p| p.value.as_ref()) {
+        document.label = format!("{ip} ({hostname})");
+        document.add_text(hostname, Field::Hostname);
+    }
+    if let Some(checks) = &enrichment.dns_checks
+        && let Some(hostname) = &checks.value.verified_hostname
+    {
+        document.add_text(hostname, Field::Hostname);
+    }
+    if let Some(whois) = &enrichment.whois
+        && let Some(org) = WhoisRecord::parse(&whois.value).org
+    {
+        document.add_text(&org, Field::WhoisOrg);
+    }
+    if let Some(rdap) = &enrichment.rdap {
+        for org in [&rdap.value.registrant, &rdap.value.name]
+            .into_iter()
+            .flatten()
+        {
+            document.add_text(org, Field::WhoisOrg);
+        }
+    }
+    document
+}
+
+/// Splits text into lower case words and their parts.
+/// Words with dots or at signs, like domains and mail addresses, are also indexed
+/// with each suffix, so that `example.com` finds `mail.example.com`.
+fn terms(text: &str) -> BTreeSet<String> {
+    let mut terms = BTreeSet::new();
+    for word in terms_of_query(text) {
+        for (index, c) in word.char_indices() {
+            if c == '.' || c == '@' {
+                let suffix = &word[index + c.len_utf8()..];
+                if !suffix.is_empty() {
+                    terms.insert(suffix.to_string());
+                }
+            }
+        }
+        for part in word.split(|c: char| !c.is_alphanumeric()) {
+            if !part.is_empty() {
+                terms.insert(part.to_string());
+            }
+        }
+        terms.insert(word);
+    }
+    terms
+}
+
+/// Splits a query into lower case words without leading or trailing punctuation
+fn terms_of_query(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || "<>()[]{}\"',;".contains(c))
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enrichment::Timestamped;
+    use crate::{dmarc, tls};
+    use std::sync::Arc;
+
+    fn dmarc_reports(names: &[&str]) -> BTreeMap<String, DmarcReportWithMailId> {
+        names
+            .iter()
+            .map(|name| {
+                let path = format!("testdata/dmarc-reports/{name}.xml");
+                let data = std::fs::read(path).unwrap();
+                let rwi = DmarcReportWithMailId {
+                    mail_id: String::from("1"),
+                    report: dmarc::Report::from_slice(&data).unwrap(),
+                    raw: Arc::new(data),
+                };
+                (name.to_string(), rwi)
+            })
+            .collect()
+    }
+
+    fn tls_reports(names: &[&str]) -> BTreeMap<String, TlsReportWithMailId> {
+        names
+            .iter()
+            .map(|name| {
+                let path = format!("testdata/smtp-tls-reports/{name}.json");
+                let data = std::fs::read(path).unwrap();
+                let rwi = TlsReportWithMailId {
+                    mail_id: String::from("1"),
+                    report: tls::Report::from_slice(&data).unwrap(),
+                    raw: Arc::new(data),
+                };
+                (name.to_string(), rwi)
+            })
+            .collect()
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|h| h.id.as_str()).collect()
+    }
+
+    #[test]
+    fn split_terms() {
+        let terms = terms("Report Domain: mail.example.com <abc@Example.org>");
+        for expected in [
+            "report",
+            "domain",
+            "mail.example.com",
+            "example.com",
+            "com",
+            "mail",
+            "abc@example.org",
+            "example.org",
+            "abc",
+        ] {
+            assert!(terms.contains(expected), "{expected}");
+        }
+        assert_eq!(terms_of_query("  Foo.BAR: "), vec!["foo.bar"]);
+    }
+
+    #[test]
+    fn search_reports() {
+        let mut index = SearchIndex::default();
+        let enrichments = HashMap::new();
+        index.sync_dmarc_reports(&dmarc_reports(&["google", "yahoo"]), &enrichments);
+        index.sync_tls_reports(&tls_reports(&["rfc-example"]), &enrichments);
+
+        let results = index.search("3166094538684628578", 10, &DomainScope::All);
+        assert_eq!(ids(&results.dmarc_reports), vec!["google"]);
+        assert!(results.dmarc_reports[0].fields.contains(&Field::ReportId));
+
+        let results = index.search("krs", 10, &DomainScope::All);
+        assert_eq!(ids(&results.dmarc_reports), vec!["google"]);
+        assert!(
+            results.dmarc_reports[0]
+                .fields
+                .contains(&Field::DkimSelector)
+        );
+
+        let results = index.search("company-y", 10, &DomainScope::All);
+        assert_eq!(ids(&results.tls_reports), vec!["rfc-example"]);
+
+        let results = index.search("1.2.3.4", 10, &DomainScope::All);
+        assert!(ids(&results.dmarc_reports).contains(&"google"));
+        assert_eq!(ids(&results.ips), vec!["1.2.3.4"]);
+
+        let results = index.search("2001:db8:abcd::/48", 10, &DomainScope::All);
+        assert_eq!(ids(&results.tls_reports), vec!["rfc-example"]);
+        assert_eq!(results.ips.len(), 2);
+
+        let results = index.search("google company-x", 10, &DomainScope::All);
+        assert_eq!(results.total, 0);
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let mut index = SearchIndex::default();
+        let mut enrichments = HashMap::new();
+        index.sync_dmarc_reports(&dmarc_reports(&["google", "aol"]), &enrichments);
+        assert_eq!(index.search("1.2.3.4", 10, &DomainScope::All).ips.len(), 1);
+
+        // Source IPs are removed together with the last report referencing them
+        index.sync_dmarc_reports(&dmarc_reports(&["aol"]), &enrichments);
+        assert_eq!(index.search("krs", 10, &DomainScope::All).total, 0);
+        assert!(
+            index
+                .search("1.2.3.4", 10, &DomainScope::All)
+                .ips
+                .is_empty()
+        );
+        assert!(
+            index
+                .documents
+                .keys()
+                .all(|e| *e != Entity::Ip("1.2.3.4".parse().unwrap()))
+        );
+
+        // Enrichment data of new IPs is indexed
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        enrichments.insert(
+            ip,
+            IpEnrichment {
+                ptr: Some(Timestamped {
+                    value: Some(String::from("mx.example.net")),
+                    updated: 0,
+                }),
+                ..Default::default()
+            },
+        );
+        index.sync_dmarc_reports(&dmarc_reports(&["google", "aol"]), &enrichments);
+        let results = index.search("mx.example", 10, &DomainScope::All);
+        assert_eq!(ids(&results.ips), vec!["1.2.3.4"]);
+        assert_eq!(results.ips[0].label, "1.2.3.4 (mx.example.net)");
+
+        // Updated enrichment data replaces the old terms
+        let enrichment = IpEnrichment {
+            ptr: Some(Timestamped {
+                value: Some(String::from("other.example.net")),
+                updated: 0,
+            }),
+            ..Default::default()
+        };
+        index.update_ip(ip, Some(&enrichment));
+        assert_eq!(index.search("mx.example", 10, &DomainScope::All).total, 0);
+        assert_eq!(index.search("other", 10, &DomainScope::All).ips.len(), 1);
+
+        // Everything is removed without reports
+        index.sync_dmarc_reports(&BTreeMap::new(), &enrichments);
+        assert!(index.documents.is_empty());
+        assert!(index.terms.is_empty());
+        assert!(index.ips.is_empty());
+        assert!(index.mail_reports.is_empty());
+    }
+
+    #[test]
+    fn scoped_results() {
+        let mut index = SearchIndex::default();
+        let enrichments = HashMap::new();
+        let mut reports = dmarc_reports(&["google", "aol"]);
+        reports.get_mut("aol").unwrap().mail_id = String::from("2");
+        index.sync_dmarc_reports(&reports, &enrichments);
+        let mails = ["1", "2"]
+            .into_iter()
+            .map(|id| {
+                let mail = Mail {
+                    id: id.to_string(),
+                    subject: String::from("Report"),
+                    ..Default::default()
+                };
+                (id.to_string(), mail)
+            })
+            .collect();
+        index.sync_mails(&mails);
+
+        let scope = DomainScope::Domains(vec![String::from("foo-bar.io")]);
+        let results = index.search("0.0.0.0/0", 10, &scope);
+        assert_eq!(ids(&results.dmarc_reports), vec!["google"]);
+        assert_eq!(ids(&results.ips), vec!["1.2.3.4"]);
+        assert_eq!(results.total, 2);
+
+        let results = index.search("report", 10, &scope);
+        assert_eq!(ids(&results.mails), vec!["1"]);
+        assert_eq!(index.search("report", 10, &DomainScope::All).mails.len(), 2);
+    }
+
+    #[test]
+    fn limit_results() {
+        let mut index = SearchIndex::default();
+        index.sync_dmarc_reports(&dmarc_reports(&["google", "yahoo", "aol"]), &HashMap::new());
+        let results = index.search("0.0.0.0/0", 1, &DomainScope::All);
+        assert_eq!(results.dmarc_reports.len(), 1);
+        assert!(results.total > 1);
+    }
+}
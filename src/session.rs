@@ -0,0 +1,225 @@
+use crate::users::{DomainScope, Role, User};
+use anyhow::{Result, ensure};
+use axum::http::HeaderMap;
+use axum::http::header::COOKIE;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// Name of the cookie with the session of users logged in via OpenID Connect
+pub const SESSION_COOKIE: &str = "dmarc_session";
+
+/// Name of the cookie that binds a started login to the browser
+pub const LOGIN_COOKIE: &str = "dmarc_login";
+
+/// Lifetime of the login cookie in seconds
+const LOGIN_COOKIE_LIFETIME: u64 = 600;
+
+/// Content of the session cookie
+#[derive(Serialize, Deserialize)]
+struct SessionData {
+    name: String,
+    role: Role,
+    /// None if all domains are visible
+    domains: Option<Vec<String>>,
+    /// Expiration time as Unix timestamp
+    exp: u64,
+}
+
+/// Creates and verifies session cookies signed with HMAC-SHA256.
+/// The sessions are not stored on the server, they are valid until they expire.
+pub struct Sessions {
+    key: hmac::Key,
+    lifetime: u64,
+    secure: bool,
+}
+
+impl Sessions {
+    /// Without secret a random key is used and all sessions end with a restart of the application.
+    /// Secure cookies are only sent by browsers via HTTPS.
+    pub fn new(secret: Option<&str>, lifetime_hours: u64, secure: bool) -> Result<Self> {
+        let key = if let Some(secret) = secret {
+            ensure!(
+                secret.len() >= 32,
+                "Session secret must be at least 32 characters long"
+            );
+            hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())
+        } else {
+            hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .map_err(|_| anyhow::anyhow!("Failed to generate session key"))?
+        };
+        ensure!(lifetime_hours > 0, "Session lifetime must not be zero");
+        Ok(Self {
+            key,
+            lifetime: lifetime_hours * 3600,
+            secure,
+        })
+    }
+
+    /// Value for a `Set-Cookie` header that starts a new session for the user
+    pub fn create_cookie(&self, user: &User) -> String {
+        let data = SessionData {
+            name: user.name.clone(),
+            role: user.role,
+            domains: match &user.scope {
+                DomainScope::All => None,
+                DomainScope::Domains(domains) => Some(domains.clone()),
+            },
+            exp: unix_time() + self.lifetime,
+        };
+        let json = serde_json::to_vec(&data).expect("Failed to serialize session");
+        let payload = URL_SAFE_NO_PAD.encode(json);
+        let tag = hmac::sign(&self.key, payload.as_bytes());
+        let value = format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()));
+        self.cookie(SESSION_COOKIE, &value, self.lifetime)
+    }
+
+    /// Value for a `Set-Cookie` header that removes the session cookie
+    pub fn remove_cookie(&self) -> String {
+        self.cookie(SESSION_COOKIE, "", 0)
+    }
+
+    /// Value for a `Set-Cookie` header with the state of a started login
+    pub fn login_cookie(&self, state: &str) -> String {
+        self.cookie(LOGIN_COOKIE, state, LOGIN_COOKIE_LIFETIME)
+    }
+
+    /// Value for a `Set-Cookie` header that removes the login cookie
+    pub fn remove_login_cookie(&self) -> String {
+        self.cookie(LOGIN_COOKIE, "", 0)
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: u64) -> String {
+        let secure = if self.secure { "; Secure" } else { "" };
+        format!("{name}={value}; Max-Age={max_age}; Path=/; HttpOnly; SameSite=Lax{secure}")
+    }
+
+    /// Returns the user of a valid session cookie in the request headers
+    pub fn user(&self, headers: &HeaderMap) -> Option<User> {
+        cookie_values(headers, SESSION_COOKIE)
+            .into_iter()
+            .find_map(|value| self.verify(value, unix_time()))
+    }
+
+    fn verify(&self, value: &str, now: u64) -> Option<User> {
+        let (payload, tag) = value.split_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        hmac::verify(&self.key, payload.as_bytes(), &tag).ok()?;
+        let json = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        let data: SessionData = serde_json::from_slice(&json).ok()?;
+        if data.exp <= now {
+            return None;
+        }
+        Some(User {
+            name: data.name,
+            role: data.role,
+            scope: match data.domains {
+                None => DomainScope::All,
+                Some(domains) => DomainScope::Domains(domains),
+            },
+        })
+    }
+}
+
+/// Returns the values of all cookies with the name from the request headers
+pub fn cookie_values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .filter(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// Creates random URL safe strings for the state, nonce and PKCE verifier of logins
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to create random bytes");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Failed to get Unix time stamp")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn viewer() -> User {
+        User {
+            name: String::from("jane"),
+            role: Role::Viewer,
+            scope: DomainScope::Domains(vec![String::from("example.com")]),
+        }
+    }
+
+    fn headers(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[test]
+    fn session_cookie() {
+        let sessions = Sessions::new(None, 1, true).unwrap();
+        let set_cookie = sessions.create_cookie(&viewer());
+        assert!(set_cookie.contains("; HttpOnly; SameSite=Lax; Secure"));
+        let value = set_cookie.split(';').next().unwrap();
+
+        let user = sessions
+            .user(&headers(&format!("other=1; {value}")))
+            .unwrap();
+        assert_eq!(user.name, "jane");
+        assert_eq!(user.role, Role::Viewer);
+        assert_eq!(user.scope, viewer().scope);
+
+        // Other key
+        let other = Sessions::new(Some(&"x".repeat(32)), 1, false).unwrap();
+        assert!(other.user(&headers(value)).is_none());
+    }
+
+    #[test]
+    fn tampered_and_expired_sessions() {
+        let sessions = Sessions::new(Some(&"s".repeat(32)), 1, false).unwrap();
+        let set_cookie = sessions.create_cookie(&viewer());
+        let value = set_cookie
+            .split(';')
+            .next()
+            .unwrap()
+            .strip_prefix("dmarc_session=")
+            .unwrap();
+        assert!(sessions.verify(value, unix_time()).is_some());
+        assert!(sessions.verify(value, unix_time() + 3600).is_none());
+
+        // Payload with admin role and the original signature
+        let (_, tag) = value.split_once('.').unwrap();
+        let data = SessionData {
+            name: String::from("jane"),
+            role: Role::Admin,
+            domains: None,
+            exp: unix_time() + 3600,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&data).unwrap());
+        assert!(
+            sessions
+                .verify(&format!("{payload}.{tag}"), unix_time())
+                .is_none()
+        );
+        assert!(sessions.verify("garbage", unix_time()).is_none());
+
+        assert!(Sessions::new(Some("short"), 1, false).is_err());
+    }
+}
@@ -1,13 +1,22 @@
+use crate::asn_db::AsnDatabase;
 use crate::dns_client::DnsClient;
 use crate::dns_client_cached::DnsClientCached;
-use crate::geolocate::Location;
+use crate::enrichment::IpEnrichment;
+use crate::events::{self, Event};
+use crate::geolocate::{Location, LocationProvider};
+use crate::mta_sts::StsPolicyCache;
+use crate::search::SearchIndex;
+use crate::update_status::UpdateTracker;
+use crate::users::DomainScope;
 use crate::{cache_map::CacheMap, mail::Mail};
 use crate::{dmarc, tls};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::IpAddr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
+use tokio::sync::broadcast;
 
 const CACHE_SIZE: usize = 10000;
 
@@ -16,13 +25,76 @@ const CACHE_SIZE: usize = 10000;
 pub struct DmarcReportWithMailId {
     pub mail_id: String,
     pub report: dmarc::Report,
+    /// Original bytes of the extracted XML file
+    #[serde(skip)]
+    pub raw: Arc<Vec<u8>>,
+}
+
+impl DmarcReportWithMailId {
+    /// True if the domain of the published policy is in the scope
+    pub fn is_visible(&self, scope: &DomainScope) -> bool {
+        scope.allows(&self.report.policy_published.domain)
+    }
 }
 
 /// SMTP TLS report with ID of the mail that contained the report
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TlsReportWithMailId {
     pub mail_id: String,
     pub report: tls::Report,
+    /// Original bytes of the extracted JSON file
+    #[serde(skip)]
+    pub raw: Arc<Vec<u8>>,
+}
+
+impl TlsReportWithMailId {
+    /// True if the scope contains the domain of at least one of the policies
+    pub fn is_visible(&self, scope: &DomainScope) -> bool {
+        self.report
+            .policies
+            .iter()
+            .any(|p| scope.allows(&p.policy.policy_domain))
+    }
+
+    /// The report with only the policies of the domains in the scope.
+    /// Reports can contain policies of several domains that are not all visible.
+    pub fn scoped_report(&self, scope: &DomainScope) -> Cow<'_, tls::Report> {
+        let policies = &self.report.policies;
+        if policies
+            .iter()
+            .all(|p| scope.allows(&p.policy.policy_domain))
+        {
+            return Cow::Borrowed(&self.report);
+        }
+        let mut report = self.report.clone();
+        report
+            .policies
+            .retain(|p| scope.allows(&p.policy.policy_domain));
+        Cow::Owned(report)
+    }
+
+    /// Same as the report with mail ID, but only with the policies of the domains in the scope
+    pub fn scoped(&self, scope: &DomainScope) -> Cow<'_, Self> {
+        match self.scoped_report(scope) {
+            Cow::Borrowed(_) => Cow::Borrowed(self),
+            Cow::Owned(report) => Cow::Owned(Self {
+                mail_id: self.mail_id.clone(),
+                report,
+                raw: self.raw.clone(),
+            }),
+        }
+    }
+
+    /// Sending and receiving IPs from the failure details of the policies in the scope
+    pub fn scoped_ips<'a>(&'a self, scope: &'a DomainScope) -> impl Iterator<Item = IpAddr> + 'a {
+        self.report
+            .policies
+            .iter()
+            .filter(move |p| scope.allows(&p.policy.policy_domain))
+            .flat_map(|p| p.failure_details.iter().flatten())
+            .flat_map(|d| [d.sending_mta_ip, d.receiving_ip])
+            .flatten()
+    }
 }
 
 /// The type of a file that can contain report data
@@ -80,10 +152,48 @@ pub struct AppState {
 
     /// DNS client with cache
     pub dns_client: Arc<DnsClientCached>,
+
+    /// MTA-STS policies fetched for the checks of domains
+    pub sts_policies: Arc<StsPolicyCache>,
+
+    /// Optional offline IP to ASN database
+    pub asn_database: Option<Arc<AsnDatabase>>,
+
+    /// Provider for IP locations, None if lookups are disabled
+    pub location_provider: Option<Arc<dyn LocationProvider>>,
+
+    /// DNSBL zones for the DNS checks of source IPs
+    pub dnsbl_zones: Vec<String>,
+
+    /// PTR, location and whois information for source IPs from the background enrichment
+    pub enrichments: HashMap<IpAddr, IpEnrichment>,
+
+    /// Incremented after changes of the enrichments, including the locations cached by them.
+    /// Part of the ETags of API responses.
+    pub enrichments_version: u64,
+
+    /// Incremented with every location lookup of the API, since they change the source details.
+    /// Part of the ETags of API responses.
+    pub locations_version: u64,
+
+    /// Search index over mails, reports and source IPs.
+    /// Has its own lock to allow searches without locking the whole state.
+    pub search_index: Arc<RwLock<SearchIndex>>,
+
+    /// Channel for the events of the background task, subscribed by the `/events` endpoint
+    pub events: broadcast::Sender<Event>,
+
+    /// Status and progress of the background update, also used to request updates
+    pub update_tracker: UpdateTracker,
 }
 
 impl AppState {
-    pub fn new(dns_client: DnsClient) -> Self {
+    pub fn new(
+        dns_client: DnsClient,
+        asn_database: Option<AsnDatabase>,
+        location_provider: Option<Arc<dyn LocationProvider>>,
+        dnsbl_zones: Vec<String>,
+    ) -> Self {
         let dns_client = Arc::new(DnsClientCached::new(dns_client, CACHE_SIZE));
         let start_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -100,8 +210,177 @@ impl AppState {
             parsing_errors: HashMap::new(),
             ip_location_cache: CacheMap::new(CACHE_SIZE).expect("Failed to create location cache"),
             dns_client,
+            sts_policies: Arc::new(StsPolicyCache::new(CACHE_SIZE)),
+            asn_database: asn_database.map(Arc::new),
+            location_provider,
+            dnsbl_zones,
+            enrichments: HashMap::new(),
+            enrichments_version: 0,
+            locations_version: 0,
+            search_index: Arc::new(RwLock::new(SearchIndex::default())),
+            events: broadcast::channel(events::CHANNEL_CAPACITY).0,
+            update_tracker: UpdateTracker::default(),
             start_time,
             last_update_duration: 0.0,
         }
     }
+
+    /// DMARC reports visible in the scope
+    pub fn scoped_dmarc_reports<'a>(
+        &'a self,
+        scope: &'a DomainScope,
+    ) -> impl Iterator<Item = (&'a String, &'a DmarcReportWithMailId)> {
+        self.dmarc_reports
+            .iter()
+            .filter(move |(_, rwi)| rwi.is_visible(scope))
+    }
+
+    /// SMTP TLS reports visible in the scope
+    pub fn scoped_tls_reports<'a>(
+        &'a self,
+        scope: &'a DomainScope,
+    ) -> impl Iterator<Item = (&'a String, &'a TlsReportWithMailId)> {
+        self.tls_reports
+            .iter()
+            .filter(move |(_, rwi)| rwi.is_visible(scope))
+    }
+
+    /// Mails that contain at least one report visible in the scope.
+    /// Without domain restrictions, this includes mails without any reports.
+    pub fn scoped_mails<'a>(
+        &'a self,
+        scope: &'a DomainScope,
+    ) -> impl Iterator<Item = (&'a String, &'a Mail)> {
+        let visible = self.visible_mail_ids(scope);
+        self.mails
+            .iter()
+            .filter(move |(id, _)| scope.is_all() || visible.contains(id.as_str()))
+    }
+
+    /// True if the mail exists and is visible in the scope
+    pub fn is_mail_visible(&self, id: &str, scope: &DomainScope) -> bool {
+        self.mails.contains_key(id)
+            && (scope.is_all()
+                || self
+                    .dmarc_reports
+                    .values()
+                    .any(|r| r.mail_id == id && r.is_visible(scope))
+                || self
+                    .tls_reports
+                    .values()
+                    .any(|r| r.mail_id == id && r.is_visible(scope)))
+    }
+
+    /// True if the IP is part of a report visible in the scope,
+    /// like the source IPs in the search results
+    pub fn is_ip_visible(&self, ip: &IpAddr, scope: &DomainScope) -> bool {
+        scope.is_all()
+            || self
+                .scoped_dmarc_reports(scope)
+                .any(|(_, r)| r.report.record.iter().any(|rec| rec.row.source_ip == *ip))
+            || self
+                .scoped_tls_reports(scope)
+                .any(|(_, r)| r.scoped_ips(scope).any(|i| i == *ip))
+    }
+
+    /// IPs of all reports visible in the scope, empty without domain restrictions
+    pub fn visible_ips(&self, scope: &DomainScope) -> HashSet<IpAddr> {
+        if scope.is_all() {
+            return HashSet::new();
+        }
+        self.scoped_dmarc_reports(scope)
+            .flat_map(|(_, r)| r.report.record.iter().map(|rec| rec.row.source_ip))
+            .chain(
+                self.scoped_tls_reports(scope)
+                    .flat_map(|(_, r)| r.scoped_ips(scope)),
+            )
+            .collect()
+    }
+
+    fn visible_mail_ids<'a>(&'a self, scope: &'a DomainScope) -> HashSet<&'a str> {
+        if scope.is_all() {
+            return HashSet::new();
+        }
+        self.scoped_dmarc_reports(scope)
+            .map(|(_, r)| r.mail_id.as_str())
+            .chain(
+                self.scoped_tls_reports(scope)
+                    .map(|(_, r)| r.mail_id.as_str()),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_client::DnsServer;
+    use std::time::Duration;
+
+    #[test]
+    fn scoped_tls_report() {
+        let json = std::fs::read("testdata/smtp-tls-reports/rfc-example.json").unwrap();
+        let mut report = tls::Report::from_slice(&json).unwrap();
+        let mut other = report.policies[0].clone();
+        other.policy.policy_domain = String::from("other.example");
+        report.policies.push(other);
+        let rwi = TlsReportWithMailId {
+            mail_id: String::from("mail"),
+            report,
+            raw: Arc::new(json),
+        };
+
+        let all = DomainScope::All;
+        assert!(matches!(rwi.scoped_report(&all), Cow::Borrowed(_)));
+        assert!(matches!(rwi.scoped(&all), Cow::Borrowed(_)));
+
+        let scope = DomainScope::Domains(vec![String::from("company-y.example")]);
+        let scoped = rwi.scoped(&scope);
+        assert_eq!(scoped.mail_id, "mail");
+        assert_eq!(scoped.report.policies.len(), 1);
+        assert_eq!(
+            scoped.report.policies[0].policy.policy_domain,
+            "company-y.example"
+        );
+        let json = serde_json::to_string(&scoped).unwrap();
+        assert!(!json.contains("other.example"));
+    }
+
+    #[test]
+    fn visible_ips() {
+        let json = std::fs::read("testdata/smtp-tls-reports/rfc-example.json").unwrap();
+        let report = tls::Report::from_slice(&json).unwrap();
+        let ips: HashSet<IpAddr> = report
+            .policies
+            .iter()
+            .flat_map(|p| p.failure_details.iter().flatten())
+            .flat_map(|d| [d.sending_mta_ip, d.receiving_ip])
+            .flatten()
+            .collect();
+        assert!(!ips.is_empty());
+
+        let server: DnsServer = "127.0.0.1:53".parse().unwrap();
+        let dns_client = DnsClient::new(vec![server], Duration::from_secs(1), false).unwrap();
+        let mut state = AppState::new(dns_client, None, None, Vec::new());
+        state.tls_reports.insert(
+            String::from("hash"),
+            TlsReportWithMailId {
+                mail_id: String::from("mail"),
+                report,
+                raw: Arc::new(json),
+            },
+        );
+
+        let own = DomainScope::Domains(vec![String::from("company-y.example")]);
+        let other = DomainScope::Domains(vec![String::from("other.example")]);
+        let unknown = IpAddr::from([198, 51, 100, 1]);
+        for ip in &ips {
+            assert!(state.is_ip_visible(ip, &own));
+            assert!(!state.is_ip_visible(ip, &other));
+        }
+        assert!(!state.is_ip_visible(&unknown, &own));
+        assert!(state.is_ip_visible(&unknown, &DomainScope::All));
+        assert_eq!(state.visible_ips(&own), ips);
+        assert!(state.visible_ips(&other).is_empty());
+    }
 }
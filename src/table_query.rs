@@ -0,0 +1,419 @@
+use anyhow::{Context, Result, bail, ensure};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+/// Flat row of a table with column names as keys
+pub type Row = Map<String, Value>;
+
+/// Number of rows per page if no limit was requested
+const DEFAULT_LIMIT: usize = 100;
+
+/// Maximum number of rows per page
+const MAX_LIMIT: usize = 1000;
+
+/// Query parameters with a special meaning, all others are column filters
+const RESERVED_PARAMS: [&str; 4] = ["group_by", "sort", "limit", "cursor"];
+
+/// Name of the column with the summed up counts of grouped rows
+const COUNT_COLUMN: &str = "count";
+
+/// Name of the column with the number of rows in a group
+const ROWS_COLUMN: &str = "rows";
+
+/// Name of the column with the unique key of grouped rows
+const GROUP_KEY_COLUMN: &str = "key";
+
+/// Filtering, grouping, sorting and cursor pagination for flat rows.
+/// Filters compare the string form of values case-insensitively
+/// and match columns with lists if any of the elements matches.
+#[derive(Debug, Default, PartialEq)]
+pub struct TableQuery {
+    pub filters: Vec<(String, String)>,
+    pub group_by: Vec<String>,
+    /// Columns to sort by with true for descending order
+    pub sort: Vec<(String, bool)>,
+    pub limit: usize,
+    /// Key of the last row of the previous page
+    pub cursor: Option<String>,
+}
+
+/// Single page of rows
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub rows: Vec<Row>,
+    /// Number of matching rows or groups on all pages
+    pub total: usize,
+    /// Cursor for the next page, None for the last page
+    pub next_cursor: Option<String>,
+}
+
+impl TableQuery {
+    /// Reads the query from URL parameters like
+    /// `org=google.com&group_by=source_ip&sort=-count&limit=50`.
+    /// All parameters and columns in them must be known columns.
+    pub fn from_params(params: &HashMap<String, String>, columns: &[&str]) -> Result<Self> {
+        let check_column = |column: &str| -> Result<String> {
+            ensure!(columns.contains(&column), "Unknown column: {column}");
+            Ok(column.to_string())
+        };
+        let list = |name: &str| {
+            params
+                .get(name)
+                .map(|v| {
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|c| !c.is_empty())
+                        .map(String::from)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+
+        let mut filters = Vec::new();
+        for (name, value) in params {
+            if !RESERVED_PARAMS.contains(&name.as_str()) {
+                filters.push((check_column(name)?, value.clone()));
+            }
+        }
+        filters.sort();
+
+        let group_by = list("group_by")
+            .iter()
+            .map(|c| check_column(c))
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(
+            !group_by.iter().any(|c| c == COUNT_COLUMN),
+            "Cannot group by column {COUNT_COLUMN}"
+        );
+
+        let mut sort = Vec::new();
+        for column in list("sort") {
+            let (column, descending) = match column.strip_prefix('-') {
+                Some(column) => (column.to_string(), true),
+                None => (column, false),
+            };
+            // Grouped rows can also be sorted by their aggregated columns
+            let aggregated =
+                !group_by.is_empty() && [COUNT_COLUMN, ROWS_COLUMN].contains(&column.as_str());
+            if !aggregated {
+                check_column(&column)?;
+                if !group_by.is_empty() && !group_by.contains(&column) {
+                    bail!("Cannot sort grouped rows by column {column}");
+                }
+            }
+            sort.push((column, descending));
+        }
+
+        let limit = match params.get("limit") {
+            Some(limit) => limit
+                .parse::<usize>()
+                .context("Invalid limit")?
+                .clamp(1, MAX_LIMIT),
+            None => DEFAULT_LIMIT,
+        };
+
+        Ok(Self {
+            filters,
+            group_by,
+            sort,
+            limit,
+            cursor: params.get("cursor").cloned(),
+        })
+    }
+
+    /// Returns the columns of the selected rows, for groups these are the grouped columns and the sums
+    pub fn columns(&self, columns: &[&str]) -> Vec<String> {
+        if self.group_by.is_empty() {
+            columns.iter().map(|c| c.to_string()).collect()
+        } else {
+            let mut columns = self.group_by.clone();
+            columns.push(String::from(COUNT_COLUMN));
+            columns.push(String::from(ROWS_COLUMN));
+            columns
+        }
+    }
+
+    /// Filters, groups and sorts the rows without pagination, like for exports.
+    /// Returns the rows with the name of their key column, which is `key` for groups.
+    pub fn select<'a>(&self, rows: Vec<Row>, key_column: &'a str) -> (Vec<Row>, &'a str) {
+        let mut rows: Vec<Row> = rows
+            .into_iter()
+            .filter(|row| {
+                self.filters
+                    .iter()
+                    .all(|(column, value)| matches(row.get(column), value))
+            })
+            .collect();
+
+        let key_column = if self.group_by.is_empty() {
+            key_column
+        } else {
+            rows = group(rows, &self.group_by);
+            GROUP_KEY_COLUMN
+        };
+
+        // Without explicit order, groups with the largest counts come first
+        let default_sort = [(String::from(COUNT_COLUMN), true)];
+        let sort = if self.sort.is_empty() && !self.group_by.is_empty() {
+            &default_sort[..]
+        } else {
+            &self.sort[..]
+        };
+        rows.sort_by(|a, b| {
+            sort.iter()
+                .map(|(column, descending)| {
+                    let ordering = compare(a.get(column), b.get(column));
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| compare(a.get(key_column), b.get(key_column)))
+        });
+
+        (rows, key_column)
+    }
+
+    /// Applies the query to the rows.
+    /// The key column must contain a unique string for each row and is used for the cursor.
+    pub fn apply(&self, rows: Vec<Row>, key_column: &str) -> Result<Page> {
+        let (rows, key_column) = self.select(rows, key_column);
+        let total = rows.len();
+        let start = match &self.cursor {
+            Some(cursor) => {
+                let position = rows
+                    .iter()
+                    .position(|row| row.get(key_column).and_then(Value::as_str) == Some(cursor))
+                    .context("Invalid cursor")?;
+                position + 1
+            }
+            None => 0,
+        };
+        let rows: Vec<Row> = rows.into_iter().skip(start).take(self.limit).collect();
+        let next_cursor = if start + rows.len() < total {
+            rows.last()
+                .and_then(|row| row.get(key_column))
+                .and_then(Value::as_str)
+                .map(String::from)
+        } else {
+            None
+        };
+
+        Ok(Page {
+            rows,
+            total,
+            next_cursor,
+        })
+    }
+}
+
+/// String form of a value for filtering, grouping and exports
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values
+            .iter()
+            .map(value_to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+fn matches(value: Option<&Value>, expected: &str) -> bool {
+    match value {
+        Some(Value::Array(values)) => values
+            .iter()
+            .any(|v| value_to_string(v).eq_ignore_ascii_case(expected)),
+        Some(value) => value_to_string(value).eq_ignore_ascii_case(expected),
+        None => expected.is_empty(),
+    }
+}
+
+/// Orders missing values first, numbers by value and everything else by its string form
+fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    let a = a.filter(|v| !v.is_null());
+    let b = b.filter(|v| !v.is_null());
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(Value::Number(a)), Some(Value::Number(b))) => {
+            let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            a.total_cmp(&b)
+        }
+        (Some(a), Some(b)) => value_to_string(a).cmp(&value_to_string(b)),
+    }
+}
+
+/// Groups the rows by the columns and sums up their counts
+fn group(rows: Vec<Row>, columns: &[String]) -> Vec<Row> {
+    let mut groups: BTreeMap<Vec<String>, Row> = BTreeMap::new();
+    for row in rows {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|c| row.get(c).map(value_to_string).unwrap_or_default())
+            .collect();
+        let count = row.get(COUNT_COLUMN).and_then(Value::as_u64).unwrap_or(1);
+        let group = groups.entry(values.clone()).or_insert_with(|| {
+            let mut group = Row::new();
+            for column in columns {
+                group.insert(
+                    column.clone(),
+                    row.get(column).cloned().unwrap_or(Value::Null),
+                );
+            }
+            let key = serde_json::to_string(&values).expect("Failed to serialize JSON");
+            group.insert(String::from(GROUP_KEY_COLUMN), Value::from(key));
+            group.insert(String::from(COUNT_COLUMN), Value::from(0u64));
+            group.insert(String::from(ROWS_COLUMN), Value::from(0u64));
+            group
+        });
+        for (column, add) in [(COUNT_COLUMN, count), (ROWS_COLUMN, 1)] {
+            let sum = group.get(column).and_then(Value::as_u64).unwrap_or(0) + add;
+            group.insert(String::from(column), Value::from(sum));
+        }
+    }
+    groups.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const COLUMNS: [&str; 5] = ["id", "org", "source_ip", "count", "dkim_domains"];
+
+    fn rows() -> Vec<Row> {
+        [
+            json!({"id": "a", "org": "Google", "source_ip": "192.0.2.1", "count": 5, "dkim_domains": ["example.com"]}),
+            json!({"id": "b", "org": "google", "source_ip": "192.0.2.2", "count": 1, "dkim_domains": []}),
+            json!({"id": "c", "org": "Yahoo", "source_ip": "192.0.2.1", "count": 10, "dkim_domains": ["example.com", "example.net"]}),
+            json!({"id": "d", "org": "Outlook", "source_ip": "192.0.2.3", "count": 2, "dkim_domains": ["example.net"]}),
+        ]
+        .into_iter()
+        .map(|v| v.as_object().unwrap().clone())
+        .collect()
+    }
+
+    fn query(params: &[(&str, &str)]) -> Result<TableQuery> {
+        let params = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        TableQuery::from_params(&params, &COLUMNS)
+    }
+
+    fn ids(page: &Page, column: &str) -> Vec<String> {
+        page.rows
+            .iter()
+            .map(|r| value_to_string(r.get(column).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_params() {
+        let q = query(&[
+            ("org", "google"),
+            ("group_by", "org,source_ip"),
+            ("sort", "-count,org"),
+            ("limit", "5000"),
+        ])
+        .unwrap();
+        assert_eq!(
+            q.filters,
+            vec![(String::from("org"), String::from("google"))]
+        );
+        assert_eq!(q.group_by, vec!["org", "source_ip"]);
+        assert_eq!(
+            q.sort,
+            vec![(String::from("count"), true), (String::from("org"), false)]
+        );
+        assert_eq!(q.limit, MAX_LIMIT);
+
+        assert!(query(&[("unknown", "x")]).is_err());
+        assert!(query(&[("sort", "unknown")]).is_err());
+        assert!(query(&[("group_by", "org"), ("sort", "source_ip")]).is_err());
+        assert!(query(&[("limit", "x")]).is_err());
+        assert!(query(&[("group_by", "count")]).is_err());
+    }
+
+    #[test]
+    fn filter_and_sort() {
+        let page = query(&[("org", "GOOGLE")])
+            .unwrap()
+            .apply(rows(), "id")
+            .unwrap();
+        assert_eq!(ids(&page, "id"), vec!["a", "b"]);
+
+        let page = query(&[("dkim_domains", "example.net"), ("sort", "-count")])
+            .unwrap()
+            .apply(rows(), "id")
+            .unwrap();
+        assert_eq!(ids(&page, "id"), vec!["c", "d"]);
+        assert_eq!(page.total, 2);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn group_rows() {
+        let page = query(&[("group_by", "source_ip")])
+            .unwrap()
+            .apply(rows(), "id")
+            .unwrap();
+        assert_eq!(
+            ids(&page, "source_ip"),
+            vec!["192.0.2.1", "192.0.2.3", "192.0.2.2"]
+        );
+        assert_eq!(ids(&page, "count"), vec!["15", "2", "1"]);
+        assert_eq!(ids(&page, "rows"), vec!["2", "1", "1"]);
+        assert_eq!(
+            ids(&page, "key"),
+            vec!["[\"192.0.2.1\"]", "[\"192.0.2.3\"]", "[\"192.0.2.2\"]"]
+        );
+    }
+
+    #[test]
+    fn select_for_exports() {
+        let grouped = query(&[("group_by", "source_ip"), ("limit", "1")]).unwrap();
+        assert_eq!(
+            grouped.columns(&COLUMNS),
+            vec!["source_ip", "count", "rows"]
+        );
+        let (groups, key_column) = grouped.select(rows(), "id");
+        assert_eq!(key_column, "key");
+        assert_eq!(groups.len(), 3);
+
+        let plain = query(&[("sort", "-count")]).unwrap();
+        assert_eq!(plain.columns(&COLUMNS), COLUMNS);
+        let (sorted, key_column) = plain.select(rows(), "id");
+        assert_eq!(key_column, "id");
+        assert_eq!(sorted[0].get("id"), Some(&json!("c")));
+    }
+
+    #[test]
+    fn cursor_pagination() {
+        let first = query(&[("sort", "count"), ("limit", "3")])
+            .unwrap()
+            .apply(rows(), "id")
+            .unwrap();
+        assert_eq!(ids(&first, "id"), vec!["b", "d", "a"]);
+        assert_eq!(first.total, 4);
+        assert_eq!(first.next_cursor.as_deref(), Some("a"));
+
+        let second = query(&[("sort", "count"), ("limit", "3"), ("cursor", "a")])
+            .unwrap()
+            .apply(rows(), "id")
+            .unwrap();
+        assert_eq!(ids(&second, "id"), vec!["c"]);
+        assert_eq!(second.next_cursor, None);
+
+        let invalid = query(&[("cursor", "x")]).unwrap().apply(rows(), "id");
+        assert!(invalid.is_err());
+    }
+}
@@ -5,7 +5,7 @@ use std::net::IpAddr;
 
 /// The time range covered by messages in this report.
 /// Formatted according to "Internet Date/Time Format", Section 5.6 of RFC3339.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct DateRange {
     pub start_datetime: DateTime<Utc>,
@@ -25,7 +25,7 @@ pub enum PolicyType {
 }
 
 /// The policy evaluated by the reporting organization.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Policy {
     /// The type of policy that was applied by the sending domain.
@@ -52,7 +52,7 @@ pub enum TlsResultType {
 }
 
 /// A summary of the policy evaluation result.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Summary {
     pub total_successful_session_count: usize,
@@ -101,7 +101,7 @@ pub enum FailureResultType {
 }
 
 /// Aggregated failure details for a single result type.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct FailureDetails {
     /// The type of the failure.
@@ -134,7 +134,7 @@ pub struct FailureDetails {
 }
 
 /// An evaluation result for a single policy.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct PolicyResult {
     /// The policy that was evaluated.
@@ -147,7 +147,7 @@ pub struct PolicyResult {
     pub failure_details: Option<Vec<FailureDetails>>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Report {
     /// The name of the organization responsible for the report.
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// DANE TLSA record as defined in RFC 6698
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsaRecord {
+    /// Certificate usage, like 3 for DANE-EE
+    pub usage: u8,
+    /// Selector, 0 for the full certificate or 1 for the public key
+    pub selector: u8,
+    /// Matching type, 0 for exact match, 1 for SHA-256 or 2 for SHA-512
+    pub matching_type: u8,
+    /// Certificate association data as lower case hex string
+    pub data: String,
+}
+
+impl TlsaRecord {
+    /// Parses the presentation format of a record, like `3 1 1 0B9FA5...`.
+    /// The owner name, TTL, class and type in front of the data are optional
+    /// and hex data split by whitespace is joined together.
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let start = tokens
+            .iter()
+            .position(|t| t.eq_ignore_ascii_case("TLSA"))
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        let fields = &tokens[start..];
+        ensure!(fields.len() >= 4, "TLSA record has not enough fields");
+        let number = |index: usize, name: &str| -> Result<u8> {
+            fields[index]
+                .parse()
+                .with_context(|| format!("Invalid TLSA {name}: {}", fields[index]))
+        };
+        let data = fields[3..].concat().to_ascii_lowercase();
+        if data.is_empty() || !data.chars().all(|c| c.is_ascii_hexdigit()) || data.len() % 2 != 0 {
+            bail!("Invalid TLSA association data");
+        }
+        Ok(Self {
+            usage: number(0, "usage")?,
+            selector: number(1, "selector")?,
+            matching_type: number(2, "matching type")?,
+            data,
+        })
+    }
+
+    /// Reads the record from the binary data of a DNS answer
+    pub fn from_wire(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() > 3, "TLSA record is too short");
+        Ok(Self {
+            usage: data[0],
+            selector: data[1],
+            matching_type: data[2],
+            data: data[3..].iter().map(|b| format!("{b:02x}")).collect(),
+        })
+    }
+
+    /// Short form for messages with the association data cut off, like `3 1 1 0b9fa5a5...`
+    pub fn short(&self) -> String {
+        let data = match self.data.get(..12) {
+            Some(start) if self.data.len() > 12 => format!("{start}..."),
+            _ => self.data.clone(),
+        };
+        format!(
+            "{} {} {} {}",
+            self.usage, self.selector, self.matching_type, data
+        )
+    }
+}
+
+impl Display for TlsaRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.usage, self.selector, self.matching_type, self.data
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_presentation_format() {
+        let record = TlsaRecord::parse(
+            "3 0 1 1F850A337E6DB9C609C522D136A475638CC43E1ED424F8EEC8513D747D1D085D",
+        )
+        .unwrap();
+        assert_eq!(record.usage, 3);
+        assert_eq!(record.selector, 0);
+        assert_eq!(record.matching_type, 1);
+        assert_eq!(
+            record.data,
+            "1f850a337e6db9c609c522d136a475638cc43e1ed424f8eec8513d747d1d085d"
+        );
+        assert_eq!(record.short(), "3 0 1 1f850a337e6d...");
+
+        let full =
+            TlsaRecord::parse("_25._tcp.mx.example.com. 3600 IN TLSA 2 1 1 ABCD EF01").unwrap();
+        assert_eq!(full.to_string(), "2 1 1 abcdef01");
+        assert_eq!(full.short(), "2 1 1 abcdef01");
+    }
+
+    #[test]
+    fn parse_invalid_records() {
+        assert!(TlsaRecord::parse("").is_err());
+        assert!(TlsaRecord::parse("3 1 1").is_err());
+        assert!(TlsaRecord::parse("3 1 x abcd").is_err());
+        assert!(TlsaRecord::parse("3 1 1 xyz0").is_err());
+        assert!(TlsaRecord::parse("3 1 1 abc").is_err());
+        assert!(TlsaRecord::parse("300 1 1 abcd").is_err());
+    }
+
+    #[test]
+    fn parse_wire_format() {
+        let record = TlsaRecord::from_wire(&[3, 1, 1, 0xab, 0x01]).unwrap();
+        assert_eq!(record.to_string(), "3 1 1 ab01");
+        assert!(TlsaRecord::from_wire(&[3, 1, 1]).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Simple token bucket for limiting the rate of outgoing requests.
+/// The bucket starts full and is refilled continuously with the configured rate.
+/// Each request takes one token and has to wait if the bucket is empty.
+pub struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a bucket that allows the given number of requests per minute
+    pub fn per_minute(requests: u32) -> Self {
+        let capacity = requests.max(1) as f64;
+        Self {
+            state: Mutex::new(BucketState {
+                capacity,
+                tokens: capacity,
+                refill_per_sec: capacity / 60.0,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a token is available and takes it
+    pub async fn acquire(&self) {
+        loop {
+            let result = {
+                let mut state = self.state.lock().expect("Failed to lock token bucket");
+                state.try_take(Instant::now())
+            };
+            match result {
+                Ok(()) => return,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+impl BucketState {
+    /// Takes one token or returns the time to wait for the next token
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - self.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_and_refill() {
+        let bucket = TokenBucket::per_minute(2);
+        let mut state = bucket.state.lock().unwrap();
+        let start = state.last_refill;
+
+        // Bucket starts full
+        assert!(state.try_take(start).is_ok());
+        assert!(state.try_take(start).is_ok());
+
+        // Empty bucket needs 30 seconds for the next token
+        let wait = state.try_take(start).unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+
+        // Partially refilled
+        let wait = state.try_take(start + Duration::from_secs(20)).unwrap_err();
+        assert_eq!(wait.as_secs(), 10);
+
+        // Refilled enough for one more token
+        assert!(state.try_take(start + Duration::from_secs(30)).is_ok());
+        assert!(state.try_take(start + Duration::from_secs(30)).is_err());
+
+        // Never refilled above capacity
+        let later = start + Duration::from_secs(3600);
+        assert!(state.try_take(later).is_ok());
+        assert!(state.try_take(later).is_ok());
+        assert!(state.try_take(later).is_err());
+    }
+
+    #[tokio::test]
+    async fn acquire_full_bucket() {
+        let bucket = TokenBucket::per_minute(60);
+        for _ in 0..60 {
+            bucket.acquire().await;
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use tokio::sync::Notify;
+
+/// Current step of a running update
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum UpdatePhase {
+    /// Connecting to the IMAP server and listing the sub-folders
+    ListingFolders { folder: String },
+    /// Downloading the metadata of all mails in a folder
+    FetchingMetadata { folder: String },
+    /// Downloading the bodies of the mails in a folder, chunk starts at 1
+    FetchingChunk {
+        folder: String,
+        chunk: usize,
+        chunks: usize,
+    },
+    /// Extracting the XML and JSON files from the mails
+    Extracting,
+    /// Parsing the extracted files as DMARC and SMTP TLS reports
+    Parsing,
+}
+
+/// Progress counts of the running or last update
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct UpdateProgress {
+    /// Mails found in all folders
+    pub mails: usize,
+    /// Mails whose bodies were downloaded
+    pub fetched_mails: usize,
+    /// Mails whose files were extracted
+    pub extracted_mails: usize,
+    /// XML and JSON files extracted from the mails
+    pub files: usize,
+    /// Extracted files that were parsed, successfully or not
+    pub parsed_files: usize,
+}
+
+/// Error of a failed update
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct UpdateError {
+    pub error: String,
+    /// Time of the failure as Unix timestamp
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct UpdateStatus {
+    /// True while an update is running
+    pub running: bool,
+    /// True if an update was requested that has not started yet
+    pub requested: bool,
+    /// Start of the running or last update as Unix timestamp
+    pub started: Option<u64>,
+    /// End of the last update as Unix timestamp, successful or not
+    pub finished: Option<u64>,
+    /// Only set while an update is running
+    pub phase: Option<UpdatePhase>,
+    pub progress: UpdateProgress,
+    /// Stays available after later successful updates
+    pub last_error: Option<UpdateError>,
+}
+
+/// Status of the background update with its own lock,
+/// so that the update can report its progress without locking the whole application state.
+/// Also used to request updates: Requests that arrive before the next update starts are coalesced.
+#[derive(Clone, Default)]
+pub struct UpdateTracker {
+    status: Arc<Mutex<UpdateStatus>>,
+    trigger: Arc<Notify>,
+}
+
+impl UpdateTracker {
+    pub fn status(&self) -> UpdateStatus {
+        self.lock().clone()
+    }
+
+    /// Requests an update that starts immediately or after the running update
+    pub fn request(&self) -> UpdateStatus {
+        let status = {
+            let mut status = self.lock();
+            status.requested = true;
+            status.clone()
+        };
+        // Stores at most one permit, so that concurrent requests result in one update
+        self.trigger.notify_one();
+        status
+    }
+
+    /// Promise will be fulfilled when an update was requested
+    pub async fn requested(&self) {
+        self.trigger.notified().await
+    }
+
+    pub fn start(&self) {
+        let mut status = self.lock();
+        status.running = true;
+        status.requested = false;
+        status.started = Some(now());
+        status.phase = None;
+        status.progress = UpdateProgress::default();
+    }
+
+    pub fn set_phase(&self, phase: UpdatePhase) {
+        self.lock().phase = Some(phase);
+    }
+
+    pub fn update_progress(&self, update: impl FnOnce(&mut UpdateProgress)) {
+        update(&mut self.lock().progress);
+    }
+
+    /// Marks the update as finished and remembers the error of a failed update
+    pub fn finish(&self, error: Option<String>) {
+        let mut status = self.lock();
+        let timestamp = now();
+        status.running = false;
+        status.finished = Some(timestamp);
+        status.phase = None;
+        if let Some(error) = error {
+            status.last_error = Some(UpdateError { error, timestamp });
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, UpdateStatus> {
+        self.status.lock().expect("Failed to lock update status")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Failed to get Unix time stamp")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn coalesced_requests() {
+        let tracker = UpdateTracker::default();
+        assert!(tracker.request().requested);
+        assert!(tracker.request().requested);
+
+        // Both requests result in a single wake up
+        let wait = Duration::from_millis(50);
+        assert!(timeout(wait, tracker.requested()).await.is_ok());
+        assert!(timeout(wait, tracker.requested()).await.is_err());
+
+        tracker.start();
+        let status = tracker.status();
+        assert!(status.running && !status.requested);
+        assert!(status.started.is_some());
+    }
+
+    #[test]
+    fn progress_and_errors() {
+        let tracker = UpdateTracker::default();
+        tracker.start();
+        tracker.set_phase(UpdatePhase::FetchingChunk {
+            folder: String::from("INBOX"),
+            chunk: 2,
+            chunks: 3,
+        });
+        tracker.update_progress(|progress| progress.mails += 5);
+        let json = serde_json::to_value(tracker.status()).unwrap();
+        assert_eq!(json["phase"]["name"], "fetching_chunk");
+        assert_eq!(json["phase"]["chunk"], 2);
+        assert_eq!(json["progress"]["mails"], 5);
+
+        tracker.finish(Some(String::from("Connection refused")));
+        let status = tracker.status();
+        assert!(!status.running && status.phase.is_none());
+        let error = status.last_error.unwrap();
+        assert_eq!(error.error, "Connection refused");
+        assert_eq!(Some(error.timestamp), status.finished);
+
+        // Errors stay visible after successful updates
+        tracker.start();
+        tracker.finish(None);
+        let status = tracker.status();
+        assert_eq!(status.progress, UpdateProgress::default());
+        assert!(status.last_error.is_some());
+    }
+}
@@ -0,0 +1,386 @@
+use crate::cache_map::CacheMap;
+use crate::config::Configuration;
+use crate::hasher::create_hash;
+use anyhow::{Context, Result, bail, ensure};
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
+
+/// Number of successfully verified credentials that are remembered,
+/// to avoid an expensive password hash verification for each request of the UI.
+const VERIFIED_CACHE_SIZE: usize = 1000;
+
+#[derive(Parser)]
+#[command(ignore_errors = true, disable_help_flag = true)]
+struct HashPasswordArgs {
+    /// Set to read a password from stdin and print its hash for the users file
+    #[arg(long)]
+    pub hash_password: bool,
+}
+
+/// Checks for the special argument to create password hashes for the users file.
+/// If the argument is found, the hash is printed and the application exits.
+pub fn run_hash_password_if_requested() {
+    let args = HashPasswordArgs::parse();
+    if !args.hash_password {
+        return;
+    }
+    let mut password = String::new();
+    if let Err(err) = std::io::stdin().lock().read_line(&mut password) {
+        eprintln!("Failed to read password from stdin: {err}");
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        eprintln!("Password must not be empty");
+        std::process::exit(1);
+    }
+    match hash_password(password) {
+        Ok(hash) => {
+            println!("{hash}");
+            std::process::exit(0);
+        }
+        Err(err) => {
+            eprintln!("{err:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Creates an Argon2id hash in PHC string format, like `$argon2id$v=19$m=19456,t=2,p=1$...`
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow::anyhow!("Failed to hash password: {err}"))?;
+    Ok(hash.to_string())
+}
+
+/// Roles of user accounts
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Full access to the data of all domains
+    Admin,
+
+    /// Read access restricted to the data of the domains of the account
+    Viewer,
+}
+
+/// The domains whose reports and mails are visible to a user
+#[derive(Clone, PartialEq, Debug)]
+pub enum DomainScope {
+    All,
+
+    /// Lowercase domain names, subdomains are included
+    Domains(Vec<String>),
+}
+
+impl DomainScope {
+    /// True if the user may see data of this domain
+    pub fn allows(&self, domain: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Domains(domains) => {
+                let domain = domain.trim_end_matches('.').to_lowercase();
+                domains.iter().any(|allowed| {
+                    domain == *allowed
+                        || domain
+                            .strip_suffix(allowed.as_str())
+                            .is_some_and(|prefix| prefix.ends_with('.'))
+                })
+            }
+        }
+    }
+
+    pub fn is_all(&self) -> bool {
+        *self == Self::All
+    }
+}
+
+/// Authenticated user.
+/// Added to the request extensions by the authentication middleware.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub scope: DomainScope,
+}
+
+impl User {
+    /// Used when authentication is disabled
+    pub fn anonymous() -> Self {
+        Self {
+            name: String::from("anonymous"),
+            role: Role::Admin,
+            scope: DomainScope::All,
+        }
+    }
+}
+
+/// Account as defined in the users file
+#[derive(Deserialize)]
+struct UserEntry {
+    name: String,
+
+    /// Argon2 hash in PHC string format
+    password_hash: String,
+
+    role: Role,
+
+    /// Domains visible to viewers, not allowed for admins
+    #[serde(default)]
+    domains: Vec<String>,
+}
+
+enum Credential {
+    /// Plain password from the configuration of the single user mode
+    Plain(String),
+
+    /// Password hash in PHC string format
+    Hash(String),
+}
+
+struct Account {
+    credential: Credential,
+    user: User,
+}
+
+/// All user accounts that can access the HTTP server
+pub struct UserStore {
+    accounts: HashMap<String, Account>,
+
+    /// Verified for unknown users to not reveal which user names exist by response times
+    dummy_hash: String,
+
+    /// Hashes of recently verified user names and passwords
+    verified: Mutex<CacheMap<String, ()>>,
+}
+
+impl UserStore {
+    /// Loads the accounts from the users file if configured.
+    /// Otherwise falls back to the single admin user from the configuration
+    /// or disabled authentication if the password is empty.
+    pub fn from_config(config: &Configuration) -> Result<Self> {
+        if let Some(path) = &config.http_users_file {
+            return Self::from_file(path);
+        }
+        let mut accounts = HashMap::new();
+        if !config.http_server_password.is_empty() {
+            accounts.insert(
+                config.http_server_user.clone(),
+                Account {
+                    credential: Credential::Plain(config.http_server_password.clone()),
+                    user: User {
+                        name: config.http_server_user.clone(),
+                        role: Role::Admin,
+                        scope: DomainScope::All,
+                    },
+                },
+            );
+        }
+        Self::new(accounts)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path).context("Failed to read users file")?;
+        Self::from_json(&json)
+    }
+
+    fn from_json(json: &str) -> Result<Self> {
+        let entries: Vec<UserEntry> =
+            serde_json::from_str(json).context("Failed to parse users file")?;
+        ensure!(!entries.is_empty(), "Users file contains no users");
+        let mut accounts = HashMap::new();
+        for entry in entries {
+            ensure!(!entry.name.is_empty(), "User name must not be empty");
+            ensure!(
+                !entry.name.contains(':'),
+                "User name {} must not contain colons",
+                entry.name
+            );
+            PasswordHash::new(&entry.password_hash).map_err(|err| {
+                anyhow::anyhow!("Invalid password hash of user {}: {err}", entry.name)
+            })?;
+            let scope = match entry.role {
+                Role::Admin => {
+                    ensure!(
+                        entry.domains.is_empty(),
+                        "Admin {} can see all domains and must not have a domain list",
+                        entry.name
+                    );
+                    DomainScope::All
+                }
+                Role::Viewer => {
+                    ensure!(
+                        !entry.domains.is_empty(),
+                        "Viewer {} needs at least one domain",
+                        entry.name
+                    );
+                    DomainScope::Domains(
+                        entry
+                            .domains
+                            .iter()
+                            .map(|d| d.trim_end_matches('.').to_lowercase())
+                            .collect(),
+                    )
+                }
+            };
+            let account = Account {
+                credential: Credential::Hash(entry.password_hash),
+                user: User {
+                    name: entry.name.clone(),
+                    role: entry.role,
+                    scope,
+                },
+            };
+            if accounts.insert(entry.name.clone(), account).is_some() {
+                bail!("User {} is defined more than once", entry.name);
+            }
+        }
+        Self::new(accounts)
+    }
+
+    fn new(accounts: HashMap<String, Account>) -> Result<Self> {
+        Ok(Self {
+            accounts,
+            dummy_hash: hash_password("dummy")?,
+            verified: Mutex::new(CacheMap::new(VERIFIED_CACHE_SIZE)?),
+        })
+    }
+
+    /// Authentication is disabled without any accounts
+    pub fn is_enabled(&self) -> bool {
+        !self.accounts.is_empty()
+    }
+
+    pub fn user_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Returns the user if the password is correct.
+    /// Verifying password hashes is intentionally slow and should not run on the async runtime.
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<User> {
+        let Some(account) = self.accounts.get(name) else {
+            let _ = verify_hash(&self.dummy_hash, password);
+            return None;
+        };
+        let valid = match &account.credential {
+            Credential::Plain(expected) => {
+                // Convert values to equal length hash before comparison
+                let expected = create_hash(&[expected.as_bytes()]);
+                let actual = create_hash(&[password.as_bytes()]);
+
+                // Use constant time comparison to avoid timing attacks
+                expected.as_bytes().ct_eq(actual.as_bytes()).unwrap_u8() == 1
+            }
+            Credential::Hash(hash) => {
+                let key = create_hash(&[
+                    hash.as_bytes(),
+                    b":",
+                    name.as_bytes(),
+                    b":",
+                    password.as_bytes(),
+                ]);
+                let mut verified = self.verified.lock().expect("Failed to lock verified cache");
+                if verified.get(&key).is_some() {
+                    true
+                } else {
+                    drop(verified);
+                    let valid = verify_hash(hash, password);
+                    if valid {
+                        verified = self.verified.lock().expect("Failed to lock verified cache");
+                        verified.insert(key, ());
+                    }
+                    valid
+                }
+            }
+        };
+        valid.then(|| account.user.clone())
+    }
+}
+
+fn verify_hash(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users_json() -> String {
+        let admin = hash_password("secret").unwrap();
+        let viewer = hash_password("letmein").unwrap();
+        format!(
+            r#"[
+                {{"name": "admin", "password_hash": "{admin}", "role": "admin"}},
+                {{"name": "acme", "password_hash": "{viewer}", "role": "viewer", "domains": ["Acme.com."]}}
+            ]"#
+        )
+    }
+
+    #[test]
+    fn domain_scope() {
+        let scope = DomainScope::Domains(vec![String::from("acme.com")]);
+        assert!(scope.allows("acme.com"));
+        assert!(scope.allows("ACME.com."));
+        assert!(scope.allows("mail.acme.com"));
+        assert!(!scope.allows("notacme.com"));
+        assert!(!scope.allows("acme.com.evil.org"));
+        assert!(!scope.allows("example.com"));
+        assert!(DomainScope::All.allows("example.com"));
+    }
+
+    #[test]
+    fn authenticate() {
+        let store = UserStore::from_json(&users_json()).unwrap();
+        assert!(store.is_enabled());
+
+        let admin = store.authenticate("admin", "secret").unwrap();
+        assert_eq!(admin.role, Role::Admin);
+        assert!(admin.scope.is_all());
+
+        let viewer = store.authenticate("acme", "letmein").unwrap();
+        assert_eq!(viewer.role, Role::Viewer);
+        assert_eq!(
+            viewer.scope,
+            DomainScope::Domains(vec![String::from("acme.com")])
+        );
+
+        // Second time from cache
+        assert!(store.authenticate("acme", "letmein").is_some());
+        assert!(store.authenticate("acme", "secret").is_none());
+        assert!(store.authenticate("unknown", "secret").is_none());
+    }
+
+    #[test]
+    fn invalid_users_files() {
+        let hash = hash_password("pw").unwrap();
+        for json in [
+            String::from("[]"),
+            String::from(r#"[{"name": "a", "password_hash": "plain", "role": "admin"}]"#),
+            format!(r#"[{{"name": "a", "password_hash": "{hash}", "role": "viewer"}}]"#),
+            format!(
+                r#"[{{"name": "a", "password_hash": "{hash}", "role": "admin", "domains": ["a.com"]}}]"#
+            ),
+            format!(
+                r#"[{{"name": "a", "password_hash": "{hash}", "role": "admin"}},
+                    {{"name": "a", "password_hash": "{hash}", "role": "admin"}}]"#
+            ),
+            format!(r#"[{{"name": "a:b", "password_hash": "{hash}", "role": "admin"}}]"#),
+        ] {
+            assert!(UserStore::from_json(&json).is_err(), "{json}");
+        }
+    }
+}
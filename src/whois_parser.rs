@@ -0,0 +1,274 @@
+use crate::ip_net::IpNet;
+use serde::Serialize;
+use std::mem::take;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Keys of the objects that describe IP networks in the different registry formats
+const NETWORK_KEYS: [&str; 3] = ["netrange", "inetnum", "inet6num"];
+
+/// Normalized fields from the whois response of any of the regional registries.
+/// ARIN uses its own format, RIPE, APNIC and AFRINIC use RPSL and LACNIC a variant of it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct WhoisRecord {
+    /// Registry that provided the record, like `ARIN` or `RIPE`
+    pub registry: Option<String>,
+    /// IP range as given by the registry, like `192.0.2.0 - 192.0.2.255`
+    pub range: Option<String>,
+    /// Networks in CIDR notation that make up the range
+    pub cidrs: Vec<String>,
+    pub netname: Option<String>,
+    pub org: Option<String>,
+    /// Two letter country code
+    pub country: Option<String>,
+    pub abuse_mailbox: Option<String>,
+    /// Origin AS of the route, like `AS64496`
+    pub origin_as: Option<String>,
+}
+
+/// Attributes of a single object with lower case keys
+type Object = Vec<(String, String)>;
+
+impl WhoisRecord {
+    /// Parses the raw text of a whois response.
+    /// If the response contains multiple networks, the last and most specific one is used.
+    pub fn parse(text: &str) -> Self {
+        let objects = parse_objects(text);
+
+        // All objects after the network belong to it, like organizations, contacts and routes
+        let start = objects
+            .iter()
+            .rposition(|o| o.iter().any(|(k, _)| NETWORK_KEYS.contains(&k.as_str())));
+        let section = match start {
+            Some(start) => &objects[start..],
+            None => &objects[..],
+        };
+        let first = |keys: &[&str]| {
+            section
+                .iter()
+                .flatten()
+                .find(|(k, v)| keys.contains(&k.as_str()) && !v.is_empty())
+                .map(|(_, v)| v.clone())
+        };
+
+        let range = first(&NETWORK_KEYS);
+        let cidrs = match first(&["cidr"]) {
+            Some(cidrs) => cidrs.split(',').map(|c| c.trim().to_string()).collect(),
+            None => range.as_deref().map(range_to_cidrs).unwrap_or_default(),
+        };
+
+        // Fall back to the first description of the network
+        let org = first(&["orgname", "org-name", "owner"]).or_else(|| {
+            let network = section.first()?;
+            network
+                .iter()
+                .find(|(k, v)| k == "descr" && !v.is_empty())
+                .map(|(_, v)| v.clone())
+        });
+
+        let abuse_mailbox = first(&["orgabuseemail", "abuse-mailbox"])
+            .or_else(|| abuse_from_comment(text))
+            .or_else(|| abuse_from_handle(section));
+
+        let registry = first(&["source"])
+            .and_then(|s| s.split_whitespace().next().map(str::to_uppercase))
+            .or_else(|| {
+                let has_key = |key: &str| objects.iter().flatten().any(|(k, _)| k == key);
+                if has_key("netrange") {
+                    Some(String::from("ARIN"))
+                } else if has_key("ownerid") || text.contains("LACNIC") {
+                    Some(String::from("LACNIC"))
+                } else {
+                    None
+                }
+            });
+
+        Self {
+            registry,
+            range,
+            cidrs,
+            netname: first(&["netname"]),
+            org,
+            country: first(&["country"]),
+            abuse_mailbox,
+            origin_as: first(&["originas", "origin", "aut-num"]),
+        }
+    }
+}
+
+/// Splits the text into objects separated by empty lines.
+/// Comments and continuation lines of multi-line values are skipped.
+fn parse_objects(text: &str) -> Vec<Object> {
+    let mut objects = Vec::new();
+    let mut current = Object::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                objects.push(take(&mut current));
+            }
+            continue;
+        }
+        if line.starts_with(['%', '#', '+']) || line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            continue;
+        }
+        current.push((key.to_ascii_lowercase(), value.trim().to_string()));
+    }
+    if !current.is_empty() {
+        objects.push(current);
+    }
+    objects
+}
+
+/// Converts ranges in CIDR notation or `first - last` notation to CIDRs
+fn range_to_cidrs(range: &str) -> Vec<String> {
+    if let Ok(net) = IpNet::from_str(range) {
+        return vec![net.to_string()];
+    }
+    let Some((first, last)) = range.split_once('-') else {
+        return Vec::new();
+    };
+    let (Ok(first), Ok(last)) = (
+        IpAddr::from_str(first.trim()),
+        IpAddr::from_str(last.trim()),
+    ) else {
+        return Vec::new();
+    };
+    IpNet::from_range(first, last)
+        .map(|net| vec![net.to_string()])
+        .unwrap_or_default()
+}
+
+/// RPSL registries add a comment like `% Abuse contact for '...' is 'abuse@example.com'`
+fn abuse_from_comment(text: &str) -> Option<String> {
+    text.lines()
+        .filter(|line| line.starts_with("% Abuse contact for"))
+        .find_map(|line| {
+            let (_, mailbox) = line.rsplit_once(" is '")?;
+            let mailbox = mailbox.trim_end().strip_suffix('\'')?;
+            (!mailbox.is_empty()).then(|| mailbox.to_string())
+        })
+}
+
+/// LACNIC only references the abuse contact by its handle
+fn abuse_from_handle(section: &[Object]) -> Option<String> {
+    let handle = section
+        .iter()
+        .flatten()
+        .find(|(k, _)| k == "abuse-c")
+        .map(|(_, v)| v)?;
+    section
+        .iter()
+        .find(|o| {
+            o.iter().any(|(k, v)| {
+                (k == "nic-hdl" || k == "nic-hdl-br") && v.eq_ignore_ascii_case(handle)
+            })
+        })?
+        .iter()
+        .find(|(k, _)| k == "e-mail")
+        .map(|(_, v)| v.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_file(name: &str) -> WhoisRecord {
+        let text = std::fs::read_to_string(format!("testdata/whois/{name}")).unwrap();
+        WhoisRecord::parse(&text)
+    }
+
+    #[test]
+    fn arin() {
+        let record = parse_file("arin.txt");
+        assert_eq!(record.registry.as_deref(), Some("ARIN"));
+        assert_eq!(record.range.as_deref(), Some("8.8.8.0 - 8.8.8.255"));
+        assert_eq!(record.cidrs, vec!["8.8.8.0/24"]);
+        assert_eq!(record.netname.as_deref(), Some("GOGL"));
+        assert_eq!(record.org.as_deref(), Some("Google LLC"));
+        assert_eq!(record.country.as_deref(), Some("US"));
+        assert_eq!(
+            record.abuse_mailbox.as_deref(),
+            Some("network-abuse@google.com")
+        );
+        assert_eq!(record.origin_as.as_deref(), Some("AS15169"));
+    }
+
+    #[test]
+    fn ripe() {
+        let record = parse_file("ripe.txt");
+        assert_eq!(record.registry.as_deref(), Some("RIPE"));
+        assert_eq!(record.range.as_deref(), Some("193.0.0.0 - 193.0.7.255"));
+        assert_eq!(record.cidrs, vec!["193.0.0.0/21"]);
+        assert_eq!(record.netname.as_deref(), Some("RIPE-NCC"));
+        assert_eq!(
+            record.org.as_deref(),
+            Some("Reseaux IP Europeens Network Coordination Centre (RIPE NCC)")
+        );
+        assert_eq!(record.country.as_deref(), Some("NL"));
+        assert_eq!(record.abuse_mailbox.as_deref(), Some("abuse@ripe.net"));
+        assert_eq!(record.origin_as.as_deref(), Some("AS3333"));
+    }
+
+    #[test]
+    fn apnic() {
+        let record = parse_file("apnic.txt");
+        assert_eq!(record.registry.as_deref(), Some("APNIC"));
+        assert_eq!(record.cidrs, vec!["1.1.1.0/24"]);
+        assert_eq!(record.netname.as_deref(), Some("APNIC-LABS"));
+        assert_eq!(
+            record.org.as_deref(),
+            Some("APNIC Research and Development")
+        );
+        assert_eq!(record.country.as_deref(), Some("AU"));
+        assert_eq!(record.abuse_mailbox.as_deref(), Some("helpdesk@apnic.net"));
+        assert_eq!(record.origin_as.as_deref(), Some("AS13335"));
+    }
+
+    #[test]
+    fn lacnic() {
+        let record = parse_file("lacnic.txt");
+        assert_eq!(record.registry.as_deref(), Some("LACNIC"));
+        assert_eq!(record.range.as_deref(), Some("200.160.0.0/20"));
+        assert_eq!(record.cidrs, vec!["200.160.0.0/20"]);
+        assert_eq!(record.netname, None);
+        assert_eq!(
+            record.org.as_deref(),
+            Some("Núcleo de Inf. e Coord. do Ponto BR - NIC.BR")
+        );
+        assert_eq!(record.country.as_deref(), Some("BR"));
+        assert_eq!(record.abuse_mailbox.as_deref(), Some("cert@cert.br"));
+        assert_eq!(record.origin_as.as_deref(), Some("AS22548"));
+    }
+
+    #[test]
+    fn afrinic() {
+        let record = parse_file("afrinic.txt");
+        assert_eq!(record.registry.as_deref(), Some("AFRINIC"));
+        assert_eq!(record.cidrs, vec!["196.216.2.0/23"]);
+        assert_eq!(record.netname.as_deref(), Some("AFRINIC-Anycast-Services"));
+        assert_eq!(
+            record.org.as_deref(),
+            Some("African Network Information Center - (AFRINIC)")
+        );
+        assert_eq!(record.country.as_deref(), Some("MU"));
+        assert_eq!(
+            record.abuse_mailbox.as_deref(),
+            Some("abusepoc@afrinic.net")
+        );
+        assert_eq!(record.origin_as.as_deref(), Some("AS33764"));
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(WhoisRecord::parse(""), WhoisRecord::default());
+
+        let record = WhoisRecord::parse("% Abuse contact for '192.0.2.0/24' is ''\n");
+        assert_eq!(record.abuse_mailbox, None);
+    }
+}
@@ -60,6 +60,16 @@ export class App extends LitElement {
         this.mailId = null;
         window.onhashchange = () => this.onHashChange();
         this.onHashChange();
+        this.subscribeEvents();
+    }
+
+    subscribeEvents() {
+        // Reload the data of the current view whenever it was changed by an update.
+        // Lagged means some events were missed, so the data might have changed as well.
+        const events = new EventSource("events");
+        const reload = () => this.params = { ...this.params };
+        events.addEventListener("update_finished", reload);
+        events.addEventListener("lagged", reload);
     }
 
     async onHashChange() {
@@ -97,6 +107,8 @@ export class App extends LitElement {
             this.mailId = hash.substring(8);
         } else if (hash == "#/sources") {
             this.component = "sources";
+        } else if (hash == "#/mta-sts") {
+            this.component = "mta-sts";
         } else if (hash == "#/about") {
             this.component = "about";
         } else {
@@ -120,6 +132,8 @@ export class App extends LitElement {
             component = html`<drv-mail id="${this.mailId}"></drv-mail>`;
         } else if (this.component == "sources") {
             component = html`<drv-sources .params="${this.params}"></drv-sources>`;
+        } else if (this.component == "mta-sts") {
+            component = html`<drv-mta-sts></drv-mta-sts>`;
         } else if (this.component == "about") {
             component = html`<drv-about></drv-about>`;
         } else {
@@ -133,6 +147,7 @@ export class App extends LitElement {
                 <a class="${this.component === "dmarc-reports" || this.component === "dmarc-report" ? "active" : ""}" href="#/dmarc-reports">DMARC<span class="xs-hidden">&nbsp;Reports</span></a>
                 <a class="${this.component === "tls-reports" || this.component === "tls-report" ? "active" : ""}" href="#/tls-reports"><span class="xs-hidden">SMTP&nbsp;</span>TLS<span class="xs-hidden">&nbsp;Reports</span></a>
                 <a class="${this.component === "sources" ? "active" : ""}" href="#/sources"><span class="xs-hidden">Mail&nbsp;</span>Sources</a>
+                <a class="${this.component === "mta-sts" ? "active" : ""}" href="#/mta-sts">MTA-STS</a>
                 <a class="xs-hidden ${this.component === "about" ? "active" : ""} right" href="#/about">About</a>
             </nav>
             <main>${component}</main>
@@ -19,6 +19,8 @@ export class DmarcReport extends LitElement {
         this.report = null;
         this.ip2dns = {};
         this.ip2location = {};
+        this.ip2rdap = {};
+        this.ip2checks = {};
         this.ipDetails = {};
         this.recordFilter = "all";
     }
@@ -39,6 +41,8 @@ export class DmarcReport extends LitElement {
             this.ipDetails[ip] = true;
             this.getDnsForIp(ip);
             this.getLocationForIp(ip);
+            this.getRdapForIp(ip);
+            this.getDnsChecksForIp(ip);
         }
         this.requestUpdate();
     }
@@ -65,6 +69,28 @@ export class DmarcReport extends LitElement {
         this.requestUpdate();
     }
 
+    async getDnsChecksForIp(ip) {
+        const response = await fetch("ips/" + ip + "/dns-checks");
+        if (response.status === 200) {
+            const result = await response.json();
+            this.ip2checks[ip] = result;
+        } else {
+            this.ip2checks[ip] = null;
+        }
+        this.requestUpdate();
+    }
+
+    async getRdapForIp(ip) {
+        const response = await fetch("ips/" + ip + "/rdap");
+        if (response.status === 200) {
+            const result = await response.json();
+            this.ip2rdap[ip] = result;
+        } else {
+            this.ip2rdap[ip] = null;
+        }
+        this.requestUpdate();
+    }
+
     renderOptional(value) {
         if (value !== null && value !== undefined) {
             return html`${value}`;
@@ -95,6 +121,52 @@ export class DmarcReport extends LitElement {
         return html`<a target="_blank" title="Show on OpenStreeMap" href="https://www.openstreetmap.org/#map=8/${lat}/${lon}">${lat}, ${lon}</a>`;
     }
 
+    renderVerifiedHostname(checks) {
+        if (checks === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (checks?.verified_hostname) {
+            return html`${checks.verified_hostname} <span class="badge badge-positive">verified</span>`;
+        } else if (checks?.hostname) {
+            return html`<span class="badge badge-negative help" title="${checks.hostname} does not resolve back to this IP">unverified</span>`;
+        } else {
+            return html`<span class="faded">n/a</span>`;
+        }
+    }
+
+    renderListings(checks) {
+        if (checks === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!checks) {
+            return html`<span class="faded">n/a</span>`;
+        } else if (checks.listed_on.length === 0 && checks.failed_zones.length > 0) {
+            return html`<span class="faded help" title="Lookups failed on ${checks.failed_zones.join(", ")}">unknown</span>`;
+        } else if (checks.listed_on.length === 0) {
+            return html`<span class="faded">not listed</span>`;
+        }
+        return checks.listed_on.map((zone) => html`<span class="badge badge-negative">Listed on ${zone}</span> `);
+    }
+
+    renderNetwork(rdap) {
+        if (rdap === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!rdap) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        const parts = [rdap.name, rdap.cidrs.join(", "), rdap.registrant].filter((p) => p);
+        return html`<a target="_blank" title="Show RDAP record" href="${rdap.source}">${parts.join(" - ") || rdap.source}</a>`;
+    }
+
+    renderAbuseContact(rdap) {
+        if (rdap === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!rdap?.abuse_contact?.emails.length) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        return rdap.abuse_contact.emails.map((email, i) =>
+            html`${i > 0 ? ", " : ""}<a href="mailto:${email}">${email}</a>`
+        );
+    }
+
     renderPropIfObjDefined(obj, prop) {
         if (obj === undefined) {
             return html`<span class="faded">loading...</span>`;
@@ -353,6 +425,22 @@ export class DmarcReport extends LitElement {
                                 }
                             </td>
                         </tr>
+                        <tr>
+                            <td class="name help" title="Host name from the PTR record that resolves back to the IP (FCrDNS)">Source IP Verified Hostname</td>
+                            <td>${this.renderVerifiedHostname(this.ip2checks[record.row.source_ip])}</td>
+                        </tr>
+                        <tr>
+                            <td class="name help" title="Listings on the configured DNSBL zones">Source IP DNSBL</td>
+                            <td>${this.renderListings(this.ip2checks[record.row.source_ip])}</td>
+                        </tr>
+                        <tr>
+                            <td class="name help" title="Registered network from the RDAP record">Source IP Network</td>
+                            <td>${this.renderNetwork(this.ip2rdap[record.row.source_ip])}</td>
+                        </tr>
+                        <tr>
+                            <td class="name help" title="Abuse contact from the RDAP record">Source IP Abuse Contact</td>
+                            <td>${this.renderAbuseContact(this.ip2rdap[record.row.source_ip])}</td>
+                        </tr>
                     </tbody>
                     <tr>
                         <td class="name">Count</td>
@@ -1,5 +1,6 @@
-import { LitElement, html } from "lit";
+import { LitElement, html, nothing } from "lit";
 import { globalStyle } from "../style.js";
+import { fetchPage } from "../utils.js";
 
 export class Mails extends LitElement {
     static styles = [globalStyle];
@@ -7,12 +8,15 @@ export class Mails extends LitElement {
     static properties = {
         params: { type: Object },
         mails: { type: Array },
+        total: { type: Number },
     };
 
     constructor() {
         super();
         this.params = {};
         this.mails = [];
+        this.total = 0;
+        this.url = null;
         this.filtered = false;
     }
 
@@ -43,10 +47,21 @@ export class Mails extends LitElement {
         if (queryParams.length > 0) {
             url += "?" + queryParams.join("&");
         }
-        const mailsResponse = await fetch(url);
-        this.mails = await mailsResponse.json();
-        this.mails.sort((a, b) => b.date - a.date);
+        this.url = url;
+        this.mails = [];
+        this.total = 0;
         this.filtered = queryParams.length > 0;
+        await this.loadMore();
+    }
+
+    async loadMore() {
+        // Mails are sorted by the server with the newest first
+        const url = this.url;
+        const page = await fetchPage(url, this.mails.length);
+        if (url === this.url) {
+            this.mails = [...this.mails, ...page.items];
+            this.total = page.total;
+        }
     }
 
     render() {
@@ -64,6 +79,12 @@ export class Mails extends LitElement {
             }
             </div>
             <drv-mail-table .mails="${this.mails}"></drv-mail-table>
+            ${this.mails.length < this.total ? html`
+                <div>
+                    Showing ${this.mails.length} of ${this.total}
+                    <button @click="${() => this.loadMore()}" class="ml button">Load More</button>
+                </div>
+            ` : nothing}
         `;
     }
 }
@@ -0,0 +1,108 @@
+import { LitElement, html } from "lit";
+import { globalStyle } from "../style.js";
+
+export class MtaSts extends LitElement {
+    static styles = [globalStyle];
+
+    static properties = {
+        checks: { type: Array },
+        loading: { type: Boolean },
+    };
+
+    constructor() {
+        super();
+        this.checks = [];
+        this.loading = true;
+        this.updateChecks();
+    }
+
+    async updateChecks() {
+        const response = await fetch("mta-sts");
+        this.checks = await response.json();
+        this.loading = false;
+    }
+
+    renderStatus(check) {
+        if (check.errors.length === 0) {
+            return html`<span class="badge badge-positive">OK</span>`;
+        } else if (!check.policy && !check.record_id) {
+            return html`<span class="badge badge-warning">Not Configured</span>`;
+        } else {
+            return html`<span class="badge badge-negative">Issues</span>`;
+        }
+    }
+
+    renderPolicy(policy) {
+        if (!policy) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        return html`
+            Mode: <b>${policy.mode}</b><br>
+            Max Age: ${policy.max_age} s<br>
+            ${policy.mx.map(mx => html`MX: ${mx}<br>`)}
+        `;
+    }
+
+    renderMxRecords(mxRecords) {
+        if (mxRecords.length === 0) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        return mxRecords.map(mx => html`
+            ${mx.host}
+            ${mx.matches_policy ?
+                html`<span class="badge badge-positive" title="Matches a pattern of the policy">Match</span>` :
+                html`<span class="badge badge-negative" title="Matches no pattern of the policy">No Match</span>`}
+            <br>
+        `);
+    }
+
+    renderReportedMx(reportedMx) {
+        if (reportedMx.length === 0) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        return reportedMx.map(reported => html`
+            ${reported.pattern}
+            ${reported.in_policy ? html`` :
+                html`<span class="badge badge-warning" title="Pattern is not part of the current policy">Outdated</span>`}
+            <br>
+        `);
+    }
+
+    render() {
+        return html`
+            <h1>MTA-STS</h1>
+            <p>
+                Checks the MTA-STS TXT record and policy of all domains from the reports.
+                The MX patterns of the policy are compared against the live MX records
+                and against the MX patterns reporters used in SMTP TLS reports.
+            </p>
+            <table>
+                <tr>
+                    <th>Domain</th>
+                    <th>Status</th>
+                    <th class="sm-hidden">Policy ID</th>
+                    <th>Policy</th>
+                    <th class="sm-hidden">MX Records</th>
+                    <th class="md-hidden help" title="MX patterns from the STS policies in SMTP TLS reports">Reported MX</th>
+                    <th class="xs-hidden">Errors</th>
+                </tr>
+                ${this.checks.length !== 0 ? this.checks.map((check) =>
+                    html`<tr>
+                        <td>${check.domain}</td>
+                        <td>${this.renderStatus(check)}</td>
+                        <td class="sm-hidden">${check.record_id ?? html`<span class="faded">n/a</span>`}</td>
+                        <td>${this.renderPolicy(check.policy)}</td>
+                        <td class="sm-hidden">${this.renderMxRecords(check.mx_records)}</td>
+                        <td class="md-hidden">${this.renderReportedMx(check.reported_mx)}</td>
+                        <td class="xs-hidden">${check.errors.map(error => html`${error}<br>`)}</td>
+                    </tr>`
+                ) : html`<tr>
+                        <td colspan="7">${this.loading ? "Checking domains..." : "No domains found."}</td>
+                    </tr>`
+                }
+            </table>
+        `;
+    }
+}
+
+customElements.define("drv-mta-sts", MtaSts);
@@ -7,12 +7,14 @@ export class Sources extends LitElement {
     static properties = {
         params: { type: Object },
         sources: { type: Array },
+        groups: { type: Array },
     };
 
     constructor() {
         super();
         this.params = {};
         this.sources = [];
+        this.groups = [];
         this.filtered = false;
     }
 
@@ -23,6 +25,11 @@ export class Sources extends LitElement {
     }
 
     async updateSources() {
+        if (this.params.group) {
+            await this.updateGroups();
+            return;
+        }
+        this.groups = [];
         const sourcesResponse = await fetch("sources");
         this.filtered = false;
         this.sources = await sourcesResponse.json();
@@ -62,6 +69,36 @@ export class Sources extends LitElement {
         }
     }
 
+    async updateGroups() {
+        const groupsResponse = await fetch("sources?group=" + encodeURIComponent(this.params.group));
+        this.filtered = true;
+        let groups = await groupsResponse.json();
+        if (this.params.domain) {
+            const lcDomain = this.params.domain.toLowerCase();
+            groups = groups.filter(g => g.domains.some(d => d.toLowerCase() === lcDomain));
+        }
+        if (this.params.issues) {
+            groups = groups.filter(g => g.issues.length > 0);
+        }
+        if (this.params.type) {
+            groups = groups.filter(g => g.types.includes(this.params.type));
+        }
+        this.sources = [];
+        this.groups = groups;
+    }
+
+    groupingTitle() {
+        if (this.params.group === "prefix") {
+            return "Network Prefix";
+        } else if (this.params.group === "announced") {
+            return "Announced Prefix";
+        } else if (this.params.group === "asn") {
+            return "Autonomous System";
+        } else {
+            return "Group";
+        }
+    }
+
     prepareIssueBadges(issues) {
         // Sort to always have the same badge order
         issues.sort();
@@ -128,6 +165,59 @@ export class Sources extends LitElement {
         }
     }
 
+    prepareDnsChecks(checks) {
+        if (!checks) {
+            return "";
+        } else if (checks.verified_hostname) {
+            return html`<span class="badge badge-positive help" title="Host name resolves back to this IP (FCrDNS)">verified</span>`;
+        } else if (checks.hostname) {
+            return html`<span class="badge badge-negative help" title="Host name does not resolve back to this IP (FCrDNS)">unverified</span>`;
+        } else {
+            return "";
+        }
+    }
+
+    prepareListingBadges(listedOn) {
+        return listedOn.map(zone =>
+            html`<span class="badge badge-negative help" title="IP is listed on the DNSBL ${zone}">Listed on ${zone}</span> `
+        );
+    }
+
+    renderGroups() {
+        return html`
+            <table>
+                <tr>
+                    <th>${this.groupingTitle()}</th>
+                    <th class="md-hidden">Name</th>
+                    <th class="help" title="Number of records from reports for all IPs in this group">Count</th>
+                    <th class="sm-hidden">Domains</th>
+                    <th class="sm-hidden help" title="Report Types">Types</th>
+                    <th class="xs-hidden help" title="Issues detected in reports from IPs of this group">Issues</th>
+                    <th>IPs</th>
+                </tr>
+                ${this.groups.length !== 0 ? this.groups.map((group) =>
+                    html`<tr>
+                        <td>${group.key}</td>
+                        <td class="md-hidden">${group.name ? group.name : html`<span class="faded">n/a</span>`}</td>
+                        <td>${group.count}</td>
+                        <td class="sm-hidden">${group.domains.map(d => html`<a href="#/sources?group=${encodeURIComponent(this.params.group)}&domain=${encodeURIComponent(d)}">${d}</a> `)}</td>
+                        <td class="sm-hidden">${group.types.sort().map(t => html`<span class="badge">${t === "Tls" ? "SMTP TLS" : "DMARC"}</span> `)}</td>
+                        <td class="xs-hidden">${this.prepareIssueBadges(group.issues)}${this.prepareListingBadges(group.listed_on)}</td>
+                        <td>
+                            <details>
+                                <summary>${group.ips.length}</summary>
+                                ${group.ips.map(ip => html`<div>${ip}</div>`)}
+                            </details>
+                        </td>
+                    </tr>`
+                ) : html`<tr>
+                        <td colspan="7">No sources found.</td>
+                    </tr>`
+                }
+            </table>
+        `;
+    }
+
     render() {
         return html`
             <h1>DMARC Mail Sources</h1>
@@ -139,6 +229,17 @@ export class Sources extends LitElement {
                     <a class="ml button" href="#/sources?type=Tls">Only Sources from SMTP TLS Reports</a>`
                 }
             </div>
+            <div>
+                Grouping: <a class="ml button" href="#/sources?group=prefix">By /24 and /48 Prefix</a>
+                <a class="ml button" href="#/sources?group=announced">By Announced Prefix</a>
+                <a class="ml button" href="#/sources?group=asn">By Autonomous System</a>
+            </div>
+            ${this.params.group ? this.renderGroups() : this.renderSources()}
+        `;
+    }
+
+    renderSources() {
+        return html`
             <table>
                 <tr>
                     <th>IP Address</th>
@@ -151,11 +252,11 @@ export class Sources extends LitElement {
                 ${this.sources.length !== 0 ? this.sources.map((source) =>
                     html`<tr> 
                         <td>${source.ip}</a></td>
-                        <td class="md-hidden">${this.prepareDnsName(source.dns)}</td>
+                        <td class="md-hidden">${this.prepareDnsName(source.dns)} ${this.prepareDnsChecks(source.dns_checks)}</td>
                         <td>${source.count}</td>
                         <td class="sm-hidden"><a href="#/sources?domain=${encodeURIComponent(source.domain)}">${source.domain}</a></td>
                         <td class="sm-hidden">${this.prepareTypesBadges(source)}</td>
-                        <td class="xs-hidden">${this.prepareIssueBadges(source.issues)}</td>
+                        <td class="xs-hidden">${this.prepareIssueBadges(source.issues)}${this.prepareListingBadges(source.dns_checks?.listed_on ?? [])}</td>
                     </tr>`
                 ) : html`<tr>
                         <td colspan="5">No sources found.</td>
@@ -19,6 +19,8 @@ export class TlsReport extends LitElement {
         this.report = null;
         this.ip2dns = {};
         this.ip2location = {};
+        this.ip2rdap = {};
+        this.ip2checks = {};
         this.ipDetails = {};
     }
 
@@ -28,6 +30,7 @@ export class TlsReport extends LitElement {
             const rwi = await response.json();
             this.report = rwi.report;
             this.mailId = rwi.mail_id;
+            this.getPolicyComparisons(this.id, [...this.report.policies]);
         }
     }
 
@@ -38,6 +41,20 @@ export class TlsReport extends LitElement {
             this.ipDetails[ip] = true;
             this.getDnsForIp(ip);
             this.getLocationForIp(ip);
+            this.getRdapForIp(ip);
+            this.getDnsChecksForIp(ip);
+        }
+        this.requestUpdate();
+    }
+
+    async getPolicyComparisons(id, policies) {
+        // Comparisons have the same order as the policies in the report
+        const response = await fetch("tls-reports/" + id + "/policies");
+        if (response.status === 200) {
+            const comparisons = await response.json();
+            policies.forEach((policy, index) => policy.comparison = comparisons[index] ?? null);
+        } else {
+            policies.forEach(policy => policy.comparison = null);
         }
         this.requestUpdate();
     }
@@ -64,6 +81,28 @@ export class TlsReport extends LitElement {
         this.requestUpdate();
     }
 
+    async getDnsChecksForIp(ip) {
+        const response = await fetch("ips/" + ip + "/dns-checks");
+        if (response.status === 200) {
+            const result = await response.json();
+            this.ip2checks[ip] = result;
+        } else {
+            this.ip2checks[ip] = null;
+        }
+        this.requestUpdate();
+    }
+
+    async getRdapForIp(ip) {
+        const response = await fetch("ips/" + ip + "/rdap");
+        if (response.status === 200) {
+            const result = await response.json();
+            this.ip2rdap[ip] = result;
+        } else {
+            this.ip2rdap[ip] = null;
+        }
+        this.requestUpdate();
+    }
+
     renderPolicyTypeBadge(result) {
         switch (result) {
             case "no-policy-found":
@@ -114,6 +153,21 @@ export class TlsReport extends LitElement {
         }
     }
 
+    renderComparison(comparison) {
+        if (comparison === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!comparison || !comparison.reported) {
+            return html`<span class="faded">n/a</span>`;
+        } else if (comparison.errors.length > 0) {
+            return html`<span class="faded">${join(comparison.errors.map(e => html`${e}`), html`<br>`)}</span>`;
+        } else if (comparison.differences.length === 0) {
+            return html`<span class="badge badge-positive">Up to date</span>`;
+        } else {
+            return html`<span class="badge badge-warning">Outdated</span><br>
+                ${this.renderMultilineCell(comparison.differences)}`;
+        }
+    }
+
     renderMultilineCell(array) {
         const lines = array.map(l => html`${l}`);
         return join(lines, html`<br>`);
@@ -126,6 +180,52 @@ export class TlsReport extends LitElement {
         return html`<a target="_blank" title="Show on OpenStreeMap" href="https://www.openstreetmap.org/#map=8/${lat}/${lon}">${lat}, ${lon}</a>`;
     }
 
+    renderVerifiedHostname(checks) {
+        if (checks === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (checks?.verified_hostname) {
+            return html`${checks.verified_hostname} <span class="badge badge-positive">verified</span>`;
+        } else if (checks?.hostname) {
+            return html`<span class="badge badge-negative help" title="${checks.hostname} does not resolve back to this IP">unverified</span>`;
+        } else {
+            return html`<span class="faded">n/a</span>`;
+        }
+    }
+
+    renderListings(checks) {
+        if (checks === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!checks) {
+            return html`<span class="faded">n/a</span>`;
+        } else if (checks.listed_on.length === 0 && checks.failed_zones.length > 0) {
+            return html`<span class="faded help" title="Lookups failed on ${checks.failed_zones.join(", ")}">unknown</span>`;
+        } else if (checks.listed_on.length === 0) {
+            return html`<span class="faded">not listed</span>`;
+        }
+        return checks.listed_on.map((zone) => html`<span class="badge badge-negative">Listed on ${zone}</span> `);
+    }
+
+    renderNetwork(rdap) {
+        if (rdap === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!rdap) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        const parts = [rdap.name, rdap.cidrs.join(", "), rdap.registrant].filter((p) => p);
+        return html`<a target="_blank" title="Show RDAP record" href="${rdap.source}">${parts.join(" - ") || rdap.source}</a>`;
+    }
+
+    renderAbuseContact(rdap) {
+        if (rdap === undefined) {
+            return html`<span class="faded">loading...</span>`;
+        } else if (!rdap?.abuse_contact?.emails.length) {
+            return html`<span class="faded">n/a</span>`;
+        }
+        return rdap.abuse_contact.emails.map((email, i) =>
+            html`${i > 0 ? ", " : ""}<a href="mailto:${email}">${email}</a>`
+        );
+    }
+
     renderPropIfObjDefined(obj, prop) {
         if (obj === undefined) {
             return html`<span class="faded">loading...</span>`;
@@ -214,6 +314,12 @@ export class TlsReport extends LitElement {
                             <td>${this.renderMultilineCell(policy.policy["mx-host"])}</td>
                         </tr>
                     ` : nothing}
+                    ${"policy-string" in policy.policy ? html`
+                        <tr>
+                            <td class="name help" title="Reported policy compared with the currently published policy">Current Policy</td>
+                            <td>${this.renderComparison(policy.comparison)}</td>
+                        </tr>
+                    ` : nothing}
                 </table>
                 <table>
                     <tr>
@@ -288,6 +394,22 @@ export class TlsReport extends LitElement {
                                     }
                                 </td>
                             </tr>
+                            <tr>
+                                <td class="name help" title="Host name from the PTR record that resolves back to the IP (FCrDNS)">Sending MTA IP Verified Hostname</td>
+                                <td>${this.renderVerifiedHostname(this.ip2checks[failureDetails["sending-mta-ip"]])}</td>
+                            </tr>
+                            <tr>
+                                <td class="name help" title="Listings on the configured DNSBL zones">Sending MTA IP DNSBL</td>
+                                <td>${this.renderListings(this.ip2checks[failureDetails["sending-mta-ip"]])}</td>
+                            </tr>
+                            <tr>
+                                <td class="name help" title="Registered network from the RDAP record">Sending MTA IP Network</td>
+                                <td>${this.renderNetwork(this.ip2rdap[failureDetails["sending-mta-ip"]])}</td>
+                            </tr>
+                            <tr>
+                                <td class="name help" title="Abuse contact from the RDAP record">Sending MTA IP Abuse Contact</td>
+                                <td>${this.renderAbuseContact(this.ip2rdap[failureDetails["sending-mta-ip"]])}</td>
+                            </tr>
                         </tbody>
                         <tbody>
                             <tr>
@@ -1,5 +1,6 @@
-import { LitElement, html } from "lit";
+import { LitElement, html, nothing } from "lit";
 import { globalStyle } from "../style.js";
+import { fetchPage } from "../utils.js";
 
 export class TlsReports extends LitElement {
     static styles = [globalStyle];
@@ -7,12 +8,15 @@ export class TlsReports extends LitElement {
     static properties = {
         params: { type: Object },
         reports: { type: Array },
+        total: { type: Number },
     };
 
     constructor() {
         super();
         this.params = {};
         this.reports = [];
+        this.total = 0;
+        this.url = null;
         this.filtered = false;
     }
 
@@ -46,10 +50,21 @@ export class TlsReports extends LitElement {
         if (urlParams.length > 0) {
             url += "?" + urlParams.join("&");
         }
-        const response = await fetch(url);
-        this.reports = await response.json();
-        this.reports.sort((a, b) => new Date(b.date_begin) - new Date(a.date_begin));
-        this.filtered = this.filtered = urlParams.length > 0;
+        this.url = url;
+        this.reports = [];
+        this.total = 0;
+        this.filtered = urlParams.length > 0;
+        await this.loadMore();
+    }
+
+    async loadMore() {
+        // Reports are sorted by the server with the newest first
+        const url = this.url;
+        const page = await fetchPage(url, this.reports.length);
+        if (url === this.url) {
+            this.reports = [...this.reports, ...page.items];
+            this.total = page.total;
+        }
     }
 
     render() {
@@ -66,6 +81,12 @@ export class TlsReports extends LitElement {
                 }
             </div>
             <drv-tls-report-table .reports="${this.reports}"></drv-tls-report-table>
+            ${this.reports.length < this.total ? html`
+                <div>
+                    Showing ${this.reports.length} of ${this.total}
+                    <button @click="${() => this.loadMore()}" class="ml button">Load More</button>
+                </div>
+            ` : nothing}
         `;
     }
 }
@@ -1,3 +1,15 @@
 export function join(elements, joiner) {
     return elements.flatMap(x => [joiner, x]).slice(1);
 }
+
+// Number of items loaded at once by the list views
+export const pageSize = 500;
+
+// Fetches one page of a list endpoint with the total number of items from the response header
+export async function fetchPage(url, offset) {
+    const separator = url.includes("?") ? "&" : "?";
+    const response = await fetch(url + separator + "limit=" + pageSize + "&offset=" + offset);
+    const items = await response.json();
+    const total = parseInt(response.headers.get("X-Total-Count") ?? items.length);
+    return { items, total };
+}